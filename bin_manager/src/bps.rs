@@ -0,0 +1,326 @@
+//! BPS patches of beat: sizes of the original and the modified file, actions building the modified
+//! file from both files and the patch, and CRC32 checksums of the files and of the patch itself.
+
+use crate::patch::{get_max_modified_len, get_run_len, BlockIndex, PatchReader};
+
+pub(crate) const MAGIC: &[u8] = b"BPS1";
+/// CRC32 checksums of the original file, the modified file and the patch.
+const FOOTER_LEN: usize = 12;
+/// Shortest run of the same byte copied from the byte before it rather than written in the patch.
+const MIN_RUN_COPY_LEN: usize = 4;
+
+/// Actions by their number in the patch.
+const ACTIONS: [Action; 4] = [
+    Action::SourceRead,
+    Action::TargetRead,
+    Action::SourceCopy,
+    Action::TargetCopy,
+];
+
+#[derive(Clone, Copy, PartialEq)]
+enum Action {
+    /// Copies bytes from the original file at the same offset.
+    SourceRead,
+    /// Copies bytes from the patch.
+    TargetRead,
+    /// Copies bytes from anywhere in the original file.
+    SourceCopy,
+    /// Copies bytes from earlier in the modified file, which may overlap with the copied bytes.
+    TargetCopy,
+}
+
+pub(crate) fn create(original: &[u8], modified: &[u8]) -> Vec<u8> {
+    let mut patch = MAGIC.to_vec();
+    write_number(&mut patch, original.len() as u64);
+    write_number(&mut patch, modified.len() as u64);
+    // No metadata.
+    write_number(&mut patch, 0);
+
+    // Unchanged bytes are read from the original file, runs of changed bytes are copied
+    // from the byte before them and bytes moved from elsewhere in the original file are
+    // copied from there, so only other changed bytes are written in the patch.
+    let source_blocks = BlockIndex::new(original);
+    let mut source_copy_offset = 0;
+    let mut target_copy_offset = 0;
+    let mut i = 0;
+    while i < modified.len() {
+        let unchanged_len = modified[i..]
+            .iter()
+            .zip(original.get(i..).unwrap_or_default())
+            .take_while(|(a, b)| a == b)
+            .count();
+        if unchanged_len > 0 {
+            write_action(&mut patch, Action::SourceRead, unchanged_len);
+            i += unchanged_len;
+            continue;
+        }
+        let run_len = get_run_len(&modified[i..]);
+        if i > 0 && modified[i - 1] == modified[i] && run_len >= MIN_RUN_COPY_LEN {
+            write_action(&mut patch, Action::TargetCopy, run_len);
+            let relative_offset = (i - 1) as i64 - target_copy_offset as i64;
+            write_signed_number(&mut patch, relative_offset);
+            target_copy_offset = i - 1 + run_len;
+            i += run_len;
+            continue;
+        }
+        let mut end = i;
+        let source_match = loop {
+            if let Some(source_match) = source_blocks.find(modified, end, i) {
+                break Some(source_match);
+            }
+            end += 1;
+            if end == modified.len()
+                || original.get(end) == Some(&modified[end])
+                || (modified[end - 1] == modified[end]
+                    && get_run_len(&modified[end..]) >= MIN_RUN_COPY_LEN)
+            {
+                break None;
+            }
+        };
+        let read_end = source_match
+            .as_ref()
+            .map_or(end, |source_match| source_match.target_offset);
+        if read_end > i {
+            write_action(&mut patch, Action::TargetRead, read_end - i);
+            patch.extend(&modified[i..read_end]);
+        }
+        i = end;
+        if let Some(source_match) = source_match {
+            write_action(&mut patch, Action::SourceCopy, source_match.len);
+            let relative_offset = source_match.source_offset as i64 - source_copy_offset as i64;
+            write_signed_number(&mut patch, relative_offset);
+            source_copy_offset = source_match.source_offset + source_match.len;
+            i = source_match.target_offset + source_match.len;
+        }
+    }
+
+    patch.extend(crc32(original).to_le_bytes());
+    patch.extend(crc32(modified).to_le_bytes());
+    patch.extend(crc32(&patch).to_le_bytes());
+    patch
+}
+
+pub(crate) fn apply(patch: &[u8], original: &[u8]) -> Result<Vec<u8>, String> {
+    if patch.len() < MAGIC.len() + FOOTER_LEN || !patch.starts_with(MAGIC) {
+        return Err(String::from("BPS patch does not start with \"BPS1\"."));
+    }
+    let footer_offset = patch.len() - FOOTER_LEN;
+    let read_crc32 = |index: usize| {
+        let offset = footer_offset + index * 4;
+        u32::from_le_bytes(patch[offset..offset + 4].try_into().unwrap())
+    };
+    if crc32(&patch[..footer_offset + 8]) != read_crc32(2) {
+        return Err(String::from(
+            "BPS patch is corrupted: its CRC32 checksum does not match.",
+        ));
+    }
+    if crc32(original) != read_crc32(0) {
+        return Err(String::from(
+            "BPS patch is not made for the given original file: CRC32 checksum of the original file does not match.",
+        ));
+    }
+
+    let mut reader = PatchReader::new(&patch[..footer_offset]);
+    reader.read_bytes(MAGIC.len())?;
+    let original_len = read_number(&mut reader)? as usize;
+    let modified_len = read_number(&mut reader)? as usize;
+    if original_len != original.len() {
+        return Err(format!(
+            "BPS patch is made for an original file of {} bytes, but the given file is {} bytes.",
+            original_len,
+            original.len()
+        ));
+    }
+    if modified_len > get_max_modified_len(original, patch) {
+        return Err(format!(
+            "BPS patch gives a modified file of {} bytes, which is too large for an original file of {} bytes.",
+            modified_len,
+            original.len()
+        ));
+    }
+    let metadata_len = read_number(&mut reader)? as usize;
+    reader.read_bytes(metadata_len)?;
+
+    let mut modified = Vec::new();
+    let mut source_copy_offset = 0usize;
+    let mut target_copy_offset = 0usize;
+    while !reader.is_at_end() {
+        let action_offset = reader.offset;
+        let data = read_number(&mut reader)?;
+        let len = (data >> 2) as usize + 1;
+        if modified.len().saturating_add(len) > modified_len {
+            return Err(format!(
+                "BPS patch action at offset {} writes past the end of the modified file.",
+                action_offset
+            ));
+        }
+        let out_of_bounds_error = || {
+            format!(
+                "BPS patch action at offset {} copies bytes from outside of the file.",
+                action_offset
+            )
+        };
+        match ACTIONS[(data & 3) as usize] {
+            Action::SourceRead => {
+                let offset = modified.len();
+                let bytes = original
+                    .get(offset..offset.saturating_add(len))
+                    .ok_or_else(out_of_bounds_error)?;
+                modified.extend(bytes);
+            }
+            Action::TargetRead => modified.extend(reader.read_bytes(len)?),
+            Action::SourceCopy => {
+                source_copy_offset = source_copy_offset
+                    .checked_add_signed(read_signed_number(&mut reader)? as isize)
+                    .ok_or_else(out_of_bounds_error)?;
+                let bytes = original
+                    .get(source_copy_offset..source_copy_offset.saturating_add(len))
+                    .ok_or_else(out_of_bounds_error)?;
+                modified.extend(bytes);
+                source_copy_offset += len;
+            }
+            Action::TargetCopy => {
+                target_copy_offset = target_copy_offset
+                    .checked_add_signed(read_signed_number(&mut reader)? as isize)
+                    .filter(|offset| *offset < modified.len())
+                    .ok_or_else(out_of_bounds_error)?;
+                // Copied bytes may overlap with the written bytes, so copy byte by byte.
+                for _ in 0..len {
+                    modified.push(modified[target_copy_offset]);
+                    target_copy_offset += 1;
+                }
+            }
+        }
+    }
+
+    if modified.len() != modified_len {
+        return Err(format!(
+            "BPS patch gives a modified file of {} bytes, but {} bytes were expected.",
+            modified.len(),
+            modified_len
+        ));
+    }
+    if crc32(&modified) != read_crc32(1) {
+        return Err(String::from(
+            "BPS patch gives a modified file with a wrong CRC32 checksum.",
+        ));
+    }
+    Ok(modified)
+}
+
+fn write_action(patch: &mut Vec<u8>, action: Action, len: usize) {
+    write_number(patch, ((len as u64 - 1) << 2) | action as u64);
+}
+
+/// Writes a number with 7 bits in each byte, the last byte having its highest bit set.
+/// Each byte after the first one also adds 1, so each number has only one encoding.
+fn write_number(patch: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let bits = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            patch.push(0x80 | bits);
+            break;
+        }
+        patch.push(bits);
+        value -= 1;
+    }
+}
+
+fn write_signed_number(patch: &mut Vec<u8>, value: i64) {
+    write_number(patch, (value.unsigned_abs() << 1) | (value < 0) as u64);
+}
+
+fn read_number(reader: &mut PatchReader) -> Result<u64, String> {
+    let offset = reader.offset;
+    let too_large_error = || format!("BPS patch has a too large number at offset {}.", offset);
+    let mut value = 0u64;
+    let mut shift = 1u64;
+    loop {
+        let byte = reader.read_u8()?;
+        value = (byte as u64 & 0x7F)
+            .checked_mul(shift)
+            .and_then(|bits| value.checked_add(bits))
+            .ok_or_else(too_large_error)?;
+        if byte & 0x80 != 0 {
+            return Ok(value);
+        }
+        shift = shift.checked_mul(0x80).ok_or_else(too_large_error)?;
+        value = value.checked_add(shift).ok_or_else(too_large_error)?;
+    }
+}
+
+fn read_signed_number(reader: &mut PatchReader) -> Result<i64, String> {
+    let value = read_number(reader)?;
+    let magnitude = (value >> 1) as i64;
+    Ok(match value & 1 {
+        0 => magnitude,
+        _ => -magnitude,
+    })
+}
+
+/// CRC32 checksum used by BPS patches (and ZIP files), with reversed polynomial 0xEDB88320.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut table = [0u32; 256];
+    for (i, entry) in table.iter_mut().enumerate() {
+        let mut value = i as u32;
+        for _ in 0..8 {
+            value = match value & 1 {
+                0 => value >> 1,
+                _ => (value >> 1) ^ 0xEDB88320,
+            };
+        }
+        *entry = value;
+    }
+    !data.iter().fold(!0u32, |crc, byte| {
+        (crc >> 8) ^ table[((crc ^ *byte as u32) & 0xFF) as usize]
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_and_write_numbers() {
+        for value in [0, 1, 0x7F, 0x80, 0x407F, 0x4080, u32::MAX as u64] {
+            let mut patch = Vec::new();
+            write_number(&mut patch, value);
+            assert_eq!(read_number(&mut PatchReader::new(&patch)).unwrap(), value);
+        }
+        let mut patch = Vec::new();
+        write_number(&mut patch, 0x80);
+        assert_eq!(patch, [0x00, 0x80]);
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+    }
+    #[test]
+    fn check_bps_crc32() {
+        let original = b"Spyro the Dragon".to_vec();
+        let modified = b"Spyro 2: Ripto's Rage!".to_vec();
+        let mut patch = create(&original, &modified);
+        assert_eq!(apply(&patch, &original).unwrap(), modified);
+        assert!(apply(&patch, b"Spyro the Dragoon")
+            .unwrap_err()
+            .contains("original file"));
+        patch[6] ^= 1;
+        assert!(apply(&patch, &original).unwrap_err().contains("corrupted"));
+    }
+    #[test]
+    fn fail_apply_bps_with_huge_modified_file() {
+        // One byte copied over and over into a modified file of 4 GB.
+        let mut patch = MAGIC.to_vec();
+        write_number(&mut patch, 0);
+        write_number(&mut patch, u32::MAX as u64);
+        write_number(&mut patch, 0);
+        write_action(&mut patch, Action::TargetRead, 1);
+        patch.push(0);
+        write_action(&mut patch, Action::TargetCopy, u32::MAX as usize - 1);
+        write_signed_number(&mut patch, 0);
+        patch.extend(crc32(&[]).to_le_bytes());
+        patch.extend(0u32.to_le_bytes());
+        patch.extend(crc32(&patch).to_le_bytes());
+        assert!(apply(&patch, &[])
+            .unwrap_err()
+            .contains("too large for an original file"));
+    }
+}
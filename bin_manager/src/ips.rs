@@ -0,0 +1,166 @@
+//! IPS patches: "PATCH", records of bytes to write at 3 byte offsets and "EOF".
+//! A 3 byte size may follow "EOF" to truncate the file (an extension of Lunar IPS).
+
+use crate::patch::{get_run_len, PatchReader};
+
+pub(crate) const MAGIC: &[u8] = b"PATCH";
+const END_MARKER: &[u8] = b"EOF";
+/// Record offset which would be read as the end marker, so records cannot start there.
+const END_MARKER_OFFSET: usize = 0x454F46;
+/// Offsets are 3 bytes, so files cannot be larger than 16 MB.
+const MAX_FILE_LEN: usize = 0x1000000;
+const MAX_RECORD_LEN: usize = 0xFFFF;
+/// Shortest run of the same byte written as a run record, which is 8 bytes long.
+const MIN_RUN_RECORD_LEN: usize = 9;
+/// Longest run of unchanged bytes written as part of a record, rather than starting a new record.
+const MAX_UNCHANGED_GAP: usize = 5;
+
+pub(crate) fn create(original: &[u8], modified: &[u8]) -> Result<Vec<u8>, String> {
+    if modified.len() > MAX_FILE_LEN {
+        return Err(format!(
+            "Modified file is {} bytes, but IPS patches only support files up to {} bytes. Use BPS or VCDIFF instead.",
+            modified.len(),
+            MAX_FILE_LEN
+        ));
+    }
+    let is_changed = |i: usize| original.get(i) != Some(&modified[i]);
+
+    let mut patch = MAGIC.to_vec();
+    let mut i = 0;
+    while i < modified.len() {
+        if !is_changed(i) {
+            i += 1;
+            continue;
+        }
+        // Changes close to each other are written in the same record.
+        let mut end = i;
+        while end < modified.len() {
+            if is_changed(end) {
+                end += 1;
+                continue;
+            }
+            match (end..modified.len().min(end + MAX_UNCHANGED_GAP + 1)).find(|j| is_changed(*j)) {
+                Some(next_change) => end = next_change,
+                None => break,
+            }
+        }
+
+        while i < end {
+            // Record cannot start at the end marker offset, so it starts at the byte before.
+            let start = match i {
+                END_MARKER_OFFSET => i - 1,
+                _ => i,
+            };
+            let run_len = get_run_len(&modified[i..end]).min(MAX_RECORD_LEN);
+            if start == i && run_len >= MIN_RUN_RECORD_LEN {
+                patch.extend(&(i as u32).to_be_bytes()[1..]);
+                patch.extend([0, 0]);
+                patch.extend(&(run_len as u16).to_be_bytes());
+                patch.push(modified[i]);
+                i += run_len;
+                continue;
+            }
+            // Record ends before a run long enough for a run record.
+            let mut record_end = i + 1;
+            while record_end < end
+                && record_end - start < MAX_RECORD_LEN
+                && get_run_len(&modified[record_end..end]) < MIN_RUN_RECORD_LEN
+            {
+                record_end += 1;
+            }
+            patch.extend(&(start as u32).to_be_bytes()[1..]);
+            patch.extend(&((record_end - start) as u16).to_be_bytes());
+            patch.extend(&modified[start..record_end]);
+            i = record_end;
+        }
+    }
+    patch.extend(END_MARKER);
+    if modified.len() < original.len() {
+        patch.extend(&(modified.len() as u32).to_be_bytes()[1..]);
+    }
+    Ok(patch)
+}
+
+pub(crate) fn apply(patch: &[u8], original: &[u8]) -> Result<Vec<u8>, String> {
+    let mut reader = PatchReader::new(patch);
+    if reader.read_bytes(MAGIC.len())? != MAGIC {
+        return Err(String::from("IPS patch does not start with \"PATCH\"."));
+    }
+    let read_u24 = |reader: &mut PatchReader| -> Result<usize, String> {
+        let bytes = reader.read_bytes(3)?;
+        Ok(u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]) as usize)
+    };
+
+    let mut modified = original.to_vec();
+    loop {
+        let offset_bytes = reader.read_bytes(3)?;
+        if offset_bytes == END_MARKER {
+            break;
+        }
+        let offset =
+            u32::from_be_bytes([0, offset_bytes[0], offset_bytes[1], offset_bytes[2]]) as usize;
+        let len = u16::from_be_bytes(reader.read_bytes(2)?.try_into().unwrap()) as usize;
+        let (len, bytes) = match len {
+            // Run of the same byte.
+            0 => {
+                let len = u16::from_be_bytes(reader.read_bytes(2)?.try_into().unwrap()) as usize;
+                (len, vec![reader.read_u8()?; len])
+            }
+            len => (len, reader.read_bytes(len)?.to_vec()),
+        };
+        if modified.len() < offset + len {
+            modified.resize(offset + len, 0);
+        }
+        modified[offset..offset + len].copy_from_slice(&bytes);
+    }
+    match patch.len() - reader.offset {
+        0 => {}
+        3 => {
+            let len = read_u24(&mut reader)?;
+            modified.truncate(len);
+        }
+        count => {
+            return Err(format!(
+                "IPS patch has {} unexpected bytes after \"EOF\" at offset {}.",
+                count, reader.offset
+            ))
+        }
+    }
+    Ok(modified)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn apply_ips_records() {
+        let patch = [
+            b"PATCH".as_slice(),
+            &[0, 0, 1, 0, 2, 0xAA, 0xBB],
+            &[0, 0, 6, 0, 0, 0, 3, 0xCC],
+            b"EOF",
+            &[0, 0, 8],
+        ]
+        .concat();
+        assert_eq!(
+            apply(&patch, &[0; 10]).unwrap(),
+            [0, 0xAA, 0xBB, 0, 0, 0, 0xCC, 0xCC]
+        );
+        assert!(apply(b"PATCH\0\0\x01\0\x02\xAA", &[0; 10]).is_err());
+        assert!(apply(b"PATCHEOF\0", &[0; 10]).is_err());
+    }
+    #[test]
+    fn create_ips_record_around_end_marker_offset() {
+        let original = vec![0; END_MARKER_OFFSET + 2];
+        let mut modified = original.clone();
+        modified[END_MARKER_OFFSET] = 1;
+        let patch = create(&original, &modified).unwrap();
+        assert_eq!(
+            &patch[5..8],
+            &(END_MARKER_OFFSET as u32 - 1).to_be_bytes()[1..]
+        );
+        assert_eq!(apply(&patch, &original).unwrap(), modified);
+        assert!(create(&[], &vec![0; MAX_FILE_LEN + 1]).is_err());
+    }
+}
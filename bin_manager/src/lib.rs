@@ -1,9 +1,24 @@
-use std::{fs::File, io::{Error, Read, Seek, SeekFrom}};
+use std::{
+    fs::File,
+    io::{Error, Read, Seek, SeekFrom},
+};
 
-pub fn read_bytes_from_file(file_path: &str, offset: usize, count: usize) -> Result<Vec<u8>, Error> {
+mod bps;
+mod ips;
+mod patch;
+mod vcdiff;
+
+pub use bps::crc32;
+pub use patch::{PatchFormat, PATCH_FORMAT_NAMES};
+
+pub fn read_bytes_from_file(
+    file_path: &str,
+    offset: usize,
+    count: usize,
+) -> Result<Vec<u8>, Error> {
     let mut file = File::open(file_path)?;
     file.seek(SeekFrom::Start(offset as u64))?;
     let mut buffer = vec![0; count];
     file.read_exact(&mut buffer)?;
     Ok(buffer)
-}
\ No newline at end of file
+}
@@ -0,0 +1,249 @@
+//! Binary patches in standard formats, so that changes can be distributed without the original files.
+
+use crate::{bps, ips, vcdiff};
+use std::collections::HashMap;
+
+/// Size of the blocks of the original file looked up to find bytes moved in the modified file.
+const BLOCK_LEN: usize = 16;
+/// How many times larger than the original file and the patch a modified file may be.
+const MAX_ORIGINAL_GROWTH: usize = 4;
+const MAX_PATCH_GROWTH: usize = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PatchFormat {
+    /// International Patching System: records of bytes to write at offsets, up to 16 MB files.
+    Ips,
+    /// Binary Patching System of beat: copies from the original and the patched file,
+    /// with CRC32 checksums of both and of the patch.
+    Bps,
+    /// Generic differencing format of RFC 3284, also written by xdelta3.
+    Vcdiff,
+}
+pub const PATCH_FORMAT_NAMES: &[&str] = &["ips", "bps", "vcdiff"];
+impl PatchFormat {
+    /// Gets a patch format by its name (see [PATCH_FORMAT_NAMES]).
+    pub fn from_name(name: &str) -> Result<Self, String> {
+        match name {
+            "ips" => Ok(Self::Ips),
+            "bps" => Ok(Self::Bps),
+            "vcdiff" | "xdelta" => Ok(Self::Vcdiff),
+            _ => Err(format!(
+                "Unknown patch format \"{}\". Supported patch formats include {}.",
+                name,
+                PATCH_FORMAT_NAMES.join(", ")
+            )),
+        }
+    }
+    /// Gets a patch format by a file extension, like "ips" or "xdelta".
+    pub fn from_file_extension(extension: &str) -> Option<Self> {
+        match extension.to_ascii_lowercase().as_str() {
+            "ips" => Some(Self::Ips),
+            "bps" => Some(Self::Bps),
+            "vcdiff" | "vcd" | "xdelta" => Some(Self::Vcdiff),
+            _ => None,
+        }
+    }
+    /// Detects the format of a patch by its magic bytes.
+    pub fn detect(patch: &[u8]) -> Option<Self> {
+        if patch.starts_with(ips::MAGIC) {
+            Some(Self::Ips)
+        } else if patch.starts_with(bps::MAGIC) {
+            Some(Self::Bps)
+        } else if patch.starts_with(vcdiff::MAGIC) {
+            Some(Self::Vcdiff)
+        } else {
+            None
+        }
+    }
+    /// Creates a patch turning the original file into the modified file.
+    pub fn create_patch(&self, original: &[u8], modified: &[u8]) -> Result<Vec<u8>, String> {
+        match self {
+            Self::Ips => ips::create(original, modified),
+            Self::Bps => Ok(bps::create(original, modified)),
+            Self::Vcdiff => Ok(vcdiff::create(original, modified)),
+        }
+    }
+    /// Applies a patch to the original file, giving the modified file.
+    pub fn apply_patch(&self, patch: &[u8], original: &[u8]) -> Result<Vec<u8>, String> {
+        match self {
+            Self::Ips => ips::apply(patch, original),
+            Self::Bps => bps::apply(patch, original),
+            Self::Vcdiff => vcdiff::apply(patch, original),
+        }
+    }
+}
+
+/// Reads a patch from start to end, failing if the patch ends too early.
+pub(crate) struct PatchReader<'a> {
+    data: &'a [u8],
+    pub offset: usize,
+}
+impl<'a> PatchReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, offset: 0 }
+    }
+    pub fn is_at_end(&self) -> bool {
+        self.offset >= self.data.len()
+    }
+    pub fn read_bytes(&mut self, count: usize) -> Result<&'a [u8], String> {
+        let bytes = self
+            .offset
+            .checked_add(count)
+            .and_then(|end| self.data.get(self.offset..end))
+            .ok_or(format!(
+                "Patch ends unexpectedly when reading {} bytes at offset {}.",
+                count, self.offset
+            ))?;
+        self.offset += count;
+        Ok(bytes)
+    }
+    pub fn read_u8(&mut self) -> Result<u8, String> {
+        Ok(self.read_bytes(1)?[0])
+    }
+}
+
+/// Gets the largest size of a modified file a patch may give. Sizes in patches are not trusted
+/// beyond it, so that a corrupted patch of a few bytes fails instead of using gigabytes of memory.
+pub(crate) fn get_max_modified_len(original: &[u8], patch: &[u8]) -> usize {
+    original
+        .len()
+        .saturating_mul(MAX_ORIGINAL_GROWTH)
+        .saturating_add(patch.len().saturating_mul(MAX_PATCH_GROWTH))
+}
+
+/// Gets the length of the run of the same byte at the beginning of the given bytes.
+pub(crate) fn get_run_len(bytes: &[u8]) -> usize {
+    bytes
+        .first()
+        .map_or(0, |first| bytes.iter().take_while(|b| *b == first).count())
+}
+
+/// Bytes of the modified file found in the original file.
+pub(crate) struct SourceMatch {
+    pub target_offset: usize,
+    pub source_offset: usize,
+    pub len: usize,
+}
+
+/// Blocks of the original file by their bytes, so that bytes moved in the modified file
+/// are copied from the original file rather than written in the patch.
+pub(crate) struct BlockIndex<'a> {
+    source: &'a [u8],
+    blocks: HashMap<&'a [u8], usize>,
+}
+impl<'a> BlockIndex<'a> {
+    pub fn new(source: &'a [u8]) -> Self {
+        let mut blocks = HashMap::new();
+        for (i, block) in source.chunks_exact(BLOCK_LEN).enumerate() {
+            blocks.entry(block).or_insert(i * BLOCK_LEN);
+        }
+        Self { source, blocks }
+    }
+    /// Finds the block of the original file at the given position of the modified file,
+    /// extended forwards and backwards down to `start` as long as the bytes match.
+    pub fn find(&self, target: &[u8], position: usize, start: usize) -> Option<SourceMatch> {
+        let block = target.get(position..position + BLOCK_LEN)?;
+        let source_offset = *self.blocks.get(block)?;
+        let forward_len = target[position + BLOCK_LEN..]
+            .iter()
+            .zip(&self.source[source_offset + BLOCK_LEN..])
+            .take_while(|(a, b)| a == b)
+            .count();
+        let backward_len = target[start..position]
+            .iter()
+            .rev()
+            .zip(self.source[..source_offset].iter().rev())
+            .take_while(|(a, b)| a == b)
+            .count();
+        Some(SourceMatch {
+            target_offset: position - backward_len,
+            source_offset: source_offset - backward_len,
+            len: backward_len + BLOCK_LEN + forward_len,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Original and modified files with changed, inserted and removed bytes and runs.
+    fn get_files() -> Vec<(Vec<u8>, Vec<u8>)> {
+        let original = (0..5000u32)
+            .map(|i| (i * 7 + i / 13) as u8)
+            .collect::<Vec<_>>();
+        let mut changed = original.clone();
+        changed[10] = 0xFF;
+        changed[100..140].fill(0);
+        changed[4999] ^= 1;
+        let mut grown = changed.clone();
+        grown.extend([0xAA; 300]);
+        grown.extend(b"Spyro");
+        let shrunk = changed[..3000].to_vec();
+        let mut moved = b"Spyro".to_vec();
+        moved.extend(&original[2000..]);
+        moved.extend(&original[..2000]);
+        vec![
+            (original.clone(), changed),
+            (original.clone(), moved),
+            (original.clone(), grown),
+            (original.clone(), shrunk),
+            (original.clone(), original.clone()),
+            (Vec::new(), original.clone()),
+            (original, Vec::new()),
+        ]
+    }
+
+    #[test]
+    fn create_and_apply_patches() {
+        for format in [PatchFormat::Ips, PatchFormat::Bps, PatchFormat::Vcdiff] {
+            for (original, modified) in get_files() {
+                let patch = format.create_patch(&original, &modified).unwrap();
+                assert_eq!(PatchFormat::detect(&patch), Some(format));
+                assert_eq!(
+                    format.apply_patch(&patch, &original).unwrap(),
+                    modified,
+                    "{:?} patch from {} to {} bytes",
+                    format,
+                    original.len(),
+                    modified.len()
+                );
+                // Truncated patches fail instead of giving a partly patched file.
+                if !patch.is_empty() {
+                    assert!(format
+                        .apply_patch(&patch[..patch.len() - 1], &original)
+                        .is_err());
+                }
+            }
+        }
+    }
+    #[test]
+    fn copy_moved_bytes() {
+        // Pseudo-random bytes, so that each block is found at one offset only.
+        let original = (0..5000u32)
+            .scan(1u32, |state, _| {
+                *state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                Some((*state >> 16) as u8)
+            })
+            .collect::<Vec<_>>();
+        let mut modified = b"Spyro".to_vec();
+        modified.extend(&original[2000..]);
+        modified.extend(&original[..2000]);
+        let index = BlockIndex::new(&original);
+        let found = index.find(&modified, 5 + BLOCK_LEN, 0).unwrap();
+        assert_eq!(found.target_offset, 5);
+        assert_eq!(found.source_offset, 2000);
+        assert_eq!(found.len, 3000);
+        assert!(index.find(&modified, 0, 0).is_none());
+        // Moved bytes are copied, so only the inserted bytes are written in the patch.
+        for format in [PatchFormat::Bps, PatchFormat::Vcdiff] {
+            let patch = format.create_patch(&original, &modified).unwrap();
+            assert!(
+                patch.len() < 64,
+                "{:?} patch is {} bytes",
+                format,
+                patch.len()
+            );
+        }
+    }
+}
@@ -0,0 +1,457 @@
+//! VCDIFF patches of RFC 3284, as written by xdelta3: windows of the modified file, each built from
+//! a segment of the original file with instructions coded by the default code table.
+//! Secondary compression and custom code tables of xdelta3 are not supported.
+
+use crate::patch::{get_max_modified_len, get_run_len, BlockIndex, PatchReader};
+
+pub(crate) const MAGIC: &[u8] = &[0xD6, 0xC3, 0xC4, 0x00];
+/// Header indicator bits.
+const VCD_DECOMPRESS: u8 = 0x01;
+const VCD_CODETABLE: u8 = 0x02;
+/// Application header written by xdelta3, which has the file names.
+const VCD_APPHEADER: u8 = 0x04;
+/// Window indicator bits.
+const VCD_SOURCE: u8 = 0x01;
+const VCD_TARGET: u8 = 0x02;
+/// Adler32 checksum of the window, written by xdelta3 and open-vcdiff.
+const VCD_ADLER32: u8 = 0x04;
+/// Size of windows written, so that patches of large files are applied in parts.
+const WINDOW_LEN: usize = 0x800000;
+/// Shortest run of the same byte written as a run instruction.
+const MIN_RUN_LEN: usize = 4;
+/// Sizes of the caches of recent addresses used by the default code table.
+const NEAR_CACHE_LEN: usize = 4;
+const SAME_CACHE_LEN: usize = 3;
+/// Address modes of copies: not cached ones, relative to the current position, and cached ones.
+const VCD_SELF: u8 = 0;
+const VCD_HERE: u8 = 1;
+
+#[derive(Clone, Copy, PartialEq)]
+enum InstructionKind {
+    Noop,
+    /// Adds bytes from the data section.
+    Add,
+    /// Repeats a byte from the data section.
+    Run,
+    /// Copies bytes from the source segment or from earlier in the window.
+    Copy,
+}
+
+/// One or two instructions coded by one byte, with their sizes (0 if in the instruction section)
+/// and address modes of copies.
+#[derive(Clone, Copy)]
+struct CodeTableEntry {
+    instructions: [(InstructionKind, usize, u8); 2],
+}
+
+/// Default code table of RFC 3284 (section 5.6).
+fn get_default_code_table() -> Vec<CodeTableEntry> {
+    use InstructionKind::*;
+    let single = |kind, size, mode| CodeTableEntry {
+        instructions: [(kind, size, mode), (Noop, 0, 0)],
+    };
+    let double = |first, second| CodeTableEntry {
+        instructions: [first, second],
+    };
+    let mode_count = 2 + NEAR_CACHE_LEN as u8 + SAME_CACHE_LEN as u8;
+
+    let mut table = vec![single(Run, 0, 0)];
+    table.extend((0..=17).map(|size| single(Add, size, 0)));
+    for mode in 0..mode_count {
+        table.push(single(Copy, 0, mode));
+        table.extend((4..=18).map(|size| single(Copy, size, mode)));
+    }
+    for mode in 0..mode_count {
+        // Copies with same cache modes are short, so they are combined with fewer sizes.
+        let copy_sizes = match mode < 2 + NEAR_CACHE_LEN as u8 {
+            true => 4..=6,
+            false => 4..=4,
+        };
+        for add_size in 1..=4 {
+            for copy_size in copy_sizes.clone() {
+                table.push(double((Add, add_size, 0), (Copy, copy_size, mode)));
+            }
+        }
+    }
+    for mode in 0..mode_count {
+        table.push(double((Copy, 4, mode), (Add, 1, 0)));
+    }
+    table
+}
+
+/// Caches of recently copied addresses, which copy addresses can be relative to.
+struct AddressCache {
+    near: [usize; NEAR_CACHE_LEN],
+    next_near_slot: usize,
+    same: [usize; SAME_CACHE_LEN * 256],
+}
+impl AddressCache {
+    fn new() -> Self {
+        Self {
+            near: [0; NEAR_CACHE_LEN],
+            next_near_slot: 0,
+            same: [0; SAME_CACHE_LEN * 256],
+        }
+    }
+    fn update(&mut self, address: usize) {
+        self.near[self.next_near_slot] = address;
+        self.next_near_slot = (self.next_near_slot + 1) % NEAR_CACHE_LEN;
+        self.same[address % (SAME_CACHE_LEN * 256)] = address;
+    }
+    fn decode(&mut self, here: usize, mode: u8, reader: &mut PatchReader) -> Result<usize, String> {
+        let address = match mode {
+            VCD_SELF => read_number(reader)? as usize,
+            VCD_HERE => here
+                .checked_sub(read_number(reader)? as usize)
+                .ok_or(String::from(
+                    "VCDIFF copy address is before the beginning of the window.",
+                ))?,
+            mode if (mode as usize) < 2 + NEAR_CACHE_LEN => {
+                self.near[mode as usize - 2].saturating_add(read_number(reader)? as usize)
+            }
+            mode => {
+                let index = (mode as usize - 2 - NEAR_CACHE_LEN) * 256 + reader.read_u8()? as usize;
+                self.same[index]
+            }
+        };
+        if address >= here {
+            return Err(format!(
+                "VCDIFF copy address {} is not before the current position {}.",
+                address, here
+            ));
+        }
+        self.update(address);
+        Ok(address)
+    }
+}
+
+pub(crate) fn create(original: &[u8], modified: &[u8]) -> Vec<u8> {
+    let mut patch = MAGIC.to_vec();
+    patch.push(0);
+
+    // Each window uses the segment of the original file at the same offset. Unchanged bytes
+    // and bytes moved within the segment are copied from it, runs are repeated and other
+    // changed bytes are added.
+    for window_offset in (0..modified.len()).step_by(WINDOW_LEN) {
+        let window = &modified[window_offset..modified.len().min(window_offset + WINDOW_LEN)];
+        let segment = original
+            .get(window_offset..original.len().min(window_offset + WINDOW_LEN))
+            .unwrap_or_default();
+        let segment_blocks = BlockIndex::new(segment);
+
+        let mut data = Vec::new();
+        let mut instructions = Vec::new();
+        let mut addresses = Vec::new();
+        let mut i = 0;
+        while i < window.len() {
+            let unchanged_len = window[i..]
+                .iter()
+                .zip(segment.get(i..).unwrap_or_default())
+                .take_while(|(a, b)| a == b)
+                .count();
+            let run_len = get_run_len(&window[i..]);
+            if unchanged_len >= MIN_RUN_LEN {
+                // Copy of size 0 and mode VCD_SELF, with the address relative to the segment.
+                instructions.push(19);
+                write_number(&mut instructions, unchanged_len as u64);
+                write_number(&mut addresses, i as u64);
+                i += unchanged_len;
+            } else if run_len >= MIN_RUN_LEN {
+                instructions.push(0);
+                write_number(&mut instructions, run_len as u64);
+                data.push(window[i]);
+                i += run_len;
+            } else {
+                let mut end = i;
+                let segment_match = loop {
+                    if let Some(segment_match) = segment_blocks.find(window, end, i) {
+                        break Some(segment_match);
+                    }
+                    end += 1;
+                    if end == window.len()
+                        || get_run_len(&window[end..]) >= MIN_RUN_LEN
+                        || window[end..]
+                            .iter()
+                            .zip(segment.get(end..).unwrap_or_default())
+                            .take_while(|(a, b)| a == b)
+                            .nth(MIN_RUN_LEN - 1)
+                            .is_some()
+                    {
+                        break None;
+                    }
+                };
+                let add_end = segment_match
+                    .as_ref()
+                    .map_or(end, |segment_match| segment_match.target_offset);
+                if add_end > i {
+                    // Add of size 0, with the size in the instruction section.
+                    instructions.push(1);
+                    write_number(&mut instructions, (add_end - i) as u64);
+                    data.extend(&window[i..add_end]);
+                }
+                i = end;
+                if let Some(segment_match) = segment_match {
+                    instructions.push(19);
+                    write_number(&mut instructions, segment_match.len as u64);
+                    write_number(&mut addresses, segment_match.source_offset as u64);
+                    i = segment_match.target_offset + segment_match.len;
+                }
+            }
+        }
+
+        let mut delta = Vec::new();
+        write_number(&mut delta, window.len() as u64);
+        // No secondary compression.
+        delta.push(0);
+        write_number(&mut delta, data.len() as u64);
+        write_number(&mut delta, instructions.len() as u64);
+        write_number(&mut delta, addresses.len() as u64);
+        delta.extend(adler32(window).to_be_bytes());
+        delta.extend(data);
+        delta.extend(instructions);
+        delta.extend(addresses);
+
+        match segment.is_empty() {
+            true => patch.push(VCD_ADLER32),
+            false => {
+                patch.push(VCD_SOURCE | VCD_ADLER32);
+                write_number(&mut patch, segment.len() as u64);
+                write_number(&mut patch, window_offset as u64);
+            }
+        }
+        write_number(&mut patch, delta.len() as u64);
+        patch.extend(delta);
+    }
+    patch
+}
+
+pub(crate) fn apply(patch: &[u8], original: &[u8]) -> Result<Vec<u8>, String> {
+    let mut reader = PatchReader::new(patch);
+    if reader.read_bytes(MAGIC.len())? != MAGIC {
+        return Err(String::from(
+            "VCDIFF patch does not start with magic bytes D6 C3 C4 00.",
+        ));
+    }
+    let header_indicator = reader.read_u8()?;
+    if header_indicator & (VCD_DECOMPRESS | VCD_CODETABLE) != 0 {
+        return Err(String::from(
+            "VCDIFF patch uses secondary compression or a custom code table, which are not supported. Create the patch with xdelta3 -S none.",
+        ));
+    }
+    if header_indicator & VCD_APPHEADER != 0 {
+        let len = read_number(&mut reader)? as usize;
+        reader.read_bytes(len)?;
+    }
+
+    let code_table = get_default_code_table();
+    let max_modified_len = get_max_modified_len(original, patch);
+    let mut modified = Vec::new();
+    while !reader.is_at_end() {
+        let window_offset = reader.offset;
+        let window_indicator = reader.read_u8()?;
+        let segment = match window_indicator & (VCD_SOURCE | VCD_TARGET) {
+            0 => Vec::new(),
+            indicator => {
+                let len = read_number(&mut reader)? as usize;
+                let position = read_number(&mut reader)? as usize;
+                let file = match indicator {
+                    VCD_SOURCE => original,
+                    VCD_TARGET => &modified[..],
+                    _ => {
+                        return Err(format!(
+                            "VCDIFF window at offset {} has both source and target segments.",
+                            window_offset
+                        ))
+                    }
+                };
+                file.get(position..position.saturating_add(len))
+                    .ok_or(format!(
+                        "VCDIFF window at offset {} uses a segment outside of the file.",
+                        window_offset
+                    ))?
+                    .to_vec()
+            }
+        };
+
+        let delta_len = read_number(&mut reader)? as usize;
+        let mut delta_reader = PatchReader::new(reader.read_bytes(delta_len)?);
+        let window_len = read_number(&mut delta_reader)? as usize;
+        if modified.len().saturating_add(window_len) > max_modified_len {
+            return Err(format!(
+                "VCDIFF window at offset {} gives a modified file larger than {} bytes, which is too large for an original file of {} bytes.",
+                window_offset,
+                max_modified_len,
+                original.len()
+            ));
+        }
+        if delta_reader.read_u8()? != 0 {
+            return Err(String::from(
+                "VCDIFF patch uses secondary compression, which is not supported. Create the patch with xdelta3 -S none.",
+            ));
+        }
+        let data_len = read_number(&mut delta_reader)? as usize;
+        let instructions_len = read_number(&mut delta_reader)? as usize;
+        let addresses_len = read_number(&mut delta_reader)? as usize;
+        let checksum = match window_indicator & VCD_ADLER32 {
+            0 => None,
+            _ => Some(u32::from_be_bytes(
+                delta_reader.read_bytes(4)?.try_into().unwrap(),
+            )),
+        };
+        let mut data = PatchReader::new(delta_reader.read_bytes(data_len)?);
+        let mut instructions = PatchReader::new(delta_reader.read_bytes(instructions_len)?);
+        let mut addresses = PatchReader::new(delta_reader.read_bytes(addresses_len)?);
+
+        let mut window = Vec::new();
+        let mut address_cache = AddressCache::new();
+        while !instructions.is_at_end() {
+            let entry = code_table[instructions.read_u8()? as usize];
+            for (kind, size, mode) in entry.instructions {
+                if kind == InstructionKind::Noop {
+                    continue;
+                }
+                let size = match size {
+                    0 => read_number(&mut instructions)? as usize,
+                    size => size,
+                };
+                if window.len().saturating_add(size) > window_len {
+                    return Err(format!(
+                        "VCDIFF window at offset {} writes past its end.",
+                        window_offset
+                    ));
+                }
+                match kind {
+                    InstructionKind::Add => window.extend(data.read_bytes(size)?),
+                    InstructionKind::Run => {
+                        let byte = data.read_u8()?;
+                        window.resize(window.len() + size, byte);
+                    }
+                    _ => {
+                        let here = segment.len() + window.len();
+                        let address = address_cache.decode(here, mode, &mut addresses)?;
+                        // Copies from the window may overlap with the copied bytes,
+                        // so copy byte by byte.
+                        for address in address..address + size {
+                            let byte = match address.checked_sub(segment.len()) {
+                                None => segment[address],
+                                Some(window_address) => window[window_address],
+                            };
+                            window.push(byte);
+                        }
+                    }
+                }
+            }
+        }
+
+        if window.len() != window_len {
+            return Err(format!(
+                "VCDIFF window at offset {} is {} bytes, but {} bytes were expected.",
+                window_offset,
+                window.len(),
+                window_len
+            ));
+        }
+        if checksum.is_some_and(|checksum| checksum != adler32(&window)) {
+            return Err(format!(
+                "VCDIFF window at offset {} has a wrong Adler32 checksum, so the patch is not made for the given original file.",
+                window_offset
+            ));
+        }
+        modified.extend(window);
+    }
+    Ok(modified)
+}
+
+/// Writes a number with 7 bits in each byte, most significant bits first,
+/// all bytes except the last one having their highest bit set.
+fn write_number(output: &mut Vec<u8>, value: u64) {
+    let mut bytes = vec![(value & 0x7F) as u8];
+    let mut value = value >> 7;
+    while value != 0 {
+        bytes.push(0x80 | (value & 0x7F) as u8);
+        value >>= 7;
+    }
+    output.extend(bytes.iter().rev());
+}
+
+fn read_number(reader: &mut PatchReader) -> Result<u64, String> {
+    let offset = reader.offset;
+    let mut value = 0u64;
+    loop {
+        let byte = reader.read_u8()?;
+        if value >> 57 != 0 {
+            return Err(format!(
+                "VCDIFF patch has a too large number at offset {}.",
+                offset
+            ));
+        }
+        value = (value << 7) | (byte & 0x7F) as u64;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+}
+
+/// Adler32 checksum of zlib, used by xdelta3 for windows.
+fn adler32(data: &[u8]) -> u32 {
+    const MODULO: u32 = 65521;
+    let (mut a, mut b) = (1u32, 0u32);
+    // Sums are reduced in chunks, which cannot overflow before the reduction.
+    for chunk in data.chunks(5552) {
+        for byte in chunk {
+            a += *byte as u32;
+            b += a;
+        }
+        a %= MODULO;
+        b %= MODULO;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn apply_vcdiff_with_default_code_table() {
+        let table = get_default_code_table();
+        assert_eq!(table.len(), 256);
+        assert!(matches!(
+            table[163].instructions,
+            [(InstructionKind::Add, 1, 0), (InstructionKind::Copy, 4, 0)]
+        ));
+        assert!(matches!(
+            table[255].instructions,
+            [(InstructionKind::Copy, 4, 8), (InstructionKind::Add, 1, 0)]
+        ));
+
+        // Window of 12 bytes from a source segment of 4 bytes: add 1 byte and copy 4 bytes from
+        // the segment, copy 4 bytes from the window (VCD_HERE), run of 3 bytes.
+        let delta = [&[12, 0, 2, 4, 2][..], b"!?", &[163, 20 + 16, 0, 3], &[0, 5]].concat();
+        let patch = [MAGIC, &[0, VCD_SOURCE, 4, 1, delta.len() as u8], &delta].concat();
+        assert_eq!(apply(&patch, b"_abcd").unwrap(), b"!abcd!abc???");
+    }
+    #[test]
+    fn fail_apply_vcdiff_with_huge_window() {
+        // Window of 4 GB with one run.
+        let mut delta = Vec::new();
+        write_number(&mut delta, u32::MAX as u64);
+        delta.extend([0, 1, 6, 0, 0x55, 0]);
+        write_number(&mut delta, u32::MAX as u64);
+        let patch = [MAGIC, &[0, 0, delta.len() as u8], &delta].concat();
+        assert!(apply(&patch, &[])
+            .unwrap_err()
+            .contains("too large for an original file"));
+    }
+    #[test]
+    fn read_and_write_numbers() {
+        let mut output = Vec::new();
+        write_number(&mut output, 123456789);
+        assert_eq!(output, [0xBA, 0xEF, 0x9A, 0x15]);
+        assert_eq!(
+            read_number(&mut PatchReader::new(&output)).unwrap(),
+            123456789
+        );
+        assert_eq!(adler32(b"Wikipedia"), 0x11E60398);
+    }
+}
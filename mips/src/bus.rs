@@ -0,0 +1,122 @@
+//! Memory seen by the interpreter (see [crate::Cpu]).
+
+/// Memory the CPU reads from and writes into. Accesses are little endian, and `None` stands for
+/// a bus error, like an access to an address nothing is mapped at.
+///
+/// Only byte accesses have to be implemented, halfwords and words are made of bytes by default.
+/// Alignment is checked by the CPU before accessing the bus.
+pub trait Bus {
+    fn read_u8(&mut self, address: u32) -> Option<u8>;
+    fn write_u8(&mut self, address: u32, value: u8) -> Option<()>;
+    fn read_u16(&mut self, address: u32) -> Option<u16> {
+        Some(u16::from_le_bytes([
+            self.read_u8(address)?,
+            self.read_u8(address.wrapping_add(1))?,
+        ]))
+    }
+    fn read_u32(&mut self, address: u32) -> Option<u32> {
+        let mut bytes = [0; 4];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = self.read_u8(address.wrapping_add(i as u32))?;
+        }
+        Some(u32::from_le_bytes(bytes))
+    }
+    fn write_u16(&mut self, address: u32, value: u16) -> Option<()> {
+        for (i, byte) in value.to_le_bytes().into_iter().enumerate() {
+            self.write_u8(address.wrapping_add(i as u32), byte)?;
+        }
+        Some(())
+    }
+    fn write_u32(&mut self, address: u32, value: u32) -> Option<()> {
+        for (i, byte) in value.to_le_bytes().into_iter().enumerate() {
+            self.write_u8(address.wrapping_add(i as u32), byte)?;
+        }
+        Some(())
+    }
+}
+
+/// 2 MB of main RAM and 1 KB of scratchpad of the Playstation, for running code headlessly.
+///
+/// Main RAM is mirrored in the first 8 MB of KUSEG, KSEG0 and KSEG1, and scratchpad is at
+/// 0x1F800000 in KUSEG and KSEG0. Nothing else (like I/O ports or BIOS) is mapped.
+pub struct Memory {
+    pub ram: Vec<u8>,
+    pub scratchpad: Vec<u8>,
+}
+impl Memory {
+    const RAM_SIZE: usize = 0x200000;
+    const RAM_MIRRORS_END: u32 = 0x800000;
+    const SCRATCHPAD_ADDRESS: u32 = 0x1F800000;
+    const SCRATCHPAD_SIZE: usize = 0x400;
+
+    pub fn new() -> Self {
+        Self {
+            ram: vec![0; Self::RAM_SIZE],
+            scratchpad: vec![0; Self::SCRATCHPAD_SIZE],
+        }
+    }
+    /// Writes bytes at an address, like code and data of an executable. Returns `None` if
+    /// the bytes do not fit in the memory.
+    pub fn load(&mut self, address: u32, bytes: &[u8]) -> Option<()> {
+        for (i, byte) in bytes.iter().enumerate() {
+            self.write_u8(address.checked_add(i as u32)?, *byte)?;
+        }
+        Some(())
+    }
+    /// Gets the byte at an address, if the address is mapped.
+    fn get_byte_mut(&mut self, address: u32) -> Option<&mut u8> {
+        let (physical_address, is_kseg1) = match address {
+            0x00000000..=0x7FFFFFFF => (address, false),
+            0x80000000..=0x9FFFFFFF => (address - 0x80000000, false),
+            0xA0000000..=0xBFFFFFFF => (address - 0xA0000000, true),
+            _ => return None,
+        };
+        if physical_address < Self::RAM_MIRRORS_END {
+            return self.ram.get_mut(physical_address as usize % Self::RAM_SIZE);
+        }
+        // Scratchpad is the data cache used as RAM, so it cannot be reached uncached.
+        match physical_address.checked_sub(Self::SCRATCHPAD_ADDRESS) {
+            Some(offset) if !is_kseg1 => self.scratchpad.get_mut(offset as usize),
+            _ => None,
+        }
+    }
+}
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Bus for Memory {
+    fn read_u8(&mut self, address: u32) -> Option<u8> {
+        self.get_byte_mut(address).map(|byte| *byte)
+    }
+    fn write_u8(&mut self, address: u32, value: u8) -> Option<()> {
+        *self.get_byte_mut(address)? = value;
+        Some(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn access_mirrored_memory() {
+        let mut memory = Memory::new();
+        assert_eq!(memory.write_u32(0x80010000, 0x12345678), Some(()));
+        for address in [0x00010000, 0xA0010000, 0x80210000, 0x00610000] {
+            assert_eq!(memory.read_u32(address), Some(0x12345678));
+        }
+        assert_eq!(memory.read_u16(0x80010002), Some(0x1234));
+        assert_eq!(memory.read_u8(0x80010000), Some(0x78));
+
+        assert_eq!(memory.write_u16(0x1F8003FE, 0xABCD), Some(()));
+        assert_eq!(memory.read_u16(0x9F8003FE), Some(0xABCD));
+        assert_eq!(memory.read_u8(0xBF800000), None);
+        assert_eq!(memory.read_u8(0x1F800400), None);
+        assert_eq!(memory.read_u8(0x1F801070), None);
+        assert_eq!(memory.write_u32(0x1F8003FE, 0), None);
+        assert_eq!(memory.load(0x801FFFFE, &[1, 2]), Some(()));
+        assert_eq!(memory.load(0xFFFFFFFF, &[1, 2]), None);
+    }
+}
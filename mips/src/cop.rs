@@ -0,0 +1,184 @@
+//! Coprocessor registers and GTE commands of Playstation.
+//!
+//! Playstation has two coprocessors in use:
+//! * COP0, the system control coprocessor, which handles exceptions
+//! * COP2, the geometry transformation engine (GTE), which does 3D math
+
+/// Names of COP0 registers. Registers without a name are shown as "$<number>".
+#[rustfmt::skip]
+pub const COP0_REGISTERS: &[&str; 32] = &[
+    "", "", "", "bpc", "", "bda", "jumpdest", "dcic", "badvaddr", "bdam", "", "bpcm", "sr",
+    "cause", "epc", "prid", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "",
+];
+/// Names of GTE data registers, which are accessed with mfc2, mtc2, lwc2 and swc2.
+#[rustfmt::skip]
+pub const GTE_DATA_REGISTERS: &[&str; 32] = &[
+    "vxy0", "vz0", "vxy1", "vz1", "vxy2", "vz2", "rgbc", "otz", "ir0", "ir1", "ir2", "ir3",
+    "sxy0", "sxy1", "sxy2", "sxyp", "sz0", "sz1", "sz2", "sz3", "rgb0", "rgb1", "rgb2", "res1",
+    "mac0", "mac1", "mac2", "mac3", "irgb", "orgb", "lzcs", "lzcr",
+];
+/// Names of GTE control registers, which are accessed with cfc2 and ctc2.
+#[rustfmt::skip]
+pub const GTE_CONTROL_REGISTERS: &[&str; 32] = &[
+    "r11r12", "r13r21", "r22r23", "r31r32", "r33", "trx", "try", "trz", "l11l12", "l13l21",
+    "l22l23", "l31l32", "l33", "rbk", "gbk", "bbk", "lr1lr2", "lr3lg1", "lg2lg3", "lb1lb2", "lb3",
+    "rfc", "gfc", "bfc", "ofx", "ofy", "h", "dqa", "dqb", "zsf3", "zsf4", "flag",
+];
+
+pub fn format_cop_register(names: &[&str; 32], index: u8) -> String {
+    match names[index as usize] {
+        "" => format!("${}", index),
+        name => String::from(name),
+    }
+}
+/// Parses a coprocessor register from a string, which is either a register name or "$<number>".
+pub fn parse_cop_register(names: &[&str; 32], content: &str) -> Result<u8, String> {
+    if let Some(register_number) = content.strip_prefix("$") {
+        return match register_number.parse::<u8>() {
+            Ok(register_number) if register_number <= 31 => Ok(register_number),
+            _ => Err(format!(
+                "Coprocessor register \"{}\" is out of range ($0-$31)",
+                content
+            )),
+        };
+    }
+    names
+        .iter()
+        .position(|name| !name.is_empty() && *name == content)
+        .map(|i| i as u8)
+        .ok_or_else(|| format!("Unknown coprocessor register \"{}\".", content))
+}
+
+/// A GTE command with the encoding Sony's tools use for it.
+struct GteCommand {
+    name: &'static str,
+    /// Bits 0-24 of the instruction. Besides the command number (bits 0-5),
+    /// Sony's tools set bits 20-24 that the hardware ignores, so they are
+    /// stored here to assemble commands back exactly.
+    encoding: u32,
+}
+#[rustfmt::skip]
+const GTE_COMMANDS: &[GteCommand] = &[
+    GteCommand { name: "rtps", encoding: 0x0180001 },
+    GteCommand { name: "nclip", encoding: 0x1400006 },
+    GteCommand { name: "op", encoding: 0x170000C },
+    GteCommand { name: "dpcs", encoding: 0x0780010 },
+    GteCommand { name: "intpl", encoding: 0x0980011 },
+    GteCommand { name: "mvmva", encoding: 0x0400012 },
+    GteCommand { name: "ncds", encoding: 0x0E80413 },
+    GteCommand { name: "cdp", encoding: 0x1280414 },
+    GteCommand { name: "ncdt", encoding: 0x0F80416 },
+    GteCommand { name: "nccs", encoding: 0x108041B },
+    GteCommand { name: "cc", encoding: 0x128041C },
+    GteCommand { name: "ncs", encoding: 0x0C8041E },
+    GteCommand { name: "nct", encoding: 0x0D80420 },
+    GteCommand { name: "sqr", encoding: 0x0A00428 },
+    GteCommand { name: "dcpl", encoding: 0x0680029 },
+    GteCommand { name: "dpct", encoding: 0x0F8002A },
+    GteCommand { name: "avsz3", encoding: 0x158002D },
+    GteCommand { name: "avsz4", encoding: 0x168002E },
+    GteCommand { name: "rtpt", encoding: 0x0280030 },
+    GteCommand { name: "gpf", encoding: 0x190003D },
+    GteCommand { name: "gpl", encoding: 0x1A0003E },
+    GteCommand { name: "ncct", encoding: 0x118043F },
+];
+
+/// A field of a GTE command.
+struct GteField {
+    name: &'static str,
+    shift: u32,
+    mask: u32,
+    /// Readable names of field values by value.
+    value_names: &'static [&'static str],
+}
+/// Fields in the order they are written in assembly.
+#[rustfmt::skip]
+const GTE_FIELDS: &[GteField] = &[
+    // Shift fraction of results by 12 bits
+    GteField { name: "sf", shift: 19, mask: 0b1, value_names: &[] },
+    // Multiplication matrix: rotation, light or light color matrix
+    GteField { name: "mx", shift: 17, mask: 0b11, value_names: &["rt", "llm", "lcm"] },
+    // Multiplication vector: V0, V1, V2 or IR
+    GteField { name: "v", shift: 15, mask: 0b11, value_names: &["v0", "v1", "v2", "ir"] },
+    // Translation vector: translation, background color, far color or none
+    GteField { name: "cv", shift: 13, mask: 0b11, value_names: &["tr", "bk", "fc", "none"] },
+    // Limit negative results to 0
+    GteField { name: "lm", shift: 10, mask: 0b1, value_names: &[] },
+];
+const GTE_COMMAND_NUMBER_MASK: u32 = 0b111111;
+const GTE_COMMAND_MASK: u32 = 0x1FFFFFF;
+
+/// Formats a GTE command (bits 0-24 of the instruction) as assembly.
+///
+/// Fields are written only when they differ from how Sony's tools encode the command,
+/// except for mvmva, whose fields are always written. Commands that cannot be written
+/// with a mnemonic (like ones with unused bits set) are written as "cop2 <command>".
+pub fn format_gte_command(command: u32) -> String {
+    let field_mask = GTE_FIELDS.iter().fold(GTE_COMMAND_NUMBER_MASK, |mask, f| {
+        mask | (f.mask << f.shift)
+    });
+    let gte_command = GTE_COMMANDS.iter().find(|c| {
+        c.encoding & GTE_COMMAND_NUMBER_MASK == command & GTE_COMMAND_NUMBER_MASK
+            && c.encoding & !field_mask == command & !field_mask
+    });
+    let Some(gte_command) = gte_command else {
+        return format!("cop2 0x{:07X}", command & GTE_COMMAND_MASK);
+    };
+
+    let fields = GTE_FIELDS
+        .iter()
+        .filter_map(|field| {
+            let value = (command >> field.shift) & field.mask;
+            let original_value = (gte_command.encoding >> field.shift) & field.mask;
+            if value == original_value && gte_command.name != "mvmva" {
+                return None;
+            }
+            Some(match field.value_names.get(value as usize) {
+                Some(value_name) => format!("{}={}", field.name, value_name),
+                None => format!("{}={}", field.name, value),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    if fields.is_empty() {
+        String::from(gte_command.name)
+    } else {
+        format!("{} {}", gte_command.name, fields.join(", "))
+    }
+}
+/// Parses a GTE command by its name and fields (like "sf=1").
+/// Returns `None` if the name is not a GTE command.
+pub fn parse_gte_command(name: &str, fields: &[&str]) -> Option<Result<u32, String>> {
+    let gte_command = GTE_COMMANDS.iter().find(|c| c.name == name)?;
+
+    let mut command = gte_command.encoding;
+    for field in fields.iter() {
+        let Some((field_name, value)) = field.split_once("=") else {
+            return Some(Err(format!(
+                "Invalid field \"{}\" for GTE command \"{}\". Fields are given as <name>=<value>.",
+                field, name
+            )));
+        };
+        let Some(gte_field) = GTE_FIELDS.iter().find(|f| f.name == field_name) else {
+            return Some(Err(format!(
+                "Unknown field \"{}\" for GTE command \"{}\".",
+                field_name, name
+            )));
+        };
+        let value = match gte_field.value_names.iter().position(|n| *n == value) {
+            Some(value) => value as u32,
+            None => match value.parse::<u32>() {
+                Ok(value) if value <= gte_field.mask => value,
+                _ => {
+                    return Some(Err(format!(
+                        "Invalid value \"{}\" for field \"{}\" of GTE command \"{}\".",
+                        value, field_name, name
+                    )))
+                }
+            },
+        };
+        command &= !(gte_field.mask << gte_field.shift);
+        command |= value << gte_field.shift;
+    }
+    Some(Ok(command))
+}
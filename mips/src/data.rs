@@ -0,0 +1,483 @@
+//! Data directives, which assemble values instead of instructions.
+//!
+//! * `.word value, ...` 4-byte values. A value can also be a label or an absolute address
+//!   ("@<address>"), which is resolved once addresses of all labels are known.
+//! * `.half value, ...` 2-byte values
+//! * `.byte value, ...` 1-byte values
+//! * `.float value, ...` 4-byte single precision floating point values
+//! * `.ascii "text", ...` Text without a null termination byte
+//! * `.asciz "text", ...` Text followed by a null termination byte
+//! * `.space size[, fill]` Bytes filled with a value (zero by default)
+//! * `.align power[, fill]` Bytes filled with a value (zero by default) up to the next
+//!   address divisible by 2 to the given power, like 4 bytes with `.align 2`
+//! * `.incbin "path"[, offset[, size]]` Bytes of a file, relative to the include directory
+//!
+//! Values are stored in little-endian byte order and are not aligned automatically.
+//!
+//! # Escape sequences
+//! * `\\` Backslash
+//! * `\"` Double quote
+//! * `\n` Line break (byte 0x0A)
+//! * `\r` Carriage return (byte 0x0D)
+//! * `\t` Tab (byte 0x09)
+//! * `\0` Null termination byte
+//! * `\xNN` Raw byte, where NN is a hexadecimal value
+//! * `\cNN` Control code, where NN is a hexadecimal value (encodes the same as `\xNN`)
+//! * `\c{name}` Control code with a name, given by the text encoding
+
+use crate::{parse_integer, Error, NodeKind, NodeParser, Relocation, Span};
+
+/// Data larger than the main RAM of the Playstation (2 MB) cannot be loaded.
+const MAX_DATA_LEN: i64 = 0x200000;
+
+/// Encodes text of a string as UTF-8 bytes. Escape sequences (see [the module](self))
+/// are replaced with the bytes they stand for.
+///
+/// ```
+/// assert_eq!(mips::encode_text("HI\\n\\xFF\\0").unwrap(), b"HI\n\xFF\0");
+/// assert!(mips::encode_text("\\q").is_err());
+/// ```
+pub fn encode_text(value: &str) -> Result<Vec<u8>, String> {
+    encode_text_with(
+        value,
+        |c| {
+            let mut buffer = [0; 4];
+            Some(c.encode_utf8(&mut buffer).as_bytes().to_vec())
+        },
+        |_| None,
+    )
+}
+/// Encodes text of a string with a given encoding of characters, which returns `None` for
+/// characters it cannot encode. Escape sequences (see [the module](self)) are replaced with
+/// the bytes they stand for, control codes with names by the given lookup.
+pub fn encode_text_with(
+    value: &str,
+    encode_char: impl Fn(char) -> Option<Vec<u8>>,
+    get_control_code_by_name: impl Fn(&str) -> Option<u8>,
+) -> Result<Vec<u8>, String> {
+    let mut result = Vec::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let bytes = encode_char(c).ok_or_else(|| {
+                format!(
+                    "Character '{}' in \"{}\" cannot be encoded.",
+                    c.escape_default(),
+                    value
+                )
+            })?;
+            result.extend_from_slice(&bytes);
+            continue;
+        }
+
+        match chars.next() {
+            Some('\\') => result.push(b'\\'),
+            Some('"') => result.push(b'"'),
+            Some('n') => result.push(b'\n'),
+            Some('r') => result.push(b'\r'),
+            Some('t') => result.push(b'\t'),
+            Some('0') => result.push(0),
+            Some('c') if chars.as_str().starts_with('{') => {
+                let name = chars
+                    .by_ref()
+                    .skip(1)
+                    .take_while(|c| *c != '}')
+                    .collect::<String>();
+                let b = get_control_code_by_name(&name).ok_or_else(|| {
+                    format!("Unknown control code \"\\c{{{}}}\" in \"{}\".", name, value)
+                })?;
+                result.push(b);
+            }
+            Some(escape @ ('x' | 'c')) => {
+                let digits = chars.by_ref().take(2).collect::<String>();
+                let b = Some(&digits)
+                    .filter(|digits| {
+                        digits.len() == 2 && digits.chars().all(|c| c.is_ascii_hexdigit())
+                    })
+                    .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+                    .ok_or_else(|| {
+                        format!(
+                            "Invalid escape sequence \"\\{}{}\" in \"{}\": expected two hexadecimal digits.",
+                            escape, digits, value
+                        )
+                    })?;
+                result.push(b);
+            }
+            Some(other) => {
+                return Err(format!(
+                    "Unknown escape sequence \"\\{}\" in \"{}\".",
+                    other, value
+                ))
+            }
+            None => return Err(format!("Escape sequence not finished in \"{}\".", value)),
+        }
+    }
+    Ok(result)
+}
+/// Writes a character of decoded text into a string, as an escape sequence if it has one
+/// (see [the module](self)), so that [encode_text_with] gives the same character back.
+pub fn push_escaped_char(text: &mut String, value: char) {
+    match value {
+        '\\' => text.push_str("\\\\"),
+        '"' => text.push_str("\\\""),
+        '\n' => text.push_str("\\n"),
+        '\r' => text.push_str("\\r"),
+        '\t' => text.push_str("\\t"),
+        _ => text.push(value),
+    }
+}
+/// Writes a byte which is not a character of decoded text into a string as an escape sequence:
+/// a null termination byte, a control code (by its name if it has one) or a raw byte.
+pub fn push_escaped_byte(
+    text: &mut String,
+    value: u8,
+    is_control_code: bool,
+    control_code_name: Option<&str>,
+) {
+    match (value, control_code_name) {
+        (0, _) => text.push_str("\\0"),
+        (_, Some(name)) => text.push_str(&format!("\\c{{{}}}", name)),
+        _ if is_control_code => text.push_str(&format!("\\c{:02X}", value)),
+        _ => text.push_str(&format!("\\x{:02X}", value)),
+    }
+}
+
+/// Removes a comment (starting with '#' character) from a line.
+/// '#' characters in double quoted text do not start a comment.
+pub(crate) fn strip_comment(line: &str) -> &str {
+    let mut is_in_text = false;
+    let mut is_escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if is_escaped => is_escaped = false,
+            '\\' if is_in_text => is_escaped = true,
+            '"' => is_in_text = !is_in_text,
+            '#' if !is_in_text => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+/// Splits arguments of a directive by commas, except commas in double quoted text.
+fn split_arguments(arguments: &str) -> Vec<&str> {
+    if arguments.trim().is_empty() {
+        return Vec::new();
+    }
+    let mut result = Vec::new();
+    let mut start = 0;
+    let mut is_in_text = false;
+    let mut is_escaped = false;
+    for (i, c) in arguments.char_indices() {
+        match c {
+            _ if is_escaped => is_escaped = false,
+            '\\' if is_in_text => is_escaped = true,
+            '"' => is_in_text = !is_in_text,
+            ',' if !is_in_text => {
+                result.push(arguments[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    result.push(arguments[start..].trim());
+    result
+}
+
+/// Parses an integer argument, which must be within the given range.
+fn parse_value(value: &str, min: i64, max: i64) -> Result<i64, String> {
+    match parse_integer(value) {
+        Some(parsed) if (min..=max).contains(&parsed) => Ok(parsed),
+        _ => Err(format!(
+            "Could not parse value \"{}\": expected an integer between {} and {}",
+            value, min, max
+        )),
+    }
+}
+
+/// Parses text in double quotes, returning the text between the quotes.
+fn parse_quoted(value: &str) -> Result<&str, String> {
+    value
+        .strip_prefix("\"")
+        .and_then(|value| value.strip_suffix("\""))
+        .ok_or_else(|| {
+            format!(
+                "Could not parse text {}: expected text in double quotes",
+                value
+            )
+        })
+}
+
+impl NodeParser<'_> {
+    /// Parses a data directive (a line starting with '.') into a data node.
+    pub(crate) fn parse_directive(&mut self, line: &str, line_span: Span, current_line: u64) {
+        let (name, arguments) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let arguments = split_arguments(arguments);
+
+        // Labels in words, by offset in the data.
+        let mut labels = Vec::new();
+        let result = match name {
+            ".word" => arguments
+                .iter()
+                .enumerate()
+                .map(|(i, value)| {
+                    if parse_integer(value).is_none() && !value.is_empty() {
+                        labels.push((i * 4, String::from(*value)));
+                        return Ok(vec![0; 4]);
+                    }
+                    let value = parse_value(value, i32::MIN as i64, u32::MAX as i64)?;
+                    Ok((value as u32).to_le_bytes().to_vec())
+                })
+                .collect::<Result<Vec<_>, String>>()
+                .map(|values| values.concat()),
+            ".half" => arguments
+                .iter()
+                .map(|value| {
+                    let value = parse_value(value, i16::MIN as i64, u16::MAX as i64)?;
+                    Ok((value as u16).to_le_bytes().to_vec())
+                })
+                .collect::<Result<Vec<_>, String>>()
+                .map(|values| values.concat()),
+            ".byte" => arguments
+                .iter()
+                .map(|value| {
+                    parse_value(value, i8::MIN as i64, u8::MAX as i64).map(|value| value as u8)
+                })
+                .collect::<Result<Vec<_>, String>>(),
+            ".float" => arguments
+                .iter()
+                .map(|value| {
+                    let value = value.parse::<f32>().map_err(|_| {
+                        format!(
+                            "Could not parse value \"{}\": expected a floating point number",
+                            value
+                        )
+                    })?;
+                    Ok(value.to_le_bytes().to_vec())
+                })
+                .collect::<Result<Vec<_>, String>>()
+                .map(|values| values.concat()),
+            ".ascii" | ".asciz" => arguments
+                .iter()
+                .map(|value| {
+                    let mut bytes = (self.options.encode_text)(parse_quoted(value)?)?;
+                    if name == ".asciz" {
+                        bytes.push(0);
+                    }
+                    Ok(bytes)
+                })
+                .collect::<Result<Vec<_>, String>>()
+                .map(|values| values.concat()),
+            ".space" => self.parse_space(&arguments),
+            ".align" => self.parse_align(&arguments),
+            ".incbin" => self.parse_incbin(&arguments),
+            _ => Err(format!("Unknown directive \"{}\"", name)),
+        };
+        let result = result.and_then(|bytes| match bytes.is_empty() && arguments.is_empty() {
+            true => Err(format!("No values given for directive \"{}\"", name)),
+            false => Ok(bytes),
+        });
+
+        match result {
+            Ok(bytes) => {
+                for (offset, label) in labels {
+                    self.relocations.push((
+                        self.nodes.len(),
+                        Relocation::Word { offset },
+                        label,
+                        line_span.clone(),
+                    ));
+                }
+                self.push_data_node(bytes, current_line);
+            }
+            Err(message) => self.errors.push(Error::Syntax {
+                message,
+                span: line_span,
+            }),
+        }
+    }
+    /// Parses arguments of ".space size[, fill]".
+    fn parse_space(&self, arguments: &[&str]) -> Result<Vec<u8>, String> {
+        let (size, fill) = match arguments {
+            [size] => (size, 0),
+            [size, fill] => (size, parse_value(fill, i8::MIN as i64, u8::MAX as i64)?),
+            _ => return Err(String::from("Expected \".space size[, fill]\"")),
+        };
+        let size = parse_value(size, 0, MAX_DATA_LEN)?;
+        Ok(vec![fill as u8; size as usize])
+    }
+    /// Parses arguments of ".align power[, fill]".
+    fn parse_align(&mut self, arguments: &[&str]) -> Result<Vec<u8>, String> {
+        let (power, fill) = match arguments {
+            [power] => (power, 0),
+            [power, fill] => (power, parse_value(fill, i8::MIN as i64, u8::MAX as i64)?),
+            _ => return Err(String::from("Expected \".align power[, fill]\"")),
+        };
+        let alignment = 1 << parse_value(power, 0, 16)?;
+        let size = (alignment - self.current_address % alignment) % alignment;
+        self.section_alignment = self.section_alignment.max(alignment);
+        Ok(vec![fill as u8; size as usize])
+    }
+    /// Parses arguments of ".incbin "path"[, offset[, size]]" and reads the file.
+    fn parse_incbin(&self, arguments: &[&str]) -> Result<Vec<u8>, String> {
+        let Some(path) = arguments.first() else {
+            return Err(String::from(
+                "Expected \".incbin \"path\"[, offset[, size]]\"",
+            ));
+        };
+        let path = self.options.include_directory.join(parse_quoted(path)?);
+        let bytes = std::fs::read(&path).map_err(|err| {
+            format!(
+                "Could not read included file \"{}\": {}",
+                path.display(),
+                err
+            )
+        })?;
+
+        let file_len = bytes.len() as i64;
+        let (offset, size) = match arguments {
+            [_] => (0, file_len),
+            [_, offset] => {
+                let offset = parse_value(offset, 0, file_len)?;
+                (offset, file_len - offset)
+            }
+            [_, offset, size] => {
+                let offset = parse_value(offset, 0, file_len)?;
+                (offset, parse_value(size, 0, file_len - offset)?)
+            }
+            _ => {
+                return Err(String::from(
+                    "Expected \".incbin \"path\"[, offset[, size]]\"",
+                ))
+            }
+        };
+        if size > MAX_DATA_LEN {
+            return Err(format!(
+                "Included file \"{}\" is larger than 0x{:X} bytes",
+                path.display(),
+                MAX_DATA_LEN
+            ));
+        }
+        Ok(bytes[offset as usize..(offset + size) as usize].to_vec())
+    }
+    fn push_data_node(&mut self, bytes: Vec<u8>, line: u64) {
+        let len = bytes.len() as u64;
+        self.nodes.push(crate::Node {
+            address: self.current_address,
+            kind: NodeKind::Data(bytes),
+            line,
+        });
+        self.current_address += len;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_nodes, parse_nodes_with_options, ParseOptions};
+
+    fn get_data(content: &str) -> Vec<(u64, Vec<u8>)> {
+        parse_nodes(content)
+            .unwrap()
+            .into_iter()
+            .filter_map(|node| match node.kind {
+                NodeKind::Data(bytes) => Some((node.address, bytes)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parse_data_directives() {
+        let content = "@at 0x80010000\n.byte 1, 0xFF, -1\n.half 0x1234\n.word 0x80010000, -2\n.float 1.5\n.space 2, 0xAA\n.align 2";
+        assert_eq!(
+            get_data(content),
+            [
+                (0x80010000, vec![1, 0xFF, 0xFF]),
+                (0x80010003, vec![0x34, 0x12]),
+                (0x80010005, vec![0, 0, 1, 0x80, 0xFE, 0xFF, 0xFF, 0xFF]),
+                (0x8001000D, vec![0, 0, 0xC0, 0x3F]),
+                (0x80010011, vec![0xAA, 0xAA]),
+                (0x80010013, vec![0]),
+            ]
+        );
+    }
+    #[test]
+    fn parse_text_directives_with_real_size() {
+        let content = "@at 0x80010000\n.ascii \"A#B\", \"\\x01\" # Comment\nconst NAME = \"SPYRO\\0\"\n.asciz \"\\\"Hi\\\", \\\\\"\nlabel:";
+        let nodes = parse_nodes(content).unwrap();
+        assert_eq!(nodes[1].kind, NodeKind::Data(b"A#B\x01".to_vec()));
+        assert_eq!(nodes[2].address, 0x80010004);
+        assert_eq!(nodes[3].address, 0x8001000A);
+        assert_eq!(nodes[3].kind, NodeKind::Data(b"\"Hi\", \\\0".to_vec()));
+        assert_eq!(nodes[4].address, 0x80010012);
+    }
+    #[test]
+    fn encode_text_with_escape_sequences() {
+        assert_eq!(encode_text("\\r\\t\\c0D\\x7F").unwrap(), b"\r\t\x0D\x7F");
+        assert_eq!(encode_text("ä").unwrap(), "ä".as_bytes());
+        // Both hexadecimal digits are needed, even at the end of the text.
+        for value in ["\\xF", "\\c1", "\\x+F", "\\xG0", "\\c{red}", "\\"] {
+            assert!(encode_text(value).is_err(), "Encoding \"{}\"", value);
+        }
+
+        let mut text = String::new();
+        for c in "\"\\\r\n\tA".chars() {
+            push_escaped_char(&mut text, c);
+        }
+        push_escaped_byte(&mut text, 0, true, None);
+        push_escaped_byte(&mut text, 0x03, true, Some("green"));
+        push_escaped_byte(&mut text, 0x1F, true, None);
+        push_escaped_byte(&mut text, 0xFF, false, None);
+        assert_eq!(text, "\\\"\\\\\\r\\n\\tA\\0\\c{green}\\c1F\\xFF");
+        let get_control_code_by_name = |name: &str| (name == "green").then_some(0x03);
+        assert_eq!(
+            encode_text_with(&text, |c| Some(vec![c as u8]), get_control_code_by_name).unwrap(),
+            b"\"\\\r\n\tA\0\x03\x1F\xFF"
+        );
+    }
+    #[test]
+    fn resolve_labels_in_words() {
+        let content = "@at 0x80010000\ntable:\n.word 0, table, @0x80020000\nend:\n.word end";
+        assert_eq!(
+            get_data(content),
+            [
+                (0x80010000, vec![0, 0, 0, 0, 0, 0, 1, 0x80, 0, 0, 2, 0x80]),
+                (0x8001000C, vec![0x0C, 0, 1, 0x80]),
+            ]
+        );
+    }
+    #[test]
+    fn include_binary_file() {
+        let include_directory = std::env::temp_dir();
+        std::fs::write(
+            include_directory.join("mips_incbin_test.bin"),
+            [1, 2, 3, 4, 5],
+        )
+        .unwrap();
+        let options = ParseOptions {
+            include_directory,
+            ..ParseOptions::default()
+        };
+        let content = ".incbin \"mips_incbin_test.bin\"\n.incbin \"mips_incbin_test.bin\", 1, 3\n.incbin \"mips_incbin_test.bin\", 2, 4";
+        let errors = parse_nodes_with_options(content, &options).unwrap_err();
+        assert_eq!(errors.len(), 1);
+
+        let nodes =
+            parse_nodes_with_options(&content[..content.rfind("\n").unwrap()], &options).unwrap();
+        assert_eq!(nodes[0].kind, NodeKind::Data(vec![1, 2, 3, 4, 5]));
+        assert_eq!(nodes[1].address, 5);
+        assert_eq!(nodes[1].kind, NodeKind::Data(vec![2, 3, 4]));
+    }
+    #[test]
+    fn fail_parse_data_directives() {
+        let content = ".byte 256\n.word nowhere\n.ascii NAME\n.incbin \"does_not_exist.bin\"\n.align 17\n.data\n.half";
+        let errors = parse_nodes(content).unwrap_err();
+        assert_eq!(errors.len(), 7);
+        assert!(matches!(errors[1], Error::UndefinedLabel { .. }));
+        assert_eq!(
+            errors[0].to_string(),
+            "Could not parse value \"256\": expected an integer between -128 and 255 (line 1, column 1)"
+        );
+    }
+}
@@ -0,0 +1,217 @@
+use std::fmt;
+
+/// Location of an error in assembly code.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Span {
+    /// Line number starting from 1.
+    pub line: u64,
+    /// Column number starting from 1, counted in characters.
+    pub column: usize,
+    /// Length in characters.
+    pub len: usize,
+}
+
+/// An error in parsing an instruction from assembly code or from machine code.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Error {
+    UnknownMnemonic {
+        mnemonic: String,
+        span: Span,
+    },
+    WrongOperandCount {
+        mnemonic: String,
+        /// Allowed operand counts. Some instructions can be written in more than one way.
+        expected: Vec<usize>,
+        found: usize,
+        span: Span,
+    },
+    BadRegister {
+        register: String,
+        span: Span,
+    },
+    ImmediateOutOfRange {
+        immediate: String,
+        min: i64,
+        max: i64,
+        span: Span,
+    },
+    /// Label used as a branch or jump target is not defined anywhere.
+    UndefinedLabel {
+        label: String,
+        span: Span,
+    },
+    /// Label is defined more than once.
+    DuplicateLabel {
+        label: String,
+        span: Span,
+    },
+    /// Branch or jump at an address cannot reach its target address.
+    TargetOutOfRange {
+        address: u64,
+        target: u64,
+        span: Span,
+    },
+    /// Error in code expanded from a macro. Span tells where the macro is used.
+    InMacro {
+        name: String,
+        error: Box<Error>,
+        span: Span,
+    },
+    /// Machine code does not match any known instruction.
+    UnknownOpcode {
+        machine_code: u32,
+    },
+    /// Machine code has bits set in fields its instruction does not use (like the shift amount
+    /// of `add`), so assembly code of the instruction does not give back the same machine code.
+    UnusedBitsSet {
+        machine_code: u32,
+    },
+    /// Any other malformed code, like an invalid memory operand or constant assignment.
+    Syntax {
+        message: String,
+        span: Span,
+    },
+}
+impl Error {
+    pub fn span(&self) -> Option<&Span> {
+        match self {
+            Error::UnknownMnemonic { span, .. }
+            | Error::WrongOperandCount { span, .. }
+            | Error::BadRegister { span, .. }
+            | Error::ImmediateOutOfRange { span, .. }
+            | Error::UndefinedLabel { span, .. }
+            | Error::DuplicateLabel { span, .. }
+            | Error::TargetOutOfRange { span, .. }
+            | Error::InMacro { span, .. }
+            | Error::Syntax { span, .. } => Some(span),
+            Error::UnknownOpcode { .. } | Error::UnusedBitsSet { .. } => None,
+        }
+    }
+    /// Formats the error with a snippet of the given assembly code
+    /// pointing out where the error is.
+    ///
+    /// ```text
+    /// Unknown register "t9x" (line 2, column 6)
+    ///     2 | addu t9x, t1, t2
+    ///       |      ^^^
+    /// ```
+    pub fn to_string_with_snippet(&self, content: &str) -> String {
+        let Some(snippet) = self.span().and_then(|span| get_snippet(span, content)) else {
+            return self.to_string();
+        };
+        match self {
+            // Show both where the macro is used and where the error is in the macro.
+            Error::InMacro { error, .. } => {
+                match error.span().and_then(|span| get_snippet(span, content)) {
+                    Some(macro_snippet) => format!("{}\n{}\n{}", self, snippet, macro_snippet),
+                    None => format!("{}\n{}", self, snippet),
+                }
+            }
+            _ => format!("{}\n{}", self, snippet),
+        }
+    }
+}
+/// Gets the line of a span from given code with the span underlined.
+fn get_snippet(span: &Span, content: &str) -> Option<String> {
+    let line = content
+        .lines()
+        .nth((span.line as usize).saturating_sub(1))?;
+
+    let line_number = span.line.to_string();
+    let padding = " ".repeat(line_number.len());
+    Some(format!(
+        "    {} | {}\n    {} | {}{}",
+        line_number,
+        line,
+        padding,
+        " ".repeat(span.column.saturating_sub(1)),
+        "^".repeat(span.len.max(1))
+    ))
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::UnknownMnemonic { mnemonic, .. } => {
+                write!(f, "Unknown instruction \"{}\"", mnemonic)?
+            }
+            Error::WrongOperandCount {
+                mnemonic,
+                expected,
+                found,
+                ..
+            } => write!(
+                f,
+                "Instruction \"{}\" takes {} operands, but {} were given",
+                mnemonic,
+                expected
+                    .iter()
+                    .map(|count| count.to_string())
+                    .collect::<Vec<_>>()
+                    .join(" or "),
+                found
+            )?,
+            Error::BadRegister { register, .. } => {
+                write!(f, "Unknown register \"{}\"", register)?;
+                match register
+                    .strip_prefix("$")
+                    .unwrap_or(register)
+                    .parse::<u64>()
+                {
+                    Ok(number) if number > 31 => {
+                        write!(f, ". Register number is out of range (0-31)")?
+                    }
+                    Ok(number) if !register.starts_with("$") => {
+                        write!(f, ". Maybe you meant \"${}\"?", number)?
+                    }
+                    _ => {}
+                }
+            }
+            Error::ImmediateOutOfRange {
+                immediate,
+                min,
+                max,
+                ..
+            } => write!(
+                f,
+                "Immediate \"{}\" is out of range ({} to {})",
+                immediate, min, max
+            )?,
+            Error::UndefinedLabel { label, .. } => write!(f, "Undefined label \"{}\"", label)?,
+            Error::DuplicateLabel { label, .. } => {
+                write!(f, "Label \"{}\" is already defined", label)?
+            }
+            Error::TargetOutOfRange {
+                address, target, ..
+            } => write!(
+                f,
+                "Target address 0x{:X} cannot be reached from address 0x{:X}",
+                target, address
+            )?,
+            Error::InMacro { name, error, span } => {
+                // Location of the error in the macro is already included in the error.
+                return write!(
+                    f,
+                    "Macro \"{}\" used on line {} expands to invalid code: {}",
+                    name, span.line, error
+                );
+            }
+            Error::UnknownOpcode { machine_code } => write!(
+                f,
+                "Unknown instruction in machine code 0x{:08X} (opcode {})",
+                machine_code,
+                machine_code >> 26
+            )?,
+            Error::UnusedBitsSet { machine_code } => write!(
+                f,
+                "Machine code 0x{:08X} has bits set in fields unused by its instruction",
+                machine_code
+            )?,
+            Error::Syntax { message, .. } => write!(f, "{}", message)?,
+        }
+        if let Some(span) = self.span() {
+            write!(f, " (line {}, column {})", span.line, span.column)?;
+        }
+        Ok(())
+    }
+}
+impl std::error::Error for Error {}
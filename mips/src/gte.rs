@@ -0,0 +1,881 @@
+//! Geometry transformation engine (GTE), the coprocessor 2 of Playstation, which does
+//! the fixed-point 3D math of games: perspective transformation, lighting, depth cueing, etc.
+//!
+//! Results are bit-exact with hardware, including the flag register, saturation of
+//! intermediate results, and the unsigned Newton-Raphson (UNR) division of perspective
+//! transformation, so that geometry computed by a port matches the original exactly.
+//!
+//! [Gte] can be used on its own through the [Cop2] trait, or attached to [crate::Cpu]
+//! to run COP2 instructions.
+
+use crate::Cop2;
+
+// Data registers
+const VXY0: usize = 0;
+const RGBC: usize = 6;
+const OTZ: usize = 7;
+const IR0: usize = 8;
+const SXY0: usize = 12;
+const SXY2: usize = 14;
+const SXYP: usize = 15;
+const SZ0: usize = 16;
+const SZ3: usize = 19;
+const RGB0: usize = 20;
+const RGB2: usize = 22;
+const MAC0: usize = 24;
+const IRGB: usize = 28;
+const ORGB: usize = 29;
+const LZCS: usize = 30;
+const LZCR: usize = 31;
+// Control registers
+const RT: usize = 0;
+const TR: usize = 5;
+const LLM: usize = 8;
+const BK: usize = 13;
+const LCM: usize = 16;
+const FC: usize = 21;
+const OFX: usize = 24;
+const OFY: usize = 25;
+const H: usize = 26;
+const DQA: usize = 27;
+const DQB: usize = 28;
+const ZSF3: usize = 29;
+const ZSF4: usize = 30;
+const FLAG: usize = 31;
+
+// Flags, besides the ones of MAC1-3, IR1-3, colors and SX/SY, which are told by index.
+const FLAG_ERROR: u32 = 1 << 31;
+const FLAG_SZ3_OTZ_SATURATED: u32 = 1 << 18;
+const FLAG_DIVIDE_OVERFLOW: u32 = 1 << 17;
+const FLAG_MAC0_POSITIVE_OVERFLOW: u32 = 1 << 16;
+const FLAG_MAC0_NEGATIVE_OVERFLOW: u32 = 1 << 15;
+const FLAG_IR0_SATURATED: u32 = 1 << 12;
+/// Flags which set the error flag (bits 13-18 and 23-30).
+const FLAG_ERROR_MASK: u32 = 0x7F87E000;
+/// Flags which can be written with ctc2 (bits 0-11 are always 0, and bit 31 is computed).
+const FLAG_WRITABLE_MASK: u32 = 0x7FFFF000;
+
+/// Table of reciprocals the UNR division starts from, generated like in hardware.
+const UNR_TABLE: [u8; 0x101] = make_unr_table();
+
+const fn make_unr_table() -> [u8; 0x101] {
+    let mut table = [0; 0x101];
+    let mut i = 0;
+    while i < table.len() {
+        let value = (0x40000 / (i as i32 + 0x100) + 1) / 2 - 0x101;
+        table[i] = if value > 0 { value as u8 } else { 0 };
+        i += 1;
+    }
+    table
+}
+
+/// Fields of a command, see [crate::Instruction::Gte].
+#[derive(Clone, Copy)]
+struct Fields {
+    /// Count of fraction bits shifted out of results (sf is 0 or 1, so 0 or 12).
+    shift: u32,
+    /// Whether IR1-3 are saturated to 0 rather than -0x8000.
+    lm: bool,
+    /// Matrix of MVMVA: rotation, light, light color or garbage.
+    mx: u32,
+    /// Vector of MVMVA: V0, V1, V2 or IR.
+    v: u32,
+    /// Translation vector of MVMVA: translation, background color, far color or none.
+    cv: u32,
+}
+impl Fields {
+    fn decode(command: u32) -> Self {
+        Self {
+            shift: ((command >> 19) & 1) * 12,
+            lm: (command >> 10) & 1 != 0,
+            mx: (command >> 17) & 0b11,
+            v: (command >> 15) & 0b11,
+            cv: (command >> 13) & 0b11,
+        }
+    }
+}
+
+/// Registers of the GTE, which are all its state. Registers are stored as they are read back,
+/// so 16-bit registers are sign or zero extended as they are written.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Gte {
+    data: [u32; 32],
+    control: [u32; 32],
+}
+impl Gte {
+    pub fn new() -> Self {
+        Self {
+            data: [0; 32],
+            control: [0; 32],
+        }
+    }
+
+    fn get_ir(&self, index: usize) -> i32 {
+        self.data[IR0 + index] as i32
+    }
+    fn get_ir_vector(&self) -> [i32; 3] {
+        [self.get_ir(1), self.get_ir(2), self.get_ir(3)]
+    }
+    fn get_mac(&self, index: usize) -> i32 {
+        self.data[MAC0 + index] as i32
+    }
+    fn set_mac(&mut self, index: usize, value: i32) {
+        self.data[MAC0 + index] = value as u32;
+    }
+    /// Gets V0, V1 or V2.
+    fn get_vector(&self, index: usize) -> [i32; 3] {
+        let xy = self.data[VXY0 + index * 2];
+        let z = self.data[VXY0 + index * 2 + 1];
+        [xy as i16 as i32, (xy >> 16) as i16 as i32, z as i16 as i32]
+    }
+    /// Gets R, G and B of a color register in 4 fraction bits, like IR1-3 of colors.
+    fn get_color(&self, register: usize) -> [i32; 3] {
+        let bytes = self.data[register].to_le_bytes();
+        [0, 1, 2].map(|i| (bytes[i] as i32) << 4)
+    }
+    /// Gets a matrix of 3x3 16-bit values packed by 2 in 5 control registers.
+    fn get_matrix(&self, register: usize) -> [[i32; 3]; 3] {
+        let value = |i: usize| {
+            let word = self.control[register + i / 2];
+            (word >> ((i % 2) * 16)) as i16 as i32
+        };
+        [0, 1, 2].map(|row| [0, 1, 2].map(|column| value(row * 3 + column)))
+    }
+    fn get_control_vector(&self, register: usize) -> [i32; 3] {
+        [0, 1, 2].map(|i| self.control[register + i] as i32)
+    }
+
+    /// Checks a result of MAC1-3 (by index 0-2) for overflow of 44 bits, and truncates it to
+    /// 44 bits like hardware.
+    fn check_mac(&mut self, index: usize, value: i64) -> i64 {
+        if value >= 1 << 43 {
+            self.control[FLAG] |= 1 << (30 - index);
+        } else if value < -(1 << 43) {
+            self.control[FLAG] |= 1 << (27 - index);
+        }
+        (value << 20) >> 20
+    }
+    /// Checks a result of MAC0 for overflow of 32 bits.
+    fn check_mac0(&mut self, value: i64) -> i64 {
+        if value > i32::MAX as i64 {
+            self.control[FLAG] |= FLAG_MAC0_POSITIVE_OVERFLOW;
+        } else if value < i32::MIN as i64 {
+            self.control[FLAG] |= FLAG_MAC0_NEGATIVE_OVERFLOW;
+        }
+        value
+    }
+    /// Saturates a value of IR1-3 (by index 0-2) to -0x8000..=0x7FFF, or 0..=0x7FFF with lm.
+    fn saturate_ir(&mut self, index: usize, value: i32, lm: bool) -> i32 {
+        let min = if lm { 0 } else { -0x8000 };
+        if value < min || value > 0x7FFF {
+            self.control[FLAG] |= 1 << (24 - index);
+        }
+        value.clamp(min, 0x7FFF)
+    }
+    fn saturate_ir0(&mut self, value: i32) -> i32 {
+        if !(0..=0x1000).contains(&value) {
+            self.control[FLAG] |= FLAG_IR0_SATURATED;
+        }
+        value.clamp(0, 0x1000)
+    }
+    /// Saturates a component of a color (by index 0-2) to a byte.
+    fn saturate_color(&mut self, index: usize, value: i32) -> u8 {
+        if !(0..=0xFF).contains(&value) {
+            self.control[FLAG] |= 1 << (21 - index);
+        }
+        value.clamp(0, 0xFF) as u8
+    }
+    /// Saturates a value of SZ3 or OTZ.
+    fn saturate_z(&mut self, value: i64) -> u16 {
+        if !(0..=0xFFFF).contains(&value) {
+            self.control[FLAG] |= FLAG_SZ3_OTZ_SATURATED;
+        }
+        value.clamp(0, 0xFFFF) as u16
+    }
+    /// Saturates a screen coordinate (X by index 0, Y by index 1) to -0x400..=0x3FF.
+    fn saturate_screen_coordinate(&mut self, index: usize, value: i32) -> i16 {
+        if !(-0x400..=0x3FF).contains(&value) {
+            self.control[FLAG] |= 1 << (14 - index);
+        }
+        value.clamp(-0x400, 0x3FF) as i16
+    }
+
+    fn set_ir_from_mac(&mut self, lm: bool) {
+        for i in 0..3 {
+            let value = self.saturate_ir(i, self.get_mac(i + 1), lm);
+            self.data[IR0 + i + 1] = value as u32;
+        }
+    }
+    /// Pushes MAC1-3 as a color into the color FIFO, with the code of RGBC.
+    fn push_color_from_mac(&mut self) {
+        let [r, g, b] = [0, 1, 2].map(|i| self.saturate_color(i, self.get_mac(i + 1) >> 4));
+        let code = self.data[RGBC].to_le_bytes()[3];
+        self.data.copy_within(RGB0 + 1..=RGB2, RGB0);
+        self.data[RGB2] = u32::from_le_bytes([r, g, b, code]);
+    }
+    fn push_z(&mut self, value: u16) {
+        self.data.copy_within(SZ0 + 1..=SZ3, SZ0);
+        self.data[SZ3] = value as u32;
+    }
+    fn push_screen_xy(&mut self, value: u32) {
+        self.data.copy_within(SXY0 + 1..=SXY2, SXY0);
+        self.data[SXY2] = value;
+    }
+
+    /// Adds products to a value of MAC1-3 (by index 0-2), checking overflow after each one.
+    fn accumulate(&mut self, index: usize, value: i64, products: &[i64]) -> i64 {
+        products.iter().fold(value, |value, product| {
+            self.check_mac(index, value + product)
+        })
+    }
+    /// Sets MAC1-3 and IR1-3 to translation + matrix * vector.
+    fn multiply_matrix_by_vector(
+        &mut self,
+        matrix: [[i32; 3]; 3],
+        vector: [i32; 3],
+        translation: [i32; 3],
+        fields: Fields,
+    ) {
+        for i in 0..3 {
+            let products = [0, 1, 2].map(|j| matrix[i][j] as i64 * vector[j] as i64);
+            let value = self.accumulate(i, (translation[i] as i64) << 12, &products);
+            self.set_mac(i + 1, (value >> fields.shift) as i32);
+        }
+        self.set_ir_from_mac(fields.lm);
+    }
+    /// Same as [Self::multiply_matrix_by_vector] with the far color as translation, which is
+    /// broken in hardware: the first column is computed with the far color (only setting
+    /// flags), but the result is the product of the two other columns only.
+    fn multiply_matrix_by_vector_with_far_color(
+        &mut self,
+        matrix: [[i32; 3]; 3],
+        vector: [i32; 3],
+        fields: Fields,
+    ) {
+        let far_color = self.get_control_vector(FC);
+        for i in 0..3 {
+            let products = [0, 1, 2].map(|j| matrix[i][j] as i64 * vector[j] as i64);
+            let value = self.accumulate(i, (far_color[i] as i64) << 12, &products[..1]);
+            self.saturate_ir(i, (value >> fields.shift) as i32, false);
+            let value = self.accumulate(i, 0, &products[1..]);
+            self.set_mac(i + 1, (value >> fields.shift) as i32);
+        }
+        self.set_ir_from_mac(fields.lm);
+    }
+    /// Interpolates between values (in 12 fraction bits) and the far color by IR0, then pushes
+    /// the result as a color.
+    fn interpolate_to_far_color(&mut self, values: [i64; 3], fields: Fields) {
+        let far_color = self.get_control_vector(FC);
+        let ir0 = self.get_ir(0) as i64;
+        for (i, value) in values.into_iter().enumerate() {
+            let difference = self.check_mac(i, ((far_color[i] as i64) << 12) - value);
+            let difference = (difference >> fields.shift) as i32;
+            let difference = self.saturate_ir(i, difference, false) as i64;
+            let result = self.check_mac(i, value + ir0 * difference);
+            self.set_mac(i + 1, (result >> fields.shift) as i32);
+        }
+        self.set_ir_from_mac(fields.lm);
+        self.push_color_from_mac();
+    }
+    /// Sets MAC1-3 to a color multiplied by IR1-3, then pushes the result as a color.
+    fn multiply_color_by_ir(&mut self, fields: Fields) {
+        let color = self.get_color(RGBC);
+        let ir = self.get_ir_vector();
+        for i in 0..3 {
+            let value = self.check_mac(i, color[i] as i64 * ir[i] as i64);
+            self.set_mac(i + 1, (value >> fields.shift) as i32);
+        }
+        self.set_ir_from_mac(fields.lm);
+        self.push_color_from_mac();
+    }
+    /// Products of RGBC and IR1-3, in 12 fraction bits.
+    fn get_color_by_ir(&self) -> [i64; 3] {
+        let color = self.get_color(RGBC);
+        let ir = self.get_ir_vector();
+        [0, 1, 2].map(|i| color[i] as i64 * ir[i] as i64)
+    }
+
+    /// Divides H by SZ3 like hardware, with a reciprocal from [UNR_TABLE] refined by
+    /// Newton-Raphson. The result has 16 fraction bits and is saturated to 0x1FFFF.
+    fn divide(&mut self) -> u32 {
+        let h = self.control[H] as u16 as u32;
+        let sz3 = self.data[SZ3] as u16 as u32;
+        if h >= sz3 * 2 {
+            self.control[FLAG] |= FLAG_DIVIDE_OVERFLOW;
+            return 0x1FFFF;
+        }
+        let shift = (sz3 as u16).leading_zeros();
+        let n = (h << shift) as u64;
+        let d = sz3 << shift;
+        let u = UNR_TABLE[((d - 0x7FC0) >> 7) as usize] as u32 + 0x101;
+        let d = (0x2000080 - d * u) >> 8;
+        let d = (0x80 + d * u) >> 8;
+        (((n * d as u64) + 0x8000) >> 16).min(0x1FFFF) as u32
+    }
+
+    /// RTPS, and RTPT one vector at a time: perspective transformation of V0, V1 or V2 into
+    /// screen coordinates and Z. Depth cueing is only done for the last vector.
+    fn rtps(&mut self, vector: usize, is_last: bool, fields: Fields) {
+        let matrix = self.get_matrix(RT);
+        let vector = self.get_vector(vector);
+        let translation = self.get_control_vector(TR);
+        let mut z = 0;
+        for i in 0..3 {
+            let products = [0, 1, 2].map(|j| matrix[i][j] as i64 * vector[j] as i64);
+            z = self.accumulate(i, (translation[i] as i64) << 12, &products);
+            self.set_mac(i + 1, (z >> fields.shift) as i32);
+        }
+        for i in 0..2 {
+            let value = self.saturate_ir(i, self.get_mac(i + 1), fields.lm);
+            self.data[IR0 + i + 1] = value as u32;
+        }
+        // IR3 is saturated from MAC3, but its flag is set from Z with 12 fraction bits shifted
+        // out, even with sf=0.
+        let min = if fields.lm { 0 } else { -0x8000 };
+        if !(-0x8000..=0x7FFF).contains(&(z >> 12)) {
+            self.control[FLAG] |= 1 << 22;
+        }
+        self.data[IR0 + 3] = self.get_mac(3).clamp(min, 0x7FFF) as u32;
+        let sz3 = self.saturate_z(z >> 12);
+        self.push_z(sz3);
+
+        let h_divided_by_sz3 = self.divide() as i64;
+        let x = self
+            .check_mac0(self.control[OFX] as i32 as i64 + self.get_ir(1) as i64 * h_divided_by_sz3);
+        let y = self
+            .check_mac0(self.control[OFY] as i32 as i64 + self.get_ir(2) as i64 * h_divided_by_sz3);
+        self.set_mac(0, (y >> 16) as i32);
+        let x = self.saturate_screen_coordinate(0, (x >> 16) as i32);
+        let y = self.saturate_screen_coordinate(1, (y >> 16) as i32);
+        self.push_screen_xy(x as u16 as u32 | (y as u16 as u32) << 16);
+        if is_last {
+            let dqa = self.control[DQA] as i32 as i64;
+            let dqb = self.control[DQB] as i32 as i64;
+            let depth = self.check_mac0(dqb + dqa * h_divided_by_sz3);
+            self.set_mac(0, depth as i32);
+            self.data[IR0] = self.saturate_ir0((depth >> 12) as i32) as u32;
+        }
+    }
+    /// NCLIP: sets MAC0 to twice the signed area of the triangle of SXY0-2, which is positive
+    /// if the triangle is counterclockwise on screen.
+    fn nclip(&mut self) {
+        let [x0, x1, x2] = [0, 1, 2].map(|i| self.data[SXY0 + i] as i16 as i64);
+        let [y0, y1, y2] = [0, 1, 2].map(|i| (self.data[SXY0 + i] >> 16) as i16 as i64);
+        let value = x0 * y1 + x1 * y2 + x2 * y0 - x0 * y2 - x1 * y0 - x2 * y1;
+        let value = self.check_mac0(value);
+        self.set_mac(0, value as i32);
+    }
+    /// AVSZ3 and AVSZ4: sets OTZ to the average of the last 3 or 4 Z, scaled by ZSF3 or ZSF4.
+    fn average_z(&mut self, count: usize) {
+        let scale = match count {
+            3 => self.control[ZSF3],
+            _ => self.control[ZSF4],
+        } as i32 as i64;
+        let sum: i64 = (SZ3 + 1 - count..=SZ3)
+            .map(|register| self.data[register] as i64)
+            .sum();
+        let value = self.check_mac0(scale * sum);
+        self.set_mac(0, value as i32);
+        self.data[OTZ] = self.saturate_z(value >> 12) as u32;
+    }
+    /// MVMVA: multiplies a matrix by a vector and adds a translation, all chosen by fields.
+    fn mvmva(&mut self, fields: Fields) {
+        let matrix = match fields.mx {
+            0 => self.get_matrix(RT),
+            1 => self.get_matrix(LLM),
+            2 => self.get_matrix(LCM),
+            // There is no fourth matrix, hardware uses this garbage.
+            _ => {
+                let red = self.get_color(RGBC)[0];
+                let rt = self.get_matrix(RT);
+                [[-red, red, self.get_ir(0)], [rt[0][2]; 3], [rt[1][1]; 3]]
+            }
+        };
+        let vector = match fields.v {
+            3 => self.get_ir_vector(),
+            v => self.get_vector(v as usize),
+        };
+        match fields.cv {
+            0 => {
+                let translation = self.get_control_vector(TR);
+                self.multiply_matrix_by_vector(matrix, vector, translation, fields)
+            }
+            1 => {
+                let translation = self.get_control_vector(BK);
+                self.multiply_matrix_by_vector(matrix, vector, translation, fields)
+            }
+            2 => self.multiply_matrix_by_vector_with_far_color(matrix, vector, fields),
+            _ => self.multiply_matrix_by_vector(matrix, vector, [0; 3], fields),
+        }
+    }
+    /// Sets IR1-3 to the color of lights on a vertex of normal V0, V1 or V2 (the light matrix
+    /// by the normal, then the light color matrix by the result, plus the background color).
+    fn light(&mut self, vector: usize, fields: Fields) {
+        let normal = self.get_vector(vector);
+        self.multiply_matrix_by_vector(self.get_matrix(LLM), normal, [0; 3], fields);
+        self.color_ir(fields);
+    }
+    /// Sets IR1-3 to the light color matrix by IR1-3, plus the background color.
+    fn color_ir(&mut self, fields: Fields) {
+        let matrix = self.get_matrix(LCM);
+        let background_color = self.get_control_vector(BK);
+        self.multiply_matrix_by_vector(matrix, self.get_ir_vector(), background_color, fields);
+    }
+    /// SQR: squares IR1-3.
+    fn sqr(&mut self, fields: Fields) {
+        for i in 0..3 {
+            let ir = self.get_ir(i + 1) as i64;
+            let value = self.check_mac(i, ir * ir);
+            self.set_mac(i + 1, (value >> fields.shift) as i32);
+        }
+        self.set_ir_from_mac(fields.lm);
+    }
+    /// OP: outer product of the diagonal of the rotation matrix and IR1-3.
+    fn op(&mut self, fields: Fields) {
+        let matrix = self.get_matrix(RT);
+        let d = [0, 1, 2].map(|i| matrix[i][i] as i64);
+        let ir = self.get_ir_vector().map(|ir| ir as i64);
+        for i in 0..3 {
+            let (j, k) = ((i + 1) % 3, (i + 2) % 3);
+            let value = self.check_mac(i, ir[k] * d[j] - ir[j] * d[k]);
+            self.set_mac(i + 1, (value >> fields.shift) as i32);
+        }
+        self.set_ir_from_mac(fields.lm);
+    }
+    /// GPF and GPL: multiplies IR1-3 by IR0, added to MAC1-3 with GPL, then pushes the result
+    /// as a color.
+    fn general_purpose_interpolation(&mut self, is_adding_mac: bool, fields: Fields) {
+        let ir0 = self.get_ir(0) as i64;
+        for i in 0..3 {
+            let base = match is_adding_mac {
+                true => (self.get_mac(i + 1) as i64) << fields.shift,
+                false => 0,
+            };
+            let value = self.check_mac(i, base + ir0 * self.get_ir(i + 1) as i64);
+            self.set_mac(i + 1, (value >> fields.shift) as i32);
+        }
+        self.set_ir_from_mac(fields.lm);
+        self.push_color_from_mac();
+    }
+}
+impl Default for Gte {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Cop2 for Gte {
+    fn read_data(&mut self, register: u8) -> u32 {
+        match register as usize {
+            SXYP => self.data[SXY2],
+            IRGB | ORGB => {
+                let [r, g, b] = [1, 2, 3].map(|i| (self.get_ir(i) >> 7).clamp(0, 0x1F) as u32);
+                r | g << 5 | b << 10
+            }
+            register => self.data[register],
+        }
+    }
+    fn write_data(&mut self, register: u8, value: u32) {
+        let register = register as usize;
+        match register {
+            // VZ0-2 and IR0-3
+            1 | 3 | 5 | 8..=11 => self.data[register] = value as i16 as u32,
+            // OTZ and SZ0-3
+            OTZ | SZ0..=SZ3 => self.data[register] = value as u16 as u32,
+            SXYP => self.push_screen_xy(value),
+            IRGB => {
+                for i in 0..3 {
+                    self.data[IR0 + 1 + i] = ((value >> (i * 5)) & 0x1F) << 7;
+                }
+            }
+            ORGB | LZCR => {}
+            LZCS => {
+                self.data[LZCS] = value;
+                self.data[LZCR] = match value as i32 >= 0 {
+                    true => value.leading_zeros(),
+                    false => value.leading_ones(),
+                };
+            }
+            _ => self.data[register] = value,
+        }
+    }
+    fn read_control(&mut self, register: u8) -> u32 {
+        self.control[register as usize]
+    }
+    fn write_control(&mut self, register: u8, value: u32) {
+        let register = register as usize;
+        match register {
+            // RT33, L33, LB3, H, DQA, ZSF3 and ZSF4, where H is unsigned but read back
+            // sign extended.
+            4 | 12 | 20 | H | DQA | ZSF3 | ZSF4 => self.control[register] = value as i16 as u32,
+            FLAG => {
+                let value = value & FLAG_WRITABLE_MASK;
+                self.control[FLAG] = match value & FLAG_ERROR_MASK {
+                    0 => value,
+                    _ => value | FLAG_ERROR,
+                };
+            }
+            _ => self.control[register] = value,
+        }
+    }
+    fn execute(&mut self, command: u32) {
+        let fields = Fields::decode(command);
+        self.control[FLAG] = 0;
+        match command & 0x3F {
+            // RTPS
+            0x01 => self.rtps(0, true, fields),
+            // NCLIP
+            0x06 => self.nclip(),
+            // OP
+            0x0C => self.op(fields),
+            // DPCS
+            0x10 => {
+                let color = self.get_color(RGBC).map(|c| (c as i64) << 12);
+                self.interpolate_to_far_color(color, fields);
+            }
+            // INTPL
+            0x11 => {
+                let ir = self.get_ir_vector().map(|ir| (ir as i64) << 12);
+                self.interpolate_to_far_color(ir, fields);
+            }
+            // MVMVA
+            0x12 => self.mvmva(fields),
+            // NCDS
+            0x13 => {
+                self.light(0, fields);
+                self.interpolate_to_far_color(self.get_color_by_ir(), fields);
+            }
+            // CDP
+            0x14 => {
+                self.color_ir(fields);
+                self.interpolate_to_far_color(self.get_color_by_ir(), fields);
+            }
+            // NCDT
+            0x16 => {
+                for vector in 0..3 {
+                    self.light(vector, fields);
+                    self.interpolate_to_far_color(self.get_color_by_ir(), fields);
+                }
+            }
+            // NCCS
+            0x1B => {
+                self.light(0, fields);
+                self.multiply_color_by_ir(fields);
+            }
+            // CC
+            0x1C => {
+                self.color_ir(fields);
+                self.multiply_color_by_ir(fields);
+            }
+            // NCS
+            0x1E => {
+                self.light(0, fields);
+                self.push_color_from_mac();
+            }
+            // NCT
+            0x20 => {
+                for vector in 0..3 {
+                    self.light(vector, fields);
+                    self.push_color_from_mac();
+                }
+            }
+            // SQR
+            0x28 => self.sqr(fields),
+            // DCPL
+            0x29 => self.interpolate_to_far_color(self.get_color_by_ir(), fields),
+            // DPCT, which interpolates the oldest color of the FIFO 3 times
+            0x2A => {
+                for _ in 0..3 {
+                    let color = self.get_color(RGB0).map(|c| (c as i64) << 12);
+                    self.interpolate_to_far_color(color, fields);
+                }
+            }
+            // AVSZ3
+            0x2D => self.average_z(3),
+            // AVSZ4
+            0x2E => self.average_z(4),
+            // RTPT
+            0x30 => {
+                for vector in 0..3 {
+                    self.rtps(vector, vector == 2, fields);
+                }
+            }
+            // GPF
+            0x3D => self.general_purpose_interpolation(false, fields),
+            // GPL
+            0x3E => self.general_purpose_interpolation(true, fields),
+            // NCCT
+            0x3F => {
+                for vector in 0..3 {
+                    self.light(vector, fields);
+                    self.multiply_color_by_ir(fields);
+                }
+            }
+            // Commands which do not exist only clear the flags.
+            _ => {}
+        }
+        if self.control[FLAG] & FLAG_ERROR_MASK != 0 {
+            self.control[FLAG] |= FLAG_ERROR;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Cpu, Instruction, Memory};
+
+    fn execute(gte: &mut Gte, assembly: &str) {
+        let Ok(Instruction::Gte { command }) = Instruction::parse_from_str(assembly) else {
+            panic!("\"{}\" is not a GTE command", assembly);
+        };
+        gte.execute(command);
+    }
+    fn read_macs(gte: &mut Gte) -> [i32; 3] {
+        [25, 26, 27].map(|register| gte.read_data(register) as i32)
+    }
+    fn read_irs(gte: &mut Gte) -> [i32; 3] {
+        [9, 10, 11].map(|register| gte.read_data(register) as i32)
+    }
+    /// Makes a GTE with the identity as rotation matrix.
+    fn make_gte() -> Gte {
+        let mut gte = Gte::new();
+        gte.write_control(0, 0x1000);
+        gte.write_control(2, 0x1000);
+        gte.write_control(4, 0x1000);
+        gte
+    }
+
+    #[test]
+    fn generate_unr_table() {
+        assert_eq!(
+            UNR_TABLE[..16],
+            [
+                0xFF, 0xFD, 0xFB, 0xF9, 0xF7, 0xF5, 0xF3, 0xF1, 0xEF, 0xEE, 0xEC, 0xEA, 0xE8, 0xE6,
+                0xE4, 0xE3
+            ]
+        );
+        assert_eq!(UNR_TABLE[0x100], 0x00);
+    }
+
+    #[test]
+    fn transform_perspective() {
+        let mut gte = make_gte();
+        gte.write_control(7, 0x300);
+        gte.write_control(24, 160 << 16);
+        gte.write_control(25, 120 << 16);
+        gte.write_control(26, 0x200);
+        gte.write_control(27, 0x100);
+        gte.write_control(28, 0x100000);
+        gte.write_data(0, 0xFF80_0100);
+        gte.write_data(1, 0x200);
+        execute(&mut gte, "rtps");
+        assert_eq!(read_macs(&mut gte), [0x100, -0x80, 0x500]);
+        assert_eq!(read_irs(&mut gte), [0x100, -0x80, 0x500]);
+        assert_eq!(gte.read_data(19), 0x500);
+        // H / SZ3 is 0.4 (26215 / 0x10000), so X is 160 + 0x100 * 0.4 and Y is 120 - 0x80 * 0.4,
+        // rounded down.
+        assert_eq!(gte.read_data(14), 68 << 16 | 262);
+        assert_eq!(gte.read_data(15), 68 << 16 | 262);
+        assert_eq!(gte.read_data(24), 0x100000 + 0x100 * 26215);
+        assert_eq!(gte.read_data(8), 1894);
+        assert_eq!(gte.read_control(31), 0);
+
+        // V1 goes off screen, and V2 is at the camera, so its division overflows.
+        gte.write_data(2, 0x7000);
+        gte.write_data(3, 0x200);
+        gte.write_data(5, -0x300i32 as u32);
+        execute(&mut gte, "rtpt");
+        assert_eq!(
+            [12, 13, 14].map(|register| gte.read_data(register)),
+            [68 << 16 | 262, 120 << 16 | 0x3FF, 120 << 16 | 160]
+        );
+        assert_eq!(
+            [16, 17, 18, 19].map(|register| gte.read_data(register)),
+            [0x500, 0x500, 0x500, 0]
+        );
+        assert_eq!(gte.read_data(8), 0x1000);
+        assert_eq!(
+            gte.read_control(31),
+            FLAG_ERROR | FLAG_DIVIDE_OVERFLOW | 1 << 14 | FLAG_IR0_SATURATED
+        );
+    }
+
+    #[test]
+    fn compute_normal_clip_and_average_z() {
+        let mut gte = Gte::new();
+        for value in [0, 10, 10 << 16] {
+            gte.write_data(15, value);
+        }
+        execute(&mut gte, "nclip");
+        assert_eq!(gte.read_data(24), 100);
+        // Swapping two points makes the triangle clockwise.
+        gte.write_data(13, 10 << 16);
+        gte.write_data(14, 10);
+        execute(&mut gte, "nclip");
+        assert_eq!(gte.read_data(24) as i32, -100);
+
+        for (register, z) in [(16, 400), (17, 100), (18, 200), (19, 300)] {
+            gte.write_data(register, z);
+        }
+        gte.write_control(29, 0x1000 / 3);
+        execute(&mut gte, "avsz3");
+        assert_eq!(gte.read_data(24), 0x555 * 600);
+        assert_eq!(gte.read_data(7), 199);
+        gte.write_control(30, 0x1000 / 4);
+        execute(&mut gte, "avsz4");
+        assert_eq!(gte.read_data(7), 250);
+        assert_eq!(gte.read_control(31), 0);
+
+        gte.write_control(30, -0x400i32 as u32);
+        execute(&mut gte, "avsz4");
+        assert_eq!(gte.read_data(7), 0);
+        assert_eq!(gte.read_control(31), FLAG_ERROR | FLAG_SZ3_OTZ_SATURATED);
+    }
+
+    #[test]
+    fn multiply_vectors() {
+        let mut gte = make_gte();
+        gte.write_data(0, 2 << 16 | 1);
+        gte.write_data(1, 3);
+        for (register, value) in [(5, 0x10), (6, 0x20), (7, 0x30)] {
+            gte.write_control(register, value);
+            gte.write_control(register + 16, value);
+        }
+        execute(&mut gte, "mvmva sf=1, mx=rt, v=v0, cv=tr, lm=0");
+        assert_eq!(read_macs(&mut gte), [0x11, 0x22, 0x33]);
+        execute(&mut gte, "mvmva sf=1, mx=rt, v=v0, cv=none, lm=0");
+        assert_eq!(read_macs(&mut gte), [1, 2, 3]);
+        // With the far color, hardware drops both the translation and the first column.
+        execute(&mut gte, "mvmva sf=1, mx=rt, v=v0, cv=fc, lm=0");
+        assert_eq!(read_macs(&mut gte), [0, 2, 3]);
+        execute(&mut gte, "mvmva sf=0, mx=rt, v=ir, cv=none, lm=0");
+        assert_eq!(read_macs(&mut gte), [0, 0x2000, 0x3000]);
+
+        for (register, value) in [(9, 0x10), (10, 0x20), (11, 0x40)] {
+            gte.write_data(register, value);
+        }
+        execute(&mut gte, "op sf=1, lm=1");
+        assert_eq!(read_macs(&mut gte), [0x20, -0x30, 0x10]);
+        assert_eq!(read_irs(&mut gte), [0x20, 0, 0x10]);
+        assert_eq!(gte.read_control(31), FLAG_ERROR | 1 << 23);
+
+        gte.write_data(9, -0x100i32 as u32);
+        execute(&mut gte, "sqr sf=1");
+        assert_eq!(read_macs(&mut gte), [0x10, 0, 0]);
+        gte.write_data(9, 0x7FFF);
+        execute(&mut gte, "sqr sf=0");
+        assert_eq!(read_irs(&mut gte), [0x7FFF, 0, 0]);
+        assert_eq!(gte.read_control(31), FLAG_ERROR | 1 << 24);
+    }
+
+    #[test]
+    fn interpolate_colors() {
+        let mut gte = Gte::new();
+        gte.write_data(6, 0x7F204080);
+        gte.write_data(8, 0x800);
+        for register in 21..=23 {
+            gte.write_control(register, 0x1000);
+        }
+        // Halfway between the color and the far color.
+        execute(&mut gte, "dpcs");
+        assert_eq!(read_irs(&mut gte), [0xC00, 0xA00, 0x900]);
+        assert_eq!(gte.read_data(22), 0x7F90A0C0);
+        for (register, value) in [(9, 0x800), (10, 0x400), (11, 0x200)] {
+            gte.write_data(register, value);
+        }
+        execute(&mut gte, "intpl");
+        assert_eq!(read_irs(&mut gte), [0xC00, 0xA00, 0x900]);
+
+        for (register, value) in [(9, 0x100), (10, 0x200), (11, 0x300)] {
+            gte.write_data(register, value);
+        }
+        execute(&mut gte, "gpf sf=1");
+        assert_eq!(read_macs(&mut gte), [0x80, 0x100, 0x180]);
+        assert_eq!(gte.read_data(22), 0x7F181008);
+        execute(&mut gte, "gpl sf=1");
+        assert_eq!(read_macs(&mut gte), [0xC0, 0x180, 0x240]);
+        assert_eq!(gte.read_data(22), 0x7F24180C);
+        assert_eq!(
+            [20, 21].map(|register| gte.read_data(register)),
+            [0x7F90A0C0, 0x7F181008]
+        );
+        assert_eq!(gte.read_control(31), 0);
+    }
+
+    #[test]
+    fn light_vertices() {
+        let mut gte = Gte::new();
+        gte.write_control(8, 0x1000);
+        gte.write_control(16, 0x800);
+        gte.write_control(17, 0x400 << 16);
+        gte.write_control(19, 0x200);
+        gte.write_control(14, 0x100);
+        gte.write_data(0, 0x1000);
+        gte.write_data(6, 0x30FF8080);
+        execute(&mut gte, "ncs");
+        assert_eq!(read_macs(&mut gte), [0x800, 0x500, 0x200]);
+        assert_eq!(gte.read_data(22), 0x30205080);
+        execute(&mut gte, "nccs");
+        assert_eq!(gte.read_data(22), 0x301F2840);
+        // Depth cueing all the way to a black far color.
+        gte.write_data(8, 0x1000);
+        execute(&mut gte, "ncds");
+        assert_eq!(gte.read_data(22), 0x30000000);
+        assert_eq!(gte.read_control(31), 0);
+
+        // Colors saturate.
+        gte.write_control(14, 0x1000);
+        execute(&mut gte, "ncs");
+        assert_eq!(gte.read_data(22), 0x3020FF80);
+        assert_eq!(gte.read_control(31), 1 << 20);
+    }
+
+    #[test]
+    fn access_registers() {
+        let mut gte = Gte::new();
+        gte.write_data(1, 0x12348000);
+        assert_eq!(gte.read_data(1), 0xFFFF8000);
+        gte.write_data(7, 0xFFFF1234);
+        assert_eq!(gte.read_data(7), 0x1234);
+        gte.write_data(28, 0x7FFF);
+        assert_eq!(read_irs(&mut gte), [0xF80; 3]);
+        gte.write_data(9, -1i32 as u32);
+        gte.write_data(10, 0x7FFF);
+        gte.write_data(11, 0x100);
+        assert_eq!(gte.read_data(29), 2 << 10 | 0x1F << 5);
+        assert_eq!(gte.read_data(28), 2 << 10 | 0x1F << 5);
+        for (lzcs, lzcr) in [(0, 32), (0x00F00000, 8), (0xFF000000, 8), (0xFFFFFFFF, 32)] {
+            gte.write_data(30, lzcs);
+            assert_eq!(gte.read_data(31), lzcr);
+        }
+
+        gte.write_control(26, 0x8000);
+        assert_eq!(gte.read_control(26), 0xFFFF8000);
+        gte.write_control(31, 0xFFFFFFFF);
+        assert_eq!(gte.read_control(31), 0xFFFFF000);
+        gte.write_control(31, 0x00000FFF | FLAG_IR0_SATURATED);
+        assert_eq!(gte.read_control(31), FLAG_IR0_SATURATED);
+        execute(&mut gte, "cop2 0x0000000");
+        assert_eq!(gte.read_control(31), 0);
+    }
+
+    #[test]
+    fn run_from_interpreter() {
+        let code =
+            "mtc2 zero, sxy0\nmtc2 a0, sxy1\nmtc2 a1, sxy2\nnclip\nmfc2 v0, mac0\njr ra\nnop";
+        let mut memory = Memory::new();
+        for (i, line) in code.lines().enumerate() {
+            let instruction = Instruction::parse_from_str(line).unwrap();
+            memory.load(
+                0x80010000 + i as u32 * 4,
+                &instruction.to_machine_code().to_le_bytes(),
+            );
+        }
+        let mut cpu = Cpu::new(0);
+        cpu.cop2 = Some(Box::new(Gte::new()));
+        cpu.registers[4] = 10;
+        cpu.registers[5] = 10 << 16;
+        assert_eq!(cpu.call(&mut memory, 0x80010000, 100), Ok(7));
+        assert_eq!(cpu.registers[2], 100);
+    }
+}
@@ -0,0 +1,169 @@
+//! Addresses loaded in two halves.
+//!
+//! A 32-bit address does not fit in an immediate, so it is loaded with `lui` setting
+//! the upper half of a register, followed by an instruction adding the lower half,
+//! like `addiu` or a load or store using the register as its base:
+//!
+//! ```text
+//! lui a0, %hi(level_names)
+//! lw a1, %lo(level_names)(a0)
+//! ```
+//!
+//! The lower half is signed, so the upper half is rounded up when bit 15 of the address is set.
+
+use crate::Instruction;
+
+/// Register number of ra (the return address).
+const RA: u8 = 31;
+
+/// A `lui` instruction and an instruction adding a lower half to its register,
+/// found by [`find_hi_lo_pairs`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct HiLoPair {
+    /// Index of the `lui` instruction.
+    pub hi_index: usize,
+    /// Index of the `addiu`, load or store instruction.
+    pub lo_index: usize,
+    /// Address made from both halves.
+    pub address: u64,
+}
+
+/// Finds `lui` instructions paired with later `addiu`, load or store instructions using
+/// the same register, before the register is written again. A `lui` may be paired
+/// with many instructions, like loads of different fields of a structure.
+///
+/// Instructions are expected to run one after another, so code should be split at
+/// branch targets. Unknown instructions (`None`) end all pairs found so far.
+///
+/// ```
+/// use mips::{find_hi_lo_pairs, HiLoPair, Instruction};
+///
+/// let instructions = ["lui a0, 0x8007", "addiu a1, a1, 1", "lw t0, -16(a0)"]
+///     .map(|instruction| Instruction::parse_from_str(instruction).ok());
+/// let instructions = instructions.iter().map(Option::as_ref).collect::<Vec<_>>();
+/// let pairs = find_hi_lo_pairs(&instructions);
+/// assert_eq!(pairs, [HiLoPair { hi_index: 0, lo_index: 2, address: 0x8006FFF0 }]);
+/// ```
+pub fn find_hi_lo_pairs(instructions: &[Option<&Instruction>]) -> Vec<HiLoPair> {
+    let mut pairs = Vec::new();
+    // Index and upper half of the last lui instruction for each register.
+    let mut his: [Option<(usize, u16)>; 32] = [None; 32];
+
+    for (i, instruction) in instructions.iter().enumerate() {
+        let Some(instruction) = instruction else {
+            his = [None; 32];
+            continue;
+        };
+
+        if let Some((base, lo)) = get_lo(instruction) {
+            if let Some((hi_index, hi)) = his[base as usize] {
+                let address = ((hi as u32) << 16).wrapping_add(lo as i32 as u32);
+                pairs.push(HiLoPair {
+                    hi_index,
+                    lo_index: i,
+                    address: address as u64,
+                });
+            }
+        }
+
+        if let Some(register) = get_written_register(instruction) {
+            his[register as usize] = match instruction {
+                Instruction::IUnsigned {
+                    opcode: 0b001111, // lui, opcode 15
+                    immediate,
+                    ..
+                } => Some((i, *immediate)),
+                _ => None,
+            };
+        }
+    }
+    pairs
+}
+
+/// Gets the base register and the signed lower half of an addiu, load or store instruction.
+fn get_lo(instruction: &Instruction) -> Option<(u8, i16)> {
+    match instruction {
+        Instruction::IUnsigned {
+            // addiu, lbu and lhu, opcodes 9, 36 and 37
+            opcode: 0b001001 | 0b100100 | 0b100101,
+            rs,
+            immediate,
+            ..
+        } => Some((*rs, *immediate as i16)),
+        Instruction::ISigned {
+            // Loads and stores, opcodes 32-46, and lwc2 and swc2, opcodes 50 and 58
+            opcode: 0b100000..=0b101110 | 0b110010 | 0b111010,
+            rs,
+            immediate,
+            ..
+        } => Some((*rs, *immediate)),
+        _ => None,
+    }
+}
+
+/// Gets the general purpose register an instruction writes to, if any.
+fn get_written_register(instruction: &Instruction) -> Option<u8> {
+    let register = match instruction {
+        // mfc0, mfc2 and cfc2
+        Instruction::CopMove { rs: 0 | 2, rt, .. } => *rt,
+        Instruction::ISigned { opcode, rt, .. } | Instruction::IUnsigned { opcode, rt, .. } => {
+            match opcode {
+                // bltzal and bgezal
+                0b000001 if rt & 0b10000 != 0 => RA,
+                // Branches, stores, lwc2 and swc2
+                0b000001 | 0b000100..=0b000111 | 0b101000..=0b101110 | 0b110010 | 0b111010 => {
+                    return None
+                }
+                _ => *rt,
+            }
+        }
+        Instruction::J {
+            opcode: 0b000011, // jal, opcode 3
+            ..
+        } => RA,
+        Instruction::R { funct, rd, .. } => match funct {
+            // jr, mthi, mtlo, mult, multu, div and divu
+            8 | 17 | 19 | 24..=27 => return None,
+            _ => *rd,
+        },
+        _ => return None,
+    };
+    Some(register)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find_pairs(content: &str) -> Vec<(usize, usize, u64)> {
+        let instructions = content
+            .split("\n")
+            .map(|line| Instruction::parse_from_str(line).ok())
+            .collect::<Vec<_>>();
+        let instructions = instructions.iter().map(Option::as_ref).collect::<Vec<_>>();
+        find_hi_lo_pairs(&instructions)
+            .iter()
+            .map(|pair| (pair.hi_index, pair.lo_index, pair.address))
+            .collect()
+    }
+
+    #[test]
+    fn find_pairs_with_addiu_loads_and_stores() {
+        let content = "lui a0, 0x8007\naddiu a0, a0, 0x5000\nlui v0, 0x8008\nlw t0, -4(v0)\nsb zero, 16(v0)\nlbu t1, 0x8000(v0)";
+        assert_eq!(
+            find_pairs(content),
+            [
+                (0, 1, 0x80075000),
+                (2, 3, 0x8007FFFC),
+                (2, 4, 0x80080010),
+                (2, 5, 0x80078000)
+            ]
+        );
+    }
+    #[test]
+    fn find_no_pairs_after_register_is_written() {
+        // The register is replaced by addu, ra by jal, and all by an unknown instruction.
+        let content = "lui a0, 0x8007\naddu a0, a1, a2\nlw t0, 4(a0)\nlui ra, 0x8007\njal 0\nlw t0, 4(ra)\nlui a1, 0x8007\nunknown\nlw t0, 4(a1)";
+        assert_eq!(find_pairs(content), []);
+    }
+}
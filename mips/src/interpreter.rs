@@ -0,0 +1,884 @@
+//! Interpreter of the R3000A CPU of Playstation, for running game code headlessly, like
+//! a single function of an executable with controlled inputs in a test.
+//!
+//! Timing is not modeled (every instruction takes one step), and neither are interrupts or
+//! caches, but everything else a program can observe is: load delay slots, branch delay slots
+//! and exceptions (like arithmetic overflow), which jump into the exception vector.
+
+use std::fmt;
+
+use crate::{Bus, Instruction};
+
+/// Index of the status register (SR) in COP0.
+pub const COP0_SR: usize = 12;
+/// Index of the cause register in COP0.
+pub const COP0_CAUSE: usize = 13;
+/// Index of the exception program counter (EPC) in COP0.
+pub const COP0_EPC: usize = 14;
+/// Index of the bad virtual address register in COP0.
+pub const COP0_BADVADDR: usize = 8;
+/// Index of the processor ID register in COP0.
+pub const COP0_PRID: usize = 15;
+
+/// Address [Cpu::call] returns to, which is not mapped, so that reaching it always means
+/// the called function returned.
+pub const CALL_RETURN_ADDRESS: u32 = 0xFFFFFFF0;
+
+/// Coprocessor 2, which is the GTE on Playstation. Registers are told by their number.
+pub trait Cop2 {
+    fn read_data(&mut self, register: u8) -> u32;
+    fn write_data(&mut self, register: u8, value: u32);
+    fn read_control(&mut self, register: u8) -> u32;
+    fn write_control(&mut self, register: u8, value: u32);
+    /// Runs a command, which is bits 0-24 of the instruction.
+    fn execute(&mut self, command: u32);
+}
+
+/// Exceptions raised by instructions, by their exception code in the cause register.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Exception {
+    /// Unaligned load or instruction fetch (code 4).
+    AddressErrorLoad { address: u32 },
+    /// Unaligned store (code 5).
+    AddressErrorStore { address: u32 },
+    /// Instruction fetch from an address nothing is mapped at (code 6).
+    BusErrorInstruction { address: u32 },
+    /// Load or store at an address nothing is mapped at (code 7).
+    BusErrorData { address: u32 },
+    /// syscall instruction (code 8).
+    Syscall,
+    /// break instruction (code 9).
+    Break,
+    /// Machine code which is not an instruction (code 10).
+    ReservedInstruction { machine_code: u32 },
+    /// Instruction of a coprocessor which is missing or not enabled in SR (code 11).
+    CoprocessorUnusable { coprocessor: u8 },
+    /// Signed overflow of add, addi or sub (code 12).
+    Overflow,
+}
+impl Exception {
+    pub fn get_code(&self) -> u32 {
+        match self {
+            Exception::AddressErrorLoad { .. } => 4,
+            Exception::AddressErrorStore { .. } => 5,
+            Exception::BusErrorInstruction { .. } => 6,
+            Exception::BusErrorData { .. } => 7,
+            Exception::Syscall => 8,
+            Exception::Break => 9,
+            Exception::ReservedInstruction { .. } => 10,
+            Exception::CoprocessorUnusable { .. } => 11,
+            Exception::Overflow => 12,
+        }
+    }
+}
+impl fmt::Display for Exception {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Exception::AddressErrorLoad { address } => {
+                write!(f, "Address error loading from 0x{:08X}", address)
+            }
+            Exception::AddressErrorStore { address } => {
+                write!(f, "Address error storing into 0x{:08X}", address)
+            }
+            Exception::BusErrorInstruction { address } => {
+                write!(
+                    f,
+                    "Bus error fetching an instruction from 0x{:08X}",
+                    address
+                )
+            }
+            Exception::BusErrorData { address } => {
+                write!(f, "Bus error accessing 0x{:08X}", address)
+            }
+            Exception::Syscall => write!(f, "Syscall"),
+            Exception::Break => write!(f, "Break"),
+            Exception::ReservedInstruction { machine_code } => {
+                write!(f, "Reserved instruction 0x{:08X}", machine_code)
+            }
+            Exception::CoprocessorUnusable { coprocessor } => {
+                write!(f, "Coprocessor {} unusable", coprocessor)
+            }
+            Exception::Overflow => write!(f, "Arithmetic overflow"),
+        }
+    }
+}
+
+/// State of the CPU: general purpose registers, HI and LO, the program counter and COP0.
+pub struct Cpu {
+    /// General purpose registers, where register 0 (zero) is always 0.
+    pub registers: [u32; 32],
+    pub hi: u32,
+    pub lo: u32,
+    /// Address of the next instruction to run.
+    pub pc: u32,
+    /// Address of the instruction after the next one, which is a branch target
+    /// if the next instruction is in a branch delay slot.
+    next_pc: u32,
+    /// Load waiting in its delay slot, which lands after the current instruction.
+    load_delay: Option<(u8, u32)>,
+    /// Load of the current instruction, which waits for the next instruction.
+    next_load_delay: Option<(u8, u32)>,
+    /// Whether the current instruction is a branch or jump, so the next one is in its delay slot.
+    is_branch: bool,
+    is_in_delay_slot: bool,
+    pub cop0: [u32; 32],
+    /// GTE, if attached. Without it, COP2 instructions raise an exception.
+    pub cop2: Option<Box<dyn Cop2>>,
+}
+impl Cpu {
+    /// Makes a CPU with all registers cleared, at a given address. COP2 is enabled in SR,
+    /// like games run with.
+    pub fn new(pc: u32) -> Self {
+        let mut cop0 = [0; 32];
+        cop0[COP0_SR] = 1 << 30;
+        // R3000A
+        cop0[COP0_PRID] = 0x00000002;
+        Self {
+            registers: [0; 32],
+            hi: 0,
+            lo: 0,
+            pc,
+            next_pc: pc.wrapping_add(4),
+            load_delay: None,
+            next_load_delay: None,
+            is_branch: false,
+            is_in_delay_slot: false,
+            cop0,
+            cop2: None,
+        }
+    }
+    /// Jumps to an address, cancelling any load and branch in flight.
+    pub fn set_pc(&mut self, pc: u32) {
+        self.flush_load_delay();
+        self.pc = pc;
+        self.next_pc = pc.wrapping_add(4);
+        self.is_branch = false;
+    }
+    /// Calls a function with arguments already set in registers, running until it returns to
+    /// [CALL_RETURN_ADDRESS]. Fails on the first exception, or if the function runs more than
+    /// a given count of instructions. Returns the count of instructions run.
+    pub fn call(
+        &mut self,
+        bus: &mut dyn Bus,
+        address: u32,
+        max_instruction_count: u64,
+    ) -> Result<u64, String> {
+        const RA: usize = 31;
+        self.set_pc(address);
+        self.registers[RA] = CALL_RETURN_ADDRESS;
+        let mut instruction_count = 0;
+        while self.pc != CALL_RETURN_ADDRESS {
+            if instruction_count == max_instruction_count {
+                return Err(format!(
+                    "Function at 0x{:08X} did not return after {} instructions (PC is 0x{:08X}).",
+                    address, max_instruction_count, self.pc
+                ));
+            }
+            let pc = self.pc;
+            self.step(bus).map_err(|exception| {
+                format!(
+                    "{} at 0x{:08X} in function at 0x{:08X}.",
+                    exception, pc, address
+                )
+            })?;
+            instruction_count += 1;
+        }
+        // The last load lands before the caller gets to read it.
+        self.flush_load_delay();
+        Ok(instruction_count)
+    }
+    /// Runs one instruction. If the instruction raises an exception, the CPU jumps into
+    /// the exception vector, and the exception is returned.
+    pub fn step(&mut self, bus: &mut dyn Bus) -> Result<(), Exception> {
+        let pc = self.pc;
+        self.is_in_delay_slot = self.is_branch;
+        self.is_branch = false;
+        self.pc = self.next_pc;
+        self.next_pc = self.next_pc.wrapping_add(4);
+
+        let result = self
+            .fetch(bus, pc)
+            .and_then(|instruction| self.execute(bus, &instruction));
+        match result {
+            Ok(()) => {
+                if let Some((register, value)) = self.load_delay.take() {
+                    self.registers[register as usize] = value;
+                }
+                self.load_delay = self.next_load_delay.take();
+                Ok(())
+            }
+            Err(exception) => {
+                self.raise_exception(exception, pc);
+                Err(exception)
+            }
+        }
+    }
+    /// Gets a register as it is seen by the current instruction, which does not see a load
+    /// in its delay slot yet.
+    pub fn get_register(&self, register: u8) -> u32 {
+        self.registers[register as usize]
+    }
+    fn fetch(&mut self, bus: &mut dyn Bus, pc: u32) -> Result<Instruction, Exception> {
+        if pc % 4 != 0 {
+            return Err(Exception::AddressErrorLoad { address: pc });
+        }
+        let machine_code = bus
+            .read_u32(pc)
+            .ok_or(Exception::BusErrorInstruction { address: pc })?;
+        Instruction::parse_from_machine_code(machine_code).map_err(|_| {
+            match machine_code >> 26 {
+                // COP1 and COP3 are missing on Playstation.
+                0b010001 => Exception::CoprocessorUnusable { coprocessor: 1 },
+                0b010011 => Exception::CoprocessorUnusable { coprocessor: 3 },
+                _ => Exception::ReservedInstruction { machine_code },
+            }
+        })
+    }
+    /// Writes a register right away. A load to the same register in its delay slot is lost.
+    fn set_register(&mut self, register: u8, value: u32) {
+        if register == 0 {
+            return;
+        }
+        if self
+            .load_delay
+            .is_some_and(|(load_register, _)| load_register == register)
+        {
+            self.load_delay = None;
+        }
+        self.registers[register as usize] = value;
+    }
+    /// Writes a register after the next instruction (load delay slot).
+    /// A load to the same register already in its delay slot is lost.
+    fn set_register_delayed(&mut self, register: u8, value: u32) {
+        if register == 0 {
+            return;
+        }
+        if self
+            .load_delay
+            .is_some_and(|(load_register, _)| load_register == register)
+        {
+            self.load_delay = None;
+        }
+        self.next_load_delay = Some((register, value));
+    }
+    fn flush_load_delay(&mut self) {
+        for (register, value) in [self.load_delay.take(), self.next_load_delay.take()]
+            .into_iter()
+            .flatten()
+        {
+            self.registers[register as usize] = value;
+        }
+    }
+    fn branch(&mut self, target: u32) {
+        self.next_pc = target;
+    }
+    fn raise_exception(&mut self, exception: Exception, pc: u32) {
+        // The load of the instruction in the delay slot has already landed.
+        if let Some((register, value)) = self.load_delay.take() {
+            self.registers[register as usize] = value;
+        }
+        self.next_load_delay = None;
+
+        let mut cause = exception.get_code() << 2;
+        if let Exception::CoprocessorUnusable { coprocessor } = exception {
+            cause |= (coprocessor as u32) << 28;
+        }
+        // Exceptions in a delay slot return to the branch, so that the branch runs again.
+        let epc = if self.is_in_delay_slot {
+            cause |= 1 << 31;
+            pc.wrapping_sub(4)
+        } else {
+            pc
+        };
+        match exception {
+            Exception::AddressErrorLoad { address } | Exception::AddressErrorStore { address } => {
+                self.cop0[COP0_BADVADDR] = address
+            }
+            _ => {}
+        }
+        self.cop0[COP0_CAUSE] = (self.cop0[COP0_CAUSE] & 0x300) | cause;
+        self.cop0[COP0_EPC] = epc;
+        // Interrupt enable and kernel mode bits are pushed onto a stack of three.
+        let sr = self.cop0[COP0_SR];
+        self.cop0[COP0_SR] = (sr & !0x3F) | ((sr << 2) & 0x3F);
+        // Boot exception vectors (BEV) are in BIOS.
+        let vector = match sr & (1 << 22) {
+            0 => 0x80000080,
+            _ => 0xBFC00180,
+        };
+        self.pc = vector;
+        self.next_pc = vector.wrapping_add(4);
+        self.is_branch = false;
+    }
+    fn is_cop2_usable(&self) -> bool {
+        self.cop2.is_some() && self.cop0[COP0_SR] & (1 << 30) != 0
+    }
+    fn get_cop2(&mut self) -> Result<&mut dyn Cop2, Exception> {
+        if !self.is_cop2_usable() {
+            return Err(Exception::CoprocessorUnusable { coprocessor: 2 });
+        }
+        Ok(self.cop2.as_deref_mut().unwrap())
+    }
+    fn execute(&mut self, bus: &mut dyn Bus, instruction: &Instruction) -> Result<(), Exception> {
+        match *instruction {
+            Instruction::Nop => Ok(()),
+            Instruction::R {
+                rs,
+                rt,
+                rd,
+                shamt,
+                funct,
+                ..
+            } => self.execute_r(rs, rt, rd, shamt, funct),
+            // Immediates of some instructions are stored signed (like addi and loads) and of
+            // others unsigned (like addiu, andi, ori and xori), so they are taken as the 16 bits
+            // of the instruction. Each instruction then extends them as it needs.
+            Instruction::ISigned {
+                opcode,
+                rs,
+                rt,
+                immediate,
+            } => self.execute_i(bus, opcode, rs, rt, immediate as u16),
+            Instruction::IUnsigned {
+                opcode,
+                rs,
+                rt,
+                immediate,
+            } => self.execute_i(bus, opcode, rs, rt, immediate),
+            Instruction::J { opcode, address } => {
+                self.is_branch = true;
+                // Jumps stay in the 256 MB region of the delay slot, which self.pc is at.
+                let target = (self.pc & 0xF0000000) | (address << 2);
+                if opcode == 0b000011 {
+                    // jal, opcode 3
+                    self.set_register(31, self.next_pc);
+                }
+                self.branch(target);
+                Ok(())
+            }
+            Instruction::CopMove { opcode, rs, rt, rd } => match (opcode, rs) {
+                // mfc0
+                (0b010000, 0b00000) => {
+                    self.set_register_delayed(rt, self.cop0[rd as usize]);
+                    Ok(())
+                }
+                // mtc0
+                (0b010000, 0b00100) => {
+                    self.cop0[rd as usize] = self.get_register(rt);
+                    Ok(())
+                }
+                // mfc2
+                (0b010010, 0b00000) => {
+                    let value = self.get_cop2()?.read_data(rd);
+                    self.set_register_delayed(rt, value);
+                    Ok(())
+                }
+                // cfc2
+                (0b010010, 0b00010) => {
+                    let value = self.get_cop2()?.read_control(rd);
+                    self.set_register_delayed(rt, value);
+                    Ok(())
+                }
+                // mtc2
+                (0b010010, 0b00100) => {
+                    let value = self.get_register(rt);
+                    self.get_cop2()?.write_data(rd, value);
+                    Ok(())
+                }
+                // ctc2
+                (0b010010, 0b00110) => {
+                    let value = self.get_register(rt);
+                    self.get_cop2()?.write_control(rd, value);
+                    Ok(())
+                }
+                _ => Err(Exception::ReservedInstruction {
+                    machine_code: instruction.to_machine_code(),
+                }),
+            },
+            Instruction::Gte { command } => {
+                self.get_cop2()?.execute(command);
+                Ok(())
+            }
+            Instruction::Rfe => {
+                let sr = self.cop0[COP0_SR];
+                self.cop0[COP0_SR] = (sr & !0xF) | ((sr >> 2) & 0xF);
+                Ok(())
+            }
+        }
+    }
+    fn execute_r(&mut self, rs: u8, rt: u8, rd: u8, shamt: u8, funct: u8) -> Result<(), Exception> {
+        let s = self.get_register(rs);
+        let t = self.get_register(rt);
+        let value = match funct {
+            0b000000 => t << shamt,                        // sll, funct 0
+            0b000010 => t >> shamt,                        // srl, funct 2
+            0b000011 => ((t as i32) >> shamt) as u32,      // sra, funct 3
+            0b000100 => t << (s & 0x1F),                   // sllv, funct 4
+            0b000110 => t >> (s & 0x1F),                   // srlv, funct 6
+            0b000111 => ((t as i32) >> (s & 0x1F)) as u32, // srav, funct 7
+            // jr, funct 8
+            0b001000 => {
+                self.is_branch = true;
+                self.branch(s);
+                return Ok(());
+            }
+            // jalr, funct 9
+            0b001001 => {
+                self.is_branch = true;
+                self.set_register(rd, self.next_pc);
+                self.branch(s);
+                return Ok(());
+            }
+            0b001100 => return Err(Exception::Syscall), // syscall, funct 12
+            0b001101 => return Err(Exception::Break),   // break, funct 13
+            0b010000 => self.hi,                        // mfhi, funct 16
+            0b010010 => self.lo,                        // mflo, funct 18
+            // mthi, funct 17
+            0b010001 => {
+                self.hi = s;
+                return Ok(());
+            }
+            // mtlo, funct 19
+            0b010011 => {
+                self.lo = s;
+                return Ok(());
+            }
+            // mult, funct 24
+            0b011000 => {
+                let product = (s as i32 as i64) * (t as i32 as i64);
+                self.hi = (product >> 32) as u32;
+                self.lo = product as u32;
+                return Ok(());
+            }
+            // multu, funct 25
+            0b011001 => {
+                let product = (s as u64) * (t as u64);
+                self.hi = (product >> 32) as u32;
+                self.lo = product as u32;
+                return Ok(());
+            }
+            // div, funct 26
+            0b011010 => {
+                let (n, d) = (s as i32, t as i32);
+                (self.lo, self.hi) = match (n, d) {
+                    // Division by zero does not raise an exception, but gives garbage.
+                    (n, 0) if n >= 0 => (0xFFFFFFFF, n as u32),
+                    (n, 0) => (1, n as u32),
+                    (i32::MIN, -1) => (i32::MIN as u32, 0),
+                    (n, d) => ((n / d) as u32, (n % d) as u32),
+                };
+                return Ok(());
+            }
+            // divu, funct 27
+            0b011011 => {
+                (self.lo, self.hi) = match t {
+                    0 => (0xFFFFFFFF, s),
+                    t => (s / t, s % t),
+                };
+                return Ok(());
+            }
+            // add, funct 32
+            0b100000 => (s as i32)
+                .checked_add(t as i32)
+                .ok_or(Exception::Overflow)? as u32,
+            0b100001 => s.wrapping_add(t), // addu, funct 33
+            // sub, funct 34
+            0b100010 => (s as i32)
+                .checked_sub(t as i32)
+                .ok_or(Exception::Overflow)? as u32,
+            0b100011 => s.wrapping_sub(t), // subu, funct 35
+            0b100100 => s & t,             // and, funct 36
+            0b100101 => s | t,             // or, funct 37
+            0b100110 => s ^ t,             // xor, funct 38
+            0b100111 => !(s | t),          // nor, funct 39
+            0b101010 => ((s as i32) < (t as i32)) as u32, // slt, funct 42
+            0b101011 => (s < t) as u32,    // sltu, funct 43
+            _ => unreachable!("Unknown funct {} of a parsed R instruction", funct),
+        };
+        self.set_register(rd, value);
+        Ok(())
+    }
+    fn execute_i(
+        &mut self,
+        bus: &mut dyn Bus,
+        opcode: u8,
+        rs: u8,
+        rt: u8,
+        immediate: u16,
+    ) -> Result<(), Exception> {
+        let s = self.get_register(rs);
+        let t = self.get_register(rt);
+        let signed_immediate = immediate as i16 as u32;
+        let address = s.wrapping_add(signed_immediate);
+        // Branch targets are relative to the delay slot, which self.pc is at.
+        let branch_target = self.pc.wrapping_add(signed_immediate << 2);
+        match opcode {
+            // bltz, bgez, bltzal and bgezal (REGIMM), opcode 1
+            0b000001 => {
+                self.is_branch = true;
+                let is_taken = match rt & 1 {
+                    0 => (s as i32) < 0,
+                    _ => (s as i32) >= 0,
+                };
+                // Return address is written even if the branch is not taken.
+                if rt & 0b10000 != 0 {
+                    self.set_register(31, self.next_pc);
+                }
+                if is_taken {
+                    self.branch(branch_target);
+                }
+            }
+            // beq, bne, blez and bgtz, opcodes 4-7
+            0b000100..=0b000111 => {
+                self.is_branch = true;
+                let is_taken = match opcode {
+                    0b000100 => s == t,
+                    0b000101 => s != t,
+                    0b000110 => (s as i32) <= 0,
+                    _ => (s as i32) > 0,
+                };
+                if is_taken {
+                    self.branch(branch_target);
+                }
+            }
+            // addi, opcode 8
+            0b001000 => {
+                let value = (s as i32)
+                    .checked_add(signed_immediate as i32)
+                    .ok_or(Exception::Overflow)?;
+                self.set_register(rt, value as u32);
+            }
+            0b001001 => self.set_register(rt, s.wrapping_add(signed_immediate)), // addiu, opcode 9
+            0b001010 => self.set_register(rt, ((s as i32) < signed_immediate as i32) as u32), // slti, opcode 10
+            0b001011 => self.set_register(rt, (s < signed_immediate) as u32), // sltiu, opcode 11
+            0b001100 => self.set_register(rt, s & immediate as u32),          // andi, opcode 12
+            0b001101 => self.set_register(rt, s | immediate as u32),          // ori, opcode 13
+            0b001110 => self.set_register(rt, s ^ immediate as u32),          // xori, opcode 14
+            0b001111 => self.set_register(rt, (immediate as u32) << 16),      // lui, opcode 15
+            // lb, opcode 32
+            0b100000 => {
+                let value = bus
+                    .read_u8(address)
+                    .ok_or(Exception::BusErrorData { address })?;
+                self.set_register_delayed(rt, value as i8 as u32);
+            }
+            // lh, opcode 33
+            0b100001 => {
+                check_alignment(address, 2, false)?;
+                let value = bus
+                    .read_u16(address)
+                    .ok_or(Exception::BusErrorData { address })?;
+                self.set_register_delayed(rt, value as i16 as u32);
+            }
+            // lwl, opcode 34
+            0b100010 => {
+                let word = read_aligned_word(bus, address)?;
+                let current = self.get_register_with_load(rt);
+                let value = match address & 3 {
+                    0 => (current & 0x00FFFFFF) | (word << 24),
+                    1 => (current & 0x0000FFFF) | (word << 16),
+                    2 => (current & 0x000000FF) | (word << 8),
+                    _ => word,
+                };
+                self.set_register_delayed(rt, value);
+            }
+            // lw, opcode 35
+            0b100011 => {
+                check_alignment(address, 4, false)?;
+                let value = bus
+                    .read_u32(address)
+                    .ok_or(Exception::BusErrorData { address })?;
+                self.set_register_delayed(rt, value);
+            }
+            // lbu, opcode 36
+            0b100100 => {
+                let value = bus
+                    .read_u8(address)
+                    .ok_or(Exception::BusErrorData { address })?;
+                self.set_register_delayed(rt, value as u32);
+            }
+            // lhu, opcode 37
+            0b100101 => {
+                check_alignment(address, 2, false)?;
+                let value = bus
+                    .read_u16(address)
+                    .ok_or(Exception::BusErrorData { address })?;
+                self.set_register_delayed(rt, value as u32);
+            }
+            // lwr, opcode 38
+            0b100110 => {
+                let word = read_aligned_word(bus, address)?;
+                let current = self.get_register_with_load(rt);
+                let value = match address & 3 {
+                    0 => word,
+                    1 => (current & 0xFF000000) | (word >> 8),
+                    2 => (current & 0xFFFF0000) | (word >> 16),
+                    _ => (current & 0xFFFFFF00) | (word >> 24),
+                };
+                self.set_register_delayed(rt, value);
+            }
+            // sb, opcode 40
+            0b101000 => self.write(bus, address, |bus| bus.write_u8(address, t as u8))?,
+            // sh, opcode 41
+            0b101001 => {
+                check_alignment(address, 2, true)?;
+                self.write(bus, address, |bus| bus.write_u16(address, t as u16))?;
+            }
+            // swl, opcode 42
+            0b101010 => {
+                let word = read_aligned_word(bus, address)?;
+                let value = match address & 3 {
+                    0 => (word & 0xFFFFFF00) | (t >> 24),
+                    1 => (word & 0xFFFF0000) | (t >> 16),
+                    2 => (word & 0xFF000000) | (t >> 8),
+                    _ => t,
+                };
+                self.write(bus, address & !3, |bus| bus.write_u32(address & !3, value))?;
+            }
+            // sw, opcode 43
+            0b101011 => {
+                check_alignment(address, 4, true)?;
+                self.write(bus, address, |bus| bus.write_u32(address, t))?;
+            }
+            // swr, opcode 46
+            0b101110 => {
+                let word = read_aligned_word(bus, address)?;
+                let value = match address & 3 {
+                    0 => t,
+                    1 => (word & 0x000000FF) | (t << 8),
+                    2 => (word & 0x0000FFFF) | (t << 16),
+                    _ => (word & 0x00FFFFFF) | (t << 24),
+                };
+                self.write(bus, address & !3, |bus| bus.write_u32(address & !3, value))?;
+            }
+            // lwc2, opcode 50
+            0b110010 => {
+                self.get_cop2()?;
+                check_alignment(address, 4, false)?;
+                let value = bus
+                    .read_u32(address)
+                    .ok_or(Exception::BusErrorData { address })?;
+                self.get_cop2()?.write_data(rt, value);
+            }
+            // swc2, opcode 58
+            0b111010 => {
+                let value = self.get_cop2()?.read_data(rt);
+                check_alignment(address, 4, true)?;
+                self.write(bus, address, |bus| bus.write_u32(address, value))?;
+            }
+            _ => unreachable!("Unknown opcode {} of a parsed I instruction", opcode),
+        }
+        Ok(())
+    }
+    /// Gets a register including a load in its delay slot, which lwl and lwr merge with.
+    fn get_register_with_load(&self, register: u8) -> u32 {
+        match self.load_delay {
+            Some((load_register, value)) if load_register == register => value,
+            _ => self.get_register(register),
+        }
+    }
+    /// Writes into the bus, unless the cache is isolated (bit 16 of SR), in which case
+    /// writes go into the cache, like when BIOS flushes it.
+    fn write(
+        &mut self,
+        bus: &mut dyn Bus,
+        address: u32,
+        write: impl FnOnce(&mut dyn Bus) -> Option<()>,
+    ) -> Result<(), Exception> {
+        if self.cop0[COP0_SR] & (1 << 16) != 0 {
+            return Ok(());
+        }
+        write(bus).ok_or(Exception::BusErrorData { address })
+    }
+}
+
+/// Reads the aligned word containing an address, which lwl, lwr, swl and swr merge with.
+fn read_aligned_word(bus: &mut dyn Bus, address: u32) -> Result<u32, Exception> {
+    let address = address & !3;
+    bus.read_u32(address)
+        .ok_or(Exception::BusErrorData { address })
+}
+fn check_alignment(address: u32, size: u32, is_store: bool) -> Result<(), Exception> {
+    match (address % size, is_store) {
+        (0, _) => Ok(()),
+        (_, false) => Err(Exception::AddressErrorLoad { address }),
+        (_, true) => Err(Exception::AddressErrorStore { address }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Memory;
+
+    const CODE_ADDRESS: u32 = 0x80010000;
+    const DATA_ADDRESS: u32 = 0x80020000;
+    // Registers by their number.
+    const A0: usize = 4;
+    const A1: usize = 5;
+    const V0: usize = 2;
+    const V1: usize = 3;
+    const T0: usize = 8;
+    const T1: usize = 9;
+    const T2: usize = 10;
+    const T3: usize = 11;
+    const T4: usize = 12;
+
+    /// Calls code written one instruction per line, after setting registers with a given function.
+    fn call(code: &str, setup: impl FnOnce(&mut Cpu)) -> (Cpu, Memory, Result<u64, String>) {
+        let mut memory = Memory::new();
+        for (i, line) in code.lines().enumerate() {
+            let instruction = Instruction::parse_from_str(line.trim()).unwrap();
+            memory
+                .write_u32(CODE_ADDRESS + i as u32 * 4, instruction.to_machine_code())
+                .unwrap();
+        }
+        memory.load(
+            DATA_ADDRESS,
+            &[0x78, 0x56, 0x34, 0x12, 0xF0, 0xDE, 0xBC, 0x9A],
+        );
+        let mut cpu = Cpu::new(0);
+        cpu.registers[T0] = DATA_ADDRESS;
+        setup(&mut cpu);
+        let result = cpu.call(&mut memory, CODE_ADDRESS, 100);
+        (cpu, memory, result)
+    }
+
+    #[test]
+    fn run_load_delay_slots() {
+        let (cpu, _, result) = call(
+            "lw t1, 0(t0)\naddu t2, t1, zero\naddu t3, t1, zero\njr ra\nnop",
+            |cpu| cpu.registers[T1] = 1,
+        );
+        assert_eq!(result, Ok(5));
+        // The instruction in the delay slot still sees the old value.
+        assert_eq!(cpu.registers[T2], 1);
+        assert_eq!(cpu.registers[T3], 0x12345678);
+
+        // A write in the delay slot wins over the load.
+        let (cpu, _, _) = call("lw t1, 0(t0)\naddiu t1, zero, 5\njr ra\nnop", |_| {});
+        assert_eq!(cpu.registers[T1], 5);
+
+        // lwl in the delay slot of lwr merges with the loaded value.
+        let (cpu, _, _) = call("lwr t4, 1(t0)\nlwl t4, 4(t0)\njr ra\nnop", |cpu| {
+            cpu.registers[T4] = 0xAAAAAAAA
+        });
+        assert_eq!(cpu.registers[T4], 0xF0123456);
+
+        // Stores are not delayed, and a load of the stored word sees it.
+        let (cpu, mut memory, _) = call(
+            "sh t1, 2(t0)\nswl t1, 5(t0)\nlhu v0, 2(t0)\nlb v1, 7(t0)\njr ra\nnop",
+            |cpu| cpu.registers[T1] = 0x8899AABB,
+        );
+        assert_eq!(cpu.registers[V0], 0xAABB);
+        assert_eq!(cpu.registers[V1], 0xFFFFFF9A);
+        assert_eq!(memory.read_u32(DATA_ADDRESS + 4), Some(0x9ABC8899));
+    }
+
+    #[test]
+    fn run_branch_delay_slots() {
+        let (cpu, _, result) = call(
+            "beq zero, zero, 2\naddiu v0, zero, 1\naddiu v0, zero, 2\njr ra\naddiu v1, zero, 3",
+            |_| {},
+        );
+        assert_eq!(result, Ok(4));
+        assert_eq!((cpu.registers[V0], cpu.registers[V1]), (1, 3));
+
+        // bgezal links even when the branch is not taken.
+        let (cpu, _, _) = call("addu t1, ra, zero\nbgezal a0, 10\nnop\njr t1\nnop", |cpu| {
+            cpu.registers[A0] = 0xFFFFFFFF
+        });
+        assert_eq!(cpu.registers[31], CODE_ADDRESS + 12);
+
+        // jal returns after its delay slot.
+        let (cpu, _, result) = call(
+            "addu t1, ra, zero\njal 16389\naddiu v0, zero, 1\njr t1\nnop\njr ra\naddiu v1, v0, 1",
+            |_| {},
+        );
+        assert_eq!(result, Ok(7));
+        assert_eq!((cpu.registers[V0], cpu.registers[V1]), (1, 2));
+    }
+
+    #[test]
+    fn run_arithmetic() {
+        let (cpu, _, _) = call(
+            "mult a0, a1\nmflo v0\nmfhi v1\ndiv a0, zero\nmflo t1\nmfhi t2\ndivu a1, a0\nmflo t3\njr ra\nsra t4, a0, 4",
+            |cpu| {
+                cpu.registers[A0] = -7i32 as u32;
+                cpu.registers[A1] = 3;
+            },
+        );
+        assert_eq!(
+            (cpu.registers[V0], cpu.registers[V1]),
+            (-21i32 as u32, 0xFFFFFFFF)
+        );
+        // Division by zero gives -1 or 1 by the sign of the dividend, and the dividend as remainder.
+        assert_eq!((cpu.registers[T1], cpu.registers[T2]), (1, -7i32 as u32));
+        assert_eq!(cpu.registers[T3], 0);
+        assert_eq!(cpu.registers[T4], 0xFFFFFFFF);
+
+        // Logical immediates are extended with zeros, unlike addiu which sign-extends.
+        let (cpu, _, _) = call(
+            "andi v0, a0, 0xFFFF\nori v1, zero, 0x8000\nxori t0, a0, 0xFFFF\njr ra\naddiu t1, zero, 0x8000",
+            |cpu| {
+                cpu.registers[A0] = 0x12345678;
+            },
+        );
+        assert_eq!((cpu.registers[V0], cpu.registers[V1]), (0x5678, 0x8000));
+        assert_eq!(
+            (cpu.registers[T0], cpu.registers[T1]),
+            (0x1234A987, 0xFFFF8000)
+        );
+    }
+
+    #[test]
+    fn raise_exceptions() {
+        let (cpu, _, result) = call("addi v0, a0, 1\njr ra\nnop", |cpu| {
+            cpu.registers[A0] = 0x7FFFFFFF;
+            cpu.registers[V0] = 9;
+        });
+        assert_eq!(
+            result,
+            Err(String::from(
+                "Arithmetic overflow at 0x80010000 in function at 0x80010000."
+            ))
+        );
+        // Destination register is left as is, and the CPU is in the exception vector.
+        assert_eq!(cpu.registers[V0], 9);
+        assert_eq!(cpu.pc, 0x80000080);
+        assert_eq!(cpu.cop0[COP0_CAUSE], 12 << 2);
+        assert_eq!(cpu.cop0[COP0_EPC], CODE_ADDRESS);
+
+        // Exceptions in a delay slot set BD and return to the branch.
+        let (cpu, _, result) = call("jr ra\nsub v0, a0, a1", |cpu| {
+            cpu.registers[A0] = 0x80000000;
+            cpu.registers[A1] = 1;
+        });
+        assert!(result.is_err());
+        assert_eq!(cpu.cop0[COP0_CAUSE], (1 << 31) | (12 << 2));
+        assert_eq!(cpu.cop0[COP0_EPC], CODE_ADDRESS);
+
+        let (cpu, _, result) = call("lw v0, 2(t0)\njr ra\nnop", |_| {});
+        assert!(result
+            .unwrap_err()
+            .starts_with("Address error loading from 0x80020002"));
+        assert_eq!(cpu.cop0[COP0_BADVADDR], DATA_ADDRESS + 2);
+
+        let (cpu, _, result) = call("sw zero, 0(a0)\njr ra\nnop", |cpu| {
+            cpu.registers[A0] = 0x1F801070
+        });
+        assert!(result
+            .unwrap_err()
+            .starts_with("Bus error accessing 0x1F801070"));
+        assert_eq!(cpu.cop0[COP0_CAUSE], 7 << 2);
+
+        let (cpu, _, result) = call("mtc2 v0, vz0\njr ra\nnop", |_| {});
+        assert!(result.unwrap_err().starts_with("Coprocessor 2 unusable"));
+        assert_eq!(cpu.cop0[COP0_CAUSE], (2 << 28) | (11 << 2));
+
+        let (_, _, result) = call("beq zero, zero, -1\nnop", |_| {});
+        assert!(result
+            .unwrap_err()
+            .contains("did not return after 100 instructions"));
+    }
+}
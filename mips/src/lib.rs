@@ -1,125 +1,279 @@
-use core::panic;
-
-macro_rules! define_i_signed_instruction_parse {
-    ($parts:ident, $opcode:literal) => {
-        match $parts[1..] {
-            [rt, rs, immediate] => {
-                let rt = parse_register_value(rt).map_err(|e| e.to_string())?;
-                let rs = parse_register_value(rs).map_err(|e| e.to_string())?;
-                let immediate = parse_immediate_signed(immediate).map_err(|e| e.to_string())?;
-                Ok(Instruction::ISigned {
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::PathBuf;
+
+mod bus;
+mod cop;
+mod data;
+mod error;
+mod gte;
+mod hi_lo;
+mod interpreter;
+mod macros;
+mod pseudo;
+
+pub use bus::{Bus, Memory};
+use cop::{
+    format_cop_register, format_gte_command, parse_cop_register, parse_gte_command, COP0_REGISTERS,
+    GTE_CONTROL_REGISTERS, GTE_DATA_REGISTERS,
+};
+use data::strip_comment;
+pub use data::{encode_text, encode_text_with, push_escaped_byte, push_escaped_char};
+pub use error::{Error, Span};
+pub use gte::Gte;
+pub use hi_lo::{find_hi_lo_pairs, HiLoPair};
+pub use interpreter::{
+    Cop2, Cpu, Exception, CALL_RETURN_ADDRESS, COP0_BADVADDR, COP0_CAUSE, COP0_EPC, COP0_PRID,
+    COP0_SR,
+};
+use macros::expand_macros;
+use pseudo::parse_pseudo_instruction;
+pub use pseudo::{fold_pseudo_instruction, MAX_PSEUDO_INSTRUCTION_LEN};
+
+/// Parses an I instruction written either as "<rt>, <rs>, <immediate>"
+/// or as "<rt>, <immediate>(<rs>)".
+macro_rules! define_i_instruction_parse {
+    ($tokens:ident, $variant:ident, $parse_immediate:ident, $opcode:literal) => {
+        match $tokens.len() - 1 {
+            3 => {
+                let [rt, rs, immediate] = get_operands($tokens)?;
+                Ok(Instruction::$variant {
                     opcode: $opcode,
-                    rs,
-                    rt,
-                    immediate,
+                    rs: parse_register(rs)?,
+                    rt: parse_register(rt)?,
+                    immediate: $parse_immediate(immediate)?,
                 })
             }
-            [rt, relative_value] => {
-                let rt = parse_register_value(rt).unwrap_or_else(|e| panic!("{}", e));
-
-                let relative_value_parts = relative_value.split("(").collect::<Vec<_>>();
-
-                let immediate = relative_value_parts.get(0).unwrap_or_else(|| {
-                    panic!(
-                        "Missing immediate in relative value parts {:?}",
-                        relative_value_parts
-                    )
-                });
-                let immediate =
-                    parse_immediate_signed(immediate).unwrap_or_else(|e| panic!("{}", e));
-
-                let rs = relative_value_parts.get(1).unwrap_or_else(|| {
-                    panic!(
-                        "Missing rs in relative value parts {:?}",
-                        relative_value_parts
-                    )
-                });
-                let rs = rs.replace(")", "");
-                let rs = parse_register_value(&rs).unwrap_or_else(|e| panic!("{}", e));
-
-                Ok(Instruction::ISigned {
+            2 => {
+                let [rt, relative_value] = get_operands($tokens)?;
+                let (immediate, rs) = parse_relative_value(relative_value)?;
+                Ok(Instruction::$variant {
                     opcode: $opcode,
-                    rs,
-                    rt,
-                    immediate,
+                    rs: parse_register(rs)?,
+                    rt: parse_register(rt)?,
+                    immediate: $parse_immediate(immediate)?,
                 })
             }
-            _ => panic!("Unknown structure for instruction \"{}\"", $parts[0]),
+            _ => Err(get_wrong_operand_count_error($tokens, &[3, 2])),
         }
     };
 }
-macro_rules! define_i_unsigned_instruction_parse {
-    ($parts:ident, $opcode:literal) => {
-        match $parts[1..] {
-            [rt, rs, immediate] => {
-                let rs = parse_register_value(rs).map_err(|e| e.to_string())?;
-                let rt = parse_register_value(rt).map_err(|e| e.to_string())?;
-                let immediate = parse_immediate_unsigned(immediate).map_err(|e| e.to_string())?;
-                Ok(Instruction::IUnsigned {
+/// Parses a load or store instruction written either as "<rt>, <immediate>, <rs>"
+/// or as "<rt>, <immediate>(<rs>)".
+macro_rules! define_load_store_instruction_parse {
+    ($tokens:ident, $variant:ident, $parse_immediate:ident, $opcode:literal) => {
+        match $tokens.len() - 1 {
+            3 => {
+                let [rt, immediate, rs] = get_operands($tokens)?;
+                Ok(Instruction::$variant {
                     opcode: $opcode,
-                    rs,
-                    rt,
-                    immediate,
+                    rs: parse_register(rs)?,
+                    rt: parse_register(rt)?,
+                    immediate: $parse_immediate(immediate)?,
                 })
             }
-            [rt, relative_value] => {
-                let rt = parse_register_value(rt).unwrap_or_else(|e| panic!("{}", e));
-
-                let relative_value_parts = relative_value.split("(").collect::<Vec<_>>();
-
-                let immediate = relative_value_parts.get(0).unwrap_or_else(|| {
-                    panic!(
-                        "Missing immediate in relative value parts {:?}",
-                        relative_value_parts
-                    )
-                });
-                let immediate =
-                    parse_immediate_unsigned(immediate).unwrap_or_else(|e| panic!("{}", e));
-
-                let rs = relative_value_parts.get(1).unwrap_or_else(|| {
-                    panic!(
-                        "Missing rs in relative value parts {:?}",
-                        relative_value_parts
-                    )
-                });
-                let rs = rs.replace(")", "");
-                let rs = parse_register_value(&rs).unwrap_or_else(|e| panic!("{}", e));
-
-                Ok(Instruction::IUnsigned {
+            2 => {
+                let [rt, relative_value] = get_operands($tokens)?;
+                let (immediate, rs) = parse_relative_value(relative_value)?;
+                Ok(Instruction::$variant {
                     opcode: $opcode,
-                    rs,
-                    rt,
-                    immediate,
+                    rs: parse_register(rs)?,
+                    rt: parse_register(rt)?,
+                    immediate: $parse_immediate(immediate)?,
                 })
             }
-            _ => panic!("Unknown structure for instruction \"{}\"", $parts[0]),
+            _ => Err(get_wrong_operand_count_error($tokens, &[3, 2])),
         }
     };
 }
+/// Parses a branch instruction comparing two registers, like beq.
+macro_rules! define_branch_instruction_parse {
+    ($tokens:ident, $opcode:literal) => {{
+        let [rs, rt, address] = get_operands($tokens)?;
+        Ok(Instruction::ISigned {
+            opcode: $opcode,
+            rs: parse_register(rs)?,
+            rt: parse_register(rt)?,
+            immediate: parse_immediate_i16(address)?,
+        })
+    }};
+}
+/// Parses a branch instruction comparing a register to zero, like bgtz.
+macro_rules! define_branch_zero_instruction_parse {
+    ($tokens:ident, $opcode:literal) => {{
+        let [rs, address] = get_operands($tokens)?;
+        Ok(Instruction::ISigned {
+            opcode: $opcode,
+            rs: parse_register(rs)?,
+            rt: 0,
+            immediate: parse_immediate_i16(address)?,
+        })
+    }};
+}
+macro_rules! define_j_instruction_parse {
+    ($tokens:ident, $opcode:literal) => {{
+        let [address] = get_operands($tokens)?;
+        Ok(Instruction::J {
+            opcode: $opcode,
+            address: parse_immediate(address, 0, 0x3FFFFFF)? as u32,
+        })
+    }};
+}
 macro_rules! define_r_instruction_parse {
-    ($parts:ident, $funct:literal) => {
-        match $parts[1..] {
-            [rd, rs, rt] => {
-                let rd = parse_register_value(rd).map_err(|e| e.to_string())?;
-                let rs = parse_register_value(rs).map_err(|e| e.to_string())?;
-                let rt = parse_register_value(rt).map_err(|e| e.to_string())?;
+    ($tokens:ident, $funct:literal) => {{
+        let [rd, rs, rt] = get_operands($tokens)?;
+        Ok(Instruction::R {
+            opcode: 0,
+            rs: parse_register(rs)?,
+            rt: parse_register(rt)?,
+            rd: parse_register(rd)?,
+            shamt: 0,
+            funct: $funct,
+        })
+    }};
+}
+macro_rules! define_r_shift_instruction_parse {
+    ($tokens:ident, $funct:literal) => {{
+        let [rd, rt, shamt] = get_operands($tokens)?;
+        Ok(Instruction::R {
+            opcode: 0,
+            rs: 0,
+            rt: parse_register(rt)?,
+            rd: parse_register(rd)?,
+            shamt: parse_immediate(shamt, 0, 31)? as u8,
+            funct: $funct,
+        })
+    }};
+}
+macro_rules! define_r_shift_variable_instruction_parse {
+    ($tokens:ident, $funct:literal) => {{
+        let [rd, rt, rs] = get_operands($tokens)?;
+        Ok(Instruction::R {
+            opcode: 0,
+            rs: parse_register(rs)?,
+            rt: parse_register(rt)?,
+            rd: parse_register(rd)?,
+            shamt: 0,
+            funct: $funct,
+        })
+    }};
+}
+macro_rules! define_r_mult_div_instruction_parse {
+    ($tokens:ident, $funct:literal) => {{
+        let [rs, rt] = get_operands($tokens)?;
+        Ok(Instruction::R {
+            opcode: 0,
+            rs: parse_register(rs)?,
+            rt: parse_register(rt)?,
+            rd: 0,
+            shamt: 0,
+            funct: $funct,
+        })
+    }};
+}
+macro_rules! define_r_move_from_instruction_parse {
+    ($tokens:ident, $funct:literal) => {{
+        let [rd] = get_operands($tokens)?;
+        Ok(Instruction::R {
+            opcode: 0,
+            rs: 0,
+            rt: 0,
+            rd: parse_register(rd)?,
+            shamt: 0,
+            funct: $funct,
+        })
+    }};
+}
+macro_rules! define_r_move_to_instruction_parse {
+    ($tokens:ident, $funct:literal) => {{
+        let [rs] = get_operands($tokens)?;
+        Ok(Instruction::R {
+            opcode: 0,
+            rs: parse_register(rs)?,
+            rt: 0,
+            rd: 0,
+            shamt: 0,
+            funct: $funct,
+        })
+    }};
+}
+/// Parses an instruction with an optional 20-bit code (syscall and break).
+/// The code is stored in bits 6-25, which are the same bits as rs, rt, rd and shamt.
+macro_rules! define_r_code_instruction_parse {
+    ($tokens:ident, $funct:literal) => {
+        match $tokens.len() - 1 {
+            0 => Ok(Instruction::R {
+                opcode: 0,
+                rs: 0,
+                rt: 0,
+                rd: 0,
+                shamt: 0,
+                funct: $funct,
+            }),
+            1 => {
+                let [code] = get_operands($tokens)?;
+                let code = parse_immediate(code, 0, 0xFFFFF)?;
                 Ok(Instruction::R {
                     opcode: 0,
-                    rs,
-                    rt,
-                    rd,
-                    shamt: 0,
+                    rs: ((code >> 15) & 0b11111) as u8,
+                    rt: ((code >> 10) & 0b11111) as u8,
+                    rd: ((code >> 5) & 0b11111) as u8,
+                    shamt: (code & 0b11111) as u8,
                     funct: $funct,
                 })
             }
-            _ => panic!("Unknown structure for instruction \"{}\"", $parts[0]),
+            _ => Err(get_wrong_operand_count_error($tokens, &[0, 1])),
         }
     };
 }
+/// Parses a branch instruction of REGIMM instructions (opcode 1),
+/// which are told apart from each other by the rt field.
+macro_rules! define_regimm_instruction_parse {
+    ($tokens:ident, $rt:literal) => {{
+        let [rs, address] = get_operands($tokens)?;
+        Ok(Instruction::ISigned {
+            opcode: 0b000001, // Opcode is 1
+            rs: parse_register(rs)?,
+            rt: $rt,
+            immediate: parse_immediate_i16(address)?,
+        })
+    }};
+}
+macro_rules! define_cop_move_instruction_parse {
+    ($tokens:ident, $opcode:literal, $rs:literal, $registers:ident) => {{
+        let [rt, rd] = get_operands($tokens)?;
+        Ok(Instruction::CopMove {
+            opcode: $opcode,
+            rs: $rs,
+            rt: parse_register(rt)?,
+            rd: parse_cop_register_token($registers, rd)?,
+        })
+    }};
+}
+macro_rules! define_cop2_load_store_instruction_parse {
+    ($tokens:ident, $opcode:literal) => {{
+        let [rt, relative_value] = get_operands($tokens)?;
+        let (immediate, rs) = parse_relative_value(relative_value)?;
+        Ok(Instruction::ISigned {
+            opcode: $opcode,
+            rs: parse_register(rs)?,
+            rt: parse_cop_register_token(GTE_DATA_REGISTERS, rt)?,
+            immediate: parse_immediate_i16(immediate)?,
+        })
+    }};
+}
 
-pub const KEYWORD_ADDR: &'static str = "addr";
-pub const KEYWORD_CONST: &'static str = "const";
-const REGISTERS: &[&'static str; 32] = &[
+/// Instructions whose last operand is a branch or jump target.
+/// Targets may be written as labels or as absolute addresses ("@<address>")
+/// in addition to raw values of the immediate or address field.
+const TARGET_MNEMONICS: &[&str] = &[
+    "beq", "bgez", "bgezal", "bgtz", "blez", "bltz", "bltzal", "bne", "j", "jal",
+];
+
+pub const KEYWORD_ADDR: &str = "addr";
+pub const KEYWORD_CONST: &str = "const";
+/// Names of general purpose registers by number.
+pub const REGISTERS: &[&str; 32] = &[
     "zero", // Constant 0
     "at",   // Assembler temporary (reserved for assembler)
     "v0",   // Return value, stores result of a function call
@@ -154,8 +308,22 @@ const REGISTERS: &[&'static str; 32] = &[
     "ra",   // Return address
 ];
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Instruction {
+    /// Coprocessor register move (like mfc0 and mtc2).
+    /// Opcode tells the coprocessor and rs tells the kind of move.
+    CopMove {
+        opcode: u8,
+        rs: u8,
+        /// General purpose register.
+        rt: u8,
+        /// Coprocessor register.
+        rd: u8,
+    },
+    /// GTE (COP2) command. Stores bits 0-24 of the instruction.
+    Gte {
+        command: u32,
+    },
     ISigned {
         opcode: u8,
         rs: u8,
@@ -182,17 +350,20 @@ pub enum Instruction {
         shamt: u8,
         funct: u8,
     },
+    /// Return from exception (COP0).
+    Rfe,
 }
 
 impl Instruction {
-    pub fn parse_from_be_bytes(content: &[u8; 4]) -> Self {
+    pub fn parse_from_be_bytes(content: &[u8; 4]) -> Result<Self, Error> {
         if *content == [0, 0, 0, 0] {
-            return Instruction::Nop;
+            return Ok(Instruction::Nop);
         }
 
         let machine_code = u32::from_be_bytes(*content);
+        let unknown_opcode_error = Error::UnknownOpcode { machine_code };
         let opcode = (machine_code >> 26) as u8;
-        match opcode {
+        let instruction = match opcode {
             // R-type instruction where opcode is always 0
             0 => {
                 let rs = ((machine_code >> 21) & 0b11111) as u8;
@@ -201,22 +372,33 @@ impl Instruction {
                 let shamt = ((machine_code >> 6) & 0b11111) as u8;
                 let funct = (machine_code & 0b111111) as u8;
 
-                Instruction::R {
-                    opcode,
-                    rs,
-                    rt,
-                    rd,
-                    shamt,
-                    funct,
+                match funct {
+                    0 | 2..=4 | 6..=9 | 12 | 13 | 16..=19 | 24..=27 | 32..=39 | 42 | 43 => {
+                        Instruction::R {
+                            opcode,
+                            rs,
+                            rt,
+                            rd,
+                            shamt,
+                            funct,
+                        }
+                    }
+                    _ => return Err(unknown_opcode_error),
                 }
             }
             0b001000 => parse_i_signed_instruction(opcode, machine_code), // addi, opcode 8
             0b001001 => parse_i_unsigned_instruction(opcode, machine_code), // addiu, opcode 9
-            0b001100 => parse_i_signed_instruction(opcode, machine_code), // andi, opcode 12
+            0b001100 => parse_i_unsigned_instruction(opcode, machine_code), // andi, opcode 12
             0b000100 => parse_i_signed_instruction(opcode, machine_code), // beq, opcode 4
             0b000111 => parse_i_signed_instruction(opcode, machine_code), // bgtz, opcode 7
             0b000110 => parse_i_signed_instruction(opcode, machine_code), // blez, opcode 6
-            0b000001 => parse_i_signed_instruction(opcode, machine_code), // bltz, opcode 1
+            // bltz, bgez, bltzal and bgezal (REGIMM), opcode 1
+            0b000001 => match (machine_code >> 16) & 0b11111 {
+                0b00000 | 0b00001 | 0b10000 | 0b10001 => {
+                    parse_i_signed_instruction(opcode, machine_code)
+                }
+                _ => return Err(unknown_opcode_error),
+            },
             0b000101 => parse_i_signed_instruction(opcode, machine_code), // bne, opcode 5
             // j, opcode 2
             0b000010 => {
@@ -244,536 +426,448 @@ impl Instruction {
             0b100001 => parse_i_signed_instruction(opcode, machine_code), // lh, opcode 33
             0b100101 => parse_i_unsigned_instruction(opcode, machine_code), // lhu, opcode 37
             0b100011 => parse_i_signed_instruction(opcode, machine_code), // lw, opcode 35
-            0b001101 => parse_i_signed_instruction(opcode, machine_code), // ori, opcode 13
+            0b001101 => parse_i_unsigned_instruction(opcode, machine_code), // ori, opcode 13
             0b101000 => parse_i_signed_instruction(opcode, machine_code), // sb, opcode 40
             0b101001 => parse_i_signed_instruction(opcode, machine_code), // sh, opcode 41
             0b001010 => parse_i_signed_instruction(opcode, machine_code), // slti, opcode 10
             0b001011 => parse_i_unsigned_instruction(opcode, machine_code), // sltiu, opcode 11
             0b101011 => parse_i_signed_instruction(opcode, machine_code), // sw, opcode 43
-            _ => panic!(
-                "Unknown opcode {} ({:b} in bin) in parsing from BE bytes",
-                opcode, opcode
-            ),
-        }
+            0b001110 => parse_i_unsigned_instruction(opcode, machine_code), // xori, opcode 14
+            0b100010 => parse_i_signed_instruction(opcode, machine_code), // lwl, opcode 34
+            0b100110 => parse_i_signed_instruction(opcode, machine_code), // lwr, opcode 38
+            0b101010 => parse_i_signed_instruction(opcode, machine_code), // swl, opcode 42
+            0b101110 => parse_i_signed_instruction(opcode, machine_code), // swr, opcode 46
+            // mfc0, mtc0 and rfe (COP0), opcode 16
+            0b010000 => {
+                let rs = ((machine_code >> 21) & 0b11111) as u8;
+                match rs {
+                    0b10000 if machine_code & 0x1FFFFFF == 0b010000 => Instruction::Rfe,
+                    0b00000 | 0b00100 => parse_cop_move_instruction(opcode, machine_code)?,
+                    _ => return Err(unknown_opcode_error),
+                }
+            }
+            // mfc2, cfc2, mtc2, ctc2 and GTE commands (COP2), opcode 18
+            0b010010 => {
+                let rs = ((machine_code >> 21) & 0b11111) as u8;
+                match rs {
+                    0b10000..=0b11111 => Instruction::Gte {
+                        command: machine_code & 0x1FFFFFF,
+                    },
+                    0b00000 | 0b00010 | 0b00100 | 0b00110 => {
+                        parse_cop_move_instruction(opcode, machine_code)?
+                    }
+                    _ => return Err(unknown_opcode_error),
+                }
+            }
+            0b110010 => parse_i_signed_instruction(opcode, machine_code), // lwc2, opcode 50
+            0b111010 => parse_i_signed_instruction(opcode, machine_code), // swc2, opcode 58
+            _ => return Err(unknown_opcode_error),
+        };
+        Ok(instruction)
     }
     #[inline]
-    pub fn parse_from_machine_code(machine_code: u32) -> Self {
+    pub fn parse_from_machine_code(machine_code: u32) -> Result<Self, Error> {
         Self::parse_from_be_bytes(&machine_code.to_be_bytes())
     }
-    pub fn parse_from_le_bytes(content: &[u8; 4]) -> Self {
-        let mut content_reversed = content.clone();
+    /// Same as [Instruction::parse_from_machine_code], but fails if assembly code of the instruction
+    /// would not give back the same machine code, because bits are set in fields the instruction
+    /// does not use. Such machine code is better kept as data, for example in disassembly.
+    pub fn parse_from_machine_code_exact(machine_code: u32) -> Result<Self, Error> {
+        let instruction = Self::parse_from_machine_code(machine_code)?;
+        let assembly_code = instruction.to_instruction()?;
+        match Self::parse_from_str(&assembly_code) {
+            Ok(parsed_instruction) if parsed_instruction.to_machine_code() == machine_code => {
+                Ok(instruction)
+            }
+            _ => Err(Error::UnusedBitsSet { machine_code }),
+        }
+    }
+    pub fn parse_from_le_bytes(content: &[u8; 4]) -> Result<Self, Error> {
+        let mut content_reversed = *content;
         content_reversed.reverse();
         Self::parse_from_be_bytes(&content_reversed)
     }
     /// Parses an instruction from a string.
-    pub fn parse_from_str(content: &str) -> Result<Self, String> {
-        let content_sanitized = content.replace(",", " ").replace("  ", " ");
-        let parts = content_sanitized.split(" ").collect::<Vec<_>>();
-        match parts[0] {
-            "add" => define_r_instruction_parse!(parts, 0b100000), // Funct is 32
-            "addi" => define_i_signed_instruction_parse!(parts, 0b001000), // Opcode is 8
-            "addiu" => define_i_unsigned_instruction_parse!(parts, 0b001001), // Opcode is 9
-            "addu" => define_r_instruction_parse!(parts, 0b100001), // Funct is 33
-            "and" => define_r_instruction_parse!(parts, 0b100100), // Funct is 36
-            "andi" => define_i_signed_instruction_parse!(parts, 0b001100), // Opcode is 12
-            "beq" => match parts[1..] {
-                [rs, rt, address] => {
-                    Ok(Instruction::ISigned {
-                        opcode: 0b000100, // Opcode is 4
-                        rs: parse_register_value(rs).unwrap_or_else(|e| panic!("{}", e)),
-                        rt: parse_register_value(rt).unwrap_or_else(|e| panic!("{}", e)),
-                        immediate: parse_immediate_signed(address)
-                            .unwrap_or_else(|e| panic!("{}", e)),
-                    })
-                }
-                _ => panic!("Unknown structure for instruction \"{}\"", parts[0]),
-            },
-            "bgtz" => match parts[1..] {
-                [rs, address] => {
-                    let rs = parse_register_value(rs).unwrap_or_else(|e| panic!("{}", e));
-                    let immediate =
-                        parse_immediate_signed(address).unwrap_or_else(|e| panic!("{}", e));
-
-                    Ok(Instruction::ISigned {
-                        opcode: 0b000111, // Opcode is 7
-                        rs,
-                        rt: 0,
-                        immediate,
-                    })
-                }
-                _ => panic!("Unknown structure for instruction \"{}\"", parts[0]),
-            },
-            "blez" => match parts[1..] {
-                [rs, address] => {
-                    let rs = parse_register_value(rs).unwrap_or_else(|e| panic!("{}", e));
-                    let immediate =
-                        parse_immediate_signed(address).unwrap_or_else(|e| panic!("{}", e));
-
-                    Ok(Instruction::ISigned {
-                        opcode: 0b000110, // Opcode is 6
-                        rs,
-                        rt: 0,
-                        immediate,
-                    })
-                }
-                _ => panic!("Unknown structure for instruction \"{}\"", parts[0]),
-            },
-            "bltz" => match parts[1..] {
-                [rs, address] => {
-                    let rs = parse_register_value(rs).unwrap_or_else(|e| panic!("{}", e));
-                    let immediate =
-                        parse_immediate_signed(address).unwrap_or_else(|e| panic!("{}", e));
-
-                    Ok(Instruction::ISigned {
-                        opcode: 0b000001, // Opcode is 1
-                        rs,
-                        rt: 0,
-                        immediate,
-                    })
-                }
-                _ => panic!("Unknown structure for instruction \"{}\"", parts[0]),
-            },
-            "bne" => match parts[1..] {
-                [rs, rt, address] => {
-                    Ok(Instruction::ISigned {
-                        opcode: 0b000101, // Opcode is 5
-                        rs: parse_register_value(rs).unwrap_or_else(|e| panic!("{}", e)),
-                        rt: parse_register_value(rt).unwrap_or_else(|e| panic!("{}", e)),
-                        immediate: parse_immediate_signed(address)
-                            .unwrap_or_else(|e| panic!("{}", e)),
-                    })
-                }
-                _ => panic!("Unknown structure for instruction \"{}\"", parts[0]),
-            },
-            "j" => match parts[1..] {
-                [address] => {
-                    Ok(Instruction::J {
-                        opcode: 0b000010, // Opcode is 2
-                        address: parse_address(address).unwrap_or_else(|e| panic!("{}", e)),
-                    })
-                }
-                _ => panic!("Unknown structure for instruction \"{}\"", parts[0]),
-            },
-            "jal" => match parts[1..] {
-                [address] => {
-                    Ok(Instruction::J {
-                        opcode: 0b000011, // Opcode is 3
-                        address: parse_address(address).unwrap_or_else(|e| panic!("{}", e)),
-                    })
-                }
-                _ => panic!("Unknown structure for instruction \"{}\"", parts[0]),
-            },
-            "jalr" => match parts[1..] {
-                [rd, rs] => {
-                    let rd = parse_register_value(rd).unwrap_or_else(|e| panic!("{}", e));
-                    let rs = parse_register_value(rs).unwrap_or_else(|e| panic!("{}", e));
-                    Ok(Instruction::R {
-                        opcode: 0b000000, // Opcode is 0
-                        rs,
-                        rt: 0,
-                        rd,
-                        shamt: 0,
-                        funct: 0b001001, // Funct is 9
-                    })
-                }
-                _ => panic!("Unknown structure for instruction \"{}\"", parts[0]),
-            },
-            "jr" => match parts[1..] {
-                [rs] => {
-                    Ok(Instruction::R {
-                        opcode: 0b000000, // Opcode is 0
-                        rs: parse_register_value(rs).unwrap_or_else(|e| panic!("{}", e)),
-                        rt: 0,
-                        rd: 0,
-                        shamt: 0,
-                        funct: 0b001000, // Funct is 8
-                    })
-                }
-                _ => panic!("Unknown structure for instruction \"{}\"", parts[0]),
-            },
-            "lb" => match parts[1..] {
-                [rt, immediate, rs] => {
-                    let rt = parse_register_value(rt).unwrap_or_else(|e| panic!("{}", e));
-                    let rs = parse_register_value(rs).unwrap_or_else(|e| panic!("{}", e));
-                    let immediate =
-                        parse_immediate_signed(immediate).unwrap_or_else(|e| panic!("{}", e));
-                    Ok(Instruction::ISigned {
-                        opcode: 0b100000, // Opcode is 32
-                        rs,
-                        rt,
-                        immediate,
-                    })
-                }
-                [rt, relative_value] => {
-                    let rt = parse_register_value(rt).unwrap_or_else(|e| panic!("{}", e));
-
-                    let relative_value_parts = relative_value.split("(").collect::<Vec<_>>();
-
-                    let immediate = relative_value_parts.get(0).unwrap_or_else(|| {
-                        panic!(
-                            "Missing immediate in relative value parts {:?}",
-                            relative_value_parts
-                        )
-                    });
-                    let immediate =
-                        parse_immediate_signed(immediate).unwrap_or_else(|e| panic!("{}", e));
-
-                    let rs = relative_value_parts.get(1).unwrap_or_else(|| {
-                        panic!(
-                            "Missing rs in relative value parts {:?}",
-                            relative_value_parts
-                        )
-                    });
-                    let rs = rs.replace(")", "");
-                    let rs = parse_register_value(&rs).unwrap_or_else(|e| panic!("{}", e));
-
-                    Ok(Instruction::ISigned {
-                        opcode: 0b100000, // Opcode is 32
-                        rs,
-                        rt,
-                        immediate,
-                    })
-                }
-                _ => panic!("Unknown structure for instruction \"{}\"", parts[0]),
-            },
-            "lbu" => match parts[1..] {
-                [rt, immediate, rs] => {
-                    let rt = parse_register_value(rt).unwrap_or_else(|e| panic!("{}", e));
-                    let rs = parse_register_value(rs).unwrap_or_else(|e| panic!("{}", e));
-                    let immediate =
-                        parse_immediate_unsigned(immediate).unwrap_or_else(|e| panic!("{}", e));
-                    Ok(Instruction::IUnsigned {
-                        opcode: 0b100100, // Opcode is 36
-                        rs,
-                        rt,
-                        immediate,
-                    })
-                }
-                [rt, relative_value] => {
-                    let rt = parse_register_value(rt).unwrap_or_else(|e| panic!("{}", e));
-
-                    let relative_value_parts = relative_value.split("(").collect::<Vec<_>>();
-
-                    let immediate = relative_value_parts.get(0).unwrap_or_else(|| {
-                        panic!(
-                            "Missing immediate in relative value parts {:?}",
-                            relative_value_parts
-                        )
-                    });
-                    let immediate =
-                        parse_immediate_unsigned(immediate).unwrap_or_else(|e| panic!("{}", e));
-
-                    let rs = relative_value_parts.get(1).unwrap_or_else(|| {
-                        panic!(
-                            "Missing rs in relative value parts {:?}",
-                            relative_value_parts
-                        )
-                    });
-                    let rs = rs.replace(")", "");
-                    let rs = parse_register_value(&rs).unwrap_or_else(|e| panic!("{}", e));
-
-                    Ok(Instruction::IUnsigned {
-                        opcode: 0b100100, // Opcode is 36
-                        rs,
-                        rt,
-                        immediate,
-                    })
-                }
-                _ => panic!("Unknown structure for instruction \"{}\"", parts[0]),
-            },
-            "lh" => define_i_signed_instruction_parse!(parts, 0b100001), // Opcode is 33
-            "lhu" => define_i_unsigned_instruction_parse!(parts, 0b100101), // Opcode is 37
-            "lui" => match parts[1..] {
-                [rt, immediate] => {
-                    let rt = parse_register_value(rt).unwrap_or_else(|e| panic!("{}", e));
-                    let immediate =
-                        parse_immediate_unsigned(immediate).unwrap_or_else(|e| panic!("{}", e));
-                    Ok(Instruction::IUnsigned {
-                        opcode: 0b001111, // Opcode is 15
-                        rs: 0,
-                        rt,
-                        immediate,
-                    })
-                }
-                _ => panic!("Unknown structure for instruction \"{}\"", parts[0]),
-            },
-            "lw" => match parts[1..] {
-                [rt, immediate, rs] => {
-                    let rt = parse_register_value(rt).unwrap_or_else(|e| panic!("{}", e));
-                    let rs = parse_register_value(rs).unwrap_or_else(|e| panic!("{}", e));
-                    let immediate =
-                        parse_immediate_signed(immediate).unwrap_or_else(|e| panic!("{}", e));
-                    Ok(Instruction::ISigned {
-                        opcode: 0b100011, // Opcode is 35
-                        rs,
-                        rt,
-                        immediate,
-                    })
-                }
-                [rt, relative_value] => {
-                    let rt = parse_register_value(rt).unwrap_or_else(|e| panic!("{}", e));
-
-                    let relative_value_parts = relative_value.split("(").collect::<Vec<_>>();
-
-                    let immediate = relative_value_parts.get(0).unwrap_or_else(|| {
-                        panic!(
-                            "Missing immediate in relative value parts {:?}",
-                            relative_value_parts
-                        )
-                    });
-                    let immediate =
-                        parse_immediate_signed(immediate).unwrap_or_else(|e| panic!("{}", e));
-
-                    let rs = relative_value_parts.get(1).unwrap_or_else(|| {
-                        panic!(
-                            "Missing rs in relative value parts {:?}",
-                            relative_value_parts
-                        )
-                    });
-                    let rs = rs.replace(")", "");
-                    let rs = parse_register_value(&rs).unwrap_or_else(|e| panic!("{}", e));
-
-                    Ok(Instruction::ISigned {
-                        opcode: 0b100011, // Opcode is 35
-                        rs,
-                        rt,
-                        immediate,
-                    })
-                }
-                _ => panic!("Unknown structure for instruction \"{}\"", parts[0]),
-            },
-            "mfhi" => match parts[1..] {
-                [rd] => {
-                    let rd = parse_register_value(rd).unwrap_or_else(|e| panic!("{}", e));
-                    Ok(Instruction::R {
-                        opcode: 0,
-                        rs: 0,
-                        rt: 0,
-                        rd,
-                        shamt: 0,
-                        funct: 0b010000, // Funct is 16
-                    })
-                }
-                _ => panic!("Unknown structure for instruction \"{}\"", parts[0]),
-            },
-            "mflo" => match parts[1..] {
-                [rd] => {
-                    let rd = parse_register_value(rd).unwrap_or_else(|e| panic!("{}", e));
-                    Ok(Instruction::R {
-                        opcode: 0,
-                        rs: 0,
-                        rt: 0,
-                        rd,
-                        shamt: 0,
-                        funct: 0b010010, // Funct is 18
-                    })
-                }
-                _ => panic!("Unknown structure for instruction \"{}\"", parts[0]),
-            },
-            "mult" => match parts[1..] {
-                [rs, rt] => {
-                    let rs = parse_register_value(rs).unwrap_or_else(|e| panic!("{}", e));
-                    let rt = parse_register_value(rt).unwrap_or_else(|e| panic!("{}", e));
-                    Ok(Instruction::R {
-                        opcode: 0,
-                        rs,
-                        rt,
-                        rd: 0,
-                        shamt: 0,
-                        funct: 0b011000, // Funct is 24
-                    })
+    /// Locations of errors are given as if the string was the first line of a file.
+    pub fn parse_from_str(content: &str) -> Result<Self, Error> {
+        Self::parse_from_tokens(&tokenize(content, 1))
+    }
+    /// Parses an instruction from tokens, where the first token is the mnemonic
+    /// and the rest are operands.
+    fn parse_from_tokens(tokens: &[Token]) -> Result<Self, Error> {
+        let Some(mnemonic) = tokens.first() else {
+            return Err(Error::UnknownMnemonic {
+                mnemonic: String::new(),
+                span: Span {
+                    line: 1,
+                    column: 1,
+                    len: 0,
+                },
+            });
+        };
+        match mnemonic.text {
+            "add" => define_r_instruction_parse!(tokens, 0b100000), // Funct is 32
+            "addi" => define_i_instruction_parse!(tokens, ISigned, parse_immediate_i16, 0b001000), // Opcode is 8
+            "addiu" => {
+                define_i_instruction_parse!(tokens, IUnsigned, parse_immediate_u16, 0b001001)
+            } // Opcode is 9
+            "addu" => define_r_instruction_parse!(tokens, 0b100001), // Funct is 33
+            "and" => define_r_instruction_parse!(tokens, 0b100100),  // Funct is 36
+            "andi" => define_i_instruction_parse!(tokens, IUnsigned, parse_immediate_u16, 0b001100), // Opcode is 12
+            "beq" => define_branch_instruction_parse!(tokens, 0b000100), // Opcode is 4
+            "bgez" => define_regimm_instruction_parse!(tokens, 0b00001), // Rt is 1
+            "bgezal" => define_regimm_instruction_parse!(tokens, 0b10001), // Rt is 17
+            "bgtz" => define_branch_zero_instruction_parse!(tokens, 0b000111), // Opcode is 7
+            "blez" => define_branch_zero_instruction_parse!(tokens, 0b000110), // Opcode is 6
+            "bltz" => define_regimm_instruction_parse!(tokens, 0b00000), // Rt is 0
+            "bltzal" => define_regimm_instruction_parse!(tokens, 0b10000), // Rt is 16
+            "break" => define_r_code_instruction_parse!(tokens, 0b001101), // Funct is 13
+            "bne" => define_branch_instruction_parse!(tokens, 0b000101), // Opcode is 5
+            "cfc2" => {
+                define_cop_move_instruction_parse!(tokens, 0b010010, 0b00010, GTE_CONTROL_REGISTERS)
+            } // Opcode is 18, rs is 2
+            "cop2" => {
+                let [command] = get_operands(tokens)?;
+                Ok(Instruction::Gte {
+                    command: parse_immediate(command, 0, 0x1FFFFFF)? as u32,
+                })
+            }
+            "ctc2" => {
+                define_cop_move_instruction_parse!(tokens, 0b010010, 0b00110, GTE_CONTROL_REGISTERS)
+            } // Opcode is 18, rs is 6
+            "div" => define_r_mult_div_instruction_parse!(tokens, 0b011010), // Funct is 26
+            "divu" => define_r_mult_div_instruction_parse!(tokens, 0b011011), // Funct is 27
+            "j" => define_j_instruction_parse!(tokens, 0b000010),            // Opcode is 2
+            "jal" => define_j_instruction_parse!(tokens, 0b000011),          // Opcode is 3
+            "jalr" => {
+                let [rd, rs] = get_operands(tokens)?;
+                Ok(Instruction::R {
+                    opcode: 0b000000, // Opcode is 0
+                    rs: parse_register(rs)?,
+                    rt: 0,
+                    rd: parse_register(rd)?,
+                    shamt: 0,
+                    funct: 0b001001, // Funct is 9
+                })
+            }
+            "jr" => {
+                let [rs] = get_operands(tokens)?;
+                Ok(Instruction::R {
+                    opcode: 0b000000, // Opcode is 0
+                    rs: parse_register(rs)?,
+                    rt: 0,
+                    rd: 0,
+                    shamt: 0,
+                    funct: 0b001000, // Funct is 8
+                })
+            }
+            "lb" => {
+                define_load_store_instruction_parse!(tokens, ISigned, parse_immediate_i16, 0b100000)
+            } // Opcode is 32
+            "lbu" => define_load_store_instruction_parse!(
+                tokens,
+                IUnsigned,
+                parse_immediate_u16,
+                0b100100
+            ), // Opcode is 36
+            "lh" => {
+                define_load_store_instruction_parse!(tokens, ISigned, parse_immediate_i16, 0b100001)
+            } // Opcode is 33
+            "lhu" => define_load_store_instruction_parse!(
+                tokens,
+                IUnsigned,
+                parse_immediate_u16,
+                0b100101
+            ), // Opcode is 37
+            "lui" => {
+                let [rt, immediate] = get_operands(tokens)?;
+                Ok(Instruction::IUnsigned {
+                    opcode: 0b001111, // Opcode is 15
+                    rs: 0,
+                    rt: parse_register(rt)?,
+                    immediate: parse_immediate_u16(immediate)?,
+                })
+            }
+            "lw" => {
+                define_load_store_instruction_parse!(tokens, ISigned, parse_immediate_i16, 0b100011)
+            } // Opcode is 35
+            "lwc2" => define_cop2_load_store_instruction_parse!(tokens, 0b110010), // Opcode is 50
+            "lwl" => {
+                define_load_store_instruction_parse!(tokens, ISigned, parse_immediate_i16, 0b100010)
+            } // Opcode is 34
+            "lwr" => {
+                define_load_store_instruction_parse!(tokens, ISigned, parse_immediate_i16, 0b100110)
+            } // Opcode is 38
+            "mfc0" => define_cop_move_instruction_parse!(tokens, 0b010000, 0b00000, COP0_REGISTERS), // Opcode is 16, rs is 0
+            "mfc2" => {
+                define_cop_move_instruction_parse!(tokens, 0b010010, 0b00000, GTE_DATA_REGISTERS)
+            } // Opcode is 18, rs is 0
+            "mfhi" => define_r_move_from_instruction_parse!(tokens, 0b010000), // Funct is 16
+            "mflo" => define_r_move_from_instruction_parse!(tokens, 0b010010), // Funct is 18
+            "mtc0" => define_cop_move_instruction_parse!(tokens, 0b010000, 0b00100, COP0_REGISTERS), // Opcode is 16, rs is 4
+            "mtc2" => {
+                define_cop_move_instruction_parse!(tokens, 0b010010, 0b00100, GTE_DATA_REGISTERS)
+            } // Opcode is 18, rs is 4
+            "mthi" => define_r_move_to_instruction_parse!(tokens, 0b010001), // Funct is 17
+            "mtlo" => define_r_move_to_instruction_parse!(tokens, 0b010011), // Funct is 19
+            "mult" => define_r_mult_div_instruction_parse!(tokens, 0b011000), // Funct is 24
+            "multu" => define_r_mult_div_instruction_parse!(tokens, 0b011001), // Funct is 25
+            "nop" => {
+                let [] = get_operands(tokens)?;
+                Ok(Instruction::Nop)
+            }
+            "nor" => define_r_instruction_parse!(tokens, 0b100111), // Funct is 39
+            "or" => define_r_instruction_parse!(tokens, 0b100101),  // Funct is 37
+            "ori" => define_i_instruction_parse!(tokens, IUnsigned, parse_immediate_u16, 0b001101), // Opcode is 13
+            "rfe" => {
+                let [] = get_operands(tokens)?;
+                Ok(Instruction::Rfe)
+            }
+            "sb" => {
+                define_load_store_instruction_parse!(tokens, ISigned, parse_immediate_i16, 0b101000)
+            } // Opcode is 40
+            "sh" => {
+                define_load_store_instruction_parse!(tokens, ISigned, parse_immediate_i16, 0b101001)
+            } // Opcode is 41
+            "sll" => define_r_shift_instruction_parse!(tokens, 0b000000), // Funct is 0
+            "sllv" => define_r_shift_variable_instruction_parse!(tokens, 0b000100), // Funct is 4
+            "slt" => define_r_instruction_parse!(tokens, 0b101010),       // Funct is 42
+            "slti" => define_i_instruction_parse!(tokens, ISigned, parse_immediate_i16, 0b001010), // Opcode is 10
+            "sltiu" => {
+                define_i_instruction_parse!(tokens, IUnsigned, parse_immediate_u16, 0b001011)
+            } // Opcode is 11
+            "sltu" => define_r_instruction_parse!(tokens, 0b101011), // Funct is 43
+            "sra" => define_r_shift_instruction_parse!(tokens, 0b000011), // Funct is 3
+            "srav" => define_r_shift_variable_instruction_parse!(tokens, 0b000111), // Funct is 7
+            "srl" => define_r_shift_instruction_parse!(tokens, 0b000010), // Funct is 2
+            "srlv" => define_r_shift_variable_instruction_parse!(tokens, 0b000110), // Funct is 6
+            "sub" => define_r_instruction_parse!(tokens, 0b100010),  // Funct is 34
+            "subu" => define_r_instruction_parse!(tokens, 0b100011), // Funct is 35
+            "sw" => {
+                define_load_store_instruction_parse!(tokens, ISigned, parse_immediate_i16, 0b101011)
+            } // Opcode is 43
+            "swc2" => define_cop2_load_store_instruction_parse!(tokens, 0b111010), // Opcode is 58
+            "swl" => {
+                define_load_store_instruction_parse!(tokens, ISigned, parse_immediate_i16, 0b101010)
+            } // Opcode is 42
+            "swr" => {
+                define_load_store_instruction_parse!(tokens, ISigned, parse_immediate_i16, 0b101110)
+            } // Opcode is 46
+            "syscall" => define_r_code_instruction_parse!(tokens, 0b001100), // Funct is 12
+            "xor" => define_r_instruction_parse!(tokens, 0b100110),  // Funct is 38
+            "xori" => define_i_instruction_parse!(tokens, IUnsigned, parse_immediate_u16, 0b001110), // Opcode is 14
+            name => {
+                let fields = tokens[1..].iter().map(|t| t.text).collect::<Vec<_>>();
+                match parse_gte_command(name, &fields) {
+                    Some(command) => Ok(Instruction::Gte {
+                        command: command.map_err(|message| Error::Syntax {
+                            message,
+                            span: get_span_of_tokens(tokens),
+                        })?,
+                    }),
+                    None => Err(Error::UnknownMnemonic {
+                        mnemonic: String::from(name),
+                        span: mnemonic.span(),
+                    }),
                 }
-                _ => panic!("Unknown structure for instruction \"{}\"", parts[0]),
-            },
-            "nop" => Ok(Instruction::Nop),
-            "nor" => define_r_instruction_parse!(parts, 0b100111), // Funct is 39
-            "or" => define_r_instruction_parse!(parts, 0b100101),  // Funct is 37
-            "ori" => define_i_signed_instruction_parse!(parts, 0b001101), // Opcode is 13
-            "sb" => match parts[1..] {
-                [rt, immediate, rs] => {
-                    let rt = parse_register_value(rt).unwrap_or_else(|e| panic!("{}", e));
-                    let rs = parse_register_value(rs).unwrap_or_else(|e| panic!("{}", e));
-                    let immediate =
-                        parse_immediate_signed(immediate).unwrap_or_else(|e| panic!("{}", e));
-                    Ok(Instruction::ISigned {
-                        opcode: 0b101000, // Opcode is 40
-                        rs,
-                        rt,
-                        immediate,
-                    })
+            }
+        }
+    }
+    /// Gets the address a branch or jump instruction at a given address goes to.
+    ///
+    /// Both are relative to the delay slot (the instruction after the branch or jump).
+    /// Branches move by a signed count of instructions, whereas jumps replace the lower
+    /// 28 bits of the address, staying inside the 256 MB region of the delay slot.
+    pub fn get_target_address(&self, address: u64) -> Option<u64> {
+        let delay_slot_address = address.wrapping_add(4);
+        match self {
+            Instruction::ISigned {
+                opcode: 0b000001 | 0b000100..=0b000111, // Opcode is 1 or 4-7
+                immediate,
+                ..
+            } => Some(delay_slot_address.wrapping_add_signed(*immediate as i64 * 4) & 0xFFFFFFFF),
+            Instruction::J { address, .. } => {
+                Some((delay_slot_address & 0xF0000000) | ((*address as u64) << 2))
+            }
+            _ => None,
+        }
+    }
+    /// Whether the instruction is a branch or jump, after which the next instruction
+    /// (in the delay slot) runs before going to the target.
+    pub fn has_delay_slot(&self) -> bool {
+        match self {
+            Instruction::ISigned {
+                opcode: 0b000001 | 0b000100..=0b000111, // Opcode is 1 or 4-7
+                ..
+            }
+            | Instruction::J { .. } => true,
+            Instruction::R { funct, .. } => matches!(funct, 8 | 9), // jr and jalr
+            _ => false,
+        }
+    }
+    /// Fills a value resolved from the address of a label into the instruction at a given address.
+    fn set_relocation(
+        &mut self,
+        relocation: Relocation,
+        address: u64,
+        target: u64,
+        span: Span,
+    ) -> Result<(), Error> {
+        let value = match relocation {
+            Relocation::Target => return self.set_target_address(address, target, span),
+            Relocation::Hi => get_hi(target),
+            Relocation::Lo => get_lo(target),
+            Relocation::Word { .. } => unreachable!("Words are only filled into data"),
+        };
+        match self {
+            Instruction::ISigned { immediate, .. } => *immediate = value as i16,
+            Instruction::IUnsigned { immediate, .. } => *immediate = value,
+            _ => {
+                return Err(Error::Syntax {
+                    message: format!(
+                        "Instruction \"{}\" has no immediate for %hi or %lo",
+                        self.to_instruction()?
+                    ),
+                    span,
+                })
+            }
+        }
+        Ok(())
+    }
+    /// Sets the target of a branch or jump instruction at a given address.
+    /// Returns an error if the instruction cannot reach the target.
+    fn set_target_address(&mut self, address: u64, target: u64, span: Span) -> Result<(), Error> {
+        if target % 4 != 0 {
+            return Err(Error::Syntax {
+                message: format!("Target address 0x{:X} is not aligned to 4 bytes", target),
+                span,
+            });
+        }
+        let delay_slot_address = address + 4;
+        let out_of_range_error = || Error::TargetOutOfRange {
+            address,
+            target,
+            span: span.clone(),
+        };
+        match self {
+            Instruction::ISigned { immediate, .. } => {
+                let offset = (target as i64 - delay_slot_address as i64) / 4;
+                *immediate = i16::try_from(offset).map_err(|_| out_of_range_error())?;
+            }
+            Instruction::J { address, .. } => {
+                if target & 0xF0000000 != delay_slot_address & 0xF0000000 {
+                    return Err(out_of_range_error());
                 }
-                [rt, relative_value] => {
-                    let rt = parse_register_value(rt).unwrap_or_else(|e| panic!("{}", e));
-
-                    let relative_value_parts = relative_value.split("(").collect::<Vec<_>>();
-
-                    let immediate = relative_value_parts.get(0).unwrap_or_else(|| {
-                        panic!(
-                            "Missing immediate in relative value parts {:?}",
-                            relative_value_parts
-                        )
-                    });
-                    let immediate =
-                        parse_immediate_signed(immediate).unwrap_or_else(|e| panic!("{}", e));
-
-                    let rs = relative_value_parts.get(1).unwrap_or_else(|| {
-                        panic!(
-                            "Missing rs in relative value parts {:?}",
-                            relative_value_parts
+                *address = ((target & 0x0FFFFFFF) >> 2) as u32;
+            }
+            _ => {
+                return Err(Error::Syntax {
+                    message: format!(
+                        "Instruction \"{}\" has no branch or jump target",
+                        self.to_instruction()?
+                    ),
+                    span,
+                })
+            }
+        }
+        Ok(())
+    }
+    /// Converts the instruction to assembly code.
+    /// Returns an error if the fields of the instruction do not make up any known instruction.
+    pub fn to_instruction(&self) -> Result<String, Error> {
+        let unknown_opcode_error = || Error::UnknownOpcode {
+            machine_code: self.to_machine_code(),
+        };
+        let instruction = match self {
+            Instruction::CopMove { opcode, rs, rt, rd } => {
+                let rt = REGISTERS[*rt as usize];
+                match (opcode, rs) {
+                    (0b010000, 0b00000) => {
+                        format!("mfc0 {}, {}", rt, format_cop_register(COP0_REGISTERS, *rd))
+                    }
+                    (0b010000, 0b00100) => {
+                        format!("mtc0 {}, {}", rt, format_cop_register(COP0_REGISTERS, *rd))
+                    }
+                    (0b010010, 0b00000) => {
+                        format!(
+                            "mfc2 {}, {}",
+                            rt,
+                            format_cop_register(GTE_DATA_REGISTERS, *rd)
                         )
-                    });
-                    let rs = rs.replace(")", "");
-                    let rs = parse_register_value(&rs).unwrap_or_else(|e| panic!("{}", e));
-
-                    Ok(Instruction::ISigned {
-                        opcode: 0b101000, // Opcode is 40
-                        rs,
-                        rt,
-                        immediate,
-                    })
-                }
-                _ => panic!("Unknown structure for instruction \"{}\"", parts[0]),
-            },
-            "sh" => define_i_signed_instruction_parse!(parts, 0b101001), // Opcode is 41
-            "sll" => match parts[1..] {
-                [rd, rt, shamt] => {
-                    let rd = parse_register_value(rd).unwrap_or_else(|e| panic!("{}", e));
-                    let rt = parse_register_value(rt).unwrap_or_else(|e| panic!("{}", e));
-                    let shamt =
-                        parse_immediate_unsigned(shamt).unwrap_or_else(|e| panic!("{}", e)) as u8;
-                    Ok(Instruction::R {
-                        opcode: 0b000000, // Opcode is 0
-                        rs: 0,
-                        rt,
-                        rd,
-                        shamt,
-                        funct: 0b000000, // Funct is 0
-                    })
-                }
-                _ => panic!("Unknown structure for instruction \"{}\"", parts[0]),
-            },
-            "sllv" => define_r_instruction_parse!(parts, 0b000100), // Funct is 4
-            "slt" => define_r_instruction_parse!(parts, 0b101010),  // Funct is 42
-            "slti" => define_i_signed_instruction_parse!(parts, 0b001010), // Opcode is 10
-            "sltiu" => define_i_unsigned_instruction_parse!(parts, 0b001011), // Opcode is 11
-            "sltu" => define_r_instruction_parse!(parts, 0b101011), // Funct is 43
-            "sra" => match parts[1..] {
-                [rd, rt, shamt] => {
-                    let rd = parse_register_value(rd).unwrap_or_else(|e| panic!("{}", e));
-                    let rt = parse_register_value(rt).unwrap_or_else(|e| panic!("{}", e));
-                    let shamt =
-                        parse_immediate_unsigned(shamt).unwrap_or_else(|e| panic!("{}", e)) as u8;
-                    Ok(Instruction::R {
-                        opcode: 0b000000, // Opcode is 0
-                        rs: 0,
-                        rt,
-                        rd,
-                        shamt,
-                        funct: 0b000011, // Funct is 3
-                    })
-                }
-                _ => panic!("Unknown structure for instruction \"{}\"", parts[0]),
-            },
-            "srl" => match parts[1..] {
-                [rd, rt, shamt] => {
-                    let rd = parse_register_value(rd).unwrap_or_else(|e| panic!("{}", e));
-                    let rt = parse_register_value(rt).unwrap_or_else(|e| panic!("{}", e));
-                    let shamt =
-                        parse_immediate_unsigned(shamt).unwrap_or_else(|e| panic!("{}", e)) as u8;
-                    Ok(Instruction::R {
-                        opcode: 0b000000, // Opcode is 0
-                        rs: 0,
+                    }
+                    (0b010010, 0b00010) => format!(
+                        "cfc2 {}, {}",
                         rt,
-                        rd,
-                        shamt,
-                        funct: 0b000010, // Funct is 2
-                    })
-                }
-                _ => panic!("Unknown structure for instruction \"{}\"", parts[0]),
-            },
-            "sub" => define_r_instruction_parse!(parts, 0b100010), // Funct is 34
-            "subu" => define_r_instruction_parse!(parts, 0b100011), // Funct is 35
-            "sw" => match parts[1..] {
-                [rt, immediate, rs] => {
-                    let rt = parse_register_value(rt).unwrap_or_else(|e| panic!("{}", e));
-                    let rs = parse_register_value(rs).unwrap_or_else(|e| panic!("{}", e));
-                    let immediate =
-                        parse_immediate_signed(immediate).unwrap_or_else(|e| panic!("{}", e));
-                    Ok(Instruction::ISigned {
-                        opcode: 0b101011, // Opcode is 43
-                        rs,
-                        rt,
-                        immediate,
-                    })
-                }
-                [rt, relative_value] => {
-                    let rt = parse_register_value(rt).unwrap_or_else(|e| panic!("{}", e));
-
-                    let relative_value_parts = relative_value.split("(").collect::<Vec<_>>();
-
-                    let immediate = relative_value_parts.get(0).unwrap_or_else(|| {
-                        panic!(
-                            "Missing immediate in relative value parts {:?}",
-                            relative_value_parts
-                        )
-                    });
-                    let immediate =
-                        parse_immediate_signed(immediate).unwrap_or_else(|e| panic!("{}", e));
-
-                    let rs = relative_value_parts.get(1).unwrap_or_else(|| {
-                        panic!(
-                            "Missing rs in relative value parts {:?}",
-                            relative_value_parts
+                        format_cop_register(GTE_CONTROL_REGISTERS, *rd)
+                    ),
+                    (0b010010, 0b00100) => {
+                        format!(
+                            "mtc2 {}, {}",
+                            rt,
+                            format_cop_register(GTE_DATA_REGISTERS, *rd)
                         )
-                    });
-                    let rs = rs.replace(")", "");
-                    let rs = parse_register_value(&rs).unwrap_or_else(|e| panic!("{}", e));
-
-                    Ok(Instruction::ISigned {
-                        opcode: 0b101011, // Opcode is 43
-                        rs,
+                    }
+                    (0b010010, 0b00110) => format!(
+                        "ctc2 {}, {}",
                         rt,
-                        immediate,
-                    })
+                        format_cop_register(GTE_CONTROL_REGISTERS, *rd)
+                    ),
+                    _ => return Err(unknown_opcode_error()),
                 }
-                _ => panic!("Unknown structure for instruction \"{}\"", parts[0]),
-            },
-            _ => panic!("Unknown instruction \"{}\"", parts[0]),
-        }
-    }
-    pub fn to_instruction(&self) -> String {
-        match self {
+            }
+            Instruction::Gte { command } => format_gte_command(*command),
             Instruction::ISigned {
                 opcode,
                 rs,
                 rt,
                 immediate,
             } => {
+                // REGIMM instructions are told apart by rt, so rt is not a register for them.
+                if *opcode == 0b000001 {
+                    let rs = REGISTERS[*rs as usize];
+                    return Ok(match rt {
+                        0b00000 => format!("bltz {}, {}", rs, immediate), // Rt is 0
+                        0b00001 => format!("bgez {}, {}", rs, immediate), // Rt is 1
+                        0b10000 => format!("bltzal {}, {}", rs, immediate), // Rt is 16
+                        0b10001 => format!("bgezal {}, {}", rs, immediate), // Rt is 17
+                        _ => return Err(unknown_opcode_error()),
+                    });
+                }
+
+                // Loads and stores of COP2 use a GTE data register as rt.
+                if *opcode == 0b110010 || *opcode == 0b111010 {
+                    let name = if *opcode == 0b110010 { "lwc2" } else { "swc2" };
+                    return Ok(format!(
+                        "{} {}, {}({})",
+                        name,
+                        format_cop_register(GTE_DATA_REGISTERS, *rt),
+                        immediate,
+                        REGISTERS[*rs as usize]
+                    ));
+                }
+
                 let rs = REGISTERS[*rs as usize];
                 let rt = REGISTERS[*rt as usize];
 
                 match opcode {
                     0b001000 => format!("addi {}, {}, {}", rt, rs, immediate), // Opcode is 8
-                    0b001100 => format!("andi {}, {}, {}", rt, rs, immediate), // Opcode is 12
                     0b000100 => format!("beq {}, {}, {}", rs, rt, immediate),  // Opcode is 4
                     0b000111 => format!("bgtz {}, {}", rs, immediate),         // Opcode is 7
                     0b000110 => format!("blez {}, {}", rs, immediate),         // Opcode is 6
-                    0b000001 => format!("bltz {}, {}", rs, immediate),         // Opcode is 1
                     0b000101 => format!("bne {}, {}, {}", rs, rt, immediate),  // Opcode is 5
                     0b100000 => format!("lb {}, {}({})", rt, immediate, rs),   // Opcode is 32
                     0b100001 => format!("lh {}, {}({})", rt, immediate, rs),   // Opcode is 33
                     0b001111 => format!("lui {}, {}", rt, immediate),          // Opcode is 15
                     0b100011 => format!("lw {}, {}({})", rt, immediate, rs),   // Opcode is 35
-                    0b001101 => format!("ori {}, {}, {}", rt, rs, immediate),  // Opcode is 13
+                    0b100010 => format!("lwl {}, {}({})", rt, immediate, rs),  // Opcode is 34
+                    0b100110 => format!("lwr {}, {}({})", rt, immediate, rs),  // Opcode is 38
                     0b101000 => format!("sb {}, {}({})", rt, immediate, rs),   // Opcode is 40
                     0b101001 => format!("sh {}, {}({})", rt, immediate, rs),   // Opcode is 41
-                    0b101010 => format!("slt {}, {}({})", rt, immediate, rs),  // Opcode is 42
                     0b001010 => format!("slti {}, {}, {}", rt, rs, immediate), // Opcode is 10
                     0b101011 => format!("sw {}, {}({})", rt, immediate, rs),   // Opcode is 43
-                    _ => panic!(
-                        "Unknown opcode \"{}\" ({:b} in bin) for I instruction",
-                        opcode, opcode
-                    ),
+                    0b101010 => format!("swl {}, {}({})", rt, immediate, rs),  // Opcode is 42
+                    0b101110 => format!("swr {}, {}({})", rt, immediate, rs),  // Opcode is 46
+                    _ => return Err(unknown_opcode_error()),
                 }
             }
             Instruction::IUnsigned {
@@ -787,22 +881,23 @@ impl Instruction {
 
                 match opcode {
                     0b001001 => format!("addiu {}, {}, {}", rt, rs, immediate), // Opcode is 9
+                    0b001100 => format!("andi {}, {}, {}", rt, rs, immediate),  // Opcode is 12
                     0b100100 => format!("lbu {}, {}({})", rt, immediate, rs),   // Opcode is 36
                     0b100101 => format!("lhu {}, {}({})", rt, immediate, rs),   // Opcode is 37
                     0b001111 => format!("lui {}, {}", rt, immediate),           // Opcode is 15
+                    0b001101 => format!("ori {}, {}, {}", rt, rs, immediate),   // Opcode is 13
                     0b001011 => format!("sltiu {}, {}, {}", rt, rs, immediate), // Opcode is 11
-                    _ => panic!(
-                        "Unknown opcode \"{}\" ({:b} in bin) for I instruction",
-                        opcode, opcode
-                    ),
+                    0b001110 => format!("xori {}, {}, {}", rt, rs, immediate),  // Opcode is 14
+                    _ => return Err(unknown_opcode_error()),
                 }
             }
             Instruction::J { opcode, address } => match opcode {
                 0b000010 => format!("j {}", address),
                 0b000011 => format!("jal {}", address),
-                _ => panic!("Unknown opcode \"{}\" for J instruction", opcode),
+                _ => return Err(unknown_opcode_error()),
             },
             Instruction::Nop => String::from("nop"),
+            Instruction::Rfe => String::from("rfe"),
             Instruction::R {
                 rs,
                 rt,
@@ -811,6 +906,19 @@ impl Instruction {
                 shamt,
                 ..
             } => {
+                // Code of syscall and break instructions is stored in bits 6-25.
+                let code = ((*rs as u32) << 15)
+                    | ((*rt as u32) << 10)
+                    | ((*rd as u32) << 5)
+                    | *shamt as u32;
+                let format_code = |name: &str| {
+                    if code == 0 {
+                        String::from(name)
+                    } else {
+                        format!("{} {}", name, code)
+                    }
+                };
+
                 let rd = REGISTERS[*rd as usize];
                 let rs = REGISTERS[*rs as usize];
                 let rt = REGISTERS[*rt as usize];
@@ -819,11 +927,17 @@ impl Instruction {
                     0b100000 => format!("add {}, {}, {}", rd, rs, rt), // Funct is 32
                     0b100001 => format!("addu {}, {}, {}", rd, rs, rt), // Funct is 33
                     0b100100 => format!("and {}, {}, {}", rd, rs, rt), // Funct is 36
+                    0b001101 => format_code("break"),                  // Funct is 13
+                    0b011010 => format!("div {}, {}", rs, rt),         // Funct is 26
+                    0b011011 => format!("divu {}, {}", rs, rt),        // Funct is 27
                     0b001001 => format!("jalr {}, {}", rd, rs),        // Funct is 9
                     0b001000 => format!("jr {}", rs),                  // Funct is 8
                     0b010000 => format!("mfhi {}", rd),                // Funct is 16
                     0b010010 => format!("mflo {}", rd),                // Funct is 18
+                    0b010001 => format!("mthi {}", rs),                // Funct is 17
+                    0b010011 => format!("mtlo {}", rs),                // Funct is 19
                     0b011000 => format!("mult {}, {}", rs, rt),        // Funct is 24
+                    0b011001 => format!("multu {}, {}", rs, rt),       // Funct is 25
                     0b100111 => format!("nor {}, {}, {}", rd, rs, rt), // Funct is 39
                     0b100101 => format!("or {}, {}, {}", rd, rs, rt),  // Funct is 37
                     0b000000 => format!("sll {}, {}, {}", rd, rt, shamt), // Funct is 0
@@ -831,16 +945,49 @@ impl Instruction {
                     0b101010 => format!("slt {}, {}, {}", rd, rs, rt), // Funct is 42
                     0b101011 => format!("sltu {}, {}, {}", rd, rs, rt), // Funct is 43
                     0b000011 => format!("sra {}, {}, {}", rd, rt, shamt), // Funct is 3
+                    0b000111 => format!("srav {}, {}, {}", rd, rt, rs), // Funct is 7
                     0b000010 => format!("srl {}, {}, {}", rd, rt, shamt), // Funct is 2
+                    0b000110 => format!("srlv {}, {}, {}", rd, rt, rs), // Funct is 6
                     0b100010 => format!("sub {}, {}, {}", rd, rs, rt), // Funct is 34
                     0b100011 => format!("subu {}, {}, {}", rd, rs, rt), // Funct is 35
-                    _ => panic!(
-                        "Unknown funct \"{:b}\" ({}) for instruction {:?}",
-                        funct, funct, self
-                    ),
+                    0b001100 => format_code("syscall"),                // Funct is 12
+                    0b100110 => format!("xor {}, {}, {}", rd, rs, rt), // Funct is 38
+                    _ => return Err(unknown_opcode_error()),
                 }
             }
-        }
+        };
+        Ok(instruction)
+    }
+    /// Same as [Instruction::to_instruction], but a branch or jump target is written
+    /// as a label if one is given for the target address, otherwise as an absolute address.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use std::collections::HashMap;
+    /// # use mips::Instruction;
+    /// let instruction = Instruction::parse_from_str("bne v0, zero, 2").unwrap();
+    /// let mut labels = HashMap::new();
+    /// assert_eq!(instruction.to_instruction_at(0x80010000, &labels).unwrap(), "bne v0, zero, @0x8001000C");
+    ///
+    /// labels.insert(0x8001000C, String::from("loop_end"));
+    /// assert_eq!(instruction.to_instruction_at(0x80010000, &labels).unwrap(), "bne v0, zero, loop_end");
+    /// ```
+    pub fn to_instruction_at(
+        &self,
+        address: u64,
+        labels: &HashMap<u64, String>,
+    ) -> Result<String, Error> {
+        let instruction = self.to_instruction()?;
+        let Some(target) = self.get_target_address(address) else {
+            return Ok(instruction);
+        };
+        // Target is always the last operand.
+        let (instruction_without_target, _) = instruction.rsplit_once(' ').unwrap();
+        Ok(match labels.get(&target) {
+            Some(label) => format!("{} {}", instruction_without_target, label),
+            None => format!("{} @0x{:X}", instruction_without_target, target),
+        })
     }
     #[inline]
     pub fn to_be_bytes(&self) -> [u8; 4] {
@@ -852,6 +999,15 @@ impl Instruction {
     #[inline]
     pub fn to_machine_code(&self) -> u32 {
         match self {
+            Instruction::CopMove { opcode, rs, rt, rd } => {
+                let mut machine_code = 0u32;
+                machine_code |= (*opcode as u32) << 26;
+                machine_code |= (*rs as u32) << 21;
+                machine_code |= (*rt as u32) << 16;
+                machine_code |= (*rd as u32) << 11;
+                machine_code
+            }
+            Instruction::Gte { command } => (0b010010 << 26) | (1 << 25) | command,
             Instruction::ISigned {
                 opcode,
                 rs,
@@ -881,10 +1037,11 @@ impl Instruction {
             Instruction::J { opcode, address } => {
                 let mut machine_code = 0u32;
                 machine_code |= (*opcode as u32) << 26;
-                machine_code |= *address as u32;
+                machine_code |= *address;
                 machine_code
             }
             Instruction::Nop => 0u32,
+            Instruction::Rfe => 0x42000010,
             Instruction::R {
                 opcode,
                 rs,
@@ -910,91 +1067,174 @@ impl Instruction {
     }
 }
 
-/// Parses an address used for a J (jump) instruction from a string.
-fn parse_address(content: &str) -> Result<u32, String> {
-    if content.starts_with("0x") || content.starts_with("0X") {
-        u32::from_str_radix(&content[2..], 16)
-            .map_err(|e| format!("Could not parse address \"{}\": {}", content, e.to_string()))
-    } else if content.starts_with("-") {
-        Err(format!(
-            "Could not parse address \"{}\": {}",
-            content, "Negative number given when only unsigned immediate is expected"
-        ))
-    } else {
-        content
-            .parse::<u32>()
-            .map_err(|e| format!("Could not parse address \"{}\": {}", content, e.to_string()))
+/// A value filled into an instruction once the address of a label is known.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Relocation {
+    /// Branch or jump target.
+    Target,
+    /// Upper 16 bits of an address (for lui).
+    Hi,
+    /// Lower 16 bits of an address (for addiu and loads and stores).
+    Lo,
+    /// Whole address in a ".word" directive, at a given offset in its data.
+    Word { offset: usize },
+}
+/// Gets the upper 16 bits of an address for lui. Since the lower 16 bits are sign extended
+/// when added to the upper bits, the upper bits are rounded up if the lower bits are 0x8000 or more.
+fn get_hi(address: u64) -> u16 {
+    ((address + 0x8000) >> 16) as u16
+}
+/// Gets the lower 16 bits of an address.
+fn get_lo(address: u64) -> u16 {
+    address as u16
+}
+
+/// A part of an instruction (a mnemonic or an operand) with its location in assembly code.
+#[derive(Clone, Copy, Debug)]
+struct Token<'a> {
+    text: &'a str,
+    line: u64,
+    /// Column number starting from 1, counted in characters.
+    column: usize,
+}
+impl<'a> Token<'a> {
+    fn span(&self) -> Span {
+        Span {
+            line: self.line,
+            column: self.column,
+            len: self.text.chars().count(),
+        }
+    }
+    /// Gets a part of the token by a byte range.
+    fn slice(&self, range: Range<usize>) -> Self {
+        Self {
+            text: &self.text[range.clone()],
+            line: self.line,
+            column: self.column + self.text[..range.start].chars().count(),
+        }
     }
 }
-/// Parses an immediate value from a string.
-/// The string must be in the format "0x<hexadecimal number>" or "<decimal number>".
-/// If the string is not in the correct format, an error is returned.
-fn parse_immediate_signed(content: &str) -> Result<i16, String> {
-    if content.starts_with("0x") || content.starts_with("0X") {
-        i16::from_str_radix(&content[2..], 16)
-            .map_err(|e| format!("Could not parse immediate \"{}\": {}", content, e))
-    } else if content.starts_with("-0x") || content.starts_with("-0X") {
-        let content_without_0x = String::from("-") + &content[3..];
-        i16::from_str_radix(&content_without_0x, 16)
-            .map_err(|e| format!("Could not parse immediate \"{}\": {}", content, e))
-    } else {
-        content
-            .parse::<i16>()
-            .map_err(|e| format!("Could not parse immediate \"{}\": {}", content, e))
+/// Splits a line of assembly code into tokens separated by whitespace and commas.
+fn tokenize(content: &str, line: u64) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut token_start = None;
+    let mut token_column = 1;
+    for (column, (i, c)) in (1..).zip(content.char_indices()) {
+        let is_separator = c.is_whitespace() || c == ',';
+        match (token_start, is_separator) {
+            (None, false) => {
+                token_start = Some(i);
+                token_column = column;
+            }
+            (Some(start), true) => {
+                tokens.push(Token {
+                    text: &content[start..i],
+                    line,
+                    column: token_column,
+                });
+                token_start = None;
+            }
+            _ => {}
+        }
     }
+    if let Some(start) = token_start {
+        tokens.push(Token {
+            text: &content[start..],
+            line,
+            column: token_column,
+        });
+    }
+    tokens
 }
-/// Parses an immediate value from a string.
-/// The string must be in the format "0x<hexadecimal number>" or "<decimal number>".
-/// If the string is not in the correct format, an error is returned.
-fn parse_immediate_unsigned(content: &str) -> Result<u16, String> {
-    if content.starts_with("0x") || content.starts_with("0X") {
-        u16::from_str_radix(&content[2..], 16).map_err(|e| {
-            format!(
-                "Could not parse immediate \"{}\": {}",
-                content,
-                e.to_string()
-            )
-        })
-    } else if content.starts_with("-") {
-        Err(format!(
-            "Could not parse immediate \"{}\": {}",
-            content, "Negative number given when only unsigned immediate is expected"
-        ))
-    } else {
-        content.parse::<u16>().map_err(|e| {
-            format!(
-                "Could not parse immediate \"{}\": {}",
-                content,
-                e.to_string()
-            )
-        })
+/// Gets a span covering all given tokens.
+fn get_span_of_tokens(tokens: &[Token]) -> Span {
+    let first = tokens[0];
+    let last = tokens[tokens.len() - 1];
+    Span {
+        line: first.line,
+        column: first.column,
+        len: last.column + last.text.chars().count() - first.column,
     }
 }
-/// Parses an immediate value from a string.
-/// The string must be in the format "0x<hexadecimal number>" or "<decimal number>".
-/// If the string is not in the correct format, an error is returned.
-fn parse_immediate_unsigned_u64(content: &str) -> Result<u64, String> {
-    if content.starts_with("0x") || content.starts_with("0X") {
-        u64::from_str_radix(&content[2..], 16).map_err(|e| {
-            format!(
-                "Could not parse immediate \"{}\": {}",
-                content,
-                e.to_string()
-            )
-        })
-    } else if content.starts_with("-") {
-        Err(format!(
-            "Could not parse immediate \"{}\": {}",
-            content, "Negative number given when only unsigned immediate is expected"
-        ))
+/// Gets operands of an instruction, which must be exactly N in count.
+fn get_operands<'a, const N: usize>(tokens: &[Token<'a>]) -> Result<[Token<'a>; N], Error> {
+    tokens[1..]
+        .try_into()
+        .map_err(|_| get_wrong_operand_count_error(tokens, &[N]))
+}
+fn get_wrong_operand_count_error(tokens: &[Token], expected: &[usize]) -> Error {
+    Error::WrongOperandCount {
+        mnemonic: String::from(tokens[0].text),
+        expected: expected.to_vec(),
+        found: tokens.len() - 1,
+        span: get_span_of_tokens(tokens),
+    }
+}
+/// Parses an integer from a string.
+/// The string must be in the format "0x<hexadecimal number>", "-0x<hexadecimal number>"
+/// or "<decimal number>".
+fn parse_integer(content: &str) -> Option<i64> {
+    if let Some(hex) = content
+        .strip_prefix("0x")
+        .or_else(|| content.strip_prefix("0X"))
+    {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(hex) = content
+        .strip_prefix("-0x")
+        .or_else(|| content.strip_prefix("-0X"))
+    {
+        i64::from_str_radix(hex, 16).ok().map(|value| -value)
     } else {
-        content.parse::<u64>().map_err(|e| {
-            format!(
-                "Could not parse immediate \"{}\": {}",
-                content,
-                e.to_string()
-            )
-        })
+        content.parse::<i64>().ok()
+    }
+}
+/// Parses an immediate value, which must be within the given range.
+fn parse_immediate(token: Token, min: i64, max: i64) -> Result<i64, Error> {
+    parse_immediate_at(token.text, token.span(), min, max)
+}
+/// Same as [parse_immediate], but for a value not split into a token, with its span given.
+fn parse_immediate_at(content: &str, span: Span, min: i64, max: i64) -> Result<i64, Error> {
+    let value = parse_integer(content).ok_or_else(|| Error::Syntax {
+        message: format!("Could not parse immediate \"{}\" as a number", content),
+        span: span.clone(),
+    })?;
+    if value < min || value > max {
+        return Err(Error::ImmediateOutOfRange {
+            immediate: String::from(content),
+            min,
+            max,
+            span,
+        });
+    }
+    Ok(value)
+}
+fn parse_immediate_i16(token: Token) -> Result<i16, Error> {
+    parse_immediate(token, i16::MIN as i64, i16::MAX as i64).map(|value| value as i16)
+}
+fn parse_immediate_u16(token: Token) -> Result<u16, Error> {
+    parse_immediate(token, 0, u16::MAX as i64).map(|value| value as u16)
+}
+/// Parses an address in memory, like in "@at <address>" or in a target "@<address>".
+fn parse_address(content: &str, span: Span) -> Result<u64, Error> {
+    parse_immediate_at(content, span, 0, u32::MAX as i64).map(|value| value as u64)
+}
+/// Parses a relative value in the format "<immediate>(<register>)",
+/// which is used by loads and stores. Returns the immediate and the register.
+fn parse_relative_value(token: Token) -> Result<(Token, Token), Error> {
+    let parts = token.text.strip_suffix(")").and_then(|text| {
+        // Immediate may contain parentheses itself, like "%lo(label)(a0)".
+        let open_index = text.rfind("(")?;
+        Some((0..open_index, open_index + 1..text.len()))
+    });
+    match parts {
+        Some((immediate, register)) => Ok((token.slice(immediate), token.slice(register))),
+        None => Err(Error::Syntax {
+            message: format!(
+                "Could not parse relative value \"{}\": expected <immediate>(<register>)",
+                token.text
+            ),
+            span: token.span(),
+        }),
     }
 }
 fn parse_i_signed_instruction(opcode: u8, machine_code: u32) -> Instruction {
@@ -1008,6 +1248,19 @@ fn parse_i_signed_instruction(opcode: u8, machine_code: u32) -> Instruction {
         immediate,
     }
 }
+/// Parses a coprocessor register move. Bits 0-10 must be zero,
+/// because they are not stored in the instruction.
+fn parse_cop_move_instruction(opcode: u8, machine_code: u32) -> Result<Instruction, Error> {
+    if machine_code & 0x7FF != 0 {
+        return Err(Error::UnknownOpcode { machine_code });
+    }
+    Ok(Instruction::CopMove {
+        opcode,
+        rs: ((machine_code >> 21) & 0b11111) as u8,
+        rt: ((machine_code >> 16) & 0b11111) as u8,
+        rd: ((machine_code >> 11) & 0b11111) as u8,
+    })
+}
 fn parse_i_unsigned_instruction(opcode: u8, machine_code: u32) -> Instruction {
     let rt = ((machine_code >> 16) & 0b11111) as u8;
     let rs = ((machine_code >> 21) & 0b11111) as u8;
@@ -1025,178 +1278,586 @@ fn parse_i_unsigned_instruction(opcode: u8, machine_code: u32) -> Instruction {
 /// Each line results in a node (except empty lines, which are skipped).
 /// All comments (starting with '#' character) are ignored.
 ///
+/// Parsing does not stop at the first error. All errors found in the code are returned.
+///
+/// Each instruction, address and integer assignment takes 4 bytes, starting from the address
+/// given with "@at". String assignments and data directives (like ".word" and ".asciz")
+/// take as many bytes as their data. Branch and jump targets written as labels or as absolute addresses
+/// ("@<address>") are resolved into the instructions.
+///
+/// Pseudo-instructions (like `li` and `move`) are expanded into real instructions,
+/// each of which is a node of its own. Macros defined with "@macro" and "@endmacro"
+/// are expanded before parsing.
+///
 /// # Examples
 ///
 /// ```
-/// let nodes = parse_nodes("addiu sp, sp, 65496\nsw s0, 16(sp)");
+/// # use mips::{parse_nodes, Instruction, NodeKind};
+/// let nodes = parse_nodes("addiu sp, sp, 65496\nsw s0, 16(sp)").unwrap();
 /// assert_eq!(nodes.len(), 2);
+///
+/// let errors = parse_nodes("addiu sp, sp\nsw s0, 16(xx)").unwrap_err();
+/// assert_eq!(errors.len(), 2);
+///
+/// let nodes = parse_nodes("@at 0x80010000\nloop:\nbne v0, zero, loop\nnop").unwrap();
+/// assert_eq!(nodes[2].kind, NodeKind::Instruction(Instruction::parse_from_str("bne v0, zero, -1").unwrap()));
 /// ```
-pub fn parse_nodes(content: &str) -> Result<Vec<Node>, String> {
-    let content_lines = content.split("\n");
-
-    // Using this variable instead i iterator in the for loop below,
-    // because I wanted to use u64 instead of usize.
-    let mut current_line = 0u64;
-    let mut nodes = Vec::new();
+pub fn parse_nodes(content: &str) -> Result<Vec<Node>, Vec<Error>> {
+    parse_nodes_with_options(content, &ParseOptions::default())
+}
+/// Options for [parse_nodes_with_options].
+pub struct ParseOptions<'a> {
+    /// Labels not defined in the code, which can come from other files or from a symbol map.
+    pub external_labels: HashMap<String, u64>,
+    /// Directory that paths of files included with ".incbin" are relative to.
+    pub include_directory: PathBuf,
+    /// Encodes text of strings into bytes. By default, text is encoded with [encode_text].
+    pub encode_text: &'a dyn Fn(&str) -> Result<Vec<u8>, String>,
+    /// Addresses of sections started with "@section", which are chosen once sizes
+    /// of the sections are known (see [parse_section_sizes]). Other sections start at 0.
+    pub section_addresses: HashMap<String, u64>,
+}
+impl Default for ParseOptions<'_> {
+    fn default() -> Self {
+        Self {
+            external_labels: HashMap::new(),
+            include_directory: PathBuf::new(),
+            encode_text: &encode_text,
+            section_addresses: HashMap::new(),
+        }
+    }
+}
+/// Same as [parse_nodes], but with given options.
+pub fn parse_nodes_with_options(
+    content: &str,
+    options: &ParseOptions,
+) -> Result<Vec<Node>, Vec<Error>> {
+    let mut parser = parse_lines(content, options);
+    parser.resolve_relocations();
+
+    let NodeParser {
+        nodes, mut errors, ..
+    } = parser;
+    // Errors of relocations are found last, so sort errors to the order they appear in the code.
+    errors.sort_by_key(|error| error.span().map(|span| span.line));
+
+    if errors.is_empty() {
+        Ok(nodes)
+    } else {
+        Err(errors)
+    }
+}
+/// Gets addresses of labels and constants defined in given assembly code. Labels used
+/// in the code are not resolved, so files assembled together can get labels of each other
+/// (as [ParseOptions::external_labels]). Errors are ignored, as parsing the code finds them.
+///
+/// ```
+/// # use mips::{parse_labels, ParseOptions};
+/// let labels = parse_labels("@at 0x80010000\n  j other_file\n  nop\nhere:", &ParseOptions::default());
+/// assert_eq!(labels["here"], 0x80010008);
+/// ```
+pub fn parse_labels(content: &str, options: &ParseOptions) -> HashMap<String, u64> {
+    parse_lines(content, options).labels
+}
+/// Sections start aligned to instructions at least.
+const MIN_SECTION_ALIGNMENT: u64 = 4;
+/// Size of a section started with "@section", measured with the section starting at 0.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SectionSize {
+    pub name: String,
+    pub size: u64,
+    /// Alignment the start of the section needs, so that ".align" in it pads as it did
+    /// when measuring: 4 bytes, or the largest alignment of ".align" in the section.
+    pub alignment: u64,
+}
+/// Gets names and sizes of sections started with "@section" in given assembly code,
+/// in the order they appear. Errors are ignored, as parsing the code finds them.
+///
+/// A section is code or data which does not have to be at a given address, like new code
+/// added to an executable. Its name is a label of its start. It ends at the next "@at",
+/// "@hook" or "@section".
+///
+/// ```
+/// # use mips::{parse_section_sizes, ParseOptions};
+/// let content = "@section new_ability\n  jr ra\n  nop\n@at 0x80012340\n  jal new_ability";
+/// let sizes = parse_section_sizes(content, &ParseOptions::default());
+/// assert_eq!((sizes[0].name.as_str(), sizes[0].size), ("new_ability", 8));
+/// ```
+pub fn parse_section_sizes(content: &str, options: &ParseOptions) -> Vec<SectionSize> {
+    parse_lines(content, options).section_sizes
+}
+/// Parses all lines of given assembly code, leaving relocations unresolved.
+fn parse_lines<'a>(content: &str, options: &'a ParseOptions<'a>) -> NodeParser<'a> {
+    let mut parser = NodeParser {
+        nodes: Vec::new(),
+        errors: Vec::new(),
+        current_address: 0,
+        labels: HashMap::new(),
+        relocations: Vec::new(),
+        is_at_available: true,
+        section: None,
+        section_alignment: MIN_SECTION_ALIGNMENT,
+        section_sizes: Vec::new(),
+        options,
+    };
 
-    let mut current_address = 0u64;
+    let lines = expand_macros(content, &mut parser.errors);
+    for line in lines.iter() {
+        let error_count = parser.errors.len();
+        parser.parse_line(&line.text, line.line);
+
+        // Errors in code expanded from a macro are reported where the macro is used.
+        if let Some(macro_use) = &line.macro_use {
+            for error in parser.errors[error_count..].iter_mut() {
+                *error = Error::InMacro {
+                    name: macro_use.name.clone(),
+                    error: Box::new(error.clone()),
+                    span: macro_use.span.clone(),
+                };
+            }
+        }
+    }
+    parser.end_section();
+    parser
+}
+/// State of parsing nodes from assembly code line by line.
+struct NodeParser<'a> {
+    nodes: Vec<Node>,
+    errors: Vec<Error>,
+    current_address: u64,
+    labels: HashMap<String, u64>,
+    /// Values to fill into instructions once addresses of all labels are known.
+    /// Stores the node index, the kind of the value, the label (or "@<address>") and its span.
+    relocations: Vec<(usize, Relocation, String, Span)>,
+    /// Whether pseudo-instructions may use the at register. Changed with "@set at" and "@set noat".
+    is_at_available: bool,
+    /// Name and start address of the current section started with "@section".
+    section: Option<(String, u64)>,
+    /// Largest alignment needed by the current section (see [SectionSize::alignment]).
+    section_alignment: u64,
+    /// Sizes of sections ended so far.
+    section_sizes: Vec<SectionSize>,
+    options: &'a ParseOptions<'a>,
+}
+impl NodeParser<'_> {
+    /// Ends the current section, if any, storing its size.
+    fn end_section(&mut self) {
+        if let Some((name, start)) = self.section.take() {
+            self.section_sizes.push(SectionSize {
+                name,
+                size: self.current_address.saturating_sub(start),
+                alignment: self.section_alignment,
+            });
+        }
+    }
+    fn parse_line(&mut self, line: &str, current_line: u64) {
+        let line_without_comment = strip_comment(line);
+        let line = line_without_comment.trim();
 
-    for line in content_lines {
-        current_line += 1;
-        let line = line.split("#").collect::<Vec<&str>>();
-        let line = line[0].trim();
+        // Span of the whole line (without surrounding whitespace and comments) for errors.
+        let line_span = Span {
+            line: current_line,
+            column: line_without_comment.len() - line_without_comment.trim_start().len() + 1,
+            len: line.chars().count(),
+        };
 
         // If the line is empty, skip the line.
         if line.is_empty() {
-            continue;
+        }
+        // If the line contains a data directive, store a data node.
+        else if line.starts_with(".") {
+            self.parse_directive(line, line_span, current_line);
         }
         // If the line contains an address
-        else if line.starts_with(KEYWORD_ADDR) {
-            let addr = line[KEYWORD_ADDR.len()..].trim();
-            nodes.push(Node {
-                address: current_address,
-                kind: NodeKind::Addr(String::from(addr)),
-                line: current_line,
-            });
+        else if let Some(addr) = line.strip_prefix(KEYWORD_ADDR) {
+            let addr = addr.trim();
+            self.push_node(NodeKind::Addr(String::from(addr)), current_line);
         }
         // If the line contains a constant assignment
-        else if line.starts_with(KEYWORD_CONST) {
-            let assignment_parts = line[KEYWORD_CONST.len()..]
-                .split("=")
-                .collect::<Vec<&str>>();
+        else if let Some(assignment) = line.strip_prefix(KEYWORD_CONST) {
+            let assignment_parts = assignment.split("=").collect::<Vec<&str>>();
 
             if assignment_parts.len() != 2 {
-                return Err(format!(
-                    "Could not parse assignment \"{}\": Invalid assignment format",
-                    line
-                ));
+                self.errors.push(Error::Syntax {
+                    message: format!(
+                        "Could not parse assignment \"{}\": Invalid assignment format",
+                        line
+                    ),
+                    span: line_span,
+                });
+                return;
             }
 
             let variable_name = assignment_parts[0].trim().to_string();
             let variable_value = assignment_parts[1].trim();
+            // The value follows "const <name> =" and whitespace on the line.
+            let value_offset = line.len() - assignment_parts[1].trim_start().len();
+            let value_span = Span {
+                line: current_line,
+                column: line_span.column + line[..value_offset].chars().count(),
+                len: variable_value.chars().count(),
+            };
+
+            // Constants can be referred to by name like labels, for example with %hi and %lo.
+            if self
+                .labels
+                .insert(variable_name.clone(), self.current_address)
+                .is_some()
+            {
+                self.errors.push(Error::DuplicateLabel {
+                    label: variable_name,
+                    span: line_span,
+                });
+                return;
+            }
 
             // If the variable value is an integer
-            if let Ok(variable_value) = parse_immediate_signed(variable_value) {
-                nodes.push(Node {
-                    address: current_address,
-                    kind: NodeKind::IntegerAssignment(variable_name, variable_value as i32),
-                    line: current_line,
-                });
+            if parse_integer(variable_value).is_some() {
+                match parse_immediate_at(
+                    variable_value,
+                    value_span,
+                    i16::MIN as i64,
+                    i16::MAX as i64,
+                ) {
+                    Ok(variable_value) => self.push_node(
+                        NodeKind::IntegerAssignment(variable_name, variable_value as i32),
+                        current_line,
+                    ),
+                    Err(error) => self.errors.push(error),
+                }
             }
             // If the variable value is a string
-            else if variable_value.starts_with("\"") && variable_value.ends_with("\"") {
+            else if variable_value.len() >= 2
+                && variable_value.starts_with("\"")
+                && variable_value.ends_with("\"")
+            {
                 let variable_value = variable_value[1..variable_value.len() - 1].to_string();
-                nodes.push(Node {
-                    address: current_address,
+                // The string takes as many bytes as its encoded text.
+                let len = match (self.options.encode_text)(&variable_value) {
+                    Ok(bytes) => bytes.len() as u64,
+                    Err(message) => {
+                        self.errors.push(Error::Syntax {
+                            message,
+                            span: line_span,
+                        });
+                        return;
+                    }
+                };
+                self.nodes.push(Node {
+                    address: self.current_address,
                     kind: NodeKind::StringAssignment(variable_name, variable_value),
                     line: current_line,
                 });
+                self.current_address += len;
             }
             // If the variable value is something else
             else {
-                return Err(format!(
-                    "Could not parse assignment \"{}\": Invalid value format. Only integers and strings marked with double quotes are supported.",
-                    line
-                ));
+                self.errors.push(Error::Syntax {
+                    message: format!(
+                        "Could not parse assignment \"{}\": Invalid value format. Only integers and strings marked with double quotes are supported.",
+                        line
+                    ),
+                    span: line_span,
+                });
             }
         }
         // If the line contains a label, store a label node.
-        else if line.ends_with(":") {
-            let label = line[..line.len() - 1].to_string();
-            nodes.push(Node {
-                address: current_address,
+        else if let Some(label) = line.strip_suffix(":") {
+            let label = label.trim().to_string();
+            if self
+                .labels
+                .insert(label.clone(), self.current_address)
+                .is_some()
+            {
+                self.errors.push(Error::DuplicateLabel {
+                    label,
+                    span: line_span,
+                });
+                return;
+            }
+            self.nodes.push(Node {
+                address: self.current_address,
                 kind: NodeKind::Label(label),
                 line: current_line,
             });
         }
         // If the line contains a custom command (starting with '@'), store a custom command node.
-        else if line.starts_with("@") {
-            let custom_command_parts = line[1..].split(" ").collect::<Vec<&str>>();
+        else if let Some(custom_command) = line.strip_prefix("@") {
+            let custom_command_parts = custom_command.split(" ").collect::<Vec<&str>>();
 
             match &custom_command_parts[..] {
-                ["at", address] => {
-                    let address = parse_immediate_unsigned_u64(&address)?;
-                    current_address = address;
-                    nodes.push(Node {
-                        address: current_address,
-                        kind: NodeKind::CustomCommand(CustomCommand::At(address)),
+                [keyword @ ("at" | "hook"), address] => match parse_address(
+                    address,
+                    // The address follows "@<keyword> " on the line.
+                    Span {
+                        line: current_line,
+                        column: line_span.column + keyword.len() + 2,
+                        len: address.chars().count(),
+                    },
+                ) {
+                    Ok(address) => {
+                        self.end_section();
+                        self.current_address = address;
+                        let custom_command = match *keyword {
+                            "at" => CustomCommand::At(address),
+                            _ => CustomCommand::Hook(address),
+                        };
+                        self.nodes.push(Node {
+                            address,
+                            kind: NodeKind::CustomCommand(custom_command),
+                            line: current_line,
+                        });
+                    }
+                    Err(error) => self.errors.push(error),
+                },
+                ["section", name] => {
+                    self.end_section();
+                    let name = name.to_string();
+                    let address = self
+                        .options
+                        .section_addresses
+                        .get(&name)
+                        .copied()
+                        .unwrap_or(0);
+                    // The name of a section is also a label of its start.
+                    if self.labels.insert(name.clone(), address).is_some() {
+                        self.errors.push(Error::DuplicateLabel {
+                            label: name,
+                            span: line_span,
+                        });
+                        return;
+                    }
+                    self.current_address = address;
+                    self.section = Some((name.clone(), address));
+                    self.section_alignment = MIN_SECTION_ALIGNMENT;
+                    self.nodes.push(Node {
+                        address,
+                        kind: NodeKind::CustomCommand(CustomCommand::Section(name)),
                         line: current_line,
                     });
                 }
+                ["set", "at"] => self.is_at_available = true,
+                ["set", "noat"] => self.is_at_available = false,
                 _ => {
-                    return Err(format!(
-                        "Could not parse custom command \"{}\" (line {}): Invalid custom command format.",
-                        line, current_line
-                    ));
+                    self.errors.push(Error::Syntax {
+                        message: format!(
+                            "Could not parse custom command \"{}\": Invalid custom command format.",
+                            line
+                        ),
+                        span: line_span,
+                    });
                 }
             }
         }
         // If the line (should) contain an instruction, store an instruction node.
         else {
-            let instruction = Instruction::parse_from_str(line)?;
-            nodes.push(Node {
-                address: current_address,
-                kind: NodeKind::Instruction(instruction),
-                line: current_line,
-            });
+            let mut tokens = tokenize(line_without_comment, current_line);
+
+            if let Some(instructions) = parse_pseudo_instruction(&tokens, self.is_at_available) {
+                match instructions {
+                    Ok(instructions) => {
+                        for (instruction, relocation) in instructions {
+                            let relocations = relocation
+                                .map(|(relocation, token)| {
+                                    (relocation, String::from(token.text), token.span())
+                                })
+                                .into_iter()
+                                .collect();
+                            self.push_instruction(instruction, relocations, current_line);
+                        }
+                    }
+                    Err(error) => {
+                        self.errors.push(error);
+                        self.current_address += 4;
+                    }
+                }
+                return;
+            }
+
+            // A target that is not a number is resolved once addresses of all labels are known.
+            // Meanwhile, the instruction is parsed with a zero target.
+            let mut relocations = Vec::new();
+            if tokens.len() > 1 && TARGET_MNEMONICS.contains(&tokens[0].text) {
+                let last = tokens.len() - 1;
+                if parse_integer(tokens[last].text).is_none() {
+                    relocations.push((
+                        Relocation::Target,
+                        String::from(tokens[last].text),
+                        tokens[last].span(),
+                    ));
+                    tokens[last] = Token {
+                        text: "0",
+                        ..tokens[last]
+                    };
+                }
+            }
+
+            // Same goes for %hi and %lo operators, which are replaced with zero.
+            let operators = match parse_hi_lo_operators(&tokens) {
+                Ok(operators) => operators,
+                Err(error) => {
+                    self.errors.push(error);
+                    self.current_address += 4;
+                    return;
+                }
+            };
+            for operator in operators.iter() {
+                tokens[operator.token_index].text = &operator.operand;
+                relocations.push((
+                    operator.relocation,
+                    operator.target.clone(),
+                    operator.span.clone(),
+                ));
+            }
+
+            match Instruction::parse_from_tokens(&tokens) {
+                Ok(instruction) => self.push_instruction(instruction, relocations, current_line),
+                Err(error) => {
+                    self.errors.push(error);
+                    self.current_address += 4;
+                }
+            }
         }
     }
-
-    Ok(nodes)
-}
-/// Parses a register from a string.
-///
-/// The string must be in the format $<register_number> (like $1) or <register_name> (like "ra").
-/// The register number must be between 0 and 31 (a total of 32 possible registers).
-/// If the register number is out of range, an error is returned.
-/// If the string is not in the correct format, an error is returned.
-/// If the string is in the correct format and the register number is in range, the register number is returned.
-///
-/// # Examples
-/// ```
-/// let register_number = parse_register_value("$0").unwrap();
-/// assert_eq!(register_number, 0);
-///
-/// let register_number = parse_register_value("ra").unwrap();
-/// assert_eq!(register_number, 31);
-/// ```
-fn parse_register_value(content: &str) -> Result<u8, String> {
-    if content.starts_with("$") {
-        let register_number = content[1..].parse::<u8>().unwrap();
-        if register_number > 31 {
-            Err(format!(
-                "Register number given ({}) is out of range (0-31)",
-                register_number
-            ))
-        } else {
-            Ok(register_number)
+    /// Stores a node taking 4 bytes.
+    fn push_node(&mut self, kind: NodeKind, line: u64) {
+        self.nodes.push(Node {
+            address: self.current_address,
+            kind,
+            line,
+        });
+        self.current_address += 4;
+    }
+    /// Stores an instruction node with values to fill into it once addresses of labels are known.
+    fn push_instruction(
+        &mut self,
+        instruction: Instruction,
+        relocations: Vec<(Relocation, String, Span)>,
+        line: u64,
+    ) {
+        for (relocation, target, span) in relocations {
+            self.relocations
+                .push((self.nodes.len(), relocation, target, span));
         }
-    } else {
-        for (i, register) in REGISTERS.iter().enumerate() {
-            if content == *register {
-                return Ok(i as u8);
+        self.push_node(NodeKind::Instruction(instruction), line);
+    }
+    fn resolve_relocations(&mut self) {
+        for (node_index, relocation, target, span) in std::mem::take(&mut self.relocations) {
+            let target_address = match target.strip_prefix("@") {
+                Some(address) => parse_address(address, span.clone()),
+                None => self
+                    .labels
+                    .get(&target)
+                    .or_else(|| self.options.external_labels.get(&target))
+                    .copied()
+                    .ok_or_else(|| Error::UndefinedLabel {
+                        label: target.clone(),
+                        span: span.clone(),
+                    }),
+            };
+            let node = &mut self.nodes[node_index];
+            let result = target_address.and_then(|target_address| match &mut node.kind {
+                NodeKind::Data(bytes) => {
+                    let Relocation::Word { offset } = relocation else {
+                        unreachable!()
+                    };
+                    bytes[offset..offset + 4]
+                        .copy_from_slice(&(target_address as u32).to_le_bytes());
+                    Ok(())
+                }
+                NodeKind::Instruction(instruction) => {
+                    instruction.set_relocation(relocation, node.address, target_address, span)
+                }
+                _ => unreachable!(),
+            });
+            if let Err(error) = result {
+                self.errors.push(error);
             }
         }
+    }
+}
+/// A "%hi(<name>)" or "%lo(<name>)" operator in an operand of an instruction.
+struct HiLoOperator {
+    token_index: usize,
+    /// Operand with the operator replaced with zero, like "0(a0)" for "%lo(label)(a0)".
+    operand: String,
+    relocation: Relocation,
+    target: String,
+    span: Span,
+}
 
-        let content_as_number = content.parse::<u8>();
-        match content_as_number {
-            Ok(valid_number) => {
-                if valid_number > 31 {
-                    Err(format!(
-                        "Unknown register \"{}\". Maybe you meant \"${}\" (also make sure the register in range 0-31)?",
-                        valid_number,
-                        valid_number
-                    ))
-                } else {
-                    Err(format!(
-                        "Unknown register \"{}\". . Maybe you meant \"${}\"?",
-                        content, content
-                    ))
-                }
+/// Parses "%hi(<name>)" and "%lo(<name>)" operators from operands of an instruction.
+/// The name is a label, a constant, an absolute address ("@<address>") or a number.
+fn parse_hi_lo_operators(tokens: &[Token]) -> Result<Vec<HiLoOperator>, Error> {
+    let mut operators = Vec::new();
+    for (i, token) in tokens.iter().enumerate().skip(1) {
+        let relocation = if token.text.starts_with("%hi(") {
+            Relocation::Hi
+        } else if token.text.starts_with("%lo(") {
+            Relocation::Lo
+        } else {
+            continue;
+        };
+
+        let name_start = "%hi(".len();
+        let name_end = match token.text.find(")") {
+            Some(name_end) if name_end > name_start => name_end,
+            _ => {
+                return Err(Error::Syntax {
+                    message: format!(
+                        "Could not parse operator \"{}\": expected %hi(<name>) or %lo(<name>)",
+                        token.text
+                    ),
+                    span: token.span(),
+                })
             }
-            Err(_) => Err(format!("Unknown register \"{}\".", content)),
-        }
+        };
+        let name = token.slice(name_start..name_end);
+        let target = match parse_integer(name.text) {
+            Some(_) => format!("@{}", name.text),
+            None => String::from(name.text),
+        };
+        let operand = format!("0{}", &token.text[name_end + 1..]);
+        operators.push(HiLoOperator {
+            token_index: i,
+            operand,
+            relocation,
+            target,
+            span: name.span(),
+        });
     }
+    if operators.len() > 1 {
+        return Err(Error::Syntax {
+            message: String::from("Only one %hi or %lo operator is allowed in an instruction"),
+            span: get_span_of_tokens(tokens),
+        });
+    }
+    Ok(operators)
+}
+/// Parses a register from a token.
+///
+/// The token must be in the format $<register_number> (like $1) or <register_name> (like "ra").
+/// The register number must be between 0 and 31 (a total of 32 possible registers).
+/// If the token is not in the correct format or the register number is out of range,
+/// an error is returned.
+fn parse_register(token: Token) -> Result<u8, Error> {
+    let register_number = match token.text.strip_prefix("$") {
+        Some(register_number) => register_number.parse::<u8>().ok().filter(|n| *n <= 31),
+        None => REGISTERS
+            .iter()
+            .position(|register| token.text == *register)
+            .map(|i| i as u8),
+    };
+    register_number.ok_or_else(|| Error::BadRegister {
+        register: String::from(token.text),
+        span: token.span(),
+    })
+}
+fn parse_cop_register_token(names: &[&str; 32], token: Token) -> Result<u8, Error> {
+    parse_cop_register(names, token.text).map_err(|_| Error::BadRegister {
+        register: String::from(token.text),
+        span: token.span(),
+    })
 }
 
 #[cfg(test)]
@@ -1205,29 +1866,418 @@ mod tests {
 
     #[test]
     fn parse_negative_immediate_from_signed_value() {
-        let result = parse_immediate_signed("-32");
+        let span = Span {
+            line: 1,
+            column: 1,
+            len: 3,
+        };
+        let result = parse_immediate_at("-32", span.clone(), i16::MIN as i64, i16::MAX as i64);
         assert_eq!(result, Ok(-32));
 
-        let result = parse_immediate_signed("-0x20"); // -32 = 0xFFE0
+        let result = parse_immediate_at("-0x20", span, i16::MIN as i64, i16::MAX as i64); // -32 = 0xFFE0
         assert_eq!(result, Ok(-32));
     }
     #[test]
     fn parse_positive_immediate_from_signed_value() {
-        let result = parse_immediate_signed("32");
+        let span = Span {
+            line: 1,
+            column: 1,
+            len: 2,
+        };
+        let result = parse_immediate_at("32", span.clone(), i16::MIN as i64, i16::MAX as i64);
         assert_eq!(result, Ok(32));
 
-        let result = parse_immediate_signed("0x20"); // 32 = 0x20
+        let result = parse_immediate_at("0x20", span, i16::MIN as i64, i16::MAX as i64); // 32 = 0x20
         assert_eq!(result, Ok(32));
     }
 
+    mod errors {
+        use super::*;
+
+        #[test]
+        fn fail_parse_instruction_with_unknown_mnemonic() {
+            let result = Instruction::parse_from_str("  addx t0, t1, t2");
+            assert_eq!(
+                result,
+                Err(Error::UnknownMnemonic {
+                    mnemonic: String::from("addx"),
+                    span: Span {
+                        line: 1,
+                        column: 3,
+                        len: 4
+                    },
+                })
+            );
+        }
+        #[test]
+        fn fail_parse_instruction_with_wrong_operand_count() {
+            let result = Instruction::parse_from_str("add t0, t1");
+            assert_eq!(
+                result,
+                Err(Error::WrongOperandCount {
+                    mnemonic: String::from("add"),
+                    expected: vec![3],
+                    found: 2,
+                    span: Span {
+                        line: 1,
+                        column: 1,
+                        len: 10
+                    },
+                })
+            );
+        }
+        #[test]
+        fn fail_parse_instruction_with_bad_register() {
+            let result = Instruction::parse_from_str("lw t0, 16($32)");
+            assert_eq!(
+                result,
+                Err(Error::BadRegister {
+                    register: String::from("$32"),
+                    span: Span {
+                        line: 1,
+                        column: 11,
+                        len: 3
+                    },
+                })
+            );
+        }
+        #[test]
+        fn fail_parse_instruction_with_immediate_out_of_range() {
+            let result = Instruction::parse_from_str("sll t0, t1, 32");
+            assert_eq!(
+                result,
+                Err(Error::ImmediateOutOfRange {
+                    immediate: String::from("32"),
+                    min: 0,
+                    max: 31,
+                    span: Span {
+                        line: 1,
+                        column: 13,
+                        len: 2
+                    },
+                })
+            );
+        }
+        #[test]
+        fn fail_parse_addresses_and_constants_out_of_range() {
+            let errors =
+                parse_nodes("@at 0x100000000\n  const LIMIT = 40000\n  j @-4").unwrap_err();
+            assert_eq!(
+                errors[0],
+                Error::ImmediateOutOfRange {
+                    immediate: String::from("0x100000000"),
+                    min: 0,
+                    max: u32::MAX as i64,
+                    span: Span {
+                        line: 1,
+                        column: 5,
+                        len: 11
+                    },
+                }
+            );
+            assert_eq!(
+                errors[1],
+                Error::ImmediateOutOfRange {
+                    immediate: String::from("40000"),
+                    min: i16::MIN as i64,
+                    max: i16::MAX as i64,
+                    span: Span {
+                        line: 2,
+                        column: 17,
+                        len: 5
+                    },
+                }
+            );
+            // Span of a target address covers the "@" too.
+            assert_eq!(
+                errors[2],
+                Error::ImmediateOutOfRange {
+                    immediate: String::from("-4"),
+                    min: 0,
+                    max: u32::MAX as i64,
+                    span: Span {
+                        line: 3,
+                        column: 5,
+                        len: 3
+                    },
+                }
+            );
+        }
+        #[test]
+        fn fail_parse_instruction_with_unknown_opcode() {
+            let result = Instruction::parse_from_machine_code(0xFC000000);
+            assert_eq!(
+                result,
+                Err(Error::UnknownOpcode {
+                    machine_code: 0xFC000000
+                })
+            );
+
+            // Funct 1 is not in use
+            assert!(Instruction::parse_from_machine_code(0x00000001).is_err());
+        }
+        #[test]
+        fn fail_disassemble_instruction_with_unknown_opcode() {
+            let instruction = Instruction::J {
+                opcode: 0b111111,
+                address: 0,
+            };
+            assert_eq!(
+                instruction.to_instruction(),
+                Err(Error::UnknownOpcode {
+                    machine_code: 0xFC000000
+                })
+            );
+        }
+        #[test]
+        fn collect_all_errors_from_nodes() {
+            let content = "addiu sp, sp, -8\nnop\n  jr ra, v0 # Comment\nlw t0, 0(zz)";
+            let errors = parse_nodes(content).unwrap_err();
+            assert_eq!(errors.len(), 3);
+            assert_eq!(errors[0].span().unwrap().line, 1);
+            assert_eq!(
+                errors[1].to_string_with_snippet(content),
+                "Instruction \"jr\" takes 1 operands, but 2 were given (line 3, column 3)\n    3 |   jr ra, v0 # Comment\n      |   ^^^^^^^^^"
+            );
+            assert_eq!(
+                errors[2].span(),
+                Some(&Span {
+                    line: 4,
+                    column: 10,
+                    len: 2
+                })
+            );
+        }
+    }
+
+    mod targets {
+        use super::*;
+
+        fn get_instructions(content: &str) -> Vec<u32> {
+            parse_nodes(content)
+                .unwrap()
+                .iter()
+                .filter_map(|node| match &node.kind {
+                    NodeKind::Instruction(instruction) => Some(instruction.to_machine_code()),
+                    _ => None,
+                })
+                .collect()
+        }
+
+        #[test]
+        fn resolve_branch_targets_from_labels() {
+            let content = "@at 0x80010000\nloop:\n  beq a0, zero, loop_end\n  nop\n  bne a0, v0, loop\n  nop\nloop_end:\n  jr ra";
+            let instructions = get_instructions(content);
+            // Offsets are counted in instructions from the delay slot.
+            assert_eq!(
+                instructions[0],
+                Instruction::parse_from_str("beq a0, zero, 3")
+                    .unwrap()
+                    .to_machine_code()
+            );
+            assert_eq!(
+                instructions[2],
+                Instruction::parse_from_str("bne a0, v0, -3")
+                    .unwrap()
+                    .to_machine_code()
+            );
+        }
+        #[test]
+        fn resolve_jump_targets_from_labels_and_absolute_addresses() {
+            let content =
+                "@at 0x80010000\n  jal function\n  nop\n  j @0x80012340\n  nop\nfunction:\n  jr ra";
+            let instructions = get_instructions(content);
+            assert_eq!(instructions[0], 0x0C004004); // 0x80010010 >> 2 (lower 26 bits)
+            assert_eq!(instructions[2], 0x080048D0); // 0x80012340 >> 2 (lower 26 bits)
+        }
+        #[test]
+        fn resolve_targets_from_external_labels() {
+            let options = ParseOptions {
+                external_labels: HashMap::from([(String::from("UpdateSpyroState"), 0x80062FD4)]),
+                ..ParseOptions::default()
+            };
+            let nodes =
+                parse_nodes_with_options("@at 0x80010000\n  jal UpdateSpyroState", &options)
+                    .unwrap();
+            assert_eq!(
+                nodes[1].kind,
+                NodeKind::Instruction(Instruction::J {
+                    opcode: 3,
+                    address: 101365
+                })
+            );
+        }
+        #[test]
+        fn keep_raw_target_values() {
+            let instructions =
+                get_instructions("@at 0x80010000\n  bne v0, zero, -19\n  jal 101365");
+            assert_eq!(instructions[0], 0x1440FFED);
+            assert_eq!(instructions[1], 0x0C018BF5);
+        }
+        #[test]
+        fn disassemble_targets_as_absolute_addresses() {
+            let labels = HashMap::new();
+            let instruction = Instruction::parse_from_machine_code(0x1440FFED).unwrap();
+            assert_eq!(
+                instruction.to_instruction_at(0x80010100, &labels).unwrap(),
+                "bne v0, zero, @0x800100B8"
+            );
+            let instruction = Instruction::parse_from_machine_code(0x0C018BF5).unwrap();
+            assert_eq!(
+                instruction.to_instruction_at(0x80010000, &labels).unwrap(),
+                "jal @0x80062FD4"
+            );
+            let instruction = Instruction::parse_from_str("addiu sp, sp, 8").unwrap();
+            assert_eq!(
+                instruction.to_instruction_at(0x80010000, &labels).unwrap(),
+                "addiu sp, sp, 8"
+            );
+        }
+        #[test]
+        fn fail_resolve_undefined_and_duplicate_labels() {
+            let content = "@at 0x80010000\nloop:\n  beq a0, zero, nowhere\nloop:";
+            let errors = parse_nodes(content).unwrap_err();
+            assert_eq!(
+                errors,
+                vec![
+                    Error::UndefinedLabel {
+                        label: String::from("nowhere"),
+                        span: Span {
+                            line: 3,
+                            column: 17,
+                            len: 7
+                        },
+                    },
+                    Error::DuplicateLabel {
+                        label: String::from("loop"),
+                        span: Span {
+                            line: 4,
+                            column: 1,
+                            len: 5
+                        },
+                    },
+                ]
+            );
+        }
+        #[test]
+        fn fail_resolve_targets_out_of_range() {
+            let errors = parse_nodes("@at 0x80010000\n  beq a0, zero, @0x80030004").unwrap_err();
+            assert!(matches!(
+                errors[0],
+                Error::TargetOutOfRange {
+                    address: 0x80010000,
+                    target: 0x80030004,
+                    ..
+                }
+            ));
+
+            // Jumps cannot leave the 256 MB region of the delay slot.
+            let errors = parse_nodes("@at 0x80010000\n  j @0x00010000").unwrap_err();
+            assert!(matches!(errors[0], Error::TargetOutOfRange { .. }));
+
+            let errors = parse_nodes("@at 0x80010000\n  j @0x80010002").unwrap_err();
+            assert!(matches!(errors[0], Error::Syntax { .. }));
+        }
+        #[test]
+        fn resolve_hi_lo_operators() {
+            // Bit 15 of the address is set, so the upper half is rounded up.
+            let content = "@at 0x80010000\n  lui a0, %hi(name)\n  addiu a0, a0, %lo(name)\n  lw t0, %lo(name)(a0)\n  lui a1, %hi(0x80078000)\n@at 0x80078000\nconst name = \"SPYRO\"";
+            let instructions = get_instructions(content);
+            assert_eq!(
+                instructions[0],
+                Instruction::parse_from_str("lui a0, 0x8008")
+                    .unwrap()
+                    .to_machine_code()
+            );
+            assert_eq!(
+                instructions[1],
+                Instruction::parse_from_str("addiu a0, a0, 0x8000")
+                    .unwrap()
+                    .to_machine_code()
+            );
+            assert_eq!(
+                instructions[2],
+                Instruction::parse_from_str("lw t0, -32768(a0)")
+                    .unwrap()
+                    .to_machine_code()
+            );
+            assert_eq!(instructions[3], instructions[0] + (1 << 16)); // Register a1 instead of a0
+        }
+        #[test]
+        fn fail_resolve_hi_lo_operators() {
+            let errors = parse_nodes(
+                "  lui a0, %hi(nowhere)\n  addiu a0, a0, %lo()\n  addu a0, a0, %lo(a0)",
+            )
+            .unwrap_err();
+            assert_eq!(errors.len(), 3);
+            assert!(matches!(errors[0], Error::UndefinedLabel { .. }));
+            assert!(matches!(errors[1], Error::Syntax { .. }));
+            assert!(matches!(errors[2], Error::BadRegister { .. }));
+        }
+        #[test]
+        fn resolve_targets_in_sections() {
+            let content = "@hook 0x80012340\n  jal new_ability\n@section new_ability\n  j @0x80012348\n  nop\n@section new_data\n.word new_ability";
+            let mut options = ParseOptions::default();
+            let sizes = parse_section_sizes(content, &options);
+            assert_eq!(
+                sizes
+                    .iter()
+                    .map(|section| (section.name.as_str(), section.size, section.alignment))
+                    .collect::<Vec<_>>(),
+                [("new_ability", 8, 4), ("new_data", 4, 4)]
+            );
+            // Sections with ".align" need their start aligned as much, so that their size stays the same.
+            let sizes = parse_section_sizes(
+                "@section a\n.byte 1\n.align 4\n.align 3\n@section b\n.word 1",
+                &options,
+            );
+            assert_eq!((sizes[0].size, sizes[0].alignment), (16, 16));
+            assert_eq!((sizes[1].size, sizes[1].alignment), (4, 4));
+
+            options.section_addresses = HashMap::from([(String::from("new_ability"), 0x80070000)]);
+            let nodes = parse_nodes_with_options(content, &options).unwrap();
+            assert_eq!(
+                nodes[0].kind,
+                NodeKind::CustomCommand(CustomCommand::Hook(0x80012340))
+            );
+            assert_eq!(
+                nodes[1].kind,
+                NodeKind::Instruction(Instruction::parse_from_str("jal 0x1C000").unwrap())
+            );
+            assert_eq!(
+                nodes[2].kind,
+                NodeKind::CustomCommand(CustomCommand::Section(String::from("new_ability")))
+            );
+            assert_eq!(nodes[3].address, 0x80070000);
+            // Sections without an address start at 0.
+            assert_eq!(
+                (nodes[5].address, &nodes[6].kind),
+                (0, &NodeKind::Data(vec![0x00, 0x00, 0x07, 0x80]))
+            );
+
+            let errors = parse_nodes("@section a\nnop\n@section a").unwrap_err();
+            assert_eq!(
+                errors[0],
+                Error::DuplicateLabel {
+                    label: String::from("a"),
+                    span: Span {
+                        line: 3,
+                        column: 1,
+                        len: 10
+                    }
+                }
+            );
+        }
+    }
+
     mod add {
         use super::*;
 
         #[test]
         fn disassemble_add_instruction_from_machine_code() {
-            let instruction = Instruction::parse_from_machine_code(0x012A4020);
-            println!("{}", instruction.to_instruction());
-            assert_eq!(instruction.to_instruction(), "add t0, t1, t2");
+            let instruction = Instruction::parse_from_machine_code(0x012A4020).unwrap();
+            println!("{}", instruction.to_instruction().unwrap());
+            assert_eq!(instruction.to_instruction().unwrap(), "add t0, t1, t2");
         }
         #[test]
         fn parse_add_instruction_from_bytes() {
@@ -1235,16 +2285,16 @@ mod tests {
             let result_hex = 0x012A4020;
             assert_eq!(result_bin, result_hex);
 
-            let instruction = Instruction::parse_from_be_bytes(&[0x01, 0x2A, 0x40, 0x20]);
+            let instruction = Instruction::parse_from_be_bytes(&[0x01, 0x2A, 0x40, 0x20]).unwrap();
             assert_eq!(instruction.to_machine_code(), result_bin);
 
-            let instruction = Instruction::parse_from_le_bytes(&[0x20, 0x40, 0x2A, 0x01]);
+            let instruction = Instruction::parse_from_le_bytes(&[0x20, 0x40, 0x2A, 0x01]).unwrap();
             assert_eq!(instruction.to_machine_code(), result_bin);
         }
         #[test]
         fn parse_add_instruction_from_machine_code() {
             // add t0, t1, t2
-            let instruction = Instruction::parse_from_machine_code(0x012A4020);
+            let instruction = Instruction::parse_from_machine_code(0x012A4020).unwrap();
             let result_bin = 0b00000001001010100100000000100000;
             let result_hex = 0x012A4020;
             assert_eq!(result_bin, result_hex);
@@ -1265,8 +2315,9 @@ mod tests {
 
         #[test]
         fn disassemble_addi_instruction_from_machine_code_with_positive_immediate() {
-            let instruction = Instruction::parse_from_machine_code(0x21280020);
-            assert_eq!(instruction.to_instruction(), "addi t0, t1, 32"); // 32 = 0x20
+            let instruction = Instruction::parse_from_machine_code(0x21280020).unwrap();
+            assert_eq!(instruction.to_instruction().unwrap(), "addi t0, t1, 32");
+            // 32 = 0x20
         }
         #[test]
         fn parse_addi_instruction_from_bytes() {
@@ -1275,16 +2326,16 @@ mod tests {
             let result_hex = 0x21280020;
             assert_eq!(result_bin, result_hex);
 
-            let instruction = Instruction::parse_from_be_bytes(&[0x21, 0x28, 0x00, 0x20]);
+            let instruction = Instruction::parse_from_be_bytes(&[0x21, 0x28, 0x00, 0x20]).unwrap();
             assert_eq!(instruction.to_machine_code(), result_bin);
 
-            let instruction = Instruction::parse_from_le_bytes(&[0x20, 0x00, 0x28, 0x21]);
+            let instruction = Instruction::parse_from_le_bytes(&[0x20, 0x00, 0x28, 0x21]).unwrap();
             assert_eq!(instruction.to_machine_code(), result_bin);
         }
         #[test]
         fn parse_addi_instruction_from_machine_code() {
             // addi t0, t1, 0x20
-            let instruction = Instruction::parse_from_machine_code(0x21280020);
+            let instruction = Instruction::parse_from_machine_code(0x21280020).unwrap();
             let result_bin = 0b00100001001010000000000000100000;
             let result_hex = 0x21280020;
             assert_eq!(result_bin, result_hex);
@@ -1315,8 +2366,9 @@ mod tests {
 
         #[test]
         fn disassemble_addiu_instruction_from_machine_code_with_positive_immediate() {
-            let instruction = Instruction::parse_from_machine_code(0x25280020);
-            assert_eq!(instruction.to_instruction(), "addiu t0, t1, 32"); // 32 = 0x20
+            let instruction = Instruction::parse_from_machine_code(0x25280020).unwrap();
+            assert_eq!(instruction.to_instruction().unwrap(), "addiu t0, t1, 32");
+            // 32 = 0x20
         }
         #[test]
         fn parse_addiu_instruction_from_bytes() {
@@ -1325,16 +2377,16 @@ mod tests {
             let result_hex = 0x25280020;
             assert_eq!(result_bin, result_hex);
 
-            let instruction = Instruction::parse_from_be_bytes(&[0x25, 0x28, 0x00, 0x20]);
+            let instruction = Instruction::parse_from_be_bytes(&[0x25, 0x28, 0x00, 0x20]).unwrap();
             assert_eq!(instruction.to_machine_code(), result_bin);
 
-            let instruction = Instruction::parse_from_le_bytes(&[0x20, 0x00, 0x28, 0x25]);
+            let instruction = Instruction::parse_from_le_bytes(&[0x20, 0x00, 0x28, 0x25]).unwrap();
             assert_eq!(instruction.to_machine_code(), result_bin);
         }
         #[test]
         fn parse_addiu_instruction_from_machine_code() {
             // addiu t0, t1, 0x20
-            let instruction = Instruction::parse_from_machine_code(0x25280020);
+            let instruction = Instruction::parse_from_machine_code(0x25280020).unwrap();
             let result_bin = 0b00100101001010000000000000100000;
             let result_hex = 0x25280020;
             assert_eq!(result_bin, result_hex);
@@ -1363,17 +2415,17 @@ mod tests {
         #[test]
         fn disassemble_beq_instruction_from_bytes() {
             // beq v0, zero, 0x02
-            let instruction = Instruction::parse_from_le_bytes(&[0x02, 0x00, 0x40, 0x10]);
-            assert_eq!(instruction.to_instruction(), "beq v0, zero, 2");
+            let instruction = Instruction::parse_from_le_bytes(&[0x02, 0x00, 0x40, 0x10]).unwrap();
+            assert_eq!(instruction.to_instruction().unwrap(), "beq v0, zero, 2");
 
             // beq a0, zero, 0x09
-            let instruction = Instruction::parse_from_le_bytes(&[0x09, 0x00, 0x80, 0x10]);
-            assert_eq!(instruction.to_instruction(), "beq a0, zero, 9");
+            let instruction = Instruction::parse_from_le_bytes(&[0x09, 0x00, 0x80, 0x10]).unwrap();
+            assert_eq!(instruction.to_instruction().unwrap(), "beq a0, zero, 9");
         }
         #[test]
         fn parse_beq_instruction_from_machine_code() {
             // beq v0, zero, 0x02
-            let instruction = Instruction::parse_from_machine_code(0x10400002);
+            let instruction = Instruction::parse_from_machine_code(0x10400002).unwrap();
             let result_bin = 0b00010000010000000000000000000010;
             let result_hex = 0x10400002;
             assert_eq!(result_bin, result_hex);
@@ -1387,13 +2439,13 @@ mod tests {
         #[test]
         fn disassemble_bgtz_instruction_from_bytes() {
             // bgtz v0, 0x02
-            let instruction = Instruction::parse_from_le_bytes(&[0x02, 0x00, 0x40, 0x1C]);
-            assert_eq!(instruction.to_instruction(), "bgtz v0, 2");
+            let instruction = Instruction::parse_from_le_bytes(&[0x02, 0x00, 0x40, 0x1C]).unwrap();
+            assert_eq!(instruction.to_instruction().unwrap(), "bgtz v0, 2");
         }
         #[test]
         fn parse_bgtz_instruction_from_machine_code() {
             // bgtz t0, 0xc8
-            let instruction = Instruction::parse_from_machine_code(0x1D0000C8);
+            let instruction = Instruction::parse_from_machine_code(0x1D0000C8).unwrap();
             let result_bin = 0b00011101000000000000000011001000;
             let result_hex = 0x1D0000C8;
             assert_eq!(result_bin, result_hex);
@@ -1416,14 +2468,14 @@ mod tests {
         #[test]
         fn disassemble_bne_instruction_from_bytes() {
             // bne v0, zero, 0x04
-            let instruction = Instruction::parse_from_le_bytes(&[0x04, 0x00, 0x40, 0x14]);
-            assert_eq!(instruction.to_instruction(), "bne v0, zero, 4");
+            let instruction = Instruction::parse_from_le_bytes(&[0x04, 0x00, 0x40, 0x14]).unwrap();
+            assert_eq!(instruction.to_instruction().unwrap(), "bne v0, zero, 4");
         }
         #[test]
         fn disassemble_bne_instruction_from_machine_code() {
             // bne v0, zero, 0x04
-            let instruction = Instruction::parse_from_machine_code(0x14400004);
-            assert_eq!(instruction.to_instruction(), "bne v0, zero, 4");
+            let instruction = Instruction::parse_from_machine_code(0x14400004).unwrap();
+            assert_eq!(instruction.to_instruction().unwrap(), "bne v0, zero, 4");
         }
         #[test]
         fn parse_bne_instruction_from_string() {
@@ -1446,15 +2498,15 @@ mod tests {
             let result_hex = 0xc014870;
             assert_eq!(result_bin, result_hex);
 
-            let instruction = Instruction::parse_from_le_bytes(&[0x70, 0x48, 0x01, 0x0c]);
+            let instruction = Instruction::parse_from_le_bytes(&[0x70, 0x48, 0x01, 0x0c]).unwrap();
             assert_eq!(instruction.to_machine_code(), result_bin);
-            assert_eq!(instruction.to_instruction(), "jal 84080"); // 84080 = 0x14870
+            assert_eq!(instruction.to_instruction().unwrap(), "jal 84080"); // 84080 = 0x14870
         }
         #[test]
         fn disassemble_jal_instruction_from_machine_code() {
             // jal 0x32
-            let instruction = Instruction::parse_from_machine_code(0x0C000032);
-            assert_eq!(instruction.to_instruction(), "jal 50"); // 50 = 0x32
+            let instruction = Instruction::parse_from_machine_code(0x0C000032).unwrap();
+            assert_eq!(instruction.to_instruction().unwrap(), "jal 50"); // 50 = 0x32
         }
         #[test]
         fn parse_jal_instruction_from_string() {
@@ -1473,23 +2525,23 @@ mod tests {
         #[test]
         fn disassemble_jalr_instruction_from_bytes() {
             // jalr t0, t1
-            let result_bin = 0b00000001001000000000000000001001;
-            let result_hex = 0x01200009;
+            let result_bin = 0b00000001001000000100000000001001;
+            let result_hex = 0x01204009;
             assert_eq!(result_bin, result_hex);
 
-            let instruction = Instruction::parse_from_le_bytes(&[0x09, 0xf8, 0x40, 0x00]);
-            //assert_eq!(instruction.to_machine_code(), result_bin);
-            assert_eq!(instruction.to_instruction(), "jalr t0, t1");
+            let instruction = Instruction::parse_from_le_bytes(&[0x09, 0x40, 0x20, 0x01]).unwrap();
+            assert_eq!(instruction.to_machine_code(), result_bin);
+            assert_eq!(instruction.to_instruction().unwrap(), "jalr t0, t1");
         }
         #[test]
         fn disassemble_jalr_instruction_from_machine_code() {
             // jalr t0, t1
-            let instruction = Instruction::parse_from_machine_code(0x01204009);
-            assert_eq!(instruction.to_instruction(), "jalr t0, t1");
+            let instruction = Instruction::parse_from_machine_code(0x01204009).unwrap();
+            assert_eq!(instruction.to_instruction().unwrap(), "jalr t0, t1");
 
             // jalr v0, ra
-            let instruction = Instruction::parse_from_machine_code(0x03E01009);
-            assert_eq!(instruction.to_instruction(), "jalr v0, ra");
+            let instruction = Instruction::parse_from_machine_code(0x03E01009).unwrap();
+            assert_eq!(instruction.to_instruction().unwrap(), "jalr v0, ra");
         }
         #[test]
         fn parse_jalr_instruction_from_string() {
@@ -1515,8 +2567,8 @@ mod tests {
         #[test]
         fn disassemble_jr_instruction_from_machine_code() {
             // jr t0
-            let instruction = Instruction::parse_from_machine_code(0x01000008);
-            assert_eq!(instruction.to_instruction(), "jr t0");
+            let instruction = Instruction::parse_from_machine_code(0x01000008).unwrap();
+            assert_eq!(instruction.to_instruction().unwrap(), "jr t0");
         }
         #[test]
         fn parse_jr_instruction_from_string() {
@@ -1539,9 +2591,9 @@ mod tests {
             let result_hex = 0x81280020;
             assert_eq!(result_bin, result_hex);
 
-            let instruction = Instruction::parse_from_le_bytes(&[0x20, 0x00, 0x28, 0x81]);
+            let instruction = Instruction::parse_from_le_bytes(&[0x20, 0x00, 0x28, 0x81]).unwrap();
             assert_eq!(instruction.to_machine_code(), result_bin);
-            assert_eq!(instruction.to_instruction(), "lb t0, 32(t1)");
+            assert_eq!(instruction.to_instruction().unwrap(), "lb t0, 32(t1)");
         }
         #[test]
         fn parse_lb_instruction_from_string() {
@@ -1564,28 +2616,28 @@ mod tests {
             let result_hex = 0x00108040;
             assert_eq!(result_bin, result_hex);
 
-            let instruction = Instruction::parse_from_le_bytes(&[0x40, 0x80, 0x10, 0x00]);
+            let instruction = Instruction::parse_from_le_bytes(&[0x40, 0x80, 0x10, 0x00]).unwrap();
             assert_eq!(instruction.to_machine_code(), result_bin);
-            assert_eq!(instruction.to_instruction(), "sll s0, s0, 1");
+            assert_eq!(instruction.to_instruction().unwrap(), "sll s0, s0, 1");
 
             // sll t0, t1, 0x5
             let result_bin = 0b00000000000010010100000101000000;
             let result_hex = 0x00094140;
             assert_eq!(result_bin, result_hex);
 
-            let instruction = Instruction::parse_from_le_bytes(&[0x40, 0x41, 0x09, 0x00]);
+            let instruction = Instruction::parse_from_le_bytes(&[0x40, 0x41, 0x09, 0x00]).unwrap();
             assert_eq!(instruction.to_machine_code(), result_bin);
-            assert_eq!(instruction.to_instruction(), "sll t0, t1, 5");
+            assert_eq!(instruction.to_instruction().unwrap(), "sll t0, t1, 5");
         }
         #[test]
         fn disassemble_sll_instruction_from_machine_code() {
             // sll s0, s0, 0x1
-            let instruction = Instruction::parse_from_machine_code(0x00108040);
-            assert_eq!(instruction.to_instruction(), "sll s0, s0, 1");
+            let instruction = Instruction::parse_from_machine_code(0x00108040).unwrap();
+            assert_eq!(instruction.to_instruction().unwrap(), "sll s0, s0, 1");
 
             // sll t0, t1, 0x5
-            let instruction = Instruction::parse_from_machine_code(0x00094140);
-            assert_eq!(instruction.to_instruction(), "sll t0, t1, 5");
+            let instruction = Instruction::parse_from_machine_code(0x00094140).unwrap();
+            assert_eq!(instruction.to_instruction().unwrap(), "sll t0, t1, 5");
         }
     }
 
@@ -1599,9 +2651,9 @@ mod tests {
             let result_hex = 0x01494004;
             assert_eq!(result_bin, result_hex);
 
-            let instruction = Instruction::parse_from_le_bytes(&[0x04, 0x40, 0x49, 0x01]);
+            let instruction = Instruction::parse_from_le_bytes(&[0x04, 0x40, 0x49, 0x01]).unwrap();
             assert_eq!(instruction.to_machine_code(), result_bin);
-            assert_eq!(instruction.to_instruction(), "sllv t0, t1, t2");
+            assert_eq!(instruction.to_instruction().unwrap(), "sllv t0, t1, t2");
         }
     }
 
@@ -1615,41 +2667,248 @@ mod tests {
             let result_hex = 0x29280020;
             assert_eq!(result_bin, result_hex);
 
-            let instruction = Instruction::parse_from_le_bytes(&[0x20, 0x00, 0x28, 0x29]);
+            let instruction = Instruction::parse_from_le_bytes(&[0x20, 0x00, 0x28, 0x29]).unwrap();
             assert_eq!(instruction.to_machine_code(), result_bin);
-            assert_eq!(instruction.to_instruction(), "slti t0, t1, 32"); // 32 = 0x20
+            assert_eq!(instruction.to_instruction().unwrap(), "slti t0, t1, 32"); // 32 = 0x20
 
             // slti v0, v0, 0x5
             let result_bin = 0b00101000010000100000000000000101;
             let result_hex = 0x28420005;
             assert_eq!(result_bin, result_hex);
 
-            let instruction = Instruction::parse_from_le_bytes(&[0x05, 0x00, 0x42, 0x28]);
+            let instruction = Instruction::parse_from_le_bytes(&[0x05, 0x00, 0x42, 0x28]).unwrap();
             assert_eq!(instruction.to_machine_code(), result_bin);
-            assert_eq!(instruction.to_instruction(), "slti v0, v0, 5"); // 5 = 0x5
+            assert_eq!(instruction.to_instruction().unwrap(), "slti v0, v0, 5");
+            // 5 = 0x5
         }
         #[test]
         fn parse_slti_instruction_from_machine_code() {
             // slti t0, t1, 0x20
-            let instruction = Instruction::parse_from_machine_code(0x29280020);
+            let instruction = Instruction::parse_from_machine_code(0x29280020).unwrap();
             let result_bin = 0b00101001001010000000000000100000;
             let result_hex = 0x29280020;
             assert_eq!(result_bin, result_hex);
             assert_eq!(instruction.to_machine_code(), result_bin);
 
             // slti v0, v0, 0x5
-            let instruction = Instruction::parse_from_machine_code(0x28420005);
+            let instruction = Instruction::parse_from_machine_code(0x28420005).unwrap();
             let result_bin = 0b00101000010000100000000000000101;
             let result_hex = 0x28420005;
             assert_eq!(result_bin, result_hex);
             assert_eq!(instruction.to_machine_code(), result_bin);
         }
     }
+
+    mod swl {
+        use super::*;
+
+        #[test]
+        fn disassemble_swl_instruction_from_machine_code() {
+            // Opcode 42 is swl, not slt (which is an R instruction with funct 42)
+            let instruction = Instruction::parse_from_machine_code(0xA8880003).unwrap();
+            assert_eq!(instruction.to_instruction().unwrap(), "swl t0, 3(a0)");
+        }
+    }
+
+    mod round_trip {
+        use super::*;
+
+        /// Every integer instruction of R3000A with its machine code.
+        const INSTRUCTIONS: &[(&str, u32)] = &[
+            ("add t0, t1, t2", 0x012A4020),
+            ("addi t0, t1, -32", 0x2128FFE0),
+            ("addiu sp, sp, 65496", 0x27BDFFD8),
+            ("addu v0, a0, a1", 0x00851021),
+            ("and v0, a0, a1", 0x00851024),
+            ("andi v0, a0, 255", 0x308200FF),
+            ("beq v0, zero, 2", 0x10400002),
+            ("bgez a0, 5", 0x04810005),
+            ("bgezal a0, -3", 0x0491FFFD),
+            ("bgtz t0, 200", 0x1D0000C8),
+            ("blez t0, 4", 0x19000004),
+            ("bltz a0, 5", 0x04800005),
+            ("bltzal a0, 7", 0x04900007),
+            ("bne v0, zero, 4", 0x14400004),
+            ("break", 0x0000000D),
+            ("break 7168", 0x0007000D),
+            ("div a0, a1", 0x0085001A),
+            ("divu a0, a1", 0x0085001B),
+            ("j 1024", 0x08000400),
+            ("jal 84080", 0x0C014870),
+            ("jalr t0, t1", 0x01204009),
+            ("jr ra", 0x03E00008),
+            ("lb t0, 32(t1)", 0x81280020),
+            ("lbu t0, 32(t1)", 0x91280020),
+            ("lh t0, -2(t1)", 0x8528FFFE),
+            ("lhu t0, 2(t1)", 0x95280002),
+            ("lui a0, 32769", 0x3C048001),
+            ("lw ra, 16(sp)", 0x8FBF0010),
+            ("lwl t0, 3(a0)", 0x88880003),
+            ("lwr t0, 0(a0)", 0x98880000),
+            ("mfhi v0", 0x00001010),
+            ("mflo v0", 0x00001012),
+            ("mthi a0", 0x00800011),
+            ("mtlo a0", 0x00800013),
+            ("mult a0, a1", 0x00850018),
+            ("multu a0, a1", 0x00850019),
+            ("nop", 0x00000000),
+            ("nor v0, a0, a1", 0x00851027),
+            ("or v0, a0, a1", 0x00851025),
+            ("ori a0, zero, 64505", 0x3404FBF9),
+            ("sb t0, 32(t1)", 0xA1280020),
+            ("sh t0, 32(t1)", 0xA5280020),
+            ("sll t0, t1, 5", 0x00094140),
+            ("sllv t0, t1, t2", 0x01494004),
+            ("slt v0, a0, a1", 0x0085102A),
+            ("slti t0, t1, 32", 0x29280020),
+            ("sltiu t0, t1, 32", 0x2D280020),
+            ("sltu v0, a0, a1", 0x0085102B),
+            ("sra t0, t1, 31", 0x000947C3),
+            ("srav t0, t1, t2", 0x01494007),
+            ("srl t0, t1, 16", 0x00094402),
+            ("srlv t0, t1, t2", 0x01494006),
+            ("sub v0, a0, a1", 0x00851022),
+            ("subu v0, a0, a1", 0x00851023),
+            ("sw s0, 16(sp)", 0xAFB00010),
+            ("swl t0, 3(a0)", 0xA8880003),
+            ("swr t0, 0(a0)", 0xB8880000),
+            ("syscall", 0x0000000C),
+            ("xor v0, a0, a1", 0x00851026),
+            ("xori v0, a0, 1", 0x38820001),
+            ("xori t0, t0, 65535", 0x3908FFFF),
+        ];
+
+        /// Loads and stores written with three operands, "<rt>, <immediate>, <rs>".
+        const THREE_OPERAND_LOADS_AND_STORES: &[(&str, u32)] = &[
+            ("lb t0, 32, t1", 0x81280020),
+            ("lbu t0, 32, t1", 0x91280020),
+            ("lh t0, -2, t1", 0x8528FFFE),
+            ("lhu t0, 2, t1", 0x95280002),
+            ("lw ra, 16, sp", 0x8FBF0010),
+            ("lwl t0, 3, a0", 0x88880003),
+            ("lwr t0, 0, a0", 0x98880000),
+            ("sb t0, 32, t1", 0xA1280020),
+            ("sh t0, 32, t1", 0xA5280020),
+            ("sw s0, 16, sp", 0xAFB00010),
+            ("swl t0, 3, a0", 0xA8880003),
+            ("swr t0, 0, a0", 0xB8880000),
+        ];
+
+        #[test]
+        fn round_trip_every_instruction() {
+            for (content, machine_code) in INSTRUCTIONS.iter() {
+                let instruction = Instruction::parse_from_str(content).unwrap();
+                assert_eq!(
+                    instruction.to_machine_code(),
+                    *machine_code,
+                    "Encoding \"{}\"",
+                    content
+                );
+
+                let instruction = Instruction::parse_from_machine_code(*machine_code).unwrap();
+                assert_eq!(instruction.to_instruction().unwrap(), *content);
+
+                let instruction =
+                    Instruction::parse_from_le_bytes(&machine_code.to_le_bytes()).unwrap();
+                assert_eq!(instruction.to_le_bytes(), machine_code.to_le_bytes());
+            }
+        }
+        #[test]
+        fn parse_loads_and_stores_with_three_operands() {
+            for (content, machine_code) in THREE_OPERAND_LOADS_AND_STORES.iter() {
+                let instruction = Instruction::parse_from_str(content).unwrap();
+                assert_eq!(
+                    instruction.to_machine_code(),
+                    *machine_code,
+                    "Encoding \"{}\"",
+                    content
+                );
+
+                // The base register comes last, like in "<immediate>(<rs>)".
+                let (mnemonic, _) = content.split_once(' ').unwrap();
+                let swapped = format!("{} t0, t1, 16", mnemonic);
+                assert!(
+                    Instruction::parse_from_str(&swapped).is_err(),
+                    "Parsing \"{}\"",
+                    swapped
+                );
+            }
+        }
+        #[test]
+        fn parse_exact_machine_code() {
+            // add t0, t1, t2 with a shift amount of 1, and lui with rs of 1.
+            for machine_code in [0x012A4060, 0x3C218001] {
+                assert!(Instruction::parse_from_machine_code(machine_code).is_ok());
+                assert_eq!(
+                    Instruction::parse_from_machine_code_exact(machine_code),
+                    Err(Error::UnusedBitsSet { machine_code })
+                );
+            }
+            assert!(Instruction::parse_from_machine_code_exact(0x012A4020).is_ok());
+        }
+    }
+
+    mod coprocessor {
+        use super::*;
+
+        /// Coprocessor instructions with their machine code.
+        const INSTRUCTIONS: &[(&str, u32)] = &[
+            ("mfc0 t0, sr", 0x40086000),
+            ("mfc0 k0, epc", 0x401A7000),
+            ("mtc0 t0, sr", 0x40886000),
+            ("mtc0 zero, $0", 0x40800000),
+            ("rfe", 0x42000010),
+            ("mfc2 t0, mac0", 0x4808C000),
+            ("cfc2 t0, flag", 0x4848F800),
+            ("mtc2 t0, vxy0", 0x48880000),
+            ("ctc2 t0, trx", 0x48C82800),
+            ("lwc2 vxy0, 0(a0)", 0xC8800000),
+            ("swc2 sxy2, 8(a0)", 0xE88E0008),
+            ("rtps", 0x4A180001),
+            ("rtpt", 0x4A280030),
+            ("nclip", 0x4B400006),
+            ("avsz3", 0x4B58002D),
+            ("ncds", 0x4AE80413),
+            ("mvmva sf=1, mx=rt, v=v0, cv=tr, lm=0", 0x4A480012),
+            ("mvmva sf=1, mx=llm, v=v1, cv=none, lm=1", 0x4A4AE412),
+            ("sqr sf=1", 0x4AA80428),
+            ("cop2 0x0000001", 0x4A000001),
+        ];
+
+        #[test]
+        fn round_trip_every_coprocessor_instruction() {
+            for (content, machine_code) in INSTRUCTIONS.iter() {
+                let instruction = Instruction::parse_from_str(content).unwrap();
+                assert_eq!(
+                    instruction.to_machine_code(),
+                    *machine_code,
+                    "Encoding \"{}\"",
+                    content
+                );
+
+                let instruction = Instruction::parse_from_machine_code(*machine_code).unwrap();
+                assert_eq!(instruction.to_instruction().unwrap(), *content);
+            }
+        }
+        #[test]
+        fn parse_gte_command_fields_by_number() {
+            let instruction = Instruction::parse_from_str("mvmva sf=1, mx=1, v=1, cv=3, lm=1");
+            assert_eq!(instruction.unwrap().to_machine_code(), 0x4A4AE412);
+
+            assert!(Instruction::parse_from_str("mvmva sf=2").is_err());
+            assert!(Instruction::parse_from_str("rtps xx=1").is_err());
+        }
+    }
 }
 
 #[derive(Debug, PartialEq)]
 pub enum CustomCommand {
     At(u64),
+    /// Same as [CustomCommand::At], but the `j` or `jal` instruction after it jumps
+    /// from existing code into new code.
+    Hook(u64),
+    /// Start of a section, placed at an address chosen for it (see [parse_section_sizes]).
+    Section(String),
 }
 
 #[derive(Debug, PartialEq)]
@@ -1662,6 +2921,8 @@ pub struct Node {
 pub enum NodeKind {
     Addr(String),
     CustomCommand(CustomCommand),
+    /// Bytes of a data directive (like ".word" or ".asciz").
+    Data(Vec<u8>),
     IntegerAssignment(String, i32),
     Instruction(Instruction),
     Label(String),
@@ -0,0 +1,255 @@
+//! User-defined macros of assembly code.
+//!
+//! A macro is defined between "@macro <name> [<parameter>, ...]" and "@endmacro" lines
+//! and used like an instruction. In the body, "\<parameter>" is replaced with the
+//! argument given for the parameter and "\@" with a number unique to each use,
+//! which keeps labels inside the macro unique.
+//!
+//! ```text
+//! @macro push register
+//!     addiu sp, sp, 0xFFFC
+//!     sw \register, 0(sp)
+//! @endmacro
+//!
+//! push ra
+//! ```
+
+use std::collections::HashMap;
+
+use crate::{get_span_of_tokens, strip_comment, tokenize, Error, Span};
+
+/// Macros using other macros are expanded up to this depth,
+/// so that a macro using itself does not expand forever.
+const MAX_MACRO_DEPTH: usize = 16;
+
+/// A line of assembly code after expanding macros.
+pub(crate) struct SourceLine {
+    pub text: String,
+    /// Line number in the original code. For lines expanded from a macro,
+    /// this is the line number in the body of the macro.
+    pub line: u64,
+    /// Where the macro this line is expanded from is used, if any.
+    pub macro_use: Option<MacroUse>,
+}
+#[derive(Clone)]
+pub(crate) struct MacroUse {
+    pub name: String,
+    pub span: Span,
+}
+
+struct Macro {
+    parameters: Vec<String>,
+    /// Lines of the body with their line numbers.
+    body: Vec<(String, u64)>,
+}
+
+/// Removes macro definitions from given assembly code and expands all uses of macros.
+/// Errors are added to given errors.
+pub(crate) fn expand_macros(content: &str, errors: &mut Vec<Error>) -> Vec<SourceLine> {
+    let mut macros = HashMap::new();
+    let mut lines = Vec::new();
+    // Name, definition and span of the "@macro" line of the macro being defined.
+    let mut current_macro: Option<(String, Macro, Span)> = None;
+
+    for (i, text) in content.split("\n").enumerate() {
+        let line = i as u64 + 1;
+        let tokens = tokenize(strip_comment(text), line);
+
+        match tokens.first().map(|token| token.text) {
+            Some("@macro") => {
+                let span = get_span_of_tokens(&tokens);
+                if current_macro.is_some() {
+                    errors.push(Error::Syntax {
+                        message: String::from("Macros cannot be defined inside macros"),
+                        span,
+                    });
+                    continue;
+                }
+                let Some(name) = tokens.get(1) else {
+                    errors.push(Error::Syntax {
+                        message: String::from("No name given for macro"),
+                        span,
+                    });
+                    continue;
+                };
+                let definition = Macro {
+                    parameters: tokens[2..]
+                        .iter()
+                        .map(|token| String::from(token.text))
+                        .collect(),
+                    body: Vec::new(),
+                };
+                current_macro = Some((String::from(name.text), definition, span));
+            }
+            Some("@endmacro") => match current_macro.take() {
+                Some((name, definition, span)) => {
+                    if macros.insert(name.clone(), definition).is_some() {
+                        errors.push(Error::Syntax {
+                            message: format!("Macro \"{}\" is already defined", name),
+                            span,
+                        });
+                    }
+                }
+                None => errors.push(Error::Syntax {
+                    message: String::from("\"@endmacro\" without \"@macro\""),
+                    span: get_span_of_tokens(&tokens),
+                }),
+            },
+            _ => match current_macro.as_mut() {
+                Some((_, definition, _)) => definition.body.push((String::from(text), line)),
+                None => lines.push((text, line)),
+            },
+        }
+    }
+    if let Some((name, _, span)) = current_macro {
+        errors.push(Error::Syntax {
+            message: format!("Macro \"{}\" is not ended with \"@endmacro\"", name),
+            span,
+        });
+    }
+
+    let mut expander = MacroExpander {
+        macros: &macros,
+        use_count: 0,
+        lines: Vec::new(),
+        errors,
+    };
+    for (text, line) in lines {
+        expander.expand_line(text, line, None, 0);
+    }
+    expander.lines
+}
+
+struct MacroExpander<'a> {
+    macros: &'a HashMap<String, Macro>,
+    /// How many times macros have been used so far, for replacing "\@".
+    use_count: usize,
+    lines: Vec<SourceLine>,
+    errors: &'a mut Vec<Error>,
+}
+impl MacroExpander<'_> {
+    fn expand_line(&mut self, text: &str, line: u64, macro_use: Option<&MacroUse>, depth: usize) {
+        let tokens = tokenize(strip_comment(text), line);
+        let Some((name, definition)) = tokens
+            .first()
+            .and_then(|token| self.macros.get_key_value(token.text))
+        else {
+            self.lines.push(SourceLine {
+                text: String::from(text),
+                line,
+                macro_use: macro_use.cloned(),
+            });
+            return;
+        };
+
+        let span = get_span_of_tokens(&tokens);
+        let error = if tokens.len() - 1 != definition.parameters.len() {
+            Some(Error::WrongOperandCount {
+                mnemonic: name.clone(),
+                expected: vec![definition.parameters.len()],
+                found: tokens.len() - 1,
+                span: span.clone(),
+            })
+        } else if depth >= MAX_MACRO_DEPTH {
+            Some(Error::Syntax {
+                message: format!(
+                    "Macro \"{}\" is expanded too deeply (more than {} macros inside each other)",
+                    name, MAX_MACRO_DEPTH
+                ),
+                span: span.clone(),
+            })
+        } else {
+            None
+        };
+        if let Some(error) = error {
+            self.errors.push(match macro_use {
+                Some(macro_use) => Error::InMacro {
+                    name: macro_use.name.clone(),
+                    error: Box::new(error),
+                    span: macro_use.span.clone(),
+                },
+                None => error,
+            });
+            return;
+        }
+
+        // Errors are reported where the outermost macro is used.
+        let macro_use = macro_use.cloned().unwrap_or(MacroUse {
+            name: name.clone(),
+            span,
+        });
+
+        // Longer parameters are replaced first, so that parameter "a" does not replace a part of "\ab".
+        let mut arguments = definition
+            .parameters
+            .iter()
+            .zip(tokens[1..].iter())
+            .collect::<Vec<_>>();
+        arguments.sort_by_key(|(parameter, _)| std::cmp::Reverse(parameter.len()));
+
+        self.use_count += 1;
+        for (body_text, body_line) in definition.body.iter() {
+            let mut expanded = body_text.clone();
+            for (parameter, argument) in arguments.iter() {
+                expanded = expanded.replace(&format!("\\{}", parameter), argument.text);
+            }
+            expanded = expanded.replace("\\@", &self.use_count.to_string());
+            self.expand_line(&expanded, *body_line, Some(&macro_use), depth + 1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{parse_nodes, Error, Instruction, NodeKind};
+
+    fn get_instructions(content: &str) -> Vec<String> {
+        parse_nodes(content)
+            .unwrap()
+            .iter()
+            .filter_map(|node| match &node.kind {
+                NodeKind::Instruction(instruction) => Some(instruction.to_instruction().unwrap()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn expand_macro_with_parameters() {
+        let content = "@macro store_pair first, second, offset\n  sw \\first, \\offset(sp)\n  sw \\second, 4(sp)\n@endmacro\nstore_pair s0, s1, 8\nstore_pair ra, fp, 16";
+        assert_eq!(
+            get_instructions(content),
+            [
+                "sw s0, 8(sp)",
+                "sw s1, 4(sp)",
+                "sw ra, 16(sp)",
+                "sw fp, 4(sp)"
+            ]
+        );
+    }
+    #[test]
+    fn expand_macro_with_unique_labels() {
+        let content = "@macro wait register\nwait\\@:\n  bnez \\register, wait\\@\n  nop\n@endmacro\n@at 0x80010000\nwait a0\nwait a1";
+        let nodes = parse_nodes(content).unwrap();
+        assert_eq!(nodes[1].kind, NodeKind::Label(String::from("wait1")));
+        assert_eq!(nodes[4].kind, NodeKind::Label(String::from("wait2")));
+        assert_eq!(
+            nodes[5].kind,
+            NodeKind::Instruction(Instruction::parse_from_str("bne a1, zero, -1").unwrap())
+        );
+    }
+    #[test]
+    fn fail_expand_macro() {
+        let content = "@macro push register\n  addiu sp, sp, 0xFFFC\n  sw \\register, 0(sp)\n@endmacro\npush xx\npush ra, ra";
+        let errors = parse_nodes(content).unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert!(matches!(&errors[1], Error::WrongOperandCount { .. }));
+        assert_eq!(
+            errors[0].to_string_with_snippet(content),
+            "Macro \"push\" used on line 5 expands to invalid code: Unknown register \"xx\" (line 3, column 6)\n    5 | push xx\n      | ^^^^^^^\n    3 |   sw \\register, 0(sp)\n      |      ^^"
+        );
+
+        let errors = parse_nodes("@macro loop\nloop\n@endmacro\nloop\n@macro unended").unwrap_err();
+        assert_eq!(errors.len(), 2);
+    }
+}
@@ -0,0 +1,422 @@
+//! Pseudo-instructions, which are written as one instruction but assembled
+//! into one or more real instructions.
+//!
+//! * `move rd, rs` is `addu rd, rs, zero`
+//! * `neg rd, rs` is `sub rd, zero, rs`
+//! * `not rd, rs` is `nor rd, rs, zero`
+//! * `li rt, value` is `addiu rt, zero, value`, `ori rt, zero, value` or `lui` followed by `ori`
+//! * `la rt, address` is `lui` followed by `addiu`
+//! * `b target` is `beq zero, zero, target`
+//! * `beqz rs, target` is `beq rs, zero, target`
+//! * `bnez rs, target` is `bne rs, zero, target`
+//! * `blt rs, rt, target` is `slt at, rs, rt` followed by `bne at, zero, target`
+//!
+//! Only `blt` uses the at register (the assembler temporary), which "@set noat" disallows.
+
+use std::collections::HashMap;
+
+use crate::{
+    get_hi, get_lo, get_operands, parse_immediate, parse_immediate_i16, parse_integer,
+    parse_register, Error, Instruction, Relocation, Token, REGISTERS,
+};
+
+/// Most instructions a pseudo-instruction is assembled into.
+pub const MAX_PSEUDO_INSTRUCTION_LEN: usize = 2;
+/// Register number of at (the assembler temporary).
+const AT: u8 = 1;
+
+/// An instruction of an expanded pseudo-instruction, with a value to fill in
+/// once the address of a label is known.
+pub(crate) type ExpandedInstruction<'a> = (Instruction, Option<(Relocation, Token<'a>)>);
+
+fn get_r_instruction(funct: u8, rd: u8, rs: u8, rt: u8) -> Instruction {
+    Instruction::R {
+        opcode: 0,
+        rs,
+        rt,
+        rd,
+        shamt: 0,
+        funct,
+    }
+}
+/// Parses a branch target, which is either a raw immediate or
+/// a label (or an absolute address) resolved later.
+fn parse_branch<'a>(
+    opcode: u8,
+    rs: u8,
+    rt: u8,
+    target: Token<'a>,
+) -> Result<ExpandedInstruction<'a>, Error> {
+    let (immediate, relocation) = match parse_integer(target.text) {
+        Some(_) => (parse_immediate_i16(target)?, None),
+        None => (0, Some((Relocation::Target, target))),
+    };
+    Ok((
+        Instruction::ISigned {
+            opcode,
+            rs,
+            rt,
+            immediate,
+        },
+        relocation,
+    ))
+}
+
+/// Parses a pseudo-instruction from tokens into real instructions.
+/// Returns `None` if the mnemonic is not a pseudo-instruction.
+pub(crate) fn parse_pseudo_instruction<'a>(
+    tokens: &[Token<'a>],
+    is_at_available: bool,
+) -> Option<Result<Vec<ExpandedInstruction<'a>>, Error>> {
+    let mnemonic = tokens.first()?;
+    let result = match mnemonic.text {
+        "b" => get_operands(tokens).and_then(|[target]| {
+            Ok(vec![parse_branch(0b000100, 0, 0, target)?]) // beq, opcode 4
+        }),
+        "beqz" => get_operands(tokens).and_then(|[rs, target]| {
+            Ok(vec![parse_branch(0b000100, parse_register(rs)?, 0, target)?]) // beq, opcode 4
+        }),
+        "bnez" => get_operands(tokens).and_then(|[rs, target]| {
+            Ok(vec![parse_branch(0b000101, parse_register(rs)?, 0, target)?]) // bne, opcode 5
+        }),
+        "blt" => get_operands(tokens).and_then(|[rs, rt, target]| {
+            if !is_at_available {
+                return Err(Error::Syntax {
+                    message: String::from(
+                        "Pseudo-instruction \"blt\" uses register at, which \"@set noat\" disallows",
+                    ),
+                    span: mnemonic.span(),
+                });
+            }
+            let slt = get_r_instruction(0b101010, AT, parse_register(rs)?, parse_register(rt)?); // Funct is 42
+            Ok(vec![(slt, None), parse_branch(0b000101, AT, 0, target)?]) // bne, opcode 5
+        }),
+        "la" => get_operands(tokens).and_then(|[rt, address]| {
+            let rt = parse_register(rt)?;
+            let (hi, lo, hi_relocation, lo_relocation) = match parse_integer(address.text) {
+                Some(_) => {
+                    let address = parse_immediate(address, 0, u32::MAX as i64)? as u64;
+                    (get_hi(address), get_lo(address), None, None)
+                }
+                None => (
+                    0,
+                    0,
+                    Some((Relocation::Hi, address)),
+                    Some((Relocation::Lo, address)),
+                ),
+            };
+            let lui = Instruction::IUnsigned {
+                opcode: 0b001111, // Opcode is 15
+                rs: 0,
+                rt,
+                immediate: hi,
+            };
+            let addiu = Instruction::IUnsigned {
+                opcode: 0b001001, // Opcode is 9
+                rs: rt,
+                rt,
+                immediate: lo,
+            };
+            Ok(vec![(lui, hi_relocation), (addiu, lo_relocation)])
+        }),
+        "li" => get_operands(tokens).and_then(|[rt, value]| {
+            let rt = parse_register(rt)?;
+            let value = parse_immediate(value, i32::MIN as i64, u32::MAX as i64)?;
+            if (i16::MIN as i64..=i16::MAX as i64).contains(&value) {
+                return Ok(vec![(
+                    Instruction::IUnsigned {
+                        opcode: 0b001001, // addiu, opcode 9
+                        rs: 0,
+                        rt,
+                        immediate: value as u16,
+                    },
+                    None,
+                )]);
+            }
+            let ori = |rs: u8, immediate: u16| Instruction::IUnsigned {
+                opcode: 0b001101, // ori, opcode 13
+                rs,
+                rt,
+                immediate,
+            };
+            if (0..=u16::MAX as i64).contains(&value) {
+                return Ok(vec![(ori(0, value as u16), None)]);
+            }
+            let value = value as u32;
+            let lui = Instruction::IUnsigned {
+                opcode: 0b001111, // Opcode is 15
+                rs: 0,
+                rt,
+                immediate: (value >> 16) as u16,
+            };
+            if value & 0xFFFF == 0 {
+                Ok(vec![(lui, None)])
+            } else {
+                Ok(vec![(lui, None), (ori(rt, value as u16), None)])
+            }
+        }),
+        "move" => get_operands(tokens).and_then(|[rd, rs]| {
+            let instruction = get_r_instruction(0b100001, parse_register(rd)?, parse_register(rs)?, 0); // addu, funct 33
+            Ok(vec![(instruction, None)])
+        }),
+        "neg" => get_operands(tokens).and_then(|[rd, rs]| {
+            let instruction = get_r_instruction(0b100010, parse_register(rd)?, 0, parse_register(rs)?); // sub, funct 34
+            Ok(vec![(instruction, None)])
+        }),
+        "not" => get_operands(tokens).and_then(|[rd, rs]| {
+            let instruction = get_r_instruction(0b100111, parse_register(rd)?, parse_register(rs)?, 0); // nor, funct 39
+            Ok(vec![(instruction, None)])
+        }),
+        _ => return None,
+    };
+    Some(result)
+}
+
+/// Folds instructions from the beginning of given instructions into a pseudo-instruction,
+/// if they are assembled from one. Returns the pseudo-instruction and how many instructions it takes.
+///
+/// Instructions are folded only if the pseudo-instruction assembles back into the same instructions.
+/// Given instructions should not include instructions with a label (other than the first one),
+/// since they could not be jumped to after folding.
+///
+/// # Examples
+///
+/// ```
+/// # use std::collections::HashMap;
+/// # use mips::{fold_pseudo_instruction, Instruction};
+/// let instructions = [
+///     Instruction::parse_from_str("lui a0, 0x8007").unwrap(),
+///     Instruction::parse_from_str("addiu a0, a0, 0x5000").unwrap(),
+/// ];
+/// let folded = fold_pseudo_instruction(&instructions, 0x80010000, &HashMap::new());
+/// assert_eq!(folded, Some((String::from("la a0, 0x80075000"), 2)));
+/// ```
+pub fn fold_pseudo_instruction(
+    instructions: &[Instruction],
+    address: u64,
+    labels: &HashMap<u64, String>,
+) -> Option<(String, usize)> {
+    let get_target = |instruction: &Instruction, address: u64| {
+        let instruction = instruction.to_instruction_at(address, labels).ok()?;
+        Some(String::from(instruction.rsplit_once(' ')?.1))
+    };
+
+    if let [first, second, ..] = instructions {
+        match (first, second) {
+            (
+                Instruction::IUnsigned {
+                    opcode: 0b001111, // lui, opcode 15
+                    rt,
+                    immediate: hi,
+                    ..
+                },
+                Instruction::IUnsigned {
+                    opcode: 0b001001, // addiu, opcode 9
+                    rs,
+                    rt: second_rt,
+                    immediate: lo,
+                },
+            ) if rs == rt && second_rt == rt => {
+                let address = ((*hi as u32) << 16).wrapping_add(*lo as i16 as u32) as u64;
+                let address = match labels.get(&address) {
+                    Some(label) => label.clone(),
+                    None => format!("0x{:X}", address),
+                };
+                return Some((format!("la {}, {}", REGISTERS[*rt as usize], address), 2));
+            }
+            (
+                Instruction::IUnsigned {
+                    opcode: 0b001111, // lui, opcode 15
+                    rt,
+                    immediate: hi,
+                    ..
+                },
+                Instruction::IUnsigned {
+                    opcode: 0b001101, // ori, opcode 13
+                    rs,
+                    rt: second_rt,
+                    immediate: lo,
+                },
+            ) if rs == rt && second_rt == rt && *hi != 0 && *lo != 0 => {
+                let value = ((*hi as u32) << 16) | (*lo as u32);
+                return Some((format!("li {}, 0x{:X}", REGISTERS[*rt as usize], value), 2));
+            }
+            (
+                Instruction::R {
+                    rs,
+                    rt,
+                    rd: AT,
+                    shamt: 0,
+                    funct: 0b101010, // slt, funct 42
+                    ..
+                },
+                Instruction::ISigned {
+                    opcode: 0b000101, // bne, opcode 5
+                    rs: AT,
+                    rt: 0,
+                    ..
+                },
+            ) => {
+                let target = get_target(second, address + 4)?;
+                return Some((
+                    format!(
+                        "blt {}, {}, {}",
+                        REGISTERS[*rs as usize], REGISTERS[*rt as usize], target
+                    ),
+                    2,
+                ));
+            }
+            _ => {}
+        }
+    }
+
+    let first = instructions.first()?;
+    let pseudo_instruction = match first {
+        Instruction::R {
+            rs,
+            rt,
+            rd,
+            shamt: 0,
+            funct,
+            ..
+        } => {
+            let rd = REGISTERS[*rd as usize];
+            match (funct, rs, rt) {
+                (0b100001, _, 0) => format!("move {}, {}", rd, REGISTERS[*rs as usize]), // addu, funct 33
+                (0b100010, 0, _) => format!("neg {}, {}", rd, REGISTERS[*rt as usize]), // sub, funct 34
+                (0b100111, _, 0) => format!("not {}, {}", rd, REGISTERS[*rs as usize]), // nor, funct 39
+                _ => return None,
+            }
+        }
+        Instruction::IUnsigned {
+            opcode: 0b001001, // addiu, opcode 9
+            rs: 0,
+            rt,
+            immediate,
+        } => format!("li {}, {}", REGISTERS[*rt as usize], *immediate as i16),
+        // Values fitting in 15 bits would be assembled with addiu.
+        Instruction::IUnsigned {
+            opcode: 0b001101, // ori, opcode 13
+            rs: 0,
+            rt,
+            immediate,
+        } if *immediate > i16::MAX as u16 => {
+            format!("li {}, 0x{:X}", REGISTERS[*rt as usize], immediate)
+        }
+        Instruction::ISigned {
+            opcode: 0b000100, // beq, opcode 4
+            rs: 0,
+            rt: 0,
+            ..
+        } => format!("b {}", get_target(first, address)?),
+        Instruction::ISigned {
+            opcode: 0b000100, // beq, opcode 4
+            rs,
+            rt: 0,
+            ..
+        } => format!(
+            "beqz {}, {}",
+            REGISTERS[*rs as usize],
+            get_target(first, address)?
+        ),
+        Instruction::ISigned {
+            opcode: 0b000101, // bne, opcode 5
+            rs,
+            rt: 0,
+            ..
+        } => format!(
+            "bnez {}, {}",
+            REGISTERS[*rs as usize],
+            get_target(first, address)?
+        ),
+        _ => return None,
+    };
+    Some((pseudo_instruction, 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_nodes;
+
+    fn assemble(content: &str) -> Vec<u32> {
+        parse_nodes(content)
+            .unwrap()
+            .iter()
+            .filter_map(|node| match &node.kind {
+                crate::NodeKind::Instruction(instruction) => Some(instruction.to_machine_code()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn expand_pseudo_instructions() {
+        assert_eq!(assemble("move a0, s0"), assemble("addu a0, s0, zero"));
+        assert_eq!(assemble("neg a0, s0"), assemble("sub a0, zero, s0"));
+        assert_eq!(assemble("not a0, s0"), assemble("nor a0, s0, zero"));
+        assert_eq!(assemble("li a0, -8"), assemble("addiu a0, zero, 0xFFF8"));
+        assert_eq!(assemble("li a0, 0x8000"), assemble("ori a0, zero, 0x8000"));
+        assert_eq!(assemble("li a0, 0x10000"), assemble("lui a0, 1"));
+        assert_eq!(
+            assemble("li a0, 0x12345678"),
+            assemble("lui a0, 0x1234\nori a0, a0, 0x5678")
+        );
+        // Lower half is sign extended by addiu, so upper half is rounded up.
+        assert_eq!(
+            assemble("la a0, 0x80078000"),
+            assemble("lui a0, 0x8008\naddiu a0, a0, 0x8000")
+        );
+    }
+    #[test]
+    fn expand_pseudo_branches_with_labels() {
+        let content = "@at 0x80010000\nloop:\n  blt a0, a1, loop\n  nop\n  bnez a0, end\n  nop\n  b loop\n  la t0, end\nend:";
+        assert_eq!(
+            assemble(content),
+            assemble("slt at, a0, a1\nbne at, zero, -2\nnop\nbne a0, zero, 4\nnop\nbeq zero, zero, -6\nlui t0, 0x8001\naddiu t0, t0, 0x20")
+        );
+    }
+    #[test]
+    fn fail_expand_blt_without_at() {
+        let errors = parse_nodes("@set noat\nblt a0, a1, 2\n@set at\nblt a0, a1, 2").unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].span().unwrap().line, 2);
+    }
+    #[test]
+    fn fold_instructions_into_pseudo_instructions() {
+        let labels = HashMap::from([(0x80010008, String::from("end"))]);
+        for pseudo_instruction in [
+            "move a0, s0",
+            "neg a0, s0",
+            "not a0, s0",
+            "li a0, -8",
+            "li a0, 0x8000",
+            "li a0, 0x12345678",
+            "la a0, end",
+            "b end",
+            "beqz a0, end",
+            "bnez a0, end",
+            "blt a0, a1, end",
+        ] {
+            let content = format!(
+                "@at 0x80010000\n{}\n@at 0x80010008\nend:",
+                pseudo_instruction
+            );
+            let instructions = assemble(&content)
+                .iter()
+                .map(|machine_code| Instruction::parse_from_machine_code(*machine_code).unwrap())
+                .collect::<Vec<_>>();
+            assert_eq!(
+                fold_pseudo_instruction(&instructions, 0x80010000, &labels),
+                Some((String::from(pseudo_instruction), instructions.len()))
+            );
+        }
+
+        // Small values set with ori are assembled with addiu by li, so they are not folded.
+        let instruction = Instruction::parse_from_str("ori a0, zero, 5").unwrap();
+        assert_eq!(
+            fold_pseudo_instruction(&[instruction], 0x80010000, &labels),
+            None
+        );
+    }
+}
@@ -0,0 +1,117 @@
+//! Conflicts between mods changing the same bytes of a file on the ROM, in which case the mod
+//! applied later overwrites changes of the earlier mod, which likely breaks the earlier mod.
+
+use std::{fmt::Display, ops::Range};
+
+/// Bytes of a file on the ROM changed by an operation of a mod.
+pub struct ModChange {
+    /// Index of the mod in the order mods are applied.
+    pub mod_index: usize,
+    pub mod_name: String,
+    pub file_name: String,
+    /// Ranges of changed bytes (offsets in the file).
+    pub ranges: Vec<Range<u64>>,
+}
+
+pub struct ModConflict {
+    pub file_name: String,
+    pub range: Range<u64>,
+    pub earlier_mod_name: String,
+    pub later_mod_name: String,
+}
+impl Display for ModConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} bytes 0x{:x}-0x{:x} are changed by \"{}\" and then by \"{}\"",
+            self.file_name,
+            self.range.start,
+            self.range.end,
+            self.earlier_mod_name,
+            self.later_mod_name
+        )
+    }
+}
+
+/// Gets the ranges of bytes which differ between an original and a modified file of the same size.
+pub fn get_changed_ranges(original: &[u8], modified: &[u8]) -> Vec<Range<u64>> {
+    let mut ranges = Vec::<Range<u64>>::new();
+    for (i, _) in original
+        .iter()
+        .zip(modified)
+        .enumerate()
+        .filter(|(_, (a, b))| a != b)
+    {
+        let offset = i as u64;
+        match ranges.last_mut() {
+            Some(range) if range.end == offset => range.end += 1,
+            _ => ranges.push(offset..offset + 1),
+        }
+    }
+    ranges
+}
+
+/// Finds bytes changed by more than one mod. Changes are given in the order they were applied.
+/// Operations of the same mod may change the same bytes without a conflict.
+pub fn find_mod_conflicts(changes: &[ModChange]) -> Vec<ModConflict> {
+    let mut conflicts = Vec::new();
+    for (i, later) in changes.iter().enumerate() {
+        for earlier in changes[..i].iter().filter(|earlier| {
+            earlier.mod_index != later.mod_index && earlier.file_name == later.file_name
+        }) {
+            for later_range in later.ranges.iter() {
+                for earlier_range in earlier.ranges.iter() {
+                    let start = later_range.start.max(earlier_range.start);
+                    let end = later_range.end.min(earlier_range.end);
+                    if start < end {
+                        conflicts.push(ModConflict {
+                            file_name: later.file_name.clone(),
+                            range: start..end,
+                            earlier_mod_name: earlier.mod_name.clone(),
+                            later_mod_name: later.mod_name.clone(),
+                        });
+                    }
+                }
+            }
+        }
+    }
+    conflicts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_conflicts_between_mods() {
+        assert_eq!(
+            get_changed_ranges(&[0, 0, 0, 0, 0, 0], &[1, 1, 0, 0, 1, 0]),
+            [0..2, 4..5]
+        );
+        let change = |mod_index: usize, file_name: &str, ranges: &[(u64, u64)]| ModChange {
+            mod_index,
+            mod_name: format!("Mod {}", mod_index),
+            file_name: file_name.to_string(),
+            ranges: ranges.iter().map(|(start, end)| *start..*end).collect(),
+        };
+        let changes = [
+            change(0, "SCUS_942.28", &[(0x10, 0x20), (0x40, 0x44)]),
+            change(0, "SCUS_942.28", &[(0x18, 0x1C)]),
+            change(1, "WAD.WAD", &[(0x10, 0x20)]),
+            change(1, "SCUS_942.28", &[(0x20, 0x30), (0x42, 0x50)]),
+            change(2, "SCUS_942.28", &[(0x1E, 0x22)]),
+        ];
+        let conflicts = find_mod_conflicts(&changes)
+            .iter()
+            .map(|conflict| conflict.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            conflicts,
+            [
+                "SCUS_942.28 bytes 0x42-0x44 are changed by \"Mod 0\" and then by \"Mod 1\"",
+                "SCUS_942.28 bytes 0x1e-0x20 are changed by \"Mod 0\" and then by \"Mod 2\"",
+                "SCUS_942.28 bytes 0x20-0x22 are changed by \"Mod 1\" and then by \"Mod 2\"",
+            ]
+        );
+    }
+}
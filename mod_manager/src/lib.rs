@@ -0,0 +1,8 @@
+//! Mod packages: a manifest ("mod.toml") with assembly code, replacement files and translations
+//! applied onto a clean ROM in order.
+
+mod conflict;
+mod manifest;
+
+pub use conflict::{find_mod_conflicts, get_changed_ranges, ModChange, ModConflict};
+pub use manifest::{ModOperation, ModPackage, MANIFEST_FILE_NAME};
@@ -0,0 +1,467 @@
+//! Manifest of a mod package ("mod.toml"), which tells what the mod is and how it changes files
+//! on the ROM. Paths in the manifest are relative to the package directory.
+//!
+//! ```toml
+//! [mod]
+//! name = "Widescreen"
+//! version = "1.0"
+//! author = "Someone"                   # Optional
+//! description = "Renders in 16:9."     # Optional
+//! base_sha1 = "..."                    # Optional SHA-1 of the clean ROM image the mod is made for
+//!
+//! # Assembly code (a file or a directory of files) assembled into an executable like ps1exe-assemble.
+//! [[assembly]]
+//! file = "SCUS_942.28"
+//! source = "asm"
+//! symbols = "symbols.txt"              # Optional
+//! free = ["80070000-80071000"]         # Optional
+//! codec = "ascii"                      # Optional
+//!
+//! # Whole file replaced like rom-replace.
+//! [[replace]]
+//! file = "SYSTEM.CNF"
+//! source = "files/SYSTEM.CNF"
+//!
+//! # File in WAD (or its subfile) replaced with a file of the same size.
+//! [[wad]]
+//! file = "WAD.WAD"
+//! index = 98
+//! subfile = 2                          # Optional
+//! source = "files/level.bin"
+//!
+//! # Translated strings inserted like text-insert.
+//! [[text]]
+//! file = "SCUS_942.28"
+//! po = "text/fi.po"
+//! wad = { index = 98, load_address = "80073000" } # Optional, for strings in a file in WAD
+//! free = ["80071000-80072000"]         # Optional
+//! codec = "ascii"                      # Optional
+//! ```
+//!
+//! Operations are applied in the order they are written.
+
+use std::{
+    fmt::Display,
+    fs,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use serde::{de::Error, Deserialize, Deserializer};
+use toml::Spanned;
+
+pub const MANIFEST_FILE_NAME: &str = "mod.toml";
+
+pub struct ModPackage {
+    pub directory: PathBuf,
+    pub name: String,
+    pub version: String,
+    pub author: Option<String>,
+    pub description: Option<String>,
+    /// SHA-1 hash (in lowercase hexadecimal) of the clean ROM image the mod is made for.
+    pub base_sha1: Option<String>,
+    pub operations: Vec<ModOperation>,
+}
+impl ModPackage {
+    /// Reads the manifest of a mod package in a given directory.
+    pub fn from_directory(directory: &Path) -> Result<Self, String> {
+        let manifest_path = directory.join(MANIFEST_FILE_NAME);
+        let content = fs::read_to_string(&manifest_path).map_err(|err| {
+            format!(
+                "Failed to read mod manifest in path \"{}\": {}",
+                manifest_path.display(),
+                err
+            )
+        })?;
+        Self::parse(&content, directory).map_err(|err| {
+            format!(
+                "Failed to parse mod manifest in path \"{}\": {}",
+                manifest_path.display(),
+                err
+            )
+        })
+    }
+    pub fn parse(content: &str, directory: &Path) -> Result<Self, String> {
+        let manifest: Manifest = toml::from_str(content).map_err(|err| err.to_string())?;
+
+        // Tables are grouped by their names, so operations are put back in the order they are
+        // written by where their tables begin.
+        let mut operations = Vec::new();
+        for table in manifest.assembly {
+            let begin = table.span().start;
+            let table = table.into_inner();
+            operations.push((
+                begin,
+                ModOperation::Assembly {
+                    file: table.file,
+                    source: directory.join(table.source),
+                    symbols: table.symbols.map(|path| directory.join(path)),
+                    free: table.free,
+                    codec: table.codec,
+                },
+            ));
+        }
+        for table in manifest.replace {
+            let begin = table.span().start;
+            let table = table.into_inner();
+            operations.push((
+                begin,
+                ModOperation::Replace {
+                    file: table.file,
+                    source: directory.join(table.source),
+                },
+            ));
+        }
+        for table in manifest.wad {
+            let begin = table.span().start;
+            let table = table.into_inner();
+            operations.push((
+                begin,
+                ModOperation::Wad {
+                    file: table.file,
+                    index: table.index,
+                    subfile: table.subfile,
+                    source: directory.join(table.source),
+                },
+            ));
+        }
+        for table in manifest.text {
+            let begin = table.span().start;
+            let table = table.into_inner();
+            operations.push((
+                begin,
+                ModOperation::Text {
+                    file: table.file,
+                    po: directory.join(table.po),
+                    wad_file: table.wad.map(|wad| (wad.index, wad.load_address)),
+                    free: table.free,
+                    codec: table.codec,
+                },
+            ));
+        }
+        operations.sort_by_key(|(begin, _)| *begin);
+
+        let info = manifest.info;
+        Ok(Self {
+            directory: directory.to_path_buf(),
+            name: info.name,
+            version: info.version,
+            author: info.author,
+            description: info.description,
+            base_sha1: info.base_sha1,
+            operations: operations
+                .into_iter()
+                .map(|(_, operation)| operation)
+                .collect(),
+        })
+    }
+}
+impl Display for ModPackage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.name, self.version)?;
+        if let Some(author) = &self.author {
+            write!(f, " by {}", author)?;
+        }
+        Ok(())
+    }
+}
+
+/// Change of a mod to a file in the root directory of the ROM.
+pub enum ModOperation {
+    /// Assembles assembly code (a file or a directory of files) into an executable.
+    Assembly {
+        file: String,
+        source: PathBuf,
+        symbols: Option<PathBuf>,
+        /// Free regions of the executable for new code in sections.
+        free: Vec<Range<u64>>,
+        codec: Option<String>,
+    },
+    /// Replaces a whole file with a file of the same size.
+    Replace { file: String, source: PathBuf },
+    /// Replaces a file in WAD (or a subfile of it) with a file of the same size.
+    Wad {
+        file: String,
+        index: usize,
+        subfile: Option<usize>,
+        source: PathBuf,
+    },
+    /// Inserts translated strings of a PO file into an executable or a file in WAD.
+    Text {
+        file: String,
+        po: PathBuf,
+        /// Index of the file in WAD containing strings and the address it is loaded at.
+        wad_file: Option<(usize, u32)>,
+        /// Free regions for strings longer than the original ones.
+        free: Vec<Range<u64>>,
+        codec: Option<String>,
+    },
+}
+impl ModOperation {
+    /// Gets the name of the file on the ROM changed by the operation.
+    pub fn get_file_name(&self) -> &str {
+        match self {
+            ModOperation::Assembly { file, .. }
+            | ModOperation::Replace { file, .. }
+            | ModOperation::Wad { file, .. }
+            | ModOperation::Text { file, .. } => file,
+        }
+    }
+}
+impl Display for ModOperation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ModOperation::Assembly { file, source, .. } => {
+                write!(f, "Assemble \"{}\" into {}", source.display(), file)
+            }
+            ModOperation::Replace { file, source } => {
+                write!(f, "Replace {} with \"{}\"", file, source.display())
+            }
+            ModOperation::Wad {
+                file,
+                index,
+                subfile,
+                source,
+            } => {
+                write!(f, "Replace file #{}", index)?;
+                if let Some(subfile) = subfile {
+                    write!(f, " subfile #{}", subfile)?;
+                }
+                write!(f, " of {} with \"{}\"", file, source.display())
+            }
+            ModOperation::Text {
+                file, po, wad_file, ..
+            } => {
+                write!(f, "Insert text \"{}\" into ", po.display())?;
+                if let Some((wad_index, _)) = wad_file {
+                    write!(f, "file #{} of ", wad_index)?;
+                }
+                write!(f, "{}", file)
+            }
+        }
+    }
+}
+
+/// Manifest as written in "mod.toml". Tables of operations are turned into [ModOperation]s.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    #[serde(rename = "mod")]
+    info: ModInfo,
+    #[serde(default)]
+    assembly: Vec<Spanned<AssemblyTable>>,
+    #[serde(default)]
+    replace: Vec<Spanned<ReplaceTable>>,
+    #[serde(default)]
+    wad: Vec<Spanned<WadTable>>,
+    #[serde(default)]
+    text: Vec<Spanned<TextTable>>,
+}
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ModInfo {
+    name: String,
+    version: String,
+    author: Option<String>,
+    description: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_sha1")]
+    base_sha1: Option<String>,
+}
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AssemblyTable {
+    file: String,
+    source: PathBuf,
+    symbols: Option<PathBuf>,
+    #[serde(default, deserialize_with = "deserialize_address_ranges")]
+    free: Vec<Range<u64>>,
+    codec: Option<String>,
+}
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ReplaceTable {
+    file: String,
+    source: PathBuf,
+}
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct WadTable {
+    file: String,
+    index: usize,
+    subfile: Option<usize>,
+    source: PathBuf,
+}
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TextTable {
+    file: String,
+    po: PathBuf,
+    wad: Option<TextWadFile>,
+    #[serde(default, deserialize_with = "deserialize_address_ranges")]
+    free: Vec<Range<u64>>,
+    codec: Option<String>,
+}
+/// File in WAD containing strings, which has no header telling where it is loaded.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TextWadFile {
+    index: usize,
+    #[serde(deserialize_with = "deserialize_address")]
+    load_address: u32,
+}
+
+/// Deserializes a SHA-1 hash of 40 hexadecimal digits into lowercase.
+fn deserialize_sha1<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<String>, D::Error> {
+    let hash = String::deserialize(deserializer)?;
+    if hash.len() == 40 && hash.chars().all(|c| c.is_ascii_hexdigit()) {
+        Ok(Some(hash.to_ascii_lowercase()))
+    } else {
+        Err(D::Error::custom(format!(
+            "Base ROM hash \"{}\" is not a SHA-1 hash of 40 hexadecimal digits.",
+            hash
+        )))
+    }
+}
+/// Deserializes an address written as a hexadecimal string like "80070000".
+fn deserialize_address<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+    let address = String::deserialize(deserializer)?;
+    u32::from_str_radix(&address, 16).map_err(|_| {
+        D::Error::custom(format!(
+            "Failed to parse address \"{}\" as a hexadecimal number.",
+            address
+        ))
+    })
+}
+/// Deserializes regions of hexadecimal addresses like "80070000-80071000", as given with "--free".
+fn deserialize_address_ranges<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<Range<u64>>, D::Error> {
+    let ranges = Vec::<String>::deserialize(deserializer)?;
+    ranges
+        .iter()
+        .map(|range| {
+            range
+                .split_once('-')
+                .and_then(|(start, end)| {
+                    let start = u64::from_str_radix(start, 16).ok()?;
+                    let end = u64::from_str_radix(end, 16).ok()?;
+                    Some(start..end)
+                })
+                .filter(|range| range.start < range.end)
+                .ok_or_else(|| {
+                    D::Error::custom(format!(
+                        "Failed to parse free region \"{}\" as hexadecimal addresses like \"80070000-80071000\".",
+                        range
+                    ))
+                })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_mod_manifest() {
+        let package = ModPackage::parse(
+            r#"
+[mod]
+name = "Widescreen"
+version = "1.0"
+description = """
+Renders in 16:9."""
+base_sha1 = "A9993E364706816ABA3E25717850C26C9CD0D89D"
+
+[[wad]]
+file = "WAD.WAD"
+index = 98
+subfile = 2
+source = "level.bin"
+
+[[assembly]]
+file = "SCUS_942.28"
+source = "asm"
+free = ["80070000-80071000", "80072000-80073000"]
+
+[[text]]
+file = "SCUS_942.28"
+po = "fi.po"
+wad = { index = 98, load_address = "80073000" }
+free = ["80071000-80072000"]
+"#,
+            Path::new("mods/widescreen"),
+        )
+        .unwrap();
+        assert_eq!(package.to_string(), "Widescreen 1.0");
+        assert_eq!(package.description.as_deref(), Some("Renders in 16:9."));
+        assert_eq!(
+            package.base_sha1.as_deref(),
+            Some("a9993e364706816aba3e25717850c26c9cd0d89d")
+        );
+        // Operations are in the order they are written, even if their tables are not.
+        assert_eq!(package.operations.len(), 3);
+        assert_eq!(
+            package.operations[0].to_string(),
+            "Replace file #98 subfile #2 of WAD.WAD with \"mods/widescreen/level.bin\""
+        );
+        let ModOperation::Assembly { source, free, .. } = &package.operations[1] else {
+            panic!("Second operation is not assembly.");
+        };
+        assert_eq!(source, Path::new("mods/widescreen/asm"));
+        assert_eq!(*free, [0x80070000..0x80071000, 0x80072000..0x80073000]);
+        assert_eq!(
+            package.operations[2].to_string(),
+            "Insert text \"mods/widescreen/fi.po\" into file #98 of SCUS_942.28"
+        );
+    }
+    #[test]
+    fn fail_to_parse_invalid_mod_manifest() {
+        const MOD_TABLE: &str = "[mod]\nname = \"A\"\nversion = \"1\"\n";
+        for (content, error) in [
+            (
+                String::from("[[replace]]\nfile = \"A\"\nsource = \"B\""),
+                "missing field `mod`",
+            ),
+            (
+                String::from("[mod]\nname = \"A\""),
+                "missing field `version`",
+            ),
+            (
+                format!("{}base_sha1 = \"abc\"", MOD_TABLE),
+                "Base ROM hash \"abc\" is not a SHA-1 hash",
+            ),
+            (
+                format!("{}[[replace]]\nfile = \"A\"\nsorce = \"B\"", MOD_TABLE),
+                "unknown field `sorce`",
+            ),
+            (
+                format!(
+                    "{}[[wad]]\nfile = \"A\"\nindex = -1\nsource = \"B\"",
+                    MOD_TABLE
+                ),
+                "invalid value: integer `-1`",
+            ),
+            (format!("{}[patch]", MOD_TABLE), "unknown field `patch`"),
+            (
+                format!(
+                    "{}[[text]]\nfile = \"A\"\npo = \"B\"\nfree = [\"2-1\"]",
+                    MOD_TABLE
+                ),
+                "Failed to parse free region \"2-1\"",
+            ),
+            (
+                format!(
+                    "{}[[text]]\nfile = \"A\"\npo = \"B\"\nwad = {{ index = 1 }}",
+                    MOD_TABLE
+                ),
+                "missing field `load_address`",
+            ),
+        ] {
+            let result = ModPackage::parse(&content, Path::new("")).err().unwrap();
+            assert!(result.contains(error), "{}", result);
+        }
+    }
+}
@@ -0,0 +1,290 @@
+//! Analysis of a whole executable, which finds its functions and tells code apart from data.
+
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Range;
+
+use mips::Instruction;
+
+use crate::Function;
+
+/// Most functions analyzed, so that analysis ends even if code is misread.
+const MAX_FUNCTION_COUNT: usize = 0x10000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RegionKind {
+    /// Code of functions found by following calls.
+    Function,
+    /// Code not reached from any function, like functions only called through pointers.
+    Code,
+    Data,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Region {
+    pub range: Range<u64>,
+    pub kind: RegionKind,
+}
+
+/// Functions and regions of code and data found by [ExeAnalysis::analyze].
+pub struct ExeAnalysis {
+    /// Functions by entry address.
+    pub functions: BTreeMap<u64, Function>,
+    /// Regions covering the whole analyzed memory, sorted by address.
+    pub regions: Vec<Region>,
+}
+impl ExeAnalysis {
+    /// Finds functions by following calls (recursively) from given entry addresses,
+    /// like the initial PC and known functions. Rest of the memory is classified as code
+    /// if it looks like code (valid instructions up to a return), otherwise as data.
+    /// Jump tables of functions are data.
+    pub fn analyze(
+        range: Range<u64>,
+        entries: &[u64],
+        read_word: &dyn Fn(u64) -> Option<u32>,
+    ) -> Self {
+        let mut functions = BTreeMap::new();
+        let mut entries_to_analyze = entries.to_vec();
+        while let Some(entry) = entries_to_analyze.pop() {
+            if functions.len() >= MAX_FUNCTION_COUNT
+                || !range.contains(&entry)
+                || functions.contains_key(&entry)
+            {
+                continue;
+            }
+            let function = Function::analyze(entry, read_word);
+            entries_to_analyze.extend(function.calls.iter());
+            functions.insert(entry, function);
+        }
+
+        // Kind of each word, which is data unless found otherwise.
+        let word_count = (range.end - range.start) as usize / 4;
+        let mut kinds = vec![None; word_count];
+        let get_index = |address: u64| (address - range.start) as usize / 4;
+        for function in functions.values() {
+            for block in function.blocks.iter() {
+                for address in (block.start..block.end).step_by(4) {
+                    if range.contains(&address) {
+                        kinds[get_index(address)] = Some(RegionKind::Function);
+                    }
+                }
+            }
+        }
+        for function in functions.values() {
+            for jump_table in function.jump_tables.iter() {
+                for address in jump_table.get_range().step_by(4) {
+                    if range.contains(&address) {
+                        kinds[get_index(address)] = Some(RegionKind::Data);
+                    }
+                }
+            }
+        }
+
+        // Words not found otherwise are classified in runs between known regions.
+        let mut i = 0;
+        while i < word_count {
+            if kinds[i].is_some() {
+                i += 1;
+                continue;
+            }
+            let start = i;
+            while i < word_count && kinds[i].is_none() {
+                i += 1;
+            }
+            let words = (start..i)
+                .map(|j| read_word(range.start + j as u64 * 4))
+                .collect::<Vec<_>>();
+            for (j, kind) in classify_words(&words).into_iter().enumerate() {
+                kinds[start + j] = Some(kind);
+            }
+        }
+
+        let mut regions: Vec<Region> = Vec::new();
+        for (i, kind) in kinds.into_iter().enumerate() {
+            let kind = kind.unwrap();
+            let address = range.start + i as u64 * 4;
+            match regions.last_mut() {
+                Some(region) if region.kind == kind => region.range.end = address + 4,
+                _ => regions.push(Region {
+                    range: address..address + 4,
+                    kind,
+                }),
+            }
+        }
+
+        Self { functions, regions }
+    }
+    /// Gets the count of bytes in regions of a given kind.
+    pub fn get_byte_count(&self, kind: RegionKind) -> u64 {
+        self.regions
+            .iter()
+            .filter(|region| region.kind == kind)
+            .map(|region| region.range.end - region.range.start)
+            .sum()
+    }
+    /// Writes the call graph in Graphviz DOT format. Functions are named by given labels.
+    pub fn get_call_graph_dot(&self, labels: &HashMap<u64, String>) -> String {
+        let get_name = |address: u64| escape(&get_function_name(address, labels));
+        let mut result = String::from("digraph calls {\n");
+        for function in self.functions.values() {
+            result.push_str(&format!("    \"{}\";\n", get_name(function.entry)));
+            for call in function.calls.iter() {
+                result.push_str(&format!(
+                    "    \"{}\" -> \"{}\";\n",
+                    get_name(function.entry),
+                    get_name(*call)
+                ));
+            }
+        }
+        result.push_str("}\n");
+        result
+    }
+    /// Writes the functions with their sizes and calls, and the coverage of the analyzed memory,
+    /// in JSON format. Functions are named by given labels.
+    pub fn get_call_graph_json(&self, labels: &HashMap<u64, String>) -> String {
+        let functions = self
+            .functions
+            .values()
+            .map(|function| {
+                let range = function.get_range();
+                let calls = function
+                    .calls
+                    .iter()
+                    .map(|call| format!("\"{}\"", escape(&get_function_name(*call, labels))))
+                    .collect::<Vec<_>>();
+                format!(
+                    "    {{\"address\": \"0x{:08X}\", \"name\": \"{}\", \"size\": {}, \"calls\": [{}]}}",
+                    function.entry,
+                    escape(&get_function_name(function.entry, labels)),
+                    range.end - range.start,
+                    calls.join(", ")
+                )
+            })
+            .collect::<Vec<_>>();
+        format!(
+            "{{\n  \"functions\": [\n{}\n  ],\n  \"coverage\": {{\"function_bytes\": {}, \"code_bytes\": {}, \"data_bytes\": {}}}\n}}\n",
+            functions.join(",\n"),
+            self.get_byte_count(RegionKind::Function),
+            self.get_byte_count(RegionKind::Code),
+            self.get_byte_count(RegionKind::Data)
+        )
+    }
+}
+
+/// Gets the label of a function, or a generated name with "FUN_" prefix.
+fn get_function_name(address: u64, labels: &HashMap<u64, String>) -> String {
+    match labels.get(&address) {
+        Some(label) => label.clone(),
+        None => format!("FUN_{:08x}", address),
+    }
+}
+
+/// Escapes backslashes and double quotes for strings in DOT and JSON.
+fn escape(text: &str) -> String {
+    text.replace("\\", "\\\\").replace("\"", "\\\"")
+}
+
+/// Classifies words not reached from functions. Code is a run of valid instructions
+/// ending with a return ("jr ra") and its delay slot, not starting with zeros (nop),
+/// which are more likely padding. Rest is data.
+fn classify_words(words: &[Option<u32>]) -> Vec<RegionKind> {
+    let return_instruction = Instruction::parse_from_str("jr ra")
+        .unwrap()
+        .to_machine_code();
+    let mut kinds = Vec::with_capacity(words.len());
+    while kinds.len() < words.len() {
+        let start = kinds.len();
+        if words[start] == Some(0) {
+            kinds.push(RegionKind::Data);
+            continue;
+        }
+        let mut end = start;
+        let mut is_code = false;
+        while end < words.len() {
+            let Some(word) = words[end] else {
+                break;
+            };
+            if Instruction::parse_from_machine_code(word).is_err() {
+                break;
+            }
+            end += 1;
+            if word == return_instruction {
+                // The delay slot must be valid too.
+                is_code = words
+                    .get(end)
+                    .copied()
+                    .flatten()
+                    .is_some_and(|word| Instruction::parse_from_machine_code(word).is_ok());
+                if is_code {
+                    end += 1;
+                }
+                break;
+            }
+        }
+        let kind = match is_code {
+            true => RegionKind::Code,
+            // An invalid word is data with all words before it.
+            false => {
+                end = (end + 1).min(words.len());
+                RegionKind::Data
+            }
+        };
+        kinds.resize(end, kind);
+    }
+    kinds
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn analyze_functions_and_regions() {
+        let content = "@at 0x80010000\nmain:\n  jal function\n  nop\n  jr ra\n  nop\n.word 0x12345678\nfunction:\n  jr ra\n  nop\nunused:\n  addiu v0, zero, 1\n  jr ra\n  nop\n.space 8";
+        let mut words = HashMap::new();
+        for node in mips::parse_nodes(content).unwrap() {
+            let bytes = match node.kind {
+                mips::NodeKind::Instruction(instruction) => instruction.to_le_bytes().to_vec(),
+                mips::NodeKind::Data(bytes) => bytes,
+                _ => continue,
+            };
+            for (i, word) in bytes.chunks(4).enumerate() {
+                let word = u32::from_le_bytes(word.try_into().unwrap());
+                words.insert(node.address + i as u64 * 4, word);
+            }
+        }
+
+        let range = 0x80010000..0x80010034;
+        let analysis = ExeAnalysis::analyze(range, &[0x80010000], &|address| {
+            words.get(&address).copied()
+        });
+        assert_eq!(
+            analysis.functions.keys().copied().collect::<Vec<_>>(),
+            [0x80010000, 0x80010014]
+        );
+        let regions = analysis
+            .regions
+            .iter()
+            .map(|region| (region.range.start, region.kind))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            regions,
+            [
+                (0x80010000, RegionKind::Function),
+                (0x80010010, RegionKind::Data),
+                (0x80010014, RegionKind::Function),
+                (0x8001001C, RegionKind::Code),
+                (0x80010028, RegionKind::Data),
+            ]
+        );
+        assert_eq!(analysis.get_byte_count(RegionKind::Function), 24);
+
+        let labels = HashMap::from([(0x80010000, String::from("main"))]);
+        assert_eq!(
+            analysis.get_call_graph_dot(&labels),
+            "digraph calls {\n    \"main\";\n    \"main\" -> \"FUN_80010014\";\n    \"FUN_80010014\";\n}\n"
+        );
+        assert!(analysis
+            .get_call_graph_json(&labels)
+            .contains("{\"address\": \"0x80010000\", \"name\": \"main\", \"size\": 16, \"calls\": [\"FUN_80010014\"]}"));
+    }
+}
@@ -0,0 +1,255 @@
+//! Building executables from scratch out of code and data segments, like small homebrew test
+//! programs run on emulators to check how the hardware behaves.
+
+use crate::{ExeRegion, MemoryRegion, PS1Exe};
+
+/// Beginning and end of main RAM in KSEG0, where executables are loaded.
+const RAM_START: u64 = 0x80000000;
+const RAM_END: u64 = RAM_START + MemoryRegion::MainRam.get_size();
+const ASCII_MARKER_OFFSET: usize = 0x04C;
+
+/// Builds a Playstation executable out of code and data segments placed at given addresses.
+/// Size of the executable is padded to a multiple of 2048 bytes.
+pub struct PS1ExeBuilder {
+    destination_address_in_ram: Option<u32>,
+    initial_pc: Option<u32>,
+    initial_gp_r28: u32,
+    segments: Vec<(u32, Vec<u8>)>,
+    /// Address and size of a data section, which is usually not used.
+    data_section: (u32, u32),
+    /// Address and size of memory cleared after loading, like BSS.
+    memfill: (u32, u32),
+    /// Initial stack pointer base and offset added to it.
+    stack: (u32, u32),
+    ascii_marker: String,
+}
+impl PS1ExeBuilder {
+    pub fn new() -> Self {
+        Self {
+            destination_address_in_ram: None,
+            initial_pc: None,
+            initial_gp_r28: 0,
+            segments: Vec::new(),
+            data_section: (0, 0),
+            memfill: (0, 0),
+            stack: (0, 0),
+            // North American release of the game is the one worked on.
+            ascii_marker: String::from(ExeRegion::NtscU.get_ascii_marker()),
+        }
+    }
+    /// Sets where the executable is loaded into. Defaults to the address of the lowest segment.
+    pub fn destination_address_in_ram(mut self, address: u32) -> Self {
+        self.destination_address_in_ram = Some(address);
+        self
+    }
+    /// Sets the address execution starts at. Defaults to the destination address in RAM.
+    pub fn initial_pc(mut self, address: u32) -> Self {
+        self.initial_pc = Some(address);
+        self
+    }
+    pub fn initial_gp_r28(mut self, value: u32) -> Self {
+        self.initial_gp_r28 = value;
+        self
+    }
+    /// Adds code or data placed at a given address.
+    pub fn segment(mut self, address: u32, bytes: &[u8]) -> Self {
+        self.segments.push((address, bytes.to_vec()));
+        self
+    }
+    /// Sets the address and size of a data section. The BIOS does not use it.
+    pub fn data_section(mut self, address: u32, size: u32) -> Self {
+        self.data_section = (address, size);
+        self
+    }
+    /// Sets a region of memory cleared after loading (BSS), which must be outside the executable.
+    pub fn bss(mut self, address: u32, size: u32) -> Self {
+        self.memfill = (address, size);
+        self
+    }
+    /// Sets the initial stack pointer to a base address plus an offset. Without it, the BIOS
+    /// keeps its own stack pointer.
+    pub fn stack(mut self, base_address: u32, offset: u32) -> Self {
+        self.stack = (base_address, offset);
+        self
+    }
+    /// Sets the region marker, like "Sony Computer Entertainment Inc. for Europe area".
+    /// The BIOS does not check it, so it may also be empty.
+    pub fn ascii_marker(mut self, marker: &str) -> Self {
+        self.ascii_marker = marker.to_string();
+        self
+    }
+    /// Sets the region marker to the one of a region.
+    pub fn region(self, region: ExeRegion) -> Self {
+        self.ascii_marker(region.get_ascii_marker())
+    }
+    pub fn build(mut self) -> Result<PS1Exe, String> {
+        self.segments.retain(|(_, bytes)| !bytes.is_empty());
+        self.segments.sort_by_key(|(address, _)| *address);
+        let Some(lowest_address) = self.segments.first().map(|(address, _)| *address) else {
+            return Err(String::from(
+                "Playstation executable has no code or data segments.",
+            ));
+        };
+        let destination = self.destination_address_in_ram.unwrap_or(lowest_address) as u64;
+        if destination % 4 != 0 {
+            return Err(format!(
+                "Destination address in RAM 0x{:X} is not aligned to 4 bytes.",
+                destination
+            ));
+        }
+        let mut end = destination;
+        for (address, bytes) in self.segments.iter() {
+            let address = *address as u64;
+            if address < end {
+                return Err(match address < destination {
+                    true => format!(
+                        "Segment at 0x{:X} is before the destination address in RAM 0x{:X}.",
+                        address, destination
+                    ),
+                    false => format!(
+                        "Segment at 0x{:X} overlaps the segment before it, which ends at 0x{:X}.",
+                        address, end
+                    ),
+                });
+            }
+            end = address + bytes.len() as u64;
+        }
+        let file_size = (end - destination).div_ceil(PS1Exe::VALID_MULTIPLIER as u64);
+        let file_size = file_size * PS1Exe::VALID_MULTIPLIER as u64;
+        if destination < RAM_START || destination + file_size > RAM_END {
+            return Err(format!(
+                "Playstation executable at 0x{:X}-0x{:X} does not fit in RAM at 0x{:X}-0x{:X}.",
+                destination,
+                destination + file_size,
+                RAM_START,
+                RAM_END
+            ));
+        }
+
+        let initial_pc = self.initial_pc.unwrap_or(destination as u32);
+        if !(destination..end).contains(&(initial_pc as u64)) || initial_pc % 4 != 0 {
+            return Err(format!(
+                "Initial PC 0x{:X} is not an aligned address in the code and data at 0x{:X}-0x{:X}.",
+                initial_pc, destination, end
+            ));
+        }
+        // Memory fill area is cleared after loading, which would erase code and data.
+        let (memfill_address, memfill_size) = self.memfill;
+        let memfill_end = memfill_address as u64 + memfill_size as u64;
+        if memfill_size != 0 && (memfill_address as u64) < end && destination < memfill_end {
+            return Err(format!(
+                "BSS at 0x{:X}-0x{:X} overlaps code and data at 0x{:X}-0x{:X}, which would be cleared after loading.",
+                memfill_address, memfill_end, destination, end
+            ));
+        }
+        if !self.ascii_marker.is_ascii()
+            || self.ascii_marker.contains('\0')
+            || ASCII_MARKER_OFFSET + self.ascii_marker.len()
+                >= PS1Exe::CODE_AND_DATA_BEGIN_OFFSET as usize
+        {
+            return Err(format!(
+                "ASCII marker \"{}\" must be ASCII without null characters and fit in the header.",
+                self.ascii_marker
+            ));
+        }
+
+        let header_len = PS1Exe::CODE_AND_DATA_BEGIN_OFFSET as usize;
+        let mut data = vec![0; header_len + file_size as usize];
+        data[0x000..0x008].copy_from_slice(b"PS-X EXE");
+        for (offset, value) in [
+            (0x010, initial_pc),
+            (0x014, self.initial_gp_r28),
+            (0x018, destination as u32),
+            (0x01C, file_size as u32),
+            (0x020, self.data_section.0),
+            (0x024, self.data_section.1),
+            (0x028, memfill_address),
+            (0x02C, memfill_size),
+            (0x030, self.stack.0),
+            (0x034, self.stack.1),
+        ] {
+            data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        }
+        data[ASCII_MARKER_OFFSET..ASCII_MARKER_OFFSET + self.ascii_marker.len()]
+            .copy_from_slice(self.ascii_marker.as_bytes());
+        for (address, bytes) in self.segments.iter() {
+            let offset = header_len + (*address as u64 - destination) as usize;
+            data[offset..offset + bytes.len()].copy_from_slice(bytes);
+        }
+        PS1Exe::from_bytes(data)
+    }
+}
+impl Default for PS1ExeBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PS1ExeReader;
+
+    #[test]
+    fn build_exe_from_segments() {
+        let exe = PS1ExeBuilder::new()
+            .segment(0x80010900, b"DATA")
+            .segment(
+                0x80010000,
+                &[0x08, 0x00, 0xE0, 0x03, 0x00, 0x00, 0x00, 0x00],
+            )
+            .initial_gp_r28(0x80018000)
+            .bss(0x80011000, 0x100)
+            .stack(0x801FFF00, 0xF0)
+            .region(ExeRegion::Pal)
+            .build()
+            .unwrap();
+        assert_eq!(exe.destination_address_in_ram, 0x80010000);
+        assert_eq!(exe.initial_pc, 0x80010000);
+        assert_eq!(exe.initial_gp_r28, 0x80018000);
+        // Segments end at 0x80010904, so the size is rounded up to 0x1000.
+        assert_eq!(exe.file_size, 0x1000);
+        assert_eq!(
+            exe.ascii_marker,
+            "Sony Computer Entertainment Inc. for Europe area"
+        );
+        let data = exe.as_bytes();
+        assert_eq!(data.len(), 0x1800);
+        assert_eq!(
+            data[0x028..0x038],
+            [
+                0x00, 0x10, 0x01, 0x80, 0x00, 0x01, 0x00, 0x00, 0x00, 0xFF, 0x1F, 0x80, 0xF0, 0x00,
+                0x00, 0x00
+            ]
+        );
+        let reader = PS1ExeReader::new(&exe);
+        assert_eq!(reader.read_word(0x80010000), Some(0x03E00008));
+        assert_eq!(
+            reader.read_word(0x80010900),
+            Some(u32::from_le_bytes(*b"DATA"))
+        );
+
+        let builder = || PS1ExeBuilder::new().segment(0x80010000, &[0; 8]);
+        for (result, error) in [
+            (PS1ExeBuilder::new().build(), "no code or data"),
+            (
+                builder().segment(0x80010004, &[0; 4]).build(),
+                "overlaps the segment",
+            ),
+            (
+                builder().destination_address_in_ram(0x80010100).build(),
+                "before the destination",
+            ),
+            (builder().initial_pc(0x80010008).build(), "Initial PC"),
+            (builder().bss(0x80010004, 4).build(), "BSS"),
+            (
+                builder().segment(0x801FFFFC, &[0; 8]).build(),
+                "does not fit in RAM",
+            ),
+            (builder().ascii_marker("Ä").build(), "ASCII marker"),
+        ] {
+            let err = result.err().unwrap();
+            assert!(err.contains(error), "{}", err);
+        }
+    }
+}
@@ -0,0 +1,253 @@
+use encoding_rs::SHIFT_JIS;
+
+/// Converts between bytes of game text and strings.
+///
+/// Decoding never fails. Bytes that cannot be shown as characters are written
+/// as escape sequences, which encoding turns back into the same bytes. This way
+/// text can be disassembled and assembled back byte for byte.
+///
+/// # Escape sequences
+/// Escape sequences are the same as in text of assembly code (see [mips::encode_text_with]).
+/// * `\\` Backslash
+/// * `\"` Double quote
+/// * `\n` Line break (byte 0x0A)
+/// * `\r` Carriage return (byte 0x0D)
+/// * `\t` Tab (byte 0x09)
+/// * `\0` Null termination byte
+/// * `\xNN` Raw byte, where NN is a hexadecimal value
+/// * `\cNN` Control code, where NN is a hexadecimal value (encodes the same as `\xNN`)
+/// * `\c{name}` Control code with a name, like `\c{red}` of [SpyroCodec]
+pub trait TextCodec {
+    /// Decodes a character from the beginning of given bytes.
+    /// Returns the character and how many bytes it takes.
+    fn decode_char(&self, bytes: &[u8]) -> Option<(char, usize)>;
+    /// Encodes a character into bytes. Returns `None` if the character cannot be encoded.
+    fn encode_char(&self, value: char) -> Option<Vec<u8>>;
+    /// Whether a given byte is a control code, like a text color change.
+    fn is_control_code(&self, _value: u8) -> bool {
+        false
+    }
+    /// Gets the name of a control code, which is written as `\c{name}` instead of `\cNN`.
+    fn get_control_code_name(&self, _value: u8) -> Option<&'static str> {
+        None
+    }
+    /// Gets a control code by its name.
+    fn get_control_code_by_name(&self, _name: &str) -> Option<u8> {
+        None
+    }
+
+    fn decode(&self, bytes: &[u8]) -> String {
+        let mut result = String::with_capacity(bytes.len());
+        let mut i = 0;
+        while i < bytes.len() {
+            if let Some((c, len)) = self.decode_char(&bytes[i..]) {
+                mips::push_escaped_char(&mut result, c);
+                i += len;
+                continue;
+            }
+
+            let b = bytes[i];
+            mips::push_escaped_byte(
+                &mut result,
+                b,
+                self.is_control_code(b),
+                self.get_control_code_name(b),
+            );
+            i += 1;
+        }
+        result
+    }
+    fn encode(&self, value: &str) -> Result<Vec<u8>, String> {
+        mips::encode_text_with(
+            value,
+            |c| self.encode_char(c),
+            |name| self.get_control_code_by_name(name),
+        )
+    }
+    /// Gets the length of text in the beginning of given bytes.
+    /// Returns the length in bytes and the count of characters (control codes excluded).
+    fn get_text_len(&self, bytes: &[u8]) -> (usize, usize) {
+        let mut len = 0;
+        let mut char_count = 0;
+        while len < bytes.len() {
+            if let Some((_, char_len)) = self.decode_char(&bytes[len..]) {
+                len += char_len;
+                char_count += 1;
+            } else if self.is_control_code(bytes[len]) {
+                len += 1;
+            } else {
+                break;
+            }
+        }
+        (len, char_count)
+    }
+}
+
+/// Printable ASCII characters with line breaks and tabs.
+pub struct AsciiCodec;
+impl TextCodec for AsciiCodec {
+    fn decode_char(&self, bytes: &[u8]) -> Option<(char, usize)> {
+        let b = *bytes.first()?;
+        if b == b'\n' || b == b'\t' || (0x20..0x7F).contains(&b) {
+            Some((b as char, 1))
+        } else {
+            None
+        }
+    }
+    fn encode_char(&self, value: char) -> Option<Vec<u8>> {
+        if value.is_ascii() && self.decode_char(&[value as u8]).is_some() {
+            Some(vec![value as u8])
+        } else {
+            None
+        }
+    }
+}
+
+/// Shift-JIS used by the Japanese release, which has both single byte
+/// (ASCII and half-width katakana) and double byte (kanji, kana) characters.
+pub struct ShiftJisCodec;
+impl TextCodec for ShiftJisCodec {
+    fn decode_char(&self, bytes: &[u8]) -> Option<(char, usize)> {
+        if let Some(result) = AsciiCodec.decode_char(bytes) {
+            return Some(result);
+        }
+
+        let lead = *bytes.first()?;
+        let len = match lead {
+            0xA1..=0xDF => 1,
+            0x81..=0x9F | 0xE0..=0xFC => match bytes.get(1)? {
+                0x40..=0x7E | 0x80..=0xFC => 2,
+                _ => return None,
+            },
+            _ => return None,
+        };
+
+        let decoded =
+            SHIFT_JIS.decode_without_bom_handling_and_without_replacement(&bytes[..len])?;
+        let mut chars = decoded.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Some((c, len)),
+            _ => None,
+        }
+    }
+    fn encode_char(&self, value: char) -> Option<Vec<u8>> {
+        if value.is_ascii() {
+            return AsciiCodec.encode_char(value);
+        }
+        let mut buffer = [0u8; 4];
+        let (bytes, _, had_errors) = SHIFT_JIS.encode(value.encode_utf8(&mut buffer));
+        if had_errors {
+            None
+        } else {
+            Some(bytes.into_owned())
+        }
+    }
+}
+
+/// Control codes of Spyro the Dragon text with their names.
+/// Color codes change the color of the text after them until the next color code.
+const SPYRO_CONTROL_CODES: &[(u8, &str)] = &[
+    (0x01, "white"),
+    (0x02, "yellow"),
+    (0x03, "green"),
+    (0x04, "red"),
+    (0x05, "blue"),
+    (0x06, "purple"),
+    (0x0D, "br"),
+];
+
+/// Text encoding of Spyro the Dragon. Text is ASCII, but it may contain
+/// control codes (bytes 0x01-0x1F other than line breaks and tabs).
+/// Control codes are kept as part of strings instead of ending them.
+/// Color changes and line breaks of the game are written with names,
+/// like `\c{red}` and `\c{br}` (see [SPYRO_CONTROL_CODES]).
+pub struct SpyroCodec;
+impl TextCodec for SpyroCodec {
+    fn decode_char(&self, bytes: &[u8]) -> Option<(char, usize)> {
+        AsciiCodec.decode_char(bytes)
+    }
+    fn encode_char(&self, value: char) -> Option<Vec<u8>> {
+        AsciiCodec.encode_char(value)
+    }
+    fn is_control_code(&self, value: u8) -> bool {
+        (0x01..0x20).contains(&value) && value != b'\n' && value != b'\t'
+    }
+    fn get_control_code_name(&self, value: u8) -> Option<&'static str> {
+        SPYRO_CONTROL_CODES
+            .iter()
+            .find(|(code, _)| *code == value)
+            .map(|(_, name)| *name)
+    }
+    fn get_control_code_by_name(&self, name: &str) -> Option<u8> {
+        SPYRO_CONTROL_CODES
+            .iter()
+            .find(|(_, code_name)| *code_name == name)
+            .map(|(code, _)| *code)
+    }
+}
+
+pub const TEXT_CODEC_NAMES: &[&str] = &["ascii", "shift-jis", "spyro"];
+
+/// Gets a text codec by its name (see [TEXT_CODEC_NAMES]).
+pub fn get_text_codec_by_name(name: &str) -> Result<Box<dyn TextCodec>, String> {
+    match name {
+        "ascii" => Ok(Box::new(AsciiCodec)),
+        "shift-jis" => Ok(Box::new(ShiftJisCodec)),
+        "spyro" => Ok(Box::new(SpyroCodec)),
+        _ => Err(format!(
+            "Unknown text codec \"{}\". Supported text codecs include {}.",
+            name,
+            TEXT_CODEC_NAMES.join(", ")
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_and_encode_ascii_with_escapes() {
+        let bytes = b"SAY \"HI\"\\\n\x01\xFF";
+        let value = AsciiCodec.decode(bytes);
+        assert_eq!(value, "SAY \\\"HI\\\"\\\\\\n\\x01\\xFF");
+        assert_eq!(AsciiCodec.encode(&value).unwrap(), bytes);
+    }
+    #[test]
+    fn decode_and_encode_shift_jis() {
+        // "スパイロ" (Spyro) followed by ASCII
+        let bytes = [0x83, 0x58, 0x83, 0x70, 0x83, 0x43, 0x83, 0x8D, b'1'];
+        let value = ShiftJisCodec.decode(&bytes);
+        assert_eq!(value, "スパイロ1");
+        assert_eq!(ShiftJisCodec.encode(&value).unwrap(), bytes);
+        assert_eq!(ShiftJisCodec.get_text_len(&bytes), (9, 5));
+    }
+    #[test]
+    fn decode_and_encode_spyro_control_codes() {
+        let bytes = b"\x03GEMS\x01 50\x0D\x1F";
+        let value = SpyroCodec.decode(bytes);
+        assert_eq!(value, "\\c{green}GEMS\\c{white} 50\\c{br}\\c1F");
+        assert_eq!(SpyroCodec.encode(&value).unwrap(), bytes);
+        // Control codes may be written by their values too.
+        assert_eq!(SpyroCodec.encode("\\c03GEMS").unwrap(), b"\x03GEMS");
+        assert_eq!(SpyroCodec.get_text_len(bytes), (11, 7));
+        assert_eq!(AsciiCodec.get_text_len(bytes), (0, 0));
+        assert!(SpyroCodec.encode("\\c{pink}").is_err());
+        assert!(AsciiCodec.encode("\\c{red}").is_err());
+    }
+    #[test]
+    fn encode_same_escape_sequences_as_assembly_code() {
+        for value in ["\\r\\n", "\\x0D\\c0A", "\\\\\\\"\\t\\0"] {
+            assert_eq!(AsciiCodec.encode(value), mips::encode_text(value));
+        }
+        assert_eq!(AsciiCodec.encode("\\r").unwrap(), b"\r");
+    }
+    #[test]
+    fn fail_encode_with_invalid_escape_sequence() {
+        assert!(AsciiCodec.encode("\\q").is_err());
+        assert!(AsciiCodec.encode("\\x1").is_err());
+        assert!(AsciiCodec.encode("\\c1").is_err());
+        assert!(AsciiCodec.encode("\\x+F").is_err());
+        assert!(AsciiCodec.encode("ä").is_err());
+    }
+}
@@ -0,0 +1,635 @@
+//! Differential testing of native reimplementations of functions against the original code of
+//! an executable, which is run in the interpreter (see [mips::Cpu]) with the same inputs.
+//!
+//! Inputs of a function are registers and bytes of memory, either recorded (like from an
+//! emulator) or random. A reimplementation takes registers and memory like the original, so that
+//! results can be compared: registers the function returns or keeps, and all of memory except
+//! the stack. Failing random inputs are shrunk to a minimal case, which can be written as text
+//! and kept as a recorded case.
+
+use std::fmt;
+use std::ops::Range;
+
+use mips::{Cpu, Gte, Memory, CALL_RETURN_ADDRESS, REGISTERS};
+
+use crate::PS1Exe;
+
+const V0: usize = 2;
+const V1: usize = 3;
+const GP: usize = 28;
+const SP: usize = 29;
+const FP: usize = 30;
+const RA: usize = 31;
+
+/// Stack pointer functions are called with, near the end of main RAM.
+pub const STACK_ADDRESS: u32 = 0x801FFF00;
+/// Size of the stack below [STACK_ADDRESS] which is not compared, as the original function
+/// keeps saved registers and locals there.
+const STACK_SIZE: u32 = 0x10000;
+const RAM_ADDRESS: u32 = 0x80000000;
+const SCRATCHPAD_ADDRESS: u32 = 0x1F800000;
+
+/// Inputs of a function. Registers which are not set are 0, except sp (see [STACK_ADDRESS]),
+/// gp (the initial gp of the executable) and ra.
+///
+/// Cases are written as text, one input per line, like "a0 = 0x80070000" for a register
+/// and "memory 0x80070000 = 01 02 03" for bytes of memory.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TestCase {
+    /// Registers set before the call, by number.
+    pub registers: Vec<(usize, u32)>,
+    /// Bytes written into memory before the call, by address, like structures arguments
+    /// point to.
+    pub memory: Vec<(u32, Vec<u8>)>,
+}
+impl TestCase {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Sets a register, replacing its previous value if any.
+    pub fn set_register(&mut self, register: usize, value: u32) {
+        match self.registers.iter_mut().find(|(r, _)| *r == register) {
+            Some((_, old_value)) => *old_value = value,
+            None => self.registers.push((register, value)),
+        }
+    }
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let mut case = Self::new();
+        for (i, line) in content.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let Some((target, value)) = line.split_once('=') else {
+                return Err(format!(
+                    "Line {}: Expected \"<register> = <value>\" or \"memory <address> = <bytes>\", found \"{}\".",
+                    i + 1,
+                    line
+                ));
+            };
+            let (target, value) = (target.trim(), value.trim());
+            if let Some(address) = target.strip_prefix("memory ") {
+                let address = parse_hex_u32(address.trim())
+                    .map_err(|err| format!("Line {}: {}", i + 1, err))?;
+                let bytes = value
+                    .split_whitespace()
+                    .map(|byte| {
+                        u8::from_str_radix(byte, 16)
+                            .map_err(|_| format!("Line {}: Invalid byte \"{}\".", i + 1, byte))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                case.memory.push((address, bytes));
+                continue;
+            }
+            let register = REGISTERS
+                .iter()
+                .position(|name| *name == target)
+                .ok_or_else(|| format!("Line {}: Unknown register \"{}\".", i + 1, target))?;
+            let value = parse_hex_u32(value).map_err(|err| format!("Line {}: {}", i + 1, err))?;
+            case.set_register(register, value);
+        }
+        Ok(case)
+    }
+}
+impl fmt::Display for TestCase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (register, value) in self.registers.iter() {
+            writeln!(f, "{} = 0x{:08X}", REGISTERS[*register], value)?;
+        }
+        for (address, bytes) in self.memory.iter() {
+            writeln!(f, "memory 0x{:08X} = {}", address, format_bytes(bytes))?;
+        }
+        Ok(())
+    }
+}
+
+/// Difference between results of the original function and of its reimplementation.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Difference {
+    Register {
+        register: usize,
+        original: u32,
+        native: u32,
+    },
+    /// Bytes which differ in a row, by their address in KSEG0.
+    Memory {
+        address: u32,
+        original: Vec<u8>,
+        native: Vec<u8>,
+    },
+}
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Difference::Register {
+                register,
+                original,
+                native,
+            } => write!(
+                f,
+                "{} is 0x{:08X} in the original and 0x{:08X} in the reimplementation.",
+                REGISTERS[*register], original, native
+            ),
+            Difference::Memory {
+                address,
+                original,
+                native,
+            } => write!(
+                f,
+                "Memory at 0x{:08X} is \"{}\" in the original and \"{}\" in the reimplementation.",
+                address,
+                format_bytes(original),
+                format_bytes(native)
+            ),
+        }
+    }
+}
+
+/// Random inputs of a function, on top of a base case.
+#[derive(Clone, Debug, Default)]
+pub struct RandomInputs {
+    /// Registers given random values, like arguments. Values are biased towards edge cases
+    /// like 0, -1 and overflows.
+    pub registers: Vec<usize>,
+    /// Memory filled with random bytes, like structures arguments point to.
+    pub memory: Vec<Range<u32>>,
+    /// Inputs every case has, like pointers into the random memory.
+    pub base: TestCase,
+    /// Seed of the random generator, so that failures can be reproduced.
+    pub seed: u64,
+}
+impl RandomInputs {
+    fn generate(&self, random: &mut Random) -> TestCase {
+        let mut case = self.base.clone();
+        for register in self.registers.iter() {
+            case.set_register(*register, random.next_value());
+        }
+        for range in self.memory.iter() {
+            let bytes = range.clone().map(|_| random.next_u32() as u8).collect();
+            case.memory.push((range.start, bytes));
+        }
+        case
+    }
+}
+
+/// Results of a function: its registers and memory after the call.
+pub type Results = ([u32; 32], Memory);
+
+/// Test of a native reimplementation of a function of an executable.
+///
+/// A reimplementation is a function taking registers and memory, set like for the original
+/// function, which reads its inputs and writes its results like the original does.
+pub struct DifferentialTest<'a> {
+    exe: &'a PS1Exe,
+    address: u32,
+    /// Registers compared after the call. By default, the return values and the registers
+    /// functions keep by convention (s0-s7, gp, sp and fp).
+    pub compared_registers: Vec<usize>,
+    /// Memory which is not compared, by addresses in KSEG0 (or KUSEG for scratchpad).
+    /// By default, the stack below [STACK_ADDRESS].
+    pub ignored_memory: Vec<Range<u32>>,
+    /// Count of instructions after which the original function is considered stuck.
+    pub max_instruction_count: u64,
+}
+impl<'a> DifferentialTest<'a> {
+    pub fn new(exe: &'a PS1Exe, address: u32) -> Self {
+        let mut compared_registers = vec![V0, V1];
+        compared_registers.extend(16..=23);
+        compared_registers.extend([GP, SP, FP]);
+        let stack = STACK_ADDRESS - STACK_SIZE..STACK_ADDRESS;
+        Self {
+            exe,
+            address,
+            compared_registers,
+            ignored_memory: vec![stack],
+            max_instruction_count: 1_000_000,
+        }
+    }
+    /// Makes registers and memory the function is called with: the executable loaded in RAM,
+    /// then inputs of the case.
+    fn set_up(&self, case: &TestCase) -> Result<Results, String> {
+        let mut memory = Memory::new();
+        memory
+            .load(
+                self.exe.destination_address_in_ram,
+                self.exe.get_code_and_data(),
+            )
+            .ok_or("Executable does not fit in main RAM.")?;
+        for (address, bytes) in case.memory.iter() {
+            memory.load(*address, bytes).ok_or_else(|| {
+                format!(
+                    "Memory at 0x{:08X} of {} bytes is not in main RAM or scratchpad.",
+                    address,
+                    bytes.len()
+                )
+            })?;
+        }
+        let mut registers = [0; 32];
+        registers[GP] = self.exe.initial_gp_r28;
+        registers[SP] = STACK_ADDRESS;
+        registers[RA] = CALL_RETURN_ADDRESS;
+        for (register, value) in case.registers.iter() {
+            *registers
+                .get_mut(*register)
+                .ok_or_else(|| format!("Register number {} is out of range (0-31).", register))? =
+                *value;
+        }
+        registers[0] = 0;
+        Ok((registers, memory))
+    }
+    /// Runs the original function in the interpreter, with a GTE attached.
+    pub fn run_original(&self, case: &TestCase) -> Result<Results, String> {
+        let (registers, mut memory) = self.set_up(case)?;
+        let mut cpu = Cpu::new(self.address);
+        cpu.registers = registers;
+        cpu.cop2 = Some(Box::new(Gte::new()));
+        cpu.call(&mut memory, self.address, self.max_instruction_count)?;
+        Ok((cpu.registers, memory))
+    }
+    pub fn run_native(
+        &self,
+        case: &TestCase,
+        native: &mut impl FnMut(&mut [u32; 32], &mut Memory),
+    ) -> Result<Results, String> {
+        let (mut registers, mut memory) = self.set_up(case)?;
+        native(&mut registers, &mut memory);
+        Ok((registers, memory))
+    }
+    /// Runs both functions with a case and compares their results. Fails if the case cannot be
+    /// run, like when the original function raises an exception.
+    pub fn compare(
+        &self,
+        case: &TestCase,
+        native: &mut impl FnMut(&mut [u32; 32], &mut Memory),
+    ) -> Result<Vec<Difference>, String> {
+        let (original_registers, original_memory) = self.run_original(case)?;
+        let (native_registers, native_memory) = self.run_native(case, native)?;
+
+        let mut differences = self
+            .compared_registers
+            .iter()
+            .filter(|register| original_registers[**register] != native_registers[**register])
+            .map(|register| Difference::Register {
+                register: *register,
+                original: original_registers[*register],
+                native: native_registers[*register],
+            })
+            .collect::<Vec<_>>();
+        for (address, original, native) in [
+            (RAM_ADDRESS, &original_memory.ram, &native_memory.ram),
+            (
+                SCRATCHPAD_ADDRESS,
+                &original_memory.scratchpad,
+                &native_memory.scratchpad,
+            ),
+        ] {
+            self.compare_memory(address, original, native, &mut differences);
+        }
+        Ok(differences)
+    }
+    /// Compares bytes of memory starting at an address, grouping bytes which differ in a row.
+    fn compare_memory(
+        &self,
+        address: u32,
+        original: &[u8],
+        native: &[u8],
+        differences: &mut Vec<Difference>,
+    ) {
+        const CHUNK_LEN: usize = 0x1000;
+        let mut last_address = None;
+        // Chunks are compared first, as most of memory is the same.
+        let differing_bytes = original
+            .chunks(CHUNK_LEN)
+            .zip(native.chunks(CHUNK_LEN))
+            .enumerate()
+            .filter(|(_, (original, native))| original != native)
+            .flat_map(|(chunk_index, (original, native))| {
+                let offset = chunk_index * CHUNK_LEN;
+                (0..original.len()).map(move |i| (offset + i, original[i], native[i]))
+            });
+        for (i, original, native) in differing_bytes {
+            let address = address + i as u32;
+            if original == native || self.ignored_memory.iter().any(|r| r.contains(&address)) {
+                continue;
+            }
+            match differences.last_mut() {
+                Some(Difference::Memory {
+                    original: original_bytes,
+                    native: native_bytes,
+                    ..
+                }) if last_address == Some(address - 1) => {
+                    original_bytes.push(original);
+                    native_bytes.push(native);
+                }
+                _ => differences.push(Difference::Memory {
+                    address,
+                    original: vec![original],
+                    native: vec![native],
+                }),
+            }
+            last_address = Some(address);
+        }
+    }
+    /// Checks that both functions give the same results with a case, like a recorded one.
+    pub fn check(
+        &self,
+        case: &TestCase,
+        native: &mut impl FnMut(&mut [u32; 32], &mut Memory),
+    ) -> Result<(), String> {
+        let differences = self.compare(case, native)?;
+        if differences.is_empty() {
+            return Ok(());
+        }
+        Err(format!(
+            "Function at 0x{:08X} differs from its reimplementation with case:\n{}{}",
+            self.address,
+            case,
+            format_differences(&differences)
+        ))
+    }
+    /// Checks that both functions give the same results with random cases. The first failing
+    /// case is shrunk (see [Self::shrink]) and written in the error.
+    pub fn check_random(
+        &self,
+        inputs: &RandomInputs,
+        case_count: usize,
+        native: &mut impl FnMut(&mut [u32; 32], &mut Memory),
+    ) -> Result<(), String> {
+        let mut random = Random::new(inputs.seed);
+        for i in 0..case_count {
+            let case = inputs.generate(&mut random);
+            let differences = self.compare(&case, native).map_err(|err| {
+                format!(
+                    "Random case {} of seed {} cannot be run: {}\n{}",
+                    i, inputs.seed, err, case
+                )
+            })?;
+            if differences.is_empty() {
+                continue;
+            }
+            let case = self.shrink(&case, native);
+            let differences = self.compare(&case, native)?;
+            return Err(format!(
+                "Function at 0x{:08X} differs from its reimplementation with random case {} of seed {}, shrunk to:\n{}{}",
+                self.address,
+                i,
+                inputs.seed,
+                case,
+                format_differences(&differences)
+            ));
+        }
+        Ok(())
+    }
+    /// Shrinks a failing case to a minimal one that still fails, by removing inputs, zeroing
+    /// or dropping bytes of memory, and making values smaller, as long as the case fails.
+    pub fn shrink(
+        &self,
+        case: &TestCase,
+        native: &mut impl FnMut(&mut [u32; 32], &mut Memory),
+    ) -> TestCase {
+        let mut case = case.clone();
+        loop {
+            let failing_candidate = get_shrink_candidates(&case).into_iter().find(|candidate| {
+                matches!(self.compare(candidate, native), Ok(differences) if !differences.is_empty())
+            });
+            match failing_candidate {
+                Some(candidate) => case = candidate,
+                None => return case,
+            }
+        }
+    }
+}
+
+/// Gets cases which are smaller than a case by one step, simplest ones first. Memory comes
+/// first, so that pointers into it are not moved before it is shrunk.
+fn get_shrink_candidates(case: &TestCase) -> Vec<TestCase> {
+    let mut candidates = Vec::new();
+    for (i, (address, bytes)) in case.memory.iter().enumerate() {
+        let mut with_memory = |address: u32, bytes: Vec<u8>| {
+            let mut candidate = case.clone();
+            if bytes.is_empty() {
+                candidate.memory.remove(i);
+            } else {
+                candidate.memory[i] = (address, bytes);
+            }
+            candidates.push(candidate);
+        };
+        with_memory(*address, Vec::new());
+        let mut chunk_len = bytes.len() / 2;
+        while chunk_len > 0 {
+            for start in (0..bytes.len()).step_by(chunk_len) {
+                let end = (start + chunk_len).min(bytes.len());
+                if start == 0 {
+                    with_memory(address + end as u32, bytes[end..].to_vec());
+                }
+                if end == bytes.len() {
+                    with_memory(*address, bytes[..start].to_vec());
+                }
+                if bytes[start..end].iter().any(|byte| *byte != 0) {
+                    let mut zeroed = bytes.clone();
+                    zeroed[start..end].fill(0);
+                    with_memory(*address, zeroed);
+                }
+            }
+            chunk_len /= 2;
+        }
+        for (j, byte) in bytes.iter().enumerate().filter(|(_, byte)| **byte > 1) {
+            let mut halved = bytes.clone();
+            halved[j] = byte / 2;
+            with_memory(*address, halved);
+        }
+    }
+    for (i, (_, value)) in case.registers.iter().enumerate() {
+        let mut candidate = case.clone();
+        candidate.registers.remove(i);
+        candidates.push(candidate);
+        // Values closer to 0 by half the value, then by a quarter, and so on down to 1.
+        let value = *value as i32;
+        let mut step = value / 2;
+        while step != 0 {
+            let mut candidate = case.clone();
+            candidate.registers[i].1 = (value - step) as u32;
+            candidates.push(candidate);
+            step /= 2;
+        }
+    }
+    candidates
+}
+
+/// Xorshift generator, so that random cases are the same for a seed on every machine.
+struct Random(u64);
+impl Random {
+    /// Values often giving edge cases, like overflows.
+    const EDGE_VALUES: &'static [u32] = &[
+        0, 1, 2, 0xFFFFFFFF, 0x7FFFFFFF, 0x80000000, 0x7FFF, 0x8000, 0xFFFF, 0xFFFF8000, 0x1000,
+    ];
+
+    fn new(seed: u64) -> Self {
+        // The state must not be 0.
+        Self((seed ^ 0x9E3779B97F4A7C15) | 1)
+    }
+    fn next_u32(&mut self) -> u32 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        (self.0.wrapping_mul(0x2545F4914F6CDD1D) >> 32) as u32
+    }
+    /// Gets a value of a register: an edge value, a small value or any value.
+    fn next_value(&mut self) -> u32 {
+        match self.next_u32() % 4 {
+            0 => Self::EDGE_VALUES[self.next_u32() as usize % Self::EDGE_VALUES.len()],
+            1 => (self.next_u32() % 0x200).wrapping_sub(0x100),
+            _ => self.next_u32(),
+        }
+    }
+}
+
+fn parse_hex_u32(value: &str) -> Result<u32, String> {
+    let digits = value.strip_prefix("0x").unwrap_or(value);
+    u32::from_str_radix(digits, 16).map_err(|_| format!("Invalid hexadecimal value \"{}\".", value))
+}
+fn format_bytes(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+fn format_differences(differences: &[Difference]) -> String {
+    differences
+        .iter()
+        .map(|difference| format!("{}\n", difference))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PS1ExeBuilder;
+    use mips::{Bus, Instruction};
+
+    const A0: usize = 4;
+    const A1: usize = 5;
+    const DATA_ADDRESS: u32 = 0x80070000;
+
+    /// Makes an executable with a function at 0x80010000, written one instruction per line.
+    fn make_exe(code: &str) -> PS1Exe {
+        let bytes = code
+            .lines()
+            .flat_map(|line| Instruction::parse_from_str(line).unwrap().to_le_bytes())
+            .collect::<Vec<_>>();
+        PS1ExeBuilder::new()
+            .segment(0x80010000, &bytes)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn compare_with_native_function() {
+        // Average of a0 and a1, which is also stored at 0x80070000.
+        let exe = make_exe("addu v0, a0, a1\nsra v0, v0, 1\nlui t0, 0x8007\njr ra\nsw v0, 0(t0)");
+        let test = DifferentialTest::new(&exe, 0x80010000);
+        let mut average = |registers: &mut [u32; 32], memory: &mut Memory| {
+            let sum = registers[A0].wrapping_add(registers[A1]) as i32;
+            registers[V0] = (sum >> 1) as u32;
+            memory.write_u32(DATA_ADDRESS, registers[V0]).unwrap();
+        };
+        let mut case = TestCase::new();
+        case.set_register(A0, 7);
+        case.set_register(A1, 0xFFFFFFFF);
+        assert_eq!(test.check(&case, &mut average), Ok(()));
+
+        let inputs = RandomInputs {
+            registers: vec![A0, A1],
+            seed: 1,
+            ..Default::default()
+        };
+        assert_eq!(test.check_random(&inputs, 100, &mut average), Ok(()));
+
+        // Averaging without wrapping differs on overflow, which random values find.
+        let mut average_without_wrapping = |registers: &mut [u32; 32], memory: &mut Memory| {
+            let sum = registers[A0] as i32 as i64 + registers[A1] as i32 as i64;
+            registers[V0] = (sum >> 1) as u32;
+            memory.write_u32(DATA_ADDRESS, registers[V0]).unwrap();
+        };
+        let err = test
+            .check_random(&inputs, 100, &mut average_without_wrapping)
+            .unwrap_err();
+        assert!(err.contains("shrunk to:\na0 = "), "{}", err);
+        assert!(err.contains("v0 is 0x"), "{}", err);
+        assert!(err.contains("Memory at 0x80070003 is \"C0\""), "{}", err);
+
+        let differences = test.compare(&case, &mut |_, _| {}).unwrap();
+        assert_eq!(
+            differences,
+            [
+                Difference::Register {
+                    register: V0,
+                    original: 3,
+                    native: 0
+                },
+                Difference::Memory {
+                    address: DATA_ADDRESS,
+                    original: vec![3],
+                    native: vec![0]
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn shrink_failing_cases() {
+        // Sum of the first two bytes at a0.
+        let exe = make_exe("lbu v0, 0(a0)\nlbu v1, 1(a0)\nnop\njr ra\naddu v0, v0, v1");
+        let test = DifferentialTest::new(&exe, 0x80010000);
+        let mut first_byte_only = |registers: &mut [u32; 32], memory: &mut Memory| {
+            registers[V0] = memory.read_u8(registers[A0]).unwrap() as u32;
+            registers[V1] = memory.read_u8(registers[A0] + 1).unwrap() as u32;
+        };
+        let data = DATA_ADDRESS..DATA_ADDRESS + 0x10;
+        let mut inputs = RandomInputs {
+            memory: vec![data],
+            seed: 2,
+            ..Default::default()
+        };
+        inputs.base.set_register(A0, DATA_ADDRESS);
+        let mut random = Random::new(inputs.seed);
+        let case = inputs.generate(&mut random);
+        assert!(!test
+            .compare(&case, &mut first_byte_only)
+            .unwrap()
+            .is_empty());
+
+        // Only the second byte matters, so the rest of memory is dropped.
+        let case = test.shrink(&case, &mut first_byte_only);
+        assert_eq!(
+            case.to_string(),
+            "a0 = 0x80070000\nmemory 0x80070001 = 01\n"
+        );
+
+        // Cases which cannot be run fail the test instead of being shrunk.
+        let mut case = TestCase::new();
+        case.set_register(A0, 0x00800000);
+        let err = test.check(&case, &mut first_byte_only).unwrap_err();
+        assert!(err.starts_with("Bus error accessing 0x00800000"), "{}", err);
+    }
+
+    #[test]
+    fn read_and_write_test_cases() {
+        let content = "# Recorded in the first level\na0 = 0x80070000\nv0 = FFFFFFFF\nmemory 0x80070000 = 01 ff 7C\n";
+        let case = TestCase::parse(content).unwrap();
+        assert_eq!(case.registers, [(A0, 0x80070000), (V0, 0xFFFFFFFF)]);
+        assert_eq!(case.memory, [(DATA_ADDRESS, vec![0x01, 0xFF, 0x7C])]);
+        assert_eq!(TestCase::parse(&case.to_string()), Ok(case));
+
+        for (content, error) in [
+            ("a0 0x10", "Line 1: Expected \"<register> = <value>\""),
+            ("\nx9 = 0x10", "Line 2: Unknown register \"x9\""),
+            ("a0 = 0xZ", "Line 1: Invalid hexadecimal value \"0xZ\""),
+            ("memory 0x80070000 = 100", "Line 1: Invalid byte \"100\""),
+        ] {
+            let err = TestCase::parse(content).unwrap_err();
+            assert!(err.starts_with(error), "{}", err);
+        }
+    }
+}
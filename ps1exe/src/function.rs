@@ -0,0 +1,569 @@
+//! Control flow of functions, found by following branches and jumps from the entry of a function.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ops::Range;
+
+use mips::Instruction;
+
+/// Register number of ra (the return address).
+const RA: u8 = 31;
+/// How many instructions before a "jr" are searched for how its target is loaded.
+const MAX_JUMP_SEARCH_LEN: u64 = 32;
+/// Most targets read from a jump table.
+const MAX_JUMP_TABLE_LEN: u64 = 256;
+
+/// Instructions that run one after another, entered only from the first instruction.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BasicBlock {
+    pub start: u64,
+    /// Address after the last instruction, which is the delay slot of a branch or jump ending the block.
+    pub end: u64,
+    /// Start addresses of blocks that may run after this block.
+    pub successors: Vec<u64>,
+}
+
+/// A function found by [Function::analyze].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Function {
+    pub entry: u64,
+    /// Basic blocks sorted by address.
+    pub blocks: Vec<BasicBlock>,
+    /// Functions called with jal (and bal, bltzal and bgezal), and targets of jumps to before
+    /// the entry, which are assumed to be tail calls.
+    pub calls: BTreeSet<u64>,
+    /// Jump tables of "jr" instructions, like the ones compiled from switch statements.
+    pub jump_tables: Vec<JumpTable>,
+    /// Addresses of jr (other than "jr ra") and jalr instructions, whose targets are not known.
+    pub indirect_jumps: Vec<u64>,
+    /// Addresses reached by the function, which are not valid instructions
+    /// (like data or code outside the executable).
+    pub invalid_addresses: Vec<u64>,
+}
+
+/// Addresses of code loaded from a table by index, which a "jr" instruction jumps to.
+#[derive(Clone, Debug, PartialEq)]
+pub struct JumpTable {
+    /// Address of the "jr" instruction.
+    pub jump_address: u64,
+    /// Address of the table.
+    pub address: u64,
+    /// Targets in the table, in order.
+    pub targets: Vec<u64>,
+}
+impl JumpTable {
+    pub fn get_range(&self) -> Range<u64> {
+        self.address..self.address + self.targets.len() as u64 * 4
+    }
+}
+
+/// What happens after an instruction.
+enum Flow {
+    Next,
+    /// Continues after calling a function, if its address is known.
+    Call(Option<u64>),
+    /// Continues at given addresses after the delay slot.
+    Branch(Vec<u64>),
+    /// Jumps to another function, which returns in place of this function.
+    TailCall(u64),
+    /// Jumps to an address in a given register (other than ra).
+    IndirectJump(u8),
+    Return,
+}
+
+impl Function {
+    /// Finds a function by following branches and jumps (with their delay slots) from a given entry
+    /// address, until all paths of the function return with "jr ra".
+    ///
+    /// Targets of "jr" instructions are found when the target is loaded from a jump table
+    /// (with lui, lw and optionally addiu and addu) or set to a constant address (with addiu or ori).
+    ///
+    /// Memory is read with a given function, which returns `None` for addresses outside the memory.
+    pub fn analyze(entry: u64, read_word: &dyn Fn(u64) -> Option<u32>) -> Self {
+        let read_instruction = |address: u64| {
+            read_word(address).and_then(|word| Instruction::parse_from_machine_code(word).ok())
+        };
+        let mut instructions = BTreeMap::new();
+        // Starts of basic blocks other than the entry.
+        let mut leaders = BTreeSet::new();
+        // Successors of branches and jumps by address.
+        let mut branches = HashMap::new();
+        let mut calls = BTreeSet::new();
+        let mut jump_tables = Vec::new();
+        let mut indirect_jumps = Vec::new();
+        let mut invalid_addresses = Vec::new();
+
+        let mut addresses_to_visit = vec![entry];
+        while let Some(mut address) = addresses_to_visit.pop() {
+            while !instructions.contains_key(&address) {
+                let Some(instruction) = read_instruction(address) else {
+                    invalid_addresses.push(address);
+                    break;
+                };
+                let flow = get_flow(&instruction, address, entry);
+                instructions.insert(address, instruction);
+
+                let successors = match flow {
+                    Flow::Next => {
+                        address += 4;
+                        continue;
+                    }
+                    Flow::Call(Some(target)) => {
+                        calls.insert(target);
+                        address += 4;
+                        continue;
+                    }
+                    Flow::Call(None) => {
+                        indirect_jumps.push(address);
+                        address += 4;
+                        continue;
+                    }
+                    Flow::Branch(successors) => successors,
+                    Flow::TailCall(target) => {
+                        calls.insert(target);
+                        Vec::new()
+                    }
+                    Flow::IndirectJump(register) => {
+                        match find_jump_target(&instructions, address, register) {
+                            JumpTarget::Address(target) => {
+                                calls.insert(target);
+                                Vec::new()
+                            }
+                            JumpTarget::Table {
+                                address: table_address,
+                                len,
+                            } => {
+                                let targets = read_jump_table(table_address, len, entry, read_word);
+                                let mut successors = targets.clone();
+                                successors.sort();
+                                successors.dedup();
+                                if targets.is_empty() {
+                                    indirect_jumps.push(address);
+                                } else {
+                                    jump_tables.push(JumpTable {
+                                        jump_address: address,
+                                        address: table_address,
+                                        targets,
+                                    });
+                                }
+                                successors
+                            }
+                            JumpTarget::Unknown => {
+                                indirect_jumps.push(address);
+                                Vec::new()
+                            }
+                        }
+                    }
+                    Flow::Return => Vec::new(),
+                };
+
+                // The instruction in the delay slot runs before the branch or jump.
+                let delay_slot_address = address + 4;
+                match read_instruction(delay_slot_address) {
+                    Some(instruction) => {
+                        instructions.insert(delay_slot_address, instruction);
+                    }
+                    None => invalid_addresses.push(delay_slot_address),
+                }
+                for successor in successors.iter() {
+                    leaders.insert(*successor);
+                    addresses_to_visit.push(*successor);
+                }
+                branches.insert(address, successors);
+                break;
+            }
+        }
+
+        // Split instructions into blocks at starts of blocks, after branches and jumps and at gaps.
+        let addresses = instructions.keys().copied().collect::<Vec<_>>();
+        let mut blocks = Vec::new();
+        let mut i = 0;
+        while i < addresses.len() {
+            let start = addresses[i];
+            let mut address = start;
+            let successors = loop {
+                if let Some(successors) = branches.get(&address) {
+                    // The delay slot is a part of the block, unless it is not a valid instruction.
+                    i += 1;
+                    address += 4;
+                    if addresses.get(i) == Some(&address) {
+                        i += 1;
+                        address += 4;
+                    }
+                    break successors.clone();
+                }
+                i += 1;
+                address += 4;
+                if addresses.get(i) != Some(&address) {
+                    break Vec::new();
+                }
+                if leaders.contains(&address) {
+                    break vec![address];
+                }
+            };
+            blocks.push(BasicBlock {
+                start,
+                end: address,
+                successors,
+            });
+        }
+
+        Self {
+            entry,
+            blocks,
+            calls,
+            jump_tables,
+            indirect_jumps,
+            invalid_addresses,
+        }
+    }
+    /// Gets the addresses from the first instruction to the end of the last instruction.
+    pub fn get_range(&self) -> Range<u64> {
+        let start = self.blocks.first().map_or(self.entry, |block| block.start);
+        let end = self.blocks.last().map_or(self.entry, |block| block.end);
+        start..end
+    }
+    pub fn get_instruction_count(&self) -> usize {
+        self.blocks
+            .iter()
+            .map(|block| (block.end - block.start) as usize / 4)
+            .sum()
+    }
+    /// Writes the control flow graph of the function in Graphviz DOT format.
+    /// Each basic block is a node listing its instructions.
+    pub fn to_dot(
+        &self,
+        labels: &HashMap<u64, String>,
+        read_instruction: &dyn Fn(u64) -> Option<Instruction>,
+    ) -> String {
+        let get_name = |address: u64| match labels.get(&address) {
+            Some(label) => label.clone(),
+            None => format!("0x{:X}", address),
+        };
+        let escape = |text: String| text.replace("\\", "\\\\").replace("\"", "\\\"");
+
+        let mut result = format!("digraph \"{}\" {{\n", escape(get_name(self.entry)));
+        result.push_str("    node [shape=box, fontname=\"monospace\"];\n");
+        for block in self.blocks.iter() {
+            let mut text = format!("{}:\\l", escape(get_name(block.start)));
+            for address in (block.start..block.end).step_by(4) {
+                let instruction = match read_instruction(address)
+                    .and_then(|instruction| instruction.to_instruction_at(address, labels).ok())
+                {
+                    Some(instruction) => instruction,
+                    None => String::from("# Invalid instruction"),
+                };
+                text.push_str(&format!("    {}\\l", escape(instruction)));
+            }
+            result.push_str(&format!(
+                "    \"{}\" [label=\"{}\"];\n",
+                escape(get_name(block.start)),
+                text
+            ));
+        }
+        for block in self.blocks.iter() {
+            for successor in block.successors.iter() {
+                result.push_str(&format!(
+                    "    \"{}\" -> \"{}\";\n",
+                    escape(get_name(block.start)),
+                    escape(get_name(*successor))
+                ));
+            }
+        }
+        result.push_str("}\n");
+        result
+    }
+}
+
+/// Gets what happens after an instruction at a given address in a function starting at a given entry.
+fn get_flow(instruction: &Instruction, address: u64, entry: u64) -> Flow {
+    let target = instruction.get_target_address(address);
+    let after_delay_slot = address + 8;
+    match instruction {
+        // bltzal and bgezal (with rt 16 and 17), which is bal with rs zero
+        Instruction::ISigned {
+            opcode: 0b000001,
+            rt: 0b10000 | 0b10001,
+            ..
+        } => Flow::Call(target),
+        // beq with the same registers (like b) and bgez with zero always branch
+        Instruction::ISigned {
+            opcode: 0b000100,
+            rs,
+            rt,
+            ..
+        } if rs == rt => Flow::Branch(vec![target.unwrap()]),
+        Instruction::ISigned {
+            opcode: 0b000001,
+            rs: 0,
+            rt: 0b00001,
+            ..
+        } => Flow::Branch(vec![target.unwrap()]),
+        Instruction::ISigned {
+            opcode: 0b000001 | 0b000100..=0b000111, // Opcode is 1 or 4-7
+            ..
+        } => Flow::Branch(vec![target.unwrap(), after_delay_slot]),
+        Instruction::J {
+            opcode: 0b000011, ..
+        } => Flow::Call(target), // Opcode is 3 (jal)
+        Instruction::J { .. } => match target.unwrap() {
+            // Jumps to before the entry are assumed to go to other functions.
+            target if target < entry => Flow::TailCall(target),
+            target => Flow::Branch(vec![target]),
+        },
+        Instruction::R {
+            funct: 8, rs: RA, ..
+        } => Flow::Return, // jr ra
+        Instruction::R { funct: 8, rs, .. } => Flow::IndirectJump(*rs), // jr
+        Instruction::R { funct: 9, .. } => Flow::Call(None),            // jalr
+        _ => Flow::Next,
+    }
+}
+
+/// How the target of a "jr" instruction is loaded.
+enum JumpTarget {
+    /// From a jump table with a given count of targets, if known.
+    Table {
+        address: u64,
+        len: Option<u64>,
+    },
+    /// As a constant address, like with calls to BIOS functions.
+    Address(u64),
+    Unknown,
+}
+
+/// Searches instructions before a "jr" at a given address for how its target in a given register is loaded.
+///
+/// Jump tables of switch statements are usually loaded like this:
+///
+/// ```text
+/// sltiu v0, a0, <count of targets>
+/// beqz v0, default
+/// sll v0, a0, 2
+/// lui at, %hi(table)
+/// addu at, at, v0
+/// lw v0, %lo(table)(at)
+/// nop
+/// jr v0
+/// ```
+fn find_jump_target(
+    instructions: &BTreeMap<u64, Instruction>,
+    jump_address: u64,
+    register: u8,
+) -> JumpTarget {
+    // Lower half of the table address once the load from the table is found,
+    // and registers that may hold the upper half.
+    let mut lo: Option<i64> = None;
+    let mut registers = vec![register];
+    let mut table_address = None;
+
+    for i in 1..=MAX_JUMP_SEARCH_LEN {
+        let Some(instruction) = instructions.get(&(jump_address - i * 4)) else {
+            break;
+        };
+        match (instruction, lo, table_address) {
+            (
+                Instruction::IUnsigned {
+                    opcode: 0b001001, // addiu, opcode 9
+                    rs: 0,
+                    rt,
+                    immediate,
+                },
+                None,
+                _,
+            ) if *rt == register => return JumpTarget::Address(*immediate as i16 as u32 as u64),
+            (
+                Instruction::IUnsigned {
+                    opcode: 0b001101, // ori, opcode 13
+                    rs: 0,
+                    rt,
+                    immediate,
+                },
+                None,
+                _,
+            ) if *rt == register => return JumpTarget::Address(*immediate as u64),
+            (
+                Instruction::ISigned {
+                    opcode: 0b100011, // lw, opcode 35
+                    rs,
+                    rt,
+                    immediate,
+                },
+                None,
+                _,
+            ) if *rt == register => {
+                lo = Some(*immediate as i64);
+                registers = vec![*rs];
+            }
+            (
+                Instruction::R {
+                    funct: 0b100001, // addu, funct 33
+                    rs,
+                    rt,
+                    rd,
+                    ..
+                },
+                Some(_),
+                None,
+            ) if registers.contains(rd) => registers = vec![*rs, *rt],
+            (
+                Instruction::IUnsigned {
+                    opcode: 0b001001, // addiu, opcode 9
+                    rs,
+                    rt,
+                    immediate,
+                },
+                Some(offset),
+                None,
+            ) if rs == rt && registers.contains(rt) => lo = Some(offset + *immediate as i16 as i64),
+            (
+                Instruction::IUnsigned {
+                    opcode: 0b001111, // lui, opcode 15
+                    rt,
+                    immediate,
+                    ..
+                },
+                Some(offset),
+                None,
+            ) if registers.contains(rt) => {
+                let address = ((*immediate as i64) << 16).wrapping_add(offset);
+                table_address = Some(address as u64 & 0xFFFFFFFF);
+            }
+            (
+                Instruction::IUnsigned {
+                    opcode: 0b001011, // sltiu, opcode 11
+                    immediate,
+                    ..
+                },
+                _,
+                Some(address),
+            ) => {
+                return JumpTarget::Table {
+                    address,
+                    len: Some(*immediate as u64),
+                }
+            }
+            _ => {}
+        }
+    }
+    match table_address {
+        Some(address) => JumpTarget::Table { address, len: None },
+        None => JumpTarget::Unknown,
+    }
+}
+
+/// Reads targets of a jump table. Reading stops at a value that is not an address
+/// of an instruction after the entry of the function.
+fn read_jump_table(
+    address: u64,
+    len: Option<u64>,
+    entry: u64,
+    read_word: &dyn Fn(u64) -> Option<u32>,
+) -> Vec<u64> {
+    let mut targets = Vec::new();
+    for i in 0..len.unwrap_or(MAX_JUMP_TABLE_LEN).min(MAX_JUMP_TABLE_LEN) {
+        let Some(target) = read_word(address + i * 4).map(|target| target as u64) else {
+            break;
+        };
+        let is_instruction = read_word(target)
+            .is_some_and(|word| Instruction::parse_from_machine_code(word).is_ok());
+        if target % 4 != 0 || target < entry || !is_instruction {
+            break;
+        }
+        targets.push(target);
+    }
+    targets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Analyzes a function from assembly code at address 0x80010000.
+    fn analyze(content: &str) -> Function {
+        let mut words = HashMap::new();
+        for node in mips::parse_nodes(&format!("@at 0x80010000\n{}", content)).unwrap() {
+            match node.kind {
+                mips::NodeKind::Instruction(instruction) => {
+                    words.insert(node.address, instruction.to_machine_code());
+                }
+                mips::NodeKind::Data(bytes) => {
+                    for (i, word) in bytes.chunks(4).enumerate() {
+                        words.insert(
+                            node.address + i as u64 * 4,
+                            u32::from_le_bytes(word.try_into().unwrap()),
+                        );
+                    }
+                }
+                _ => {}
+            }
+        }
+        Function::analyze(0x80010000, &|address| words.get(&address).copied())
+    }
+
+    #[test]
+    fn analyze_function_with_branches() {
+        let content = "  beq a0, zero, skip\n  nop\n  jal 0x4000\n  nop\nskip:\n  bnez a1, skip\n  nop\n  jr ra\n  nop\n  addu v0, v0, v0";
+        let function = analyze(content);
+        assert_eq!(
+            function.blocks,
+            [
+                BasicBlock {
+                    start: 0x80010000,
+                    end: 0x80010008,
+                    successors: vec![0x80010010, 0x80010008]
+                },
+                BasicBlock {
+                    start: 0x80010008,
+                    end: 0x80010010,
+                    successors: vec![0x80010010]
+                },
+                BasicBlock {
+                    start: 0x80010010,
+                    end: 0x80010018,
+                    successors: vec![0x80010010, 0x80010018]
+                },
+                BasicBlock {
+                    start: 0x80010018,
+                    end: 0x80010020,
+                    successors: vec![]
+                },
+            ]
+        );
+        assert_eq!(function.get_range(), 0x80010000..0x80010020);
+        assert_eq!(function.calls, BTreeSet::from([0x80010000]));
+    }
+    #[test]
+    fn analyze_function_with_tail_call_and_indirect_jump() {
+        let content =
+            "  blez a0, table\n  nop\n  j @0x80000000\n  nop\ntable:\n  jr t0\n  nop\n  .word 0xFC000000";
+        let function = analyze(content);
+        assert_eq!(function.blocks.len(), 3);
+        assert_eq!(function.blocks[1].successors, []);
+        assert_eq!(function.calls, BTreeSet::from([0x80000000]));
+        assert_eq!(function.indirect_jumps, [0x80010010]);
+        assert!(function.invalid_addresses.is_empty());
+
+        let dot = function.to_dot(
+            &HashMap::from([(0x80010000, String::from("Start"))]),
+            &|_| None,
+        );
+        assert!(dot.starts_with("digraph \"Start\" {\n"));
+        assert!(dot.contains("    \"Start\" -> \"0x80010010\";\n"));
+    }
+    #[test]
+    fn analyze_function_with_jump_table() {
+        let content = "  sltiu v0, a0, 3\n  beqz v0, default\n  sll v0, a0, 2\n  lui at, %hi(table)\n  addu at, at, v0\n  lw v0, %lo(table)(at)\n  nop\n  jr v0\n  nop\ncase0:\n  jr ra\n  nop\ncase1:\ndefault:\n  addiu t2, zero, 0xA0\n  jr t2\n  nop\ntable:\n  .word case0, case1, case0, case1";
+        let function = analyze(content);
+        assert_eq!(
+            function.jump_tables,
+            [JumpTable {
+                jump_address: 0x8001001C,
+                address: 0x80010038,
+                targets: vec![0x80010024, 0x8001002C, 0x80010024]
+            }]
+        );
+        assert_eq!(function.blocks[1].successors, [0x80010024, 0x8001002C]);
+        assert_eq!(function.calls, BTreeSet::from([0xA0]));
+        assert!(function.indirect_jumps.is_empty());
+    }
+}
@@ -0,0 +1,254 @@
+//! Adding new code and data to an executable, either into free regions of it or by growing it,
+//! and hooking existing code to jump into the new code.
+
+use std::ops::Range;
+
+use mips::SectionSize;
+
+use crate::{MemoryAddress, MemoryRegion, PS1Exe, PS1ExeReader};
+
+/// Where a section was placed by [PS1Exe::place_sections].
+#[derive(Clone, Debug, PartialEq)]
+pub struct SectionPlacement {
+    pub name: String,
+    pub address: u64,
+    pub size: u64,
+    /// Whether the executable was grown for the section, because it did not fit into any free region.
+    pub is_appended: bool,
+}
+
+impl PS1Exe {
+    /// Places sections (see [mips::parse_section_sizes]) into given free regions of the executable,
+    /// which its code and data do not use anymore. Sections not fitting into any free region are
+    /// appended after the end of the executable, growing it (see [PS1Exe::grow_to]).
+    /// Sections are aligned to their [SectionSize::alignment], so that ".align" in them pads
+    /// as it did when their sizes were measured.
+    pub fn place_sections(
+        &mut self,
+        sections: &[SectionSize],
+        free_ranges: &[Range<u64>],
+    ) -> Result<Vec<SectionPlacement>, String> {
+        let Range { start, end } = self.get_memory_range();
+        if let Some(range) = free_ranges
+            .iter()
+            .find(|range| range.start < start || range.end > end)
+        {
+            return Err(format!(
+                "Free region 0x{:X}-0x{:X} is outside of the executable (0x{:X}-0x{:X}), so it would not be loaded.",
+                range.start, range.end, start, end
+            ));
+        }
+
+        let mut free_ranges = free_ranges.to_vec();
+        let mut appended_end = end;
+        let mut placements = Vec::new();
+        for section in sections.iter() {
+            let align = |address: u64| address.div_ceil(section.alignment) * section.alignment;
+            let fitting_range = free_ranges
+                .iter_mut()
+                .find(|range| align(range.start) + section.size <= range.end);
+            let (address, is_appended) = match fitting_range {
+                Some(range) => {
+                    let address = align(range.start);
+                    range.start = address + section.size;
+                    (address, false)
+                }
+                None => {
+                    let address = align(appended_end);
+                    appended_end = address + section.size;
+                    self.grow_to(appended_end)?;
+                    (address, true)
+                }
+            };
+            placements.push(SectionPlacement {
+                name: section.name.clone(),
+                address,
+                size: section.size,
+                is_appended,
+            });
+        }
+        Ok(placements)
+    }
+    /// Grows the executable with zeros to reach a given address in memory, keeping its size
+    /// a multiple of 2048 bytes. File size in the header is updated.
+    ///
+    /// Memory after the executable is often used by the game (like for its heap), so growing
+    /// the executable is only safe if that memory is known to be free.
+    pub fn grow_to(&mut self, end_address_in_memory: u64) -> Result<(), String> {
+        let Range { start, end } = self.get_memory_range();
+        if end_address_in_memory <= end {
+            return Ok(());
+        }
+        // Main RAM ends at the same offset in whichever segment the executable is loaded into.
+        let destination = MemoryAddress::parse(start)?;
+        let ram_end = start - destination.offset + MemoryRegion::MainRam.get_size();
+        if destination.region != MemoryRegion::MainRam || end_address_in_memory > ram_end {
+            return Err(format!(
+                "Growing the executable to 0x{:X} goes past the end of RAM at 0x{:X}.",
+                end_address_in_memory, ram_end
+            ));
+        }
+
+        // Memory fill area (like BSS) is cleared after loading, which would erase new code.
+        let memfill_start = self.bss_address as u64;
+        let memfill_len = self.bss_size as u64;
+        if memfill_len != 0
+            && memfill_start < end_address_in_memory
+            && end < memfill_start + memfill_len
+        {
+            return Err(format!(
+                "Growing the executable to 0x{:X} overlaps its memory fill area at 0x{:X}-0x{:X}, which is cleared after loading.",
+                end_address_in_memory,
+                memfill_start,
+                memfill_start + memfill_len
+            ));
+        }
+
+        let len = (end_address_in_memory - start) as usize;
+        let len = len.div_ceil(Self::VALID_MULTIPLIER) * Self::VALID_MULTIPLIER;
+        self.data
+            .resize(Self::CODE_AND_DATA_BEGIN_OFFSET as usize + len, 0);
+        self.file_size = len as u32;
+        self.data[0x01C..0x020].copy_from_slice(&self.file_size.to_le_bytes());
+        Ok(())
+    }
+    /// Checks that a `j` or `jal` instruction can be written over existing code at a given address
+    /// to jump into new code.
+    ///
+    /// The instruction after the address stays in the delay slot of the jump, so it still runs
+    /// (before the new code), but it cannot be a branch or jump itself. The address cannot be
+    /// in a delay slot either. New code jumped into with `j` should return after the delay slot
+    /// (to the address + 8), as the instruction there has already run.
+    pub fn check_hook(
+        &self,
+        address_in_memory: u64,
+        instruction: &mips::Instruction,
+    ) -> Result<(), String> {
+        if !matches!(instruction, mips::Instruction::J { .. }) {
+            return Err(format!(
+                "Hook at 0x{:X} must be a j or jal instruction, not \"{}\".",
+                address_in_memory,
+                instruction
+                    .to_instruction()
+                    .map_err(|err| err.to_string())?
+            ));
+        }
+        let reader = PS1ExeReader::new(self);
+        if reader.read_word(address_in_memory).is_none() {
+            return Err(format!(
+                "Hook at 0x{:X} is outside of the executable.",
+                address_in_memory
+            ));
+        }
+        if let Some(previous_instruction) = reader
+            .read_instruction(address_in_memory.wrapping_sub(4))
+            .filter(mips::Instruction::has_delay_slot)
+        {
+            return Err(format!(
+                "Hook at 0x{:X} is in the delay slot of \"{}\" at 0x{:X}.",
+                address_in_memory,
+                previous_instruction
+                    .to_instruction()
+                    .map_err(|err| err.to_string())?,
+                address_in_memory - 4
+            ));
+        }
+        if let Some(next_instruction) = reader
+            .read_instruction(address_in_memory + 4)
+            .filter(mips::Instruction::has_delay_slot)
+        {
+            return Err(format!(
+                "Hook at 0x{:X} would have \"{}\" at 0x{:X} in its delay slot.",
+                address_in_memory,
+                next_instruction
+                    .to_instruction()
+                    .map_err(|err| err.to_string())?,
+                address_in_memory + 4
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_exe(content: &str) -> PS1Exe {
+        let mut exe_bytes = vec![0; 0x1000];
+        exe_bytes[..8].copy_from_slice(b"PS-X EXE");
+        exe_bytes[0x18..0x1C].copy_from_slice(&0x80010000u32.to_le_bytes());
+        exe_bytes[0x1C..0x20].copy_from_slice(&0x800u32.to_le_bytes());
+        for node in mips::parse_nodes(content).unwrap() {
+            if let mips::NodeKind::Instruction(instruction) = node.kind {
+                let offset = (node.address - 0x80010000) as usize + 0x800;
+                exe_bytes[offset..offset + 4].copy_from_slice(&instruction.to_le_bytes());
+            }
+        }
+        PS1Exe::from_bytes(exe_bytes).unwrap()
+    }
+
+    #[test]
+    fn place_sections_into_free_regions_and_grow() {
+        let mut exe = get_exe("");
+        let section = |name: &str, size, alignment| SectionSize {
+            name: String::from(name),
+            size,
+            alignment,
+        };
+        let sections = [
+            section("a", 0x10, 4),
+            section("b", 0x6, 4),
+            section("c", 0x900, 4),
+            section("d", 0x8, 4),
+            section("e", 0x4, 0x10),
+        ];
+        let placements = exe
+            .place_sections(&sections, &[0x80010100..0x80010118, 0x80010204..0x80010208])
+            .unwrap();
+        let placements = placements
+            .iter()
+            .map(|placement| (placement.address, placement.is_appended))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            placements,
+            [
+                (0x80010100, false),
+                (0x80010110, false),
+                (0x80010800, true),
+                (0x80011100, true),
+                (0x80011110, true)
+            ]
+        );
+        // Section "e" does not fit into 0x80010204-0x80010208 once aligned to 16 bytes.
+        // Sections end at 0x80011114, so the size is rounded up to 0x1800.
+        assert_eq!(exe.file_size, 0x1800);
+        assert_eq!(exe.data.len(), 0x2000);
+        assert_eq!(exe.data[0x1C..0x20], [0x00, 0x18, 0x00, 0x00]);
+
+        assert!(exe
+            .place_sections(&sections, &[0x80010000..0x80010100, 0x80000000..0x80000100])
+            .is_err());
+        assert!(exe.grow_to(0x80200004).is_err());
+    }
+    #[test]
+    fn check_hooks_for_delay_slots() {
+        let exe = get_exe("@at 0x80010000\n  addiu v0, zero, 1\n  addiu v1, zero, 2\n  beq v0, v1, 4\n  nop\n  jr ra\n  nop");
+        let jal = mips::Instruction::parse_from_str("jal 0x1C000").unwrap();
+        assert!(exe.check_hook(0x80010000, &jal).is_ok());
+        assert_eq!(
+            exe.check_hook(0x80010004, &jal),
+            Err(String::from(
+                "Hook at 0x80010004 would have \"beq v0, v1, 4\" at 0x80010008 in its delay slot."
+            ))
+        );
+        assert_eq!(
+            exe.check_hook(0x8001000C, &jal),
+            Err(String::from(
+                "Hook at 0x8001000C is in the delay slot of \"beq v0, v1, 4\" at 0x80010008."
+            ))
+        );
+        let nop = mips::Instruction::parse_from_str("nop").unwrap();
+        assert!(exe.check_hook(0x80010000, &nop).is_err());
+    }
+}
@@ -1,7 +1,39 @@
+use std::collections::HashMap;
+use std::fmt;
 use std::fs::File;
 use std::io::{BufReader, Read, Write};
+use std::ops::Range;
 use std::str;
 
+mod analysis;
+mod builder;
+mod codec;
+mod differential;
+mod function;
+mod inject;
+mod memory_map;
+mod overlay;
+mod split;
+mod symbols;
+mod text;
+
+pub use analysis::{ExeAnalysis, Region, RegionKind};
+pub use builder::PS1ExeBuilder;
+pub use codec::{
+    get_text_codec_by_name, AsciiCodec, ShiftJisCodec, SpyroCodec, TextCodec, TEXT_CODEC_NAMES,
+};
+pub use differential::{
+    Difference, DifferentialTest, RandomInputs, Results, TestCase, STACK_ADDRESS,
+};
+pub use function::{BasicBlock, Function, JumpTable};
+pub use inject::SectionPlacement;
+pub use memory_map::{MemoryAddress, MemoryRegion, MemorySegment};
+pub use split::{SplitFile, SPLIT_HEADER_FILE_NAME};
+pub use symbols::{Symbol, SymbolKind, SymbolMap, SymbolMapFormat, SYMBOL_MAP_FORMAT_NAMES};
+pub use text::{
+    find_text_entries, parse_po, plan_text_writes, write_po, TextEntry, TextWrite, Translation,
+};
+
 pub struct PS1Exe {
     data: Vec<u8>,
     /// This value will determine where code and data from 0x800 onwards
@@ -15,6 +47,16 @@ pub struct PS1Exe {
     /// Program counter contains the address (location) of the instruction being executed at the current time.
     /// Initial PC value is usually 0x80010000 or higher in the case of Playstation executable file.
     pub initial_pc: u32,
+    /// Address and size of a data section, which is usually zero (not used).
+    pub data_section_address: u32,
+    pub data_section_size: u32,
+    /// Memory cleared after loading, like BSS.
+    pub bss_address: u32,
+    pub bss_size: u32,
+    /// Initial stack pointer is the base address plus the offset.
+    /// If the base address is zero, the BIOS keeps its own stack pointer.
+    pub stack_base_address: u32,
+    pub stack_offset: u32,
     pub ascii_marker: String,
 }
 impl PS1Exe {
@@ -22,7 +64,18 @@ impl PS1Exe {
     const PS1_EXE_NAME: &'static str = "Playstation executable file";
     const VALID_MULTIPLIER: usize = 2048;
 
-    fn from_bytes(value: Vec<u8>) -> Result<Self, String> {
+    pub fn from_bytes(value: Vec<u8>) -> Result<Self, String> {
+        // Ensure the whole header is present, so that its fields can be read
+        let header_len = Self::CODE_AND_DATA_BEGIN_OFFSET as usize;
+        if value.len() < header_len {
+            return Err(format!(
+                "{} is truncated: it is {} bytes, but its header alone is {} bytes.",
+                Self::PS1_EXE_NAME,
+                value.len(),
+                header_len
+            ));
+        }
+
         // Ensure file size in a multiple of 2048
         if value.len() % Self::VALID_MULTIPLIER != 0 {
             return Err(format!(
@@ -38,7 +91,7 @@ impl PS1Exe {
 
         let ascii_id = &value[0..PS1_EXE_HEADER_ASCII_ID_LENGTH];
 
-        let ascii_id = str::from_utf8(&ascii_id).map_err(|_| {
+        let ascii_id = str::from_utf8(ascii_id).map_err(|_| {
             format!(
                 "Failed to UTF-8 encode ASCII ID \"{}\" for {}.",
                 PS1_EXE_HEADER_ASCII_ID,
@@ -81,6 +134,24 @@ impl PS1Exe {
         let initial_pc = get_range(&value, 0x010, 4);
         let initial_pc = from_le_bytes_u32(initial_pc);
 
+        // Read the data section, the memory fill area (BSS) and the initial stack pointer
+        let data_section_address = from_le_bytes_u32(get_range(&value, 0x020, 4));
+        let data_section_size = from_le_bytes_u32(get_range(&value, 0x024, 4));
+        let bss_address = from_le_bytes_u32(get_range(&value, 0x028, 4));
+        let bss_size = from_le_bytes_u32(get_range(&value, 0x02C, 4));
+        let stack_base_address = from_le_bytes_u32(get_range(&value, 0x030, 4));
+        let stack_offset = from_le_bytes_u32(get_range(&value, 0x034, 4));
+
+        // Ensure code and data told by the header follow it
+        if file_size as usize > value.len() - header_len {
+            return Err(format!(
+                "{} is truncated: its header tells {} bytes of code and data, but only {} bytes follow the header.",
+                Self::PS1_EXE_NAME,
+                file_size,
+                value.len() - header_len
+            ));
+        }
+
         // Read the ASCII marker. Example values:
         //
         // * "Sony Computer Entertainment Inc. for Europe area" -> PAL
@@ -123,6 +194,12 @@ impl PS1Exe {
             file_size,
             initial_gp_r28,
             initial_pc,
+            data_section_address,
+            data_section_size,
+            bss_address,
+            bss_size,
+            stack_base_address,
+            stack_offset,
             ascii_marker,
         })
     }
@@ -140,107 +217,607 @@ impl PS1Exe {
         reader.read_to_end(&mut data).map_err(|err| {
             format!(
                 "Failed to read given Playstation executable file in path \"{}\": {}",
-                file_path,
-                err.to_string()
+                file_path, err
             )
         })?;
 
         Self::from_bytes(data)
     }
-    fn get_address_by_address_in_memory(&self, address: u64) -> usize {
-        address as usize - self.destination_address_in_ram as usize
-            + Self::CODE_AND_DATA_BEGIN_OFFSET as usize
+    /// Gets the region of the executable told by its ASCII marker.
+    pub fn get_region(&self) -> ExeRegion {
+        ExeRegion::from_ascii_marker(&self.ascii_marker)
+    }
+    /// Gets the whole executable, header included.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+    /// Gets the addresses in memory code and data of the executable are loaded at.
+    pub fn get_memory_range(&self) -> Range<u64> {
+        let start = self.destination_address_in_ram as u64;
+        start..start + (self.data.len() - Self::CODE_AND_DATA_BEGIN_OFFSET as usize) as u64
+    }
+    /// Gets the offset in the file of a given count of bytes at an address in memory.
+    /// The address may be in any mirror of main RAM (see [MemoryAddress::parse]), like a KSEG1
+    /// address for an executable loaded into KSEG0.
+    pub fn get_file_offset(&self, address_in_memory: u64, len: usize) -> Result<usize, String> {
+        let range = self.get_memory_range();
+        let outside_error = || {
+            format!(
+                "Address 0x{:X} ({} bytes) is outside of the executable (0x{:X}-0x{:X}).",
+                address_in_memory, len, range.start, range.end
+            )
+        };
+        let address = MemoryAddress::parse(address_in_memory)?;
+        if address.region != MemoryRegion::MainRam {
+            return Err(format!(
+                "Address 0x{:X} is in {}, not in main RAM where the executable is loaded.",
+                address_in_memory, address.region
+            ));
+        }
+        let destination = MemoryAddress::parse(range.start)?;
+        let offset = address
+            .offset
+            .checked_sub(destination.offset)
+            .ok_or_else(outside_error)?;
+        if offset + len as u64 > range.end - range.start {
+            return Err(outside_error());
+        }
+        Ok(Self::CODE_AND_DATA_BEGIN_OFFSET as usize + offset as usize)
+    }
+    /// Gets the address in memory of a byte at a given offset in the file, in the segment
+    /// the executable is loaded into.
+    pub fn get_address_in_memory(&self, file_offset: usize) -> Result<u64, String> {
+        let header_len = Self::CODE_AND_DATA_BEGIN_OFFSET as usize;
+        if !(header_len..self.data.len()).contains(&file_offset) {
+            return Err(format!(
+                "Offset 0x{:X} is outside of code and data of the executable (0x{:X}-0x{:X}).",
+                file_offset,
+                header_len,
+                self.data.len()
+            ));
+        }
+        Ok(self.get_memory_range().start + (file_offset - header_len) as u64)
+    }
+    /// Reads a given count of bytes at an address in memory (see [PS1Exe::get_file_offset]).
+    pub fn read_bytes(&self, address_in_memory: u64, len: usize) -> Result<&[u8], String> {
+        let offset = self.get_file_offset(address_in_memory, len)?;
+        Ok(&self.data[offset..offset + len])
     }
 }
 
+/// Region of an executable, told by its ASCII marker.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExeRegion {
+    /// North America (NTSC-U).
+    NtscU,
+    /// Japan (NTSC-J).
+    NtscJ,
+    /// Europe (PAL).
+    Pal,
+    /// Marker is not one of Sony's, like in homebrew executables.
+    Unknown,
+}
+impl ExeRegion {
+    pub fn from_ascii_marker(ascii_marker: &str) -> Self {
+        [ExeRegion::NtscU, ExeRegion::NtscJ, ExeRegion::Pal]
+            .into_iter()
+            .find(|region| region.get_ascii_marker() == ascii_marker.trim_end())
+            .unwrap_or(ExeRegion::Unknown)
+    }
+    /// Gets the ASCII marker of the region in executables licensed by Sony.
+    pub fn get_ascii_marker(&self) -> &'static str {
+        match self {
+            ExeRegion::NtscU => "Sony Computer Entertainment Inc. for North America area",
+            ExeRegion::NtscJ => "Sony Computer Entertainment Inc. for Japan area",
+            ExeRegion::Pal => "Sony Computer Entertainment Inc. for Europe area",
+            ExeRegion::Unknown => "",
+        }
+    }
+}
+impl fmt::Display for ExeRegion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ExeRegion::NtscU => "NTSC-U",
+            ExeRegion::NtscJ => "NTSC-J",
+            ExeRegion::Pal => "PAL",
+            ExeRegion::Unknown => "unknown",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Instruction decoded at an address, or the error if its machine code is not valid.
+type DecodedInstruction = (u64, Result<mips::Instruction, mips::Error>);
+
 pub struct PS1ExeReader<'a> {
     exe: &'a PS1Exe,
 }
 impl<'a> PS1ExeReader<'a> {
-    pub fn disassemble_at_adress_by_count(&self, address_in_memory: u64, instruction_count: usize) {
-        let address = self.exe.get_address_by_address_in_memory(address_in_memory);
+    /// Disassembles instructions from a given address onwards.
+    ///
+    /// Output starts with "@at", so it can be assembled back as is. Symbols of the given symbol map
+    /// are written as labels. Other branch and jump targets inside the disassembled range get
+    /// generated labels, and the rest of targets are written as absolute addresses.
+    ///
+    /// Sequences of instructions assembled from pseudo-instructions (like `li`) are optionally
+    /// folded back into the pseudo-instructions for readability.
+    pub fn disassemble_at_adress_by_count(
+        &self,
+        address_in_memory: u64,
+        instruction_count: usize,
+        symbols: &SymbolMap,
+        fold_pseudo_instructions: bool,
+    ) -> Result<(), String> {
+        const INSTRUCTION_LEN_IN_BYTES: usize = 4;
+        let instructions = self.decode_instructions(address_in_memory, instruction_count)?;
+
+        // Name labels like Ghidra does: functions called with jal get "FUN_" prefix,
+        // other targets get "LAB_" prefix.
+        let end_address_in_memory =
+            address_in_memory + (instruction_count * INSTRUCTION_LEN_IN_BYTES) as u64;
+        let mut generated_labels = HashMap::new();
+        for (instruction_address, instruction) in instructions.iter() {
+            let Ok(instruction) = instruction else {
+                continue;
+            };
+            let Some(target) = instruction.get_target_address(*instruction_address) else {
+                continue;
+            };
+            if !(address_in_memory..end_address_in_memory).contains(&target) {
+                continue;
+            }
+            let prefix = match instruction {
+                mips::Instruction::J {
+                    opcode: 0b000011, ..
+                } => "FUN", // Opcode is 3 (jal)
+                _ => "LAB",
+            };
+            let label = format!("{}_{:08x}", prefix, target);
+            if prefix == "FUN" {
+                generated_labels.insert(target, label);
+            } else {
+                generated_labels.entry(target).or_insert(label);
+            }
+        }
+        let mut labels = symbols.get_labels();
+        for (target, label) in generated_labels {
+            labels.entry(target).or_insert(label);
+        }
+
+        self.print_instructions(&instructions, &labels, fold_pseudo_instructions);
+        Ok(())
+    }
+    /// Disassembles a function found by following branches and jumps from its entry
+    /// (see [Function::analyze]). Each basic block gets a label, and blocks that are apart
+    /// from each other start with their own "@at".
+    pub fn disassemble_function(
+        &self,
+        function: &Function,
+        symbols: &SymbolMap,
+        fold_pseudo_instructions: bool,
+    ) -> Result<(), String> {
+        let labels = self.get_function_labels(function, symbols);
+        let range = function.get_range();
+        println!(
+            "# Function {} at 0x{:X}-0x{:X}: {} basic blocks, {} instructions",
+            labels[&function.entry],
+            range.start,
+            range.end,
+            function.blocks.len(),
+            function.get_instruction_count()
+        );
+        for address in function.indirect_jumps.iter() {
+            println!("# Jump to unknown address at 0x{:X}", address);
+        }
+        for address in function.invalid_addresses.iter() {
+            println!("# Invalid instruction at 0x{:X}", address);
+        }
+
+        // Blocks right after each other are disassembled together.
+        let mut i = 0;
+        while i < function.blocks.len() {
+            let start = function.blocks[i].start;
+            let mut end = function.blocks[i].end;
+            i += 1;
+            while i < function.blocks.len() && function.blocks[i].start == end {
+                end = function.blocks[i].end;
+                i += 1;
+            }
+            let instructions = self.decode_instructions(start, (end - start) as usize / 4)?;
+            self.print_instructions(&instructions, &labels, fold_pseudo_instructions);
+        }
+        Ok(())
+    }
+    /// Analyzes the whole executable, following calls from the initial PC
+    /// and functions of the given symbol map (see [ExeAnalysis::analyze]).
+    pub fn analyze(&self, symbols: &SymbolMap) -> ExeAnalysis {
+        let mut entries = vec![self.exe.initial_pc as u64];
+        for symbol in symbols.symbols() {
+            if symbol.kind == SymbolKind::Function {
+                entries.push(symbol.address);
+            }
+        }
+        ExeAnalysis::analyze(self.exe.get_memory_range(), &entries, &|address| {
+            self.read_word(address)
+        })
+    }
+    /// Finds a function by following branches and jumps from a given entry address.
+    pub fn analyze_function(&self, entry_address_in_memory: u64) -> Function {
+        Function::analyze(entry_address_in_memory, &|address| self.read_word(address))
+    }
+    /// Gets labels for a function: symbols of the given symbol map, and generated labels
+    /// for the function ("FUN_" prefix) and its basic blocks ("LAB_" prefix). Called functions
+    /// are not labeled unless they have a symbol, so that the disassembly can be assembled back alone.
+    pub fn get_function_labels(
+        &self,
+        function: &Function,
+        symbols: &SymbolMap,
+    ) -> HashMap<u64, String> {
+        let mut labels = symbols.get_labels();
+        labels
+            .entry(function.entry)
+            .or_insert_with(|| format!("FUN_{:08x}", function.entry));
+        for block in function.blocks.iter() {
+            labels
+                .entry(block.start)
+                .or_insert_with(|| format!("LAB_{:08x}", block.start));
+        }
+        labels
+    }
+    /// Reads an instruction at a given address. Returns `None` if the address is outside
+    /// the executable or the instruction is not valid.
+    pub fn read_instruction(&self, address_in_memory: u64) -> Option<mips::Instruction> {
+        let machine_code = self.read_word(address_in_memory)?;
+        mips::Instruction::parse_from_machine_code(machine_code).ok()
+    }
+    /// Reads a 4-byte value at a given address, which may be in any mirror of main RAM.
+    /// Returns `None` if the address is not aligned to 4 bytes or is outside the executable.
+    pub fn read_word(&self, address_in_memory: u64) -> Option<u32> {
+        if address_in_memory % 4 != 0 {
+            return None;
+        }
+        let bytes = self.exe.read_bytes(address_in_memory, 4).ok()?;
+        Some(u32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+    /// Decodes a given count of instructions from a given address onwards.
+    fn decode_instructions(
+        &self,
+        address_in_memory: u64,
+        instruction_count: usize,
+    ) -> Result<Vec<DecodedInstruction>, String> {
         const INSTRUCTION_LEN_IN_BYTES: usize = 4;
-        for i in 0..instruction_count {
-            let instruction_bytes = &self.exe.data[address + i * INSTRUCTION_LEN_IN_BYTES
-                ..address + (i + 1) * INSTRUCTION_LEN_IN_BYTES];
-            let instruction_bytes: &[u8; 4] = instruction_bytes.try_into().unwrap();
-            let instruction = mips::Instruction::parse_from_le_bytes(instruction_bytes);
-            println!("{}", instruction.to_instruction());
+        let bytes = self.exe.read_bytes(
+            address_in_memory,
+            instruction_count * INSTRUCTION_LEN_IN_BYTES,
+        )?;
+        let instructions = bytes
+            .chunks_exact(INSTRUCTION_LEN_IN_BYTES)
+            .enumerate()
+            .map(|(i, instruction_bytes)| {
+                let instruction_bytes: &[u8; 4] = instruction_bytes.try_into().unwrap();
+                let instruction_address = address_in_memory + (i * INSTRUCTION_LEN_IN_BYTES) as u64;
+                // Instructions with unused bits set are kept as data to be assembled back as is.
+                let machine_code = u32::from_le_bytes(*instruction_bytes);
+                (
+                    instruction_address,
+                    mips::Instruction::parse_from_machine_code_exact(machine_code),
+                )
+            })
+            .collect();
+        Ok(instructions)
+    }
+    /// Prints decoded instructions starting with "@at", labels at their addresses.
+    fn print_instructions(
+        &self,
+        instructions: &[(u64, Result<mips::Instruction, mips::Error>)],
+        labels: &HashMap<u64, String>,
+        fold_pseudo_instructions: bool,
+    ) {
+        let mut output = String::new();
+        self.write_instructions(instructions, labels, fold_pseudo_instructions, &mut output);
+        print!("{}", output);
+    }
+    /// Writes decoded instructions starting with "@at", labels at their addresses.
+    fn write_instructions(
+        &self,
+        instructions: &[(u64, Result<mips::Instruction, mips::Error>)],
+        labels: &HashMap<u64, String>,
+        fold_pseudo_instructions: bool,
+        output: &mut String,
+    ) {
+        let Some((address_in_memory, _)) = instructions.first() else {
+            return;
+        };
+
+        // Addresses loaded with lui and addiu, load or store pairs, by index of both instructions.
+        // Pairs are searched between labels, because code may be reached from elsewhere at a label.
+        let mut hi_lo_addresses = HashMap::new();
+        let mut start = 0;
+        for end in 1..=instructions.len() {
+            if end < instructions.len() && !labels.contains_key(&instructions[end].0) {
+                continue;
+            }
+            let block = instructions[start..end]
+                .iter()
+                .map(|(_, instruction)| instruction.as_ref().ok())
+                .collect::<Vec<_>>();
+            for pair in mips::find_hi_lo_pairs(&block) {
+                hi_lo_addresses
+                    .entry(start + pair.hi_index)
+                    .or_insert(pair.address);
+                hi_lo_addresses.insert(start + pair.lo_index, pair.address);
+            }
+            start = end;
+        }
+
+        output.push_str(&format!("@at 0x{:x}\n", address_in_memory));
+        let mut i = 0;
+        while i < instructions.len() {
+            let (instruction_address, instruction) = &instructions[i];
+            if let Some(label) = labels.get(instruction_address) {
+                output.push_str(&format!("{}:\n", label));
+            }
+            // Written as data, so that the code can be assembled back byte for byte.
+            // Instructions were decoded from the executable, so they are inside it.
+            let write_as_word = |output: &mut String, err: &mips::Error| {
+                let machine_code = self.read_word(*instruction_address).unwrap();
+                output.push_str(&format!(".word 0x{:08X} # {}\n", machine_code, err));
+            };
+            let instruction = match instruction {
+                Ok(instruction) => instruction,
+                Err(err) => {
+                    write_as_word(output, err);
+                    i += 1;
+                    continue;
+                }
+            };
+
+            if fold_pseudo_instructions {
+                // Instructions with a label cannot be folded into the previous instructions.
+                let foldable_instructions = instructions[i..]
+                    .iter()
+                    .take(mips::MAX_PSEUDO_INSTRUCTION_LEN)
+                    .enumerate()
+                    .take_while(|(j, (address, _))| *j == 0 || !labels.contains_key(address))
+                    .map_while(|(_, (_, instruction))| instruction.as_ref().ok().cloned())
+                    .collect::<Vec<_>>();
+                if let Some((pseudo_instruction, count)) = mips::fold_pseudo_instruction(
+                    &foldable_instructions,
+                    *instruction_address,
+                    labels,
+                ) {
+                    output.push_str(&format!("{}\n", pseudo_instruction));
+                    i += count;
+                    continue;
+                }
+            }
+
+            let text = match instruction.to_instruction_at(*instruction_address, labels) {
+                Ok(text) => text,
+                Err(err) => {
+                    write_as_word(output, &err);
+                    i += 1;
+                    continue;
+                }
+            };
+            match hi_lo_addresses.get(&i) {
+                Some(address) => match labels.get(address) {
+                    Some(label) => {
+                        let operator = match instruction {
+                            mips::Instruction::IUnsigned {
+                                opcode: 0b001111, ..
+                            } => "%hi", // Opcode is 15 (lui)
+                            _ => "%lo",
+                        };
+                        let operand = format!("{}({})", operator, label);
+                        output.push_str(&format!("{}\n", replace_immediate(&text, &operand)));
+                    }
+                    None => output.push_str(&format!("{} # 0x{:X}\n", text, address)),
+                },
+                None => output.push_str(&format!("{}\n", text)),
+            }
+            i += 1;
         }
     }
+    /// Disassembles a string at a given address until a given end byte.
+    /// Bytes are decoded with the given codec, so the result can be assembled back as is.
     pub fn disassemble_str_at_address_until_byte(
         &'a self,
         address_in_memory: u64,
         end_byte: u8,
-    ) -> Result<&'a str, String> {
+        codec: &dyn TextCodec,
+    ) -> Result<String, String> {
         const MAX_STR_LENGTH: usize = 256;
 
-        let address = self.exe.get_address_by_address_in_memory(address_in_memory);
-        let bytes_buffer = &self.exe.data[address..address + MAX_STR_LENGTH];
-
-        for i in 0..MAX_STR_LENGTH {
-            if bytes_buffer[i] == end_byte {
-                let str_bytes = &bytes_buffer[0..i];
-                let str = str::from_utf8(str_bytes).map_err(|err| {
-                    format!(
-                        "Failed to UTF-8 encode a string at address 0x{:X}: {}. Bytes found: {:?}",
-                        address_in_memory,
-                        err.to_string(),
-                        str_bytes
-                    )
-                })?;
-                return Ok(str);
-            }
-        }
+        let address = self.exe.get_file_offset(address_in_memory, 1)?;
+        let bytes_buffer =
+            &self.exe.data[address..(address + MAX_STR_LENGTH).min(self.exe.data.len())];
 
-        Err(format!("Maximum length for requested string reaching while disassemblying: no end byte {} found", end_byte))
+        match bytes_buffer.iter().position(|b| *b == end_byte) {
+            Some(i) => Ok(codec.decode(&bytes_buffer[0..i])),
+            None => Err(format!("Maximum length for requested string reaching while disassemblying: no end byte {} found", end_byte)),
+        }
+    }
+    /// Finds all strings and pointers to them from code and data of the executable.
+    pub fn find_text_entries(&self, codec: &dyn TextCodec) -> Vec<TextEntry> {
+        find_text_entries(
+            &self.exe.data[PS1Exe::CODE_AND_DATA_BEGIN_OFFSET as usize..],
+            self.exe.destination_address_in_ram as u64,
+            codec,
+        )
     }
     pub fn new(exe: &'a PS1Exe) -> Self {
         Self { exe }
     }
 }
 
+/// Replaces the immediate of a disassembled instruction, which is the last operand
+/// or the offset of a relative value like "16(sp)".
+fn replace_immediate(instruction: &str, immediate: &str) -> String {
+    let (start, last_operand) = instruction.rsplit_once(", ").unwrap();
+    match last_operand.find("(") {
+        Some(open_index) => format!("{}, {}{}", start, immediate, &last_operand[open_index..]),
+        None => format!("{}, {}", start, immediate),
+    }
+}
+
+/// Where bytes written via [PS1ExeWriter] come from, like a line of an assembly code file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WriteSource {
+    pub file_path: String,
+    /// Line in the file, if the bytes come from a single line.
+    pub line: Option<u64>,
+}
+impl fmt::Display for WriteSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{}", self.file_path, line),
+            None => write!(f, "{}", self.file_path),
+        }
+    }
+}
+
+/// Bytes written more than once via [PS1ExeWriter]. The later write overwrites the earlier one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WriteConflict {
+    /// Overlapping bytes as addresses in memory.
+    pub range: Range<u64>,
+    pub earlier_source: WriteSource,
+    pub later_source: WriteSource,
+}
+impl fmt::Display for WriteConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "0x{:08X}-0x{:08X} ({} bytes) written by {} was already written by {}",
+            self.range.start,
+            self.range.end,
+            self.range.end - self.range.start,
+            self.later_source,
+            self.earlier_source
+        )
+    }
+}
+
 pub struct PS1ExeWriter<'a> {
-    /// Stores information about how much of the executable
-    /// bytes has been written into via this writer.
-    data_written: Vec<u8>,
+    /// Index (plus one) of the source in `sources` which last wrote each byte of the executable,
+    /// or 0 if the byte has not been written into via this writer.
+    written_by: Vec<u32>,
+    sources: Vec<WriteSource>,
+    conflicts: Vec<WriteConflict>,
     exe: &'a mut PS1Exe,
 }
 impl<'a> PS1ExeWriter<'a> {
+    /// Gets all bytes written more than once, in the order they were written.
+    pub fn get_conflicts(&self) -> &[WriteConflict] {
+        &self.conflicts
+    }
+    /// Gets the ratio (from 0 to 1) of code and data bytes written into via this writer.
+    /// The header is not counted, as it is not in memory.
     pub fn get_percentage_of_written_bytes(&self) -> f32 {
-        let mut written_bytes_count = 0;
-        for i in 0..self.data_written.len() {
-            if self.data_written[i] != 0 {
-                written_bytes_count += 1;
+        let start = PS1Exe::CODE_AND_DATA_BEGIN_OFFSET as usize;
+        let written_bytes_count = self
+            .written_by
+            .iter()
+            .skip(start)
+            .filter(|source_index| **source_index != 0)
+            .count();
+        written_bytes_count as f32 / (self.exe.data.len() - start) as f32
+    }
+    /// Gets ranges of code and data (as addresses in memory) which have not been written into
+    /// via this writer, like parts of the executable missing from assembly code.
+    pub fn get_unwritten_ranges(&self) -> Vec<Range<u64>> {
+        self.get_ranges_by_source()
+            .into_iter()
+            .filter(|(_, source)| source.is_none())
+            .map(|(range, _)| range)
+            .collect()
+    }
+    /// Gets ranges of code and data (as addresses in memory) with the source which last wrote them.
+    pub fn get_written_ranges(&self) -> Vec<(Range<u64>, &WriteSource)> {
+        self.get_ranges_by_source()
+            .into_iter()
+            .filter_map(|(range, source)| Some((range, source?)))
+            .collect()
+    }
+    /// Splits code and data into ranges (as addresses in memory) last written by the same source.
+    fn get_ranges_by_source(&self) -> Vec<(Range<u64>, Option<&WriteSource>)> {
+        let start = PS1Exe::CODE_AND_DATA_BEGIN_OFFSET as usize;
+        let mut ranges: Vec<(Range<u64>, u32)> = Vec::new();
+        for (i, source_index) in self.written_by.iter().enumerate().skip(start) {
+            let address_in_memory = (i - start) as u64 + self.exe.get_memory_range().start;
+            match ranges.last_mut() {
+                Some((range, last_source_index)) if last_source_index == source_index => {
+                    range.end += 1
+                }
+                _ => ranges.push((address_in_memory..address_in_memory + 1, *source_index)),
             }
         }
-        written_bytes_count as f32 / self.data_written.len() as f32
+        ranges
+            .into_iter()
+            .map(|(range, source_index)| {
+                let source = source_index
+                    .checked_sub(1)
+                    .map(|source_index| &self.sources[source_index as usize]);
+                (range, source)
+            })
+            .collect()
     }
     pub fn new(exe: &'a mut PS1Exe) -> Self {
         Self {
-            data_written: Vec::new(),
+            written_by: vec![0; exe.data.len()],
+            sources: Vec::new(),
+            conflicts: Vec::new(),
             exe,
         }
     }
-    pub fn write_code(&mut self, address_in_memory: u64, code: &[u8]) -> PS1ExeWriteResult {
-        // By default, data_written vector has nothing in it, it is uninitialized.
-        // So, initialize data_written vector if it hasn't been initialized yet.
-        if self.data_written.len() == 0 {
-            let mut data_len = self.exe.data.len();
-            self.data_written = Vec::with_capacity(data_len);
-            while data_len > 0 {
-                self.data_written.push(0); // 0 means that the byte hasn't been written into yet.
-                data_len -= 1;
-            }
-        }
+    /// Writes code (or data) at a given address, remembering the source which wrote it.
+    /// Bytes already written via this writer are overwritten, and recorded as conflicts
+    /// (see [PS1ExeWriter::get_conflicts]).
+    pub fn write_code(
+        &mut self,
+        address_in_memory: u64,
+        code: &[u8],
+        source: &WriteSource,
+    ) -> Result<PS1ExeWriteResult, String> {
+        let address = self
+            .exe
+            .get_file_offset(address_in_memory, code.len())
+            .map_err(|err| format!("Failed to write bytes by {}: {}", source, err))?;
 
-        let address = self.exe.get_address_by_address_in_memory(address_in_memory);
+        if self.sources.last() != Some(source) {
+            self.sources.push(source.clone());
+        }
+        let source_index = self.sources.len() as u32;
 
-        // Mark bytes as written into based on the given address and code length
-        for i in address..address + code.len() {
-            if self.data_written[i] == 0 {
-                self.data_written[i] = 1;
+        // Mark bytes as written into by the source, finding bytes already written by others.
+        let mut conflict: Option<(Range<u64>, u32)> = None;
+        for i in 0..code.len() {
+            let earlier_source_index = self.written_by[address + i];
+            self.written_by[address + i] = source_index;
+            let byte_address_in_memory = address_in_memory + i as u64;
+            match &mut conflict {
+                Some((range, conflict_source_index))
+                    if *conflict_source_index == earlier_source_index
+                        && range.end == byte_address_in_memory =>
+                {
+                    range.end += 1;
+                    continue;
+                }
+                _ => {}
+            }
+            if let Some((range, conflict_source_index)) = conflict.take() {
+                self.push_conflict(range, conflict_source_index, source);
             }
+            if earlier_source_index != 0 {
+                conflict = Some((
+                    byte_address_in_memory..byte_address_in_memory + 1,
+                    earlier_source_index,
+                ));
+            }
+        }
+        if let Some((range, conflict_source_index)) = conflict {
+            self.push_conflict(range, conflict_source_index, source);
         }
 
         let bytes_to_write_into = &mut self.exe.data[address..address + code.len()];
@@ -256,21 +833,31 @@ impl<'a> PS1ExeWriter<'a> {
         // Overwrite bytes in the executable file with given code
         bytes_to_write_into.copy_from_slice(code);
 
-        result
+        Ok(result)
+    }
+    fn push_conflict(
+        &mut self,
+        range: Range<u64>,
+        earlier_source_index: u32,
+        source: &WriteSource,
+    ) {
+        self.conflicts.push(WriteConflict {
+            range,
+            earlier_source: self.sources[earlier_source_index as usize - 1].clone(),
+            later_source: source.clone(),
+        });
     }
     pub fn write_into_file(&self, file_path: &str) -> Result<(), String> {
         let mut file = File::create(file_path).map_err(|err| {
             format!(
                 "Failed to make a Playstation executable file to path \"{}\": {}",
-                file_path,
-                err.to_string()
+                file_path, err
             )
         })?;
         file.write_all(&self.exe.data).map_err(|err| {
             format!(
                 "Failed to write to Playstation executable file to path \"{}\": {}",
-                file_path,
-                err.to_string()
+                file_path, err
             )
         })?;
         Ok(())
@@ -281,3 +868,165 @@ pub enum PS1ExeWriteResult {
     Changed { original_code: Vec<u8> },
     Unchanged,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_whole_header() {
+        let mut exe_bytes = vec![0; 0x1000];
+        exe_bytes[..8].copy_from_slice(b"PS-X EXE");
+        for (offset, value) in [
+            (0x10, 0x80010010u32),
+            (0x14, 0x80018000),
+            (0x18, 0x80010000),
+            (0x1C, 0x800),
+            (0x20, 0x80010400),
+            (0x24, 0x100),
+            (0x28, 0x80011000),
+            (0x2C, 0x200),
+            (0x30, 0x801FFF00),
+            (0x34, 0xF0),
+        ] {
+            exe_bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        }
+        let marker = ExeRegion::NtscJ.get_ascii_marker();
+        exe_bytes[0x4C..0x4C + marker.len()].copy_from_slice(marker.as_bytes());
+        let exe = PS1Exe::from_bytes(exe_bytes.clone()).unwrap();
+        assert_eq!(
+            [
+                exe.initial_pc,
+                exe.initial_gp_r28,
+                exe.destination_address_in_ram,
+                exe.file_size,
+                exe.data_section_address,
+                exe.data_section_size,
+                exe.bss_address,
+                exe.bss_size,
+                exe.stack_base_address,
+                exe.stack_offset
+            ],
+            [
+                0x80010010, 0x80018000, 0x80010000, 0x800, 0x80010400, 0x100, 0x80011000, 0x200,
+                0x801FFF00, 0xF0
+            ]
+        );
+        assert_eq!(exe.get_region(), ExeRegion::NtscJ);
+        assert_eq!(exe.get_region().to_string(), "NTSC-J");
+        assert_eq!(ExeRegion::from_ascii_marker(""), ExeRegion::Unknown);
+
+        // Truncated files fail to be read rather than panic.
+        for len in [0, 4, 0x7FF] {
+            assert!(PS1Exe::from_bytes(exe_bytes[..len].to_vec())
+                .err()
+                .unwrap()
+                .contains("truncated"));
+        }
+        exe_bytes[0x1C..0x20].copy_from_slice(&0x1000u32.to_le_bytes());
+        assert!(PS1Exe::from_bytes(exe_bytes)
+            .err()
+            .unwrap()
+            .contains("only 2048 bytes follow the header"));
+    }
+
+    #[test]
+    fn convert_addresses_to_file_offsets() {
+        let mut exe_bytes = vec![0; 0x1000];
+        exe_bytes[..8].copy_from_slice(b"PS-X EXE");
+        exe_bytes[0x18..0x1C].copy_from_slice(&0x80010000u32.to_le_bytes());
+        exe_bytes[0x1C..0x20].copy_from_slice(&0x800u32.to_le_bytes());
+        exe_bytes[0x810..0x814].copy_from_slice(&[1, 2, 3, 4]);
+        let exe = PS1Exe::from_bytes(exe_bytes).unwrap();
+        assert_eq!(exe.get_memory_range(), 0x80010000..0x80010800);
+
+        // Mirrors of main RAM point to the same bytes.
+        for address in [0x80010010, 0x00010010, 0xA0010010, 0x80210010] {
+            assert_eq!(exe.get_file_offset(address, 4), Ok(0x810));
+            assert_eq!(exe.read_bytes(address, 4), Ok(&[1, 2, 3, 4][..]));
+        }
+        assert_eq!(exe.get_address_in_memory(0x810), Ok(0x80010010));
+        assert_eq!(exe.get_file_offset(0x800107FC, 4), Ok(0xFFC));
+
+        for (address, error) in [
+            (0x8000FFFC, "outside of the executable"),
+            (0x800107FE, "outside of the executable"),
+            (0x0, "outside of the executable"),
+            (0x1F800000, "in scratchpad, not in main RAM"),
+            (0x1F801810, "in I/O ports, not in main RAM"),
+            (0xFFFFFFFF, "not in KUSEG, KSEG0 or KSEG1"),
+        ] {
+            let err = exe.get_file_offset(address, 4).unwrap_err();
+            assert!(err.contains(error), "{}", err);
+        }
+        assert!(exe.get_address_in_memory(0x7FF).is_err());
+        assert!(exe.get_address_in_memory(0x1000).is_err());
+
+        let reader = PS1ExeReader::new(&exe);
+        assert_eq!(reader.read_word(0xA0010010), Some(0x04030201));
+        assert_eq!(reader.read_word(0x0), None);
+        assert!(reader
+            .disassemble_str_at_address_until_byte(0x0, 0, &AsciiCodec)
+            .is_err());
+    }
+
+    #[test]
+    fn find_write_conflicts() {
+        let mut exe_bytes = vec![0; 0x1000];
+        exe_bytes[..8].copy_from_slice(b"PS-X EXE");
+        exe_bytes[0x18..0x1C].copy_from_slice(&0x80010000u32.to_le_bytes());
+        exe_bytes[0x1C..0x20].copy_from_slice(&0x800u32.to_le_bytes());
+        let mut exe = PS1Exe::from_bytes(exe_bytes).unwrap();
+        let mut writer = PS1ExeWriter::new(&mut exe);
+        let get_source = |file_path: &str, line| WriteSource {
+            file_path: String::from(file_path),
+            line: Some(line),
+        };
+
+        writer
+            .write_code(0x80010000, &[1; 8], &get_source("a.s", 3))
+            .unwrap();
+        writer
+            .write_code(0x80010008, &[2; 4], &get_source("a.s", 4))
+            .unwrap();
+        writer
+            .write_code(0x80010006, &[3; 8], &get_source("b.s", 10))
+            .unwrap();
+        writer
+            .write_code(0x80010010, &[5; 4], &get_source("b.s", 12))
+            .unwrap();
+        assert!(writer
+            .write_code(0x800107FE, &[4; 4], &get_source("b.s", 11))
+            .is_err());
+
+        let conflicts = writer
+            .get_conflicts()
+            .iter()
+            .map(|conflict| conflict.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            conflicts,
+            [
+                "0x80010006-0x80010008 (2 bytes) written by b.s:10 was already written by a.s:3",
+                "0x80010008-0x8001000C (4 bytes) written by b.s:10 was already written by a.s:4"
+            ]
+        );
+        let written_ranges = writer
+            .get_written_ranges()
+            .into_iter()
+            .map(|(range, source)| (range, source.to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            written_ranges,
+            [
+                (0x80010000..0x80010006, String::from("a.s:3")),
+                (0x80010006..0x8001000E, String::from("b.s:10")),
+                (0x80010010..0x80010014, String::from("b.s:12"))
+            ]
+        );
+        assert_eq!(
+            writer.get_unwritten_ranges(),
+            [0x8001000E..0x80010010, 0x80010014..0x80010800]
+        );
+    }
+}
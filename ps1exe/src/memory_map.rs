@@ -0,0 +1,219 @@
+//! Address space of the Playstation as seen by its CPU.
+//!
+//! The same physical memory is seen through three segments: KUSEG (0x0…), KSEG0 (0x8…, cached)
+//! and KSEG1 (0xA…, uncached). On top of that, the 2 MB of main RAM are mirrored four times
+//! in the first 8 MB of each segment. Games mostly use KSEG0 addresses, but pointers into
+//! the other mirrors point to the same bytes.
+
+use std::fmt;
+
+/// Segment of the address space an address is in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MemorySegment {
+    /// User segment at 0x00000000 (mapped as is, there is no MMU).
+    Kuseg,
+    /// Cached kernel segment at 0x80000000.
+    Kseg0,
+    /// Uncached kernel segment at 0xA0000000.
+    Kseg1,
+}
+impl MemorySegment {
+    pub fn get_base_address(&self) -> u64 {
+        match self {
+            MemorySegment::Kuseg => 0x00000000,
+            MemorySegment::Kseg0 => 0x80000000,
+            MemorySegment::Kseg1 => 0xA0000000,
+        }
+    }
+}
+impl fmt::Display for MemorySegment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            MemorySegment::Kuseg => "KUSEG",
+            MemorySegment::Kseg0 => "KSEG0",
+            MemorySegment::Kseg1 => "KSEG1",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Region of physical memory.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MemoryRegion {
+    /// 2 MB of main RAM, where executables are loaded.
+    MainRam,
+    /// Expansion region 1, like the parallel port.
+    Expansion1,
+    /// 1 KB of fast RAM (data cache used as RAM), not reachable through KSEG1.
+    Scratchpad,
+    /// Hardware registers, like the GPU, SPU, DMA and timers.
+    IoPorts,
+    /// Expansion region 2, used by debugging hardware.
+    Expansion2,
+    /// Expansion region 3.
+    Expansion3,
+    /// 512 KB of BIOS ROM.
+    Bios,
+}
+impl MemoryRegion {
+    const REGIONS: [MemoryRegion; 7] = [
+        MemoryRegion::MainRam,
+        MemoryRegion::Expansion1,
+        MemoryRegion::Scratchpad,
+        MemoryRegion::IoPorts,
+        MemoryRegion::Expansion2,
+        MemoryRegion::Expansion3,
+        MemoryRegion::Bios,
+    ];
+    /// Gets the physical address the region starts at.
+    pub fn get_physical_address(&self) -> u64 {
+        match self {
+            MemoryRegion::MainRam => 0x00000000,
+            MemoryRegion::Expansion1 => 0x1F000000,
+            MemoryRegion::Scratchpad => 0x1F800000,
+            MemoryRegion::IoPorts => 0x1F801000,
+            MemoryRegion::Expansion2 => 0x1F802000,
+            MemoryRegion::Expansion3 => 0x1FA00000,
+            MemoryRegion::Bios => 0x1FC00000,
+        }
+    }
+    /// Gets the size of the region in bytes, mirrors excluded.
+    pub const fn get_size(&self) -> u64 {
+        match self {
+            MemoryRegion::MainRam => 0x200000,
+            MemoryRegion::Expansion1 => 0x800000,
+            MemoryRegion::Scratchpad => 0x400,
+            MemoryRegion::IoPorts => 0x1000,
+            MemoryRegion::Expansion2 => 0x2000,
+            MemoryRegion::Expansion3 => 0x200000,
+            MemoryRegion::Bios => 0x80000,
+        }
+    }
+    /// Gets the size of the addresses the region takes, including its mirrors.
+    fn get_mirrored_size(&self) -> u64 {
+        match self {
+            // Main RAM is mirrored in the first 8 MB.
+            MemoryRegion::MainRam => 0x800000,
+            _ => self.get_size(),
+        }
+    }
+}
+impl fmt::Display for MemoryRegion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            MemoryRegion::MainRam => "main RAM",
+            MemoryRegion::Expansion1 => "expansion region 1",
+            MemoryRegion::Scratchpad => "scratchpad",
+            MemoryRegion::IoPorts => "I/O ports",
+            MemoryRegion::Expansion2 => "expansion region 2",
+            MemoryRegion::Expansion3 => "expansion region 3",
+            MemoryRegion::Bios => "BIOS",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Address split into the segment it is seen through, the region of memory it is in,
+/// and the offset in the region with mirrors normalised.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MemoryAddress {
+    pub segment: MemorySegment,
+    pub region: MemoryRegion,
+    pub offset: u64,
+}
+impl MemoryAddress {
+    /// Parses an address, which fails if nothing is mapped at it.
+    pub fn parse(address: u64) -> Result<Self, String> {
+        let segment = match address {
+            0x00000000..=0x7FFFFFFF => MemorySegment::Kuseg,
+            0x80000000..=0x9FFFFFFF => MemorySegment::Kseg0,
+            0xA0000000..=0xBFFFFFFF => MemorySegment::Kseg1,
+            _ => {
+                return Err(format!(
+                    "Address 0x{:X} is not in KUSEG, KSEG0 or KSEG1.",
+                    address
+                ))
+            }
+        };
+        let physical_address = address - segment.get_base_address();
+        let region = MemoryRegion::REGIONS
+            .into_iter()
+            .find(|region| {
+                let start = region.get_physical_address();
+                (start..start + region.get_mirrored_size()).contains(&physical_address)
+            })
+            .ok_or_else(|| {
+                format!(
+                    "Address 0x{:X} is not in any region of memory ({} physical address 0x{:X}).",
+                    address, segment, physical_address
+                )
+            })?;
+        if region == MemoryRegion::Scratchpad && segment == MemorySegment::Kseg1 {
+            return Err(format!(
+                "Address 0x{:X} is in scratchpad, which cannot be reached through KSEG1.",
+                address
+            ));
+        }
+        let offset = (physical_address - region.get_physical_address()) % region.get_size();
+        Ok(Self {
+            segment,
+            region,
+            offset,
+        })
+    }
+    /// Gets the address in a given segment, in the first mirror of the region.
+    pub fn to_address_in(&self, segment: MemorySegment) -> u64 {
+        segment.get_base_address() + self.region.get_physical_address() + self.offset
+    }
+}
+impl fmt::Display for MemoryAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} offset 0x{:X} ({})",
+            self.region, self.offset, self.segment
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalise_mirrors() {
+        for address in [0x00010000, 0x80010000, 0xA0010000, 0x00210000, 0x80610000] {
+            let address = MemoryAddress::parse(address).unwrap();
+            assert_eq!(address.region, MemoryRegion::MainRam);
+            assert_eq!(address.offset, 0x10000);
+            assert_eq!(address.to_address_in(MemorySegment::Kseg0), 0x80010000);
+        }
+        assert_eq!(
+            MemoryAddress::parse(0xA0010000).unwrap().segment,
+            MemorySegment::Kseg1
+        );
+        let scratchpad = MemoryAddress::parse(0x1F800010).unwrap();
+        assert_eq!(
+            (scratchpad.segment, scratchpad.region, scratchpad.offset),
+            (MemorySegment::Kuseg, MemoryRegion::Scratchpad, 0x10)
+        );
+        assert_eq!(
+            MemoryAddress::parse(0x9F801810).unwrap().to_string(),
+            "I/O ports offset 0x810 (KSEG0)"
+        );
+        assert_eq!(
+            MemoryAddress::parse(0xBFC00000).unwrap().region,
+            MemoryRegion::Bios
+        );
+
+        for (address, error) in [
+            (0x00800000, "not in any region"),
+            (0xBF800000, "cannot be reached through KSEG1"),
+            (0xFFFE0130, "not in KUSEG, KSEG0 or KSEG1"),
+            (0x1_8001_0000, "not in KUSEG, KSEG0 or KSEG1"),
+        ] {
+            let err = MemoryAddress::parse(address).unwrap_err();
+            assert!(err.contains(error), "{}", err);
+        }
+    }
+}
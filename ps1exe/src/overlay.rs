@@ -0,0 +1,97 @@
+//! Overlays, which are raw code and data loaded into RAM by the game at runtime rather than by
+//! the BIOS, like code specific to a level loaded from WAD.WAD. Overlays have no header, so they
+//! are read with a load address told by the user.
+
+use crate::{MemoryAddress, MemoryRegion, PS1Exe};
+
+impl PS1Exe {
+    /// Reads an overlay loaded at a given address, so that it can be read like an executable.
+    /// A header is made up for it with the load address as both the destination address in RAM
+    /// and the initial PC. Unlike executables, the size of an overlay is not padded.
+    pub fn from_overlay_bytes(bytes: &[u8], load_address: u32) -> Result<Self, String> {
+        if bytes.is_empty() {
+            return Err(String::from("Overlay is empty."));
+        }
+        if load_address % 4 != 0 {
+            return Err(format!(
+                "Overlay load address 0x{:X} is not aligned to 4 bytes.",
+                load_address
+            ));
+        }
+        let address = MemoryAddress::parse(load_address as u64)?;
+        let ram_size = MemoryRegion::MainRam.get_size();
+        if address.region != MemoryRegion::MainRam || address.offset + bytes.len() as u64 > ram_size
+        {
+            return Err(format!(
+                "Overlay of {} bytes loaded at 0x{:X} does not fit in main RAM.",
+                bytes.len(),
+                load_address
+            ));
+        }
+
+        let header_len = Self::CODE_AND_DATA_BEGIN_OFFSET as usize;
+        let mut data = vec![0; header_len + bytes.len()];
+        data[0x000..0x008].copy_from_slice(b"PS-X EXE");
+        for (offset, value) in [
+            (0x010, load_address),
+            (0x018, load_address),
+            (0x01C, bytes.len() as u32),
+        ] {
+            data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        }
+        data[header_len..].copy_from_slice(bytes);
+        Ok(Self {
+            data,
+            destination_address_in_ram: load_address,
+            file_size: bytes.len() as u32,
+            initial_gp_r28: 0,
+            initial_pc: load_address,
+            data_section_address: 0,
+            data_section_size: 0,
+            bss_address: 0,
+            bss_size: 0,
+            stack_base_address: 0,
+            stack_offset: 0,
+            ascii_marker: String::new(),
+        })
+    }
+    /// Gets code and data of the executable without its header, like the bytes of an overlay.
+    pub fn get_code_and_data(&self) -> &[u8] {
+        &self.data[Self::CODE_AND_DATA_BEGIN_OFFSET as usize..]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PS1ExeReader;
+
+    #[test]
+    fn read_overlay_at_load_address() {
+        // jr ra, nop and a word of data, which is not a multiple of 2048 bytes.
+        let bytes = [
+            0x08, 0x00, 0xE0, 0x03, 0x00, 0x00, 0x00, 0x00, 0x01, 0x02, 0x03, 0x04,
+        ];
+        let overlay = PS1Exe::from_overlay_bytes(&bytes, 0x80073000).unwrap();
+        assert_eq!(overlay.get_memory_range(), 0x80073000..0x8007300C);
+        assert_eq!(overlay.get_code_and_data(), bytes);
+        let reader = PS1ExeReader::new(&overlay);
+        assert_eq!(reader.read_word(0x80073000), Some(0x03E00008));
+        assert_eq!(reader.read_word(0x80073008), Some(0x04030201));
+        assert_eq!(reader.read_word(0x8007300C), None);
+        let function = reader.analyze_function(0x80073000);
+        assert_eq!(function.get_range(), 0x80073000..0x80073008);
+
+        for (bytes, load_address, error) in [
+            (&[][..], 0x80073000, "empty"),
+            (&bytes[..], 0x80073002, "not aligned"),
+            (&bytes[..], 0x801FFFFC, "does not fit in main RAM"),
+            (&bytes[..], 0x1F800000, "does not fit in main RAM"),
+        ] {
+            let err = PS1Exe::from_overlay_bytes(bytes, load_address)
+                .err()
+                .unwrap();
+            assert!(err.contains(error), "{}", err);
+        }
+    }
+}
@@ -0,0 +1,458 @@
+//! Splitting a whole executable into assembly files, which assemble back into the same executable.
+
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+
+use crate::{DecodedInstruction, MemoryRegion, PS1ExeBuilder, PS1ExeReader, RegionKind, SymbolMap};
+
+/// Most words written on one ".word" line.
+const MAX_WORDS_PER_LINE: usize = 4;
+/// Name of the file with header fields of the executable, written by [PS1ExeReader::split_header].
+pub const SPLIT_HEADER_FILE_NAME: &str = "header.txt";
+
+/// An assembly file made by [PS1ExeReader::split].
+pub struct SplitFile {
+    /// File name, like "main.s", "FUN_80012340.s" or "data_80012340.s".
+    pub name: String,
+    pub content: String,
+}
+
+/// A part of the executable written into one file.
+struct Segment {
+    range: Range<u64>,
+    kind: RegionKind,
+}
+
+impl PS1ExeReader<'_> {
+    /// Splits the whole executable into assembly files: one for each function found by
+    /// [PS1ExeReader::analyze], one for each run of other code and one for each run of data.
+    ///
+    /// Labels are shared between the files, so the files must be assembled together
+    /// (see [mips::parse_labels]). Every byte of the executable is written by exactly one file,
+    /// so assembling the files onto a blank executable made out of [PS1ExeReader::split_header]
+    /// gives back the same executable.
+    pub fn split(&self, symbols: &SymbolMap, fold_pseudo_instructions: bool) -> Vec<SplitFile> {
+        let analysis = self.analyze(symbols);
+        let Range { start, end } = self.exe.get_memory_range();
+
+        // Functions right after each other are in the same region, so split regions at functions.
+        let mut segments = Vec::new();
+        for region in analysis.regions.iter() {
+            let mut segment_start = region.range.start;
+            if region.kind == RegionKind::Function {
+                for entry in analysis
+                    .functions
+                    .range(region.range.start + 1..region.range.end)
+                    .map(|(entry, _)| *entry)
+                {
+                    segments.push(Segment {
+                        range: segment_start..entry,
+                        kind: region.kind,
+                    });
+                    segment_start = entry;
+                }
+            }
+            segments.push(Segment {
+                range: segment_start..region.range.end,
+                kind: region.kind,
+            });
+        }
+        // Bytes after the last whole word are data.
+        let analyzed_end = segments.last().map_or(start, |segment| segment.range.end);
+        match segments.last_mut() {
+            Some(segment) if segment.kind == RegionKind::Data => segment.range.end = end,
+            _ if analyzed_end < end => segments.push(Segment {
+                range: analyzed_end..end,
+                kind: RegionKind::Data,
+            }),
+            _ => {}
+        }
+
+        let labels = self.get_split_labels(&analysis, &segments, symbols);
+        let mut names = HashSet::new();
+        let mut files = Vec::new();
+        for segment in segments.iter() {
+            let mut content = String::new();
+            let kind_name = match segment.kind {
+                RegionKind::Data => {
+                    self.write_data(&segment.range, &labels, &mut content);
+                    "data"
+                }
+                _ => {
+                    let instructions = self.decode_range(&segment.range);
+                    self.write_instructions(
+                        &instructions,
+                        &labels,
+                        fold_pseudo_instructions,
+                        &mut content,
+                    );
+                    "code"
+                }
+            };
+            // Files of functions and data are named by their label, if any.
+            let name = match labels.get(&segment.range.start) {
+                Some(label) if segment.kind != RegionKind::Code => label.clone(),
+                _ => format!("{}_{:08x}", kind_name, segment.range.start),
+            };
+            let name = match names.insert(name.clone()) {
+                true => format!("{}.s", name),
+                false => format!("{}_{:08x}.s", name, segment.range.start),
+            };
+            files.push(SplitFile { name, content });
+        }
+        files
+    }
+    /// Writes header fields of the executable as "name = value" lines, which
+    /// [PS1ExeBuilder::from_split_header] reads back.
+    pub fn split_header(&self) -> SplitFile {
+        let exe = self.exe;
+        let Range { start, end } = exe.get_memory_range();
+        let content = [
+            String::from("# Header of the Playstation executable, whose code and data are in the assembly code files."),
+            format!("destination_address_in_ram = 0x{:08X}", start),
+            format!("file_size = 0x{:X}", end - start),
+            format!("initial_pc = 0x{:08X}", exe.initial_pc),
+            format!("initial_gp_r28 = 0x{:08X}", exe.initial_gp_r28),
+            format!(
+                "data_section = 0x{:08X} 0x{:X}",
+                exe.data_section_address, exe.data_section_size
+            ),
+            format!("bss = 0x{:08X} 0x{:X}", exe.bss_address, exe.bss_size),
+            format!(
+                "stack = 0x{:08X} 0x{:X}",
+                exe.stack_base_address, exe.stack_offset
+            ),
+            format!("ascii_marker = {}", exe.ascii_marker),
+        ];
+        SplitFile {
+            name: String::from(SPLIT_HEADER_FILE_NAME),
+            content: content.join("\n") + "\n",
+        }
+    }
+    /// Gets labels for split files: symbols of the given symbol map, and generated labels
+    /// for functions ("FUN_" prefix) and branch and jump targets ("LAB_" prefix).
+    /// Only labels that can be written in the files are included: in the executable,
+    /// and aligned to instructions in code.
+    fn get_split_labels(
+        &self,
+        analysis: &crate::ExeAnalysis,
+        segments: &[Segment],
+        symbols: &SymbolMap,
+    ) -> HashMap<u64, String> {
+        let can_be_written = |address: u64| {
+            segments.iter().any(|segment| {
+                segment.range.contains(&address)
+                    && (segment.kind == RegionKind::Data || address % 4 == 0)
+            })
+        };
+        let mut labels = HashMap::new();
+        let mut names = HashSet::new();
+        let mut insert_label = |address: u64, name: String| {
+            if can_be_written(address) && !labels.contains_key(&address) && !names.contains(&name) {
+                names.insert(name.clone());
+                labels.insert(address, name);
+            }
+        };
+
+        for symbol in symbols.symbols() {
+            if is_label_name(&symbol.name) {
+                insert_label(symbol.address, symbol.name.clone());
+            }
+        }
+        for function in analysis.functions.values() {
+            insert_label(function.entry, format!("FUN_{:08x}", function.entry));
+        }
+        for segment in segments.iter() {
+            if segment.kind == RegionKind::Data {
+                continue;
+            }
+            let instructions = self.decode_range(&segment.range);
+            for (address, instruction) in instructions.iter() {
+                let Ok(instruction) = instruction else {
+                    continue;
+                };
+                let Some(target) = instruction.get_target_address(*address) else {
+                    continue;
+                };
+                let prefix = match instruction {
+                    mips::Instruction::J {
+                        opcode: 0b000011, ..
+                    } => "FUN", // Opcode is 3 (jal)
+                    _ => "LAB",
+                };
+                insert_label(target, format!("{}_{:08x}", prefix, target));
+            }
+        }
+        labels
+    }
+    /// Decodes instructions of a range found by the analysis, which is inside the executable.
+    fn decode_range(&self, range: &Range<u64>) -> Vec<DecodedInstruction> {
+        self.decode_instructions(range.start, (range.end - range.start) as usize / 4)
+            .unwrap()
+    }
+    /// Writes data starting with "@at". Words with a label as their value are written as the label,
+    /// like pointers to functions and jump tables.
+    fn write_data(&self, range: &Range<u64>, labels: &HashMap<u64, String>, output: &mut String) {
+        output.push_str(&format!("@at 0x{:x}\n", range.start));
+        // Ranges found by the analysis are inside the executable.
+        let bytes = self
+            .exe
+            .read_bytes(range.start, (range.end - range.start) as usize)
+            .unwrap();
+
+        let mut words = Vec::new();
+        let flush_words = |words: &mut Vec<String>, output: &mut String| {
+            if !words.is_empty() {
+                output.push_str(&format!(".word {}\n", words.join(", ")));
+                words.clear();
+            }
+        };
+        for (i, chunk) in bytes.chunks(4).enumerate() {
+            let address = range.start + i as u64 * 4;
+            if let Some(label) = labels.get(&address) {
+                flush_words(&mut words, output);
+                output.push_str(&format!("{}:\n", label));
+            }
+
+            // Labels inside of a word (or a partial last word) need the word written byte by byte.
+            let has_inner_labels = (1..4).any(|j| labels.contains_key(&(address + j)));
+            if chunk.len() < 4 || has_inner_labels {
+                flush_words(&mut words, output);
+                for (j, byte) in chunk.iter().enumerate() {
+                    if let Some(label) = labels.get(&(address + j as u64)).filter(|_| j > 0) {
+                        output.push_str(&format!("{}:\n", label));
+                    }
+                    output.push_str(&format!(".byte 0x{:02X}\n", byte));
+                }
+                continue;
+            }
+
+            let value = u32::from_le_bytes(chunk.try_into().unwrap());
+            words.push(match labels.get(&(value as u64)) {
+                Some(label) => label.clone(),
+                None => format!("0x{:08X}", value),
+            });
+            if words.len() == MAX_WORDS_PER_LINE {
+                flush_words(&mut words, output);
+            }
+        }
+        flush_words(&mut words, output);
+    }
+}
+
+impl PS1ExeBuilder {
+    /// Makes a builder of a blank executable, with code and data zeroed, out of header fields
+    /// written by [PS1ExeReader::split_header]. Split files are assembled onto it, so that
+    /// bytes missing from them are not silently kept from another executable.
+    pub fn from_split_header(content: &str) -> Result<Self, String> {
+        let mut builder = PS1ExeBuilder::new();
+        let mut destination_address_in_ram = None;
+        let mut file_size = None;
+        for (i, line) in content.lines().enumerate() {
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((name, value)) = line.split_once('=') else {
+                return Err(format!(
+                    "Line {} of header is not a \"name = value\" field: {}",
+                    i + 1,
+                    line
+                ));
+            };
+            let name = name.trim();
+            // ASCII marker is kept as it is, spaces included.
+            if name == "ascii_marker" {
+                builder = builder.ascii_marker(value.strip_prefix(' ').unwrap_or(value));
+                continue;
+            }
+            let values = value
+                .split_whitespace()
+                .map(parse_header_value)
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|err| format!("Line {} of header: {}", i + 1, err))?;
+            builder = match (name, values.as_slice()) {
+                ("destination_address_in_ram", &[address]) => {
+                    destination_address_in_ram = Some(address);
+                    builder.destination_address_in_ram(address)
+                }
+                ("file_size", &[size]) => {
+                    file_size = Some(size);
+                    builder
+                }
+                ("initial_pc", &[address]) => builder.initial_pc(address),
+                ("initial_gp_r28", &[value]) => builder.initial_gp_r28(value),
+                ("data_section", &[address, size]) => builder.data_section(address, size),
+                ("bss", &[address, size]) => builder.bss(address, size),
+                ("stack", &[base_address, offset]) => builder.stack(base_address, offset),
+                _ => {
+                    return Err(format!(
+                        "Line {} of header is an unknown field, or has a wrong count of values: {}",
+                        i + 1,
+                        line
+                    ))
+                }
+            };
+        }
+        let (Some(destination_address_in_ram), Some(file_size)) =
+            (destination_address_in_ram, file_size)
+        else {
+            return Err(String::from(
+                "Header has no destination_address_in_ram or file_size field.",
+            ));
+        };
+        // Executables bigger than RAM are rejected before making their code and data.
+        if file_size as u64 > MemoryRegion::MainRam.get_size() {
+            return Err(format!(
+                "File size 0x{:X} of header is bigger than RAM.",
+                file_size
+            ));
+        }
+        Ok(builder.segment(destination_address_in_ram, &vec![0; file_size as usize]))
+    }
+}
+
+/// Parses a value of a header field, which is a hexadecimal number like "0x80010000".
+fn parse_header_value(value: &str) -> Result<u32, String> {
+    value
+        .strip_prefix("0x")
+        .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+        .ok_or_else(|| {
+            format!(
+                "Failed to parse \"{}\" as a hexadecimal number like 0x80010000.",
+                value
+            )
+        })
+}
+
+/// Whether a symbol name can be used as a label in assembly code.
+fn is_label_name(name: &str) -> bool {
+    name.chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{PS1Exe, PS1ExeWriter, Symbol, SymbolKind, WriteSource};
+
+    #[test]
+    fn split_and_assemble_back() {
+        let content = "@at 0x80010000\n  lui a0, 0x8001\n  jal @0x80010038\n  addiu a0, a0, 0x44\n  jr ra\n  nop\n.word 0x012A4060\n  jr ra\n  nop\n@at 0x80010038\n  addiu v0, zero, 1\n  jr ra\n  nop\n@at 0x80010044\n.asciz \"Spyro\"\n.byte 7\n@at 0x80010130\n  jr ra\n  nop";
+        // Assembly code files are written the same way as by ps1exe-assemble, checking that
+        // each byte is written exactly once.
+        let assemble = |content: &str, exe: &mut PS1Exe| {
+            let source = WriteSource {
+                file_path: String::from("test.s"),
+                line: None,
+            };
+            let mut writer = PS1ExeWriter::new(exe);
+            for node in mips::parse_nodes(content).unwrap() {
+                let bytes = match node.kind {
+                    mips::NodeKind::Instruction(instruction) => instruction.to_le_bytes().to_vec(),
+                    mips::NodeKind::Data(bytes) => bytes,
+                    _ => continue,
+                };
+                writer.write_code(node.address, &bytes, &source).unwrap();
+            }
+            assert_eq!(writer.get_conflicts(), []);
+            writer.get_unwritten_ranges()
+        };
+        let mut exe = PS1ExeBuilder::new()
+            .segment(0x80010000, &[0; 0x800])
+            .initial_gp_r28(0x80018000)
+            .data_section(0x80010100, 0x30)
+            .bss(0x80011000, 0x100)
+            .stack(0x801FFF00, 0xF0)
+            .region(crate::ExeRegion::Pal)
+            .build()
+            .unwrap();
+        assert_ne!(assemble(content, &mut exe), []);
+
+        let mut symbols = SymbolMap::new();
+        for (address, name) in [(0x80010044, "name"), (0x80010045, "name_without_s")] {
+            symbols.insert(Symbol {
+                address,
+                name: String::from(name),
+                kind: SymbolKind::Data,
+                size: None,
+                type_name: None,
+                overlay: None,
+            });
+        }
+        let files = PS1ExeReader::new(&exe).split(&symbols, true);
+        let names = files
+            .iter()
+            .map(|file| file.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                "FUN_80010000.s",
+                "code_80010014.s",
+                "data_80010020.s",
+                "FUN_80010038.s",
+                "name.s",
+                "code_80010130.s",
+                "data_80010138.s"
+            ]
+        );
+        assert_eq!(
+            files[0].content,
+            "@at 0x80010000\nFUN_80010000:\nlui a0, %hi(name)\njal FUN_80010038\naddiu a0, a0, %lo(name)\njr ra\nnop\n"
+        );
+        assert!(files[1].content.contains(".word 0x012A4060 # "));
+        assert!(files[4].content.contains("\nname_without_s:\n.byte 0x70\n"));
+
+        // Assembling all files together onto a blank executable made out of the header
+        // writes every byte, and gives back the same executable.
+        let header = PS1ExeReader::new(&exe).split_header();
+        assert_eq!(header.name, SPLIT_HEADER_FILE_NAME);
+        assert!(header
+            .content
+            .contains("\nbss = 0x80011000 0x100\nstack = 0x801FFF00 0xF0\n"));
+        let mut assembled_exe = PS1ExeBuilder::from_split_header(&header.content)
+            .unwrap()
+            .build()
+            .unwrap();
+        let content = files
+            .iter()
+            .map(|file| file.content.as_str())
+            .collect::<Vec<_>>()
+            .join("\n");
+        assert_eq!(assemble(&content, &mut assembled_exe), []);
+        assert_eq!(assembled_exe.as_bytes(), exe.as_bytes());
+
+        // Files missing from the project leave blank bytes behind.
+        let mut assembled_exe = PS1ExeBuilder::from_split_header(&header.content)
+            .unwrap()
+            .build()
+            .unwrap();
+        let content = files
+            .iter()
+            .filter(|file| !file.name.starts_with("FUN_"))
+            .map(|file| file.content.as_str())
+            .collect::<Vec<_>>()
+            .join("\n");
+        assert_eq!(
+            assemble(&content, &mut assembled_exe),
+            [0x80010000..0x80010014, 0x80010038..0x80010044]
+        );
+
+        for (header, error) in [
+            ("file_size = 0x800\n", "no destination_address_in_ram"),
+            ("destination_address_in_ram = 80010000\n", "hexadecimal"),
+            ("bss = 0x80011000\n", "wrong count of values"),
+            ("entry = 0x80010000\n", "unknown field"),
+            ("file_size\n", "not a \"name = value\" field"),
+            (
+                "destination_address_in_ram = 0x80010000\nfile_size = 0xFFFFFFFF\n",
+                "bigger than RAM",
+            ),
+        ] {
+            let err = PS1ExeBuilder::from_split_header(header).err().unwrap();
+            assert!(err.contains(error), "{}", err);
+        }
+    }
+}
@@ -0,0 +1,568 @@
+use std::collections::HashMap;
+use std::fs;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SymbolKind {
+    Function,
+    Data,
+}
+
+/// A named address in memory, like a function or a global variable.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Symbol {
+    pub address: u64,
+    pub name: String,
+    pub kind: SymbolKind,
+    /// Size in bytes, if known.
+    pub size: Option<u32>,
+    /// Type of the symbol (like "void(int)" or "s16[8]"), if known.
+    pub type_name: Option<String>,
+    /// Name of the overlay the symbol is in, or `None` if the symbol is in the executable.
+    /// Overlays are loaded at the same addresses, so their symbols may share addresses.
+    pub overlay: Option<String>,
+}
+
+/// Formats of symbol map files.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SymbolMapFormat {
+    /// Format of this project, one symbol per line:
+    /// `<address> <function|data> <name> [<size>|- [<type>]]`
+    ///
+    /// Symbols of an overlay follow an `[<overlay name>]` line.
+    OpenSpyro,
+    /// symbol_addrs.txt of splat: `<name> = <address>; // type:func size:0x10`
+    Splat,
+    /// MAP file produced by IDA, where symbols are listed under "Publics by Value".
+    Ida,
+    /// SYM file of no$psx: `<address> <name>`, with data marked as `<address> .byt:<size>`.
+    NoCash,
+}
+pub const SYMBOL_MAP_FORMAT_NAMES: &[&str] = &["open-spyro", "splat", "ida", "nocash"];
+impl SymbolMapFormat {
+    /// Gets a symbol map format by its name (see [SYMBOL_MAP_FORMAT_NAMES]).
+    pub fn from_name(name: &str) -> Result<Self, String> {
+        match name {
+            "open-spyro" => Ok(Self::OpenSpyro),
+            "splat" => Ok(Self::Splat),
+            "ida" => Ok(Self::Ida),
+            "nocash" => Ok(Self::NoCash),
+            _ => Err(format!(
+                "Unknown symbol map format \"{}\". Supported symbol map formats include {}.",
+                name,
+                SYMBOL_MAP_FORMAT_NAMES.join(", ")
+            )),
+        }
+    }
+}
+
+/// Symbols by address, shared by the assembler and the disassembler.
+#[derive(Debug, Default, PartialEq)]
+pub struct SymbolMap {
+    /// Sorted by address.
+    symbols: Vec<Symbol>,
+}
+impl SymbolMap {
+    pub fn from_file_path(file_path: &str, format: SymbolMapFormat) -> Result<Self, String> {
+        let content = fs::read_to_string(file_path).map_err(|err| {
+            format!(
+                "Failed to read symbol map file in path \"{}\": {}",
+                file_path, err
+            )
+        })?;
+        Self::parse(&content, format).map_err(|err| {
+            format!(
+                "Failed to parse symbol map file in path \"{}\": {}",
+                file_path, err
+            )
+        })
+    }
+    pub fn get_by_address(&self, address: u64) -> Option<&Symbol> {
+        let index = self
+            .symbols
+            .partition_point(|symbol| symbol.address < address);
+        self.symbols.get(index).filter(|s| s.address == address)
+    }
+    pub fn get_by_name(&self, name: &str) -> Option<&Symbol> {
+        self.symbols.iter().find(|symbol| symbol.name == name)
+    }
+    /// Gets names of symbols by address, for writing labels in disassembly.
+    /// If an address has many symbols, the first one is used.
+    pub fn get_labels(&self) -> HashMap<u64, String> {
+        let mut labels = HashMap::new();
+        for symbol in self.symbols.iter() {
+            labels
+                .entry(symbol.address)
+                .or_insert_with(|| symbol.name.clone());
+        }
+        labels
+    }
+    /// Gets addresses of symbols by name, for resolving labels in assembly.
+    pub fn get_addresses(&self) -> HashMap<String, u64> {
+        self.symbols
+            .iter()
+            .map(|symbol| (symbol.name.clone(), symbol.address))
+            .collect()
+    }
+    /// Gets the symbols seen by the executable (with `None`) or by an overlay: symbols of the
+    /// executable, and symbols of the overlay which come first at the addresses they share.
+    pub fn for_overlay(&self, overlay: Option<&str>) -> Self {
+        let mut symbols = self
+            .symbols
+            .iter()
+            .filter(|symbol| symbol.overlay.is_none() || symbol.overlay.as_deref() == overlay)
+            .cloned()
+            .collect::<Vec<_>>();
+        symbols.sort_by_key(|symbol| (symbol.address, symbol.overlay.is_none()));
+        Self { symbols }
+    }
+    /// Gets names of the overlays which have symbols, in alphabetical order.
+    pub fn get_overlay_names(&self) -> Vec<&str> {
+        let mut names = self
+            .symbols
+            .iter()
+            .filter_map(|symbol| symbol.overlay.as_deref())
+            .collect::<Vec<_>>();
+        names.sort();
+        names.dedup();
+        names
+    }
+    /// Adds a symbol. A symbol with the same name in the same overlay replaces the earlier one.
+    pub fn insert(&mut self, symbol: Symbol) {
+        self.symbols
+            .retain(|s| s.name != symbol.name || s.overlay != symbol.overlay);
+        let index = self
+            .symbols
+            .partition_point(|s| s.address <= symbol.address);
+        self.symbols.insert(index, symbol);
+    }
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn parse(content: &str, format: SymbolMapFormat) -> Result<Self, String> {
+        let mut symbol_map = Self::new();
+        match format {
+            SymbolMapFormat::OpenSpyro => {
+                let mut overlay = None;
+                for (i, line) in content.lines().enumerate() {
+                    let line = line.split('#').next().unwrap().trim();
+                    if line.is_empty() {
+                        continue;
+                    }
+                    if let Some(name) = line.strip_prefix('[') {
+                        let name = name
+                            .strip_suffix(']')
+                            .map(str::trim)
+                            .filter(|name| !name.is_empty())
+                            .ok_or_else(|| {
+                                format!(
+                                    "Line {}: Expected \"[<overlay name>]\", found \"{}\".",
+                                    i + 1,
+                                    line
+                                )
+                            })?;
+                        overlay = Some(String::from(name));
+                        continue;
+                    }
+                    let mut symbol = parse_open_spyro_symbol(line)
+                        .map_err(|err| format!("Line {}: {}", i + 1, err))?;
+                    symbol.overlay = overlay.clone();
+                    symbol_map.insert(symbol);
+                }
+            }
+            SymbolMapFormat::Splat => {
+                for (i, line) in content.lines().enumerate() {
+                    let (line, attributes) = match line.split_once("//") {
+                        Some((line, attributes)) => (line.trim(), attributes),
+                        None => (line.trim(), ""),
+                    };
+                    if line.is_empty() {
+                        continue;
+                    }
+                    symbol_map.insert(
+                        parse_splat_symbol(line, attributes)
+                            .map_err(|err| format!("Line {}: {}", i + 1, err))?,
+                    );
+                }
+            }
+            SymbolMapFormat::Ida => {
+                // Symbols are listed as "<segment>:<address> <name>" after the "Publics by Value" title.
+                let publics = content
+                    .split_once("Publics by Value")
+                    .map(|(_, publics)| publics)
+                    .ok_or("No \"Publics by Value\" section found in IDA MAP file.")?;
+                for line in publics.lines() {
+                    let parts = line.split_whitespace().collect::<Vec<_>>();
+                    let [segment_and_address, name] = parts[..] else {
+                        continue;
+                    };
+                    let Some((_, address)) = segment_and_address.split_once(':') else {
+                        continue;
+                    };
+                    symbol_map.insert(Symbol {
+                        address: parse_hex_address(address)?,
+                        name: String::from(name),
+                        kind: SymbolKind::Function,
+                        size: None,
+                        type_name: None,
+                        overlay: None,
+                    });
+                }
+            }
+            SymbolMapFormat::NoCash => {
+                // Data markers (like ".byt:0010") are lines of their own,
+                // so they are applied after all symbols have been read.
+                let mut data_sizes = Vec::new();
+                for (i, line) in content.lines().enumerate() {
+                    let line = line.split(';').next().unwrap().trim();
+                    if line.is_empty() {
+                        continue;
+                    }
+                    let Some((address, name)) = line.split_once(char::is_whitespace) else {
+                        return Err(format!(
+                            "Line {}: Expected \"<address> <name>\", found \"{}\".",
+                            i + 1,
+                            line
+                        ));
+                    };
+                    let address = parse_hex_address(address)?;
+                    let name = name.trim();
+                    if let Some(size) = name
+                        .strip_prefix('.')
+                        .and_then(|marker| marker.split_once(':'))
+                        .map(|(_, size)| size)
+                    {
+                        let size = u32::from_str_radix(size, 16).map_err(|_| {
+                            format!("Line {}: Invalid data size \"{}\".", i + 1, size)
+                        })?;
+                        data_sizes.push((address, size));
+                        continue;
+                    }
+                    symbol_map.insert(Symbol {
+                        address,
+                        name: String::from(name),
+                        kind: SymbolKind::Function,
+                        size: None,
+                        type_name: None,
+                        overlay: None,
+                    });
+                }
+                for (address, size) in data_sizes {
+                    for symbol in symbol_map.symbols.iter_mut() {
+                        if symbol.address == address {
+                            symbol.kind = SymbolKind::Data;
+                            symbol.size = Some(size);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(symbol_map)
+    }
+    pub fn symbols(&self) -> &[Symbol] {
+        &self.symbols
+    }
+    /// Writes the symbol map in a given format. Only the open-spyro format has overlays,
+    /// so other formats get symbols of all overlays mixed with symbols of the executable.
+    pub fn write(&self, format: SymbolMapFormat) -> String {
+        let mut lines = Vec::new();
+        match format {
+            SymbolMapFormat::OpenSpyro => {
+                lines.push(String::from(
+                    "# <address> <function|data> <name> [<size>|- [<type>]]",
+                ));
+                // Symbols of the executable come first, as they are not in any section.
+                let mut symbols = self
+                    .symbols
+                    .iter()
+                    .filter(|symbol| symbol.overlay.is_none())
+                    .collect::<Vec<_>>();
+                for name in self.get_overlay_names() {
+                    symbols.extend(
+                        self.symbols
+                            .iter()
+                            .filter(|symbol| symbol.overlay.as_deref() == Some(name)),
+                    );
+                }
+                let mut overlay = None;
+                for symbol in symbols {
+                    if symbol.overlay != overlay {
+                        overlay = symbol.overlay.clone();
+                        lines.push(format!("[{}]", overlay.as_deref().unwrap()));
+                    }
+                    let kind = match symbol.kind {
+                        SymbolKind::Function => "function",
+                        SymbolKind::Data => "data",
+                    };
+                    let mut line = format!("0x{:08X} {} {}", symbol.address, kind, symbol.name);
+                    match (symbol.size, &symbol.type_name) {
+                        (Some(size), None) => line.push_str(&format!(" 0x{:X}", size)),
+                        (Some(size), Some(type_name)) => {
+                            line.push_str(&format!(" 0x{:X} {}", size, type_name))
+                        }
+                        (None, Some(type_name)) => line.push_str(&format!(" - {}", type_name)),
+                        (None, None) => {}
+                    }
+                    lines.push(line);
+                }
+            }
+            SymbolMapFormat::Splat => {
+                for symbol in self.symbols.iter() {
+                    let mut attributes = Vec::new();
+                    match (symbol.kind, &symbol.type_name) {
+                        (SymbolKind::Function, _) => attributes.push(String::from("type:func")),
+                        // Types of splat are single words, like "s32" or "asciz".
+                        (SymbolKind::Data, Some(type_name)) if !type_name.contains(' ') => {
+                            attributes.push(format!("type:{}", type_name))
+                        }
+                        _ => {}
+                    }
+                    if let Some(size) = symbol.size {
+                        attributes.push(format!("size:0x{:X}", size));
+                    }
+                    let mut line = format!("{} = 0x{:08X};", symbol.name, symbol.address);
+                    if !attributes.is_empty() {
+                        line.push_str(&format!(" // {}", attributes.join(" ")));
+                    }
+                    lines.push(line);
+                }
+            }
+            SymbolMapFormat::Ida => {
+                lines.push(String::new());
+                lines.push(String::from("  Address         Publics by Value"));
+                lines.push(String::new());
+                for symbol in self.symbols.iter() {
+                    lines.push(format!(
+                        " 0001:{:08X}       {}",
+                        symbol.address, symbol.name
+                    ));
+                }
+                lines.push(String::new());
+            }
+            SymbolMapFormat::NoCash => {
+                for symbol in self.symbols.iter() {
+                    lines.push(format!("{:08X} {}", symbol.address, symbol.name));
+                    if let (SymbolKind::Data, Some(size)) = (symbol.kind, symbol.size) {
+                        lines.push(format!("{:08X} .byt:{:04X}", symbol.address, size));
+                    }
+                }
+            }
+        }
+        let mut content = lines.join("\n");
+        content.push('\n');
+        content
+    }
+}
+
+/// Parses an address written in hexadecimal, with or without "0x" prefix.
+fn parse_hex_address(value: &str) -> Result<u64, String> {
+    let digits = value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+        .unwrap_or(value);
+    u64::from_str_radix(digits, 16).map_err(|_| {
+        format!(
+            "Failed to parse address \"{}\" as a hexadecimal number.",
+            value
+        )
+    })
+}
+fn parse_open_spyro_symbol(line: &str) -> Result<Symbol, String> {
+    // Type is the rest of the line, as it may contain whitespace.
+    let mut rest = line;
+    let mut get_next_part = || {
+        let (part, next_rest) = rest
+            .trim_start()
+            .split_once(char::is_whitespace)
+            .unwrap_or((rest.trim_start(), ""));
+        rest = next_rest;
+        Some(part).filter(|part| !part.is_empty())
+    };
+    let (Some(address), Some(kind), Some(name)) =
+        (get_next_part(), get_next_part(), get_next_part())
+    else {
+        return Err(format!(
+            "Expected \"<address> <function|data> <name>\", found \"{}\".",
+            line
+        ));
+    };
+    let size = get_next_part();
+    let kind = match kind {
+        "function" => SymbolKind::Function,
+        "data" => SymbolKind::Data,
+        _ => {
+            return Err(format!(
+                "Unknown symbol kind \"{}\". Symbol kind is either \"function\" or \"data\".",
+                kind
+            ))
+        }
+    };
+    let size = match size {
+        None | Some("-") => None,
+        Some(size) => Some(parse_hex_address(size)? as u32),
+    };
+    let type_name = Some(rest.trim())
+        .filter(|type_name| !type_name.is_empty())
+        .map(String::from);
+    Ok(Symbol {
+        address: parse_hex_address(address)?,
+        name: String::from(name),
+        kind,
+        size,
+        type_name,
+        overlay: None,
+    })
+}
+fn parse_splat_symbol(line: &str, attributes: &str) -> Result<Symbol, String> {
+    let (name, address) = line
+        .strip_suffix(';')
+        .and_then(|line| line.split_once('='))
+        .ok_or_else(|| format!("Expected \"<name> = <address>;\", found \"{}\".", line))?;
+
+    let mut symbol = Symbol {
+        address: parse_hex_address(address.trim())?,
+        name: String::from(name.trim()),
+        kind: SymbolKind::Data,
+        size: None,
+        type_name: None,
+        overlay: None,
+    };
+    for attribute in attributes.split_whitespace() {
+        match attribute.split_once(':') {
+            Some(("type", "func")) => symbol.kind = SymbolKind::Function,
+            Some(("type", type_name)) => symbol.type_name = Some(String::from(type_name)),
+            Some(("size", size)) => symbol.size = Some(parse_hex_address(size)? as u32),
+            // Other attributes (like "rom") do not matter here.
+            _ => {}
+        }
+    }
+    Ok(symbol)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_symbol_map() -> SymbolMap {
+        let mut symbol_map = SymbolMap::new();
+        symbol_map.insert(Symbol {
+            address: 0x80075000,
+            name: String::from("level_names"),
+            kind: SymbolKind::Data,
+            size: Some(0x40),
+            type_name: Some(String::from("char *[16]")),
+            overlay: None,
+        });
+        symbol_map.insert(Symbol {
+            address: 0x80012345,
+            name: String::from("UpdateSpyroState"),
+            kind: SymbolKind::Function,
+            size: None,
+            type_name: None,
+            overlay: None,
+        });
+        symbol_map
+    }
+
+    #[test]
+    fn write_and_parse_open_spyro_symbol_map() {
+        let symbol_map = get_symbol_map();
+        let content = symbol_map.write(SymbolMapFormat::OpenSpyro);
+        assert_eq!(
+            content,
+            "# <address> <function|data> <name> [<size>|- [<type>]]\n0x80012345 function UpdateSpyroState\n0x80075000 data level_names 0x40 char *[16]\n"
+        );
+        assert_eq!(
+            SymbolMap::parse(&content, SymbolMapFormat::OpenSpyro).unwrap(),
+            symbol_map
+        );
+        assert_eq!(
+            symbol_map.get_by_address(0x80012345).unwrap().name,
+            "UpdateSpyroState"
+        );
+        assert_eq!(symbol_map.get_addresses()["level_names"], 0x80075000);
+    }
+    #[test]
+    fn scope_symbols_by_overlay() {
+        let content = "0x80012345 function UpdateSpyroState\n0x80073000 data overlay_free\n[artisans]\n0x80073000 function UpdateGnorc\n0x80073100 function UpdateSheep\n[peace_keepers]\n0x80073000 function UpdateGnorc\n";
+        let symbol_map = SymbolMap::parse(content, SymbolMapFormat::OpenSpyro).unwrap();
+        assert_eq!(symbol_map.symbols().len(), 5);
+        assert_eq!(
+            symbol_map.get_overlay_names(),
+            ["artisans", "peace_keepers"]
+        );
+        assert_eq!(
+            symbol_map.write(SymbolMapFormat::OpenSpyro),
+            format!(
+                "# <address> <function|data> <name> [<size>|- [<type>]]\n{}",
+                content
+            )
+        );
+
+        // The same address has a different name in each overlay.
+        let artisans = symbol_map.for_overlay(Some("artisans"));
+        assert_eq!(
+            artisans.get_by_address(0x80073000).unwrap().name,
+            "UpdateGnorc"
+        );
+        assert_eq!(artisans.get_labels()[&0x80073000], "UpdateGnorc");
+        assert!(artisans.get_by_name("UpdateSheep").is_some());
+        assert!(artisans.get_by_name("UpdateSpyroState").is_some());
+        let peace_keepers = symbol_map.for_overlay(Some("peace_keepers"));
+        assert!(peace_keepers.get_by_name("UpdateSheep").is_none());
+        let exe = symbol_map.for_overlay(None);
+        assert_eq!(exe.symbols().len(), 2);
+        assert_eq!(exe.get_labels()[&0x80073000], "overlay_free");
+
+        assert!(SymbolMap::parse("[]\n", SymbolMapFormat::OpenSpyro)
+            .unwrap_err()
+            .contains("Line 1"));
+    }
+    #[test]
+    fn parse_splat_symbol_map() {
+        let content = "// Functions\nUpdateSpyroState = 0x80012345; // type:func size:0x120\nlevel_count = 0x80075100; // type:s32 rom:0x1234\n";
+        let symbol_map = SymbolMap::parse(content, SymbolMapFormat::Splat).unwrap();
+        assert_eq!(
+            symbol_map.symbols(),
+            &[
+                Symbol {
+                    address: 0x80012345,
+                    name: String::from("UpdateSpyroState"),
+                    kind: SymbolKind::Function,
+                    size: Some(0x120),
+                    type_name: None,
+                    overlay: None,
+                },
+                Symbol {
+                    address: 0x80075100,
+                    name: String::from("level_count"),
+                    kind: SymbolKind::Data,
+                    size: None,
+                    type_name: Some(String::from("s32")),
+                    overlay: None,
+                },
+            ]
+        );
+    }
+    #[test]
+    fn write_and_parse_nocash_symbol_map() {
+        let symbol_map = get_symbol_map();
+        let content = symbol_map.write(SymbolMapFormat::NoCash);
+        assert_eq!(
+            content,
+            "80012345 UpdateSpyroState\n80075000 level_names\n80075000 .byt:0040\n"
+        );
+        let parsed = SymbolMap::parse(&content, SymbolMapFormat::NoCash).unwrap();
+        assert_eq!(parsed.symbols()[1].kind, SymbolKind::Data);
+        assert_eq!(parsed.symbols()[1].size, Some(0x40));
+    }
+    #[test]
+    fn write_and_parse_ida_symbol_map() {
+        let symbol_map = get_symbol_map();
+        let content = symbol_map.write(SymbolMapFormat::Ida);
+        let parsed = SymbolMap::parse(&content, SymbolMapFormat::Ida).unwrap();
+        assert_eq!(parsed.get_labels(), symbol_map.get_labels());
+    }
+}
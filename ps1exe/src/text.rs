@@ -0,0 +1,428 @@
+use std::ops::Range;
+
+use crate::codec::TextCodec;
+
+/// A null-terminated string found in game data.
+#[derive(Debug, PartialEq)]
+pub struct TextEntry {
+    /// Address of the first byte of the string.
+    pub address: u64,
+    pub text: String,
+    /// How many bytes the string can take at its original location,
+    /// including the null termination byte and alignment padding after it.
+    pub capacity: usize,
+    /// Addresses of pointers (32-bit values) pointing to the string.
+    /// Strings are often referred to from pointer tables, like a table of level names.
+    pub pointer_addresses: Vec<u64>,
+}
+
+/// A string write resulting from inserting translated text.
+#[derive(Debug, PartialEq)]
+pub struct TextWrite {
+    pub address: u64,
+    pub bytes: Vec<u8>,
+}
+
+/// A translation of a single string read from a PO file.
+#[derive(Debug, PartialEq)]
+pub struct Translation {
+    pub address: u64,
+    pub source: String,
+    pub translation: String,
+}
+
+/// Strings shorter than this are not considered text.
+/// Short byte sequences that happen to be printable are common in code and data.
+const MIN_TEXT_LEN: usize = 3;
+/// Strings are aligned to 4 bytes in game data.
+const TEXT_ALIGNMENT: usize = 4;
+
+/// Finds all null-terminated strings from the given data.
+///
+/// Only strings beginning at an aligned address and consisting of characters
+/// the given codec can decode are found. Pointers to the strings are
+/// looked up from the same data.
+pub fn find_text_entries(data: &[u8], base_address: u64, codec: &dyn TextCodec) -> Vec<TextEntry> {
+    let mut entries = Vec::new();
+
+    let mut offset = 0;
+    while offset < data.len() {
+        let (text_len, char_count) = codec.get_text_len(&data[offset..]);
+
+        // A string must end in a null termination byte.
+        let end = offset + text_len;
+        if char_count < MIN_TEXT_LEN || data.get(end) != Some(&0) {
+            offset += TEXT_ALIGNMENT;
+            continue;
+        }
+
+        // Padding after the null termination byte can be used by a longer string as well.
+        let mut capacity = text_len + 1;
+        while (offset + capacity) % TEXT_ALIGNMENT != 0 && data.get(offset + capacity) == Some(&0) {
+            capacity += 1;
+        }
+
+        entries.push(TextEntry {
+            address: base_address + offset as u64,
+            text: codec.decode(&data[offset..end]),
+            capacity,
+            pointer_addresses: Vec::new(),
+        });
+
+        offset += capacity.next_multiple_of(TEXT_ALIGNMENT);
+    }
+
+    // Find pointers to the found strings.
+    for (i, word) in data.chunks_exact(4).enumerate() {
+        let value = u32::from_le_bytes(word.try_into().unwrap()) as u64;
+        if let Ok(entry_index) = entries.binary_search_by_key(&value, |e| e.address) {
+            entries[entry_index]
+                .pointer_addresses
+                .push(base_address + (i * 4) as u64);
+        }
+    }
+
+    entries
+}
+
+/// Plans writes for inserting translated strings back into game data.
+///
+/// A translation fitting into the capacity of the original string is written in place.
+/// A longer translation is relocated into the first given free region with space left
+/// and all pointers to the original string are changed to point to the relocated string.
+pub fn plan_text_writes(
+    entries: &[TextEntry],
+    translations: &[Translation],
+    free_regions: &[Range<u64>],
+    codec: &dyn TextCodec,
+) -> Result<Vec<TextWrite>, String> {
+    let mut writes = Vec::new();
+    let mut free_regions = free_regions.to_vec();
+
+    for translation in translations.iter() {
+        if translation.translation.is_empty() {
+            continue;
+        }
+
+        let entry = entries
+            .iter()
+            .find(|e| e.address == translation.address)
+            .ok_or_else(|| {
+                format!(
+                    "No string found at address 0x{:x} for translation of \"{}\".",
+                    translation.address, translation.source
+                )
+            })?;
+
+        if entry.text != translation.source {
+            return Err(format!(
+                "String at address 0x{:x} is \"{}\", but translation is for \"{}\".",
+                entry.address, entry.text, translation.source
+            ));
+        }
+
+        let mut bytes = codec.encode(&translation.translation)?;
+        bytes.push(0);
+
+        if bytes.len() <= entry.capacity {
+            bytes.resize(entry.capacity, 0);
+            writes.push(TextWrite {
+                address: entry.address,
+                bytes,
+            });
+            continue;
+        }
+
+        // Translation does not fit, so it needs to be relocated.
+        if entry.pointer_addresses.is_empty() {
+            return Err(format!(
+                "Translation \"{}\" for string at address 0x{:x} is {} bytes long, but only {} bytes are available and no pointers to the string were found for relocating it.",
+                translation.translation, entry.address, bytes.len(), entry.capacity
+            ));
+        }
+        if free_regions.is_empty() {
+            return Err(format!(
+                "Translation \"{}\" for string at address 0x{:x} is {} bytes long, but only {} bytes are available. Give a free region for relocating longer strings.",
+                translation.translation, entry.address, bytes.len(), entry.capacity
+            ));
+        }
+
+        let relocated_len = bytes.len().next_multiple_of(TEXT_ALIGNMENT) as u64;
+        let Some(region) = free_regions
+            .iter_mut()
+            .find(|region| region.start + relocated_len <= region.end)
+        else {
+            return Err(format!(
+                "Free regions have no space left for relocating translation \"{}\" ({} bytes).",
+                translation.translation,
+                bytes.len()
+            ));
+        };
+        let relocated_address = region.start;
+        region.start += relocated_len;
+
+        bytes.resize(relocated_len as usize, 0);
+        writes.push(TextWrite {
+            address: relocated_address,
+            bytes,
+        });
+        for pointer_address in entry.pointer_addresses.iter() {
+            writes.push(TextWrite {
+                address: *pointer_address,
+                bytes: (relocated_address as u32).to_le_bytes().to_vec(),
+            });
+        }
+    }
+
+    Ok(writes)
+}
+
+/// Writes found strings in gettext PO format, which translation tools support.
+///
+/// Each string is identified by its address stored as message context.
+/// Strings are written as decoded by their codec. Escape sequences of codecs (like `\n`
+/// and `\"`) are the same as in PO files, so strings are not escaped again.
+pub fn write_po(entries: &[TextEntry], source_name: &str) -> String {
+    let mut lines = vec![
+        format!("# Strings extracted from \"{}\".", source_name),
+        String::from("msgid \"\""),
+        String::from("msgstr \"\""),
+        String::from("\"Content-Type: text/plain; charset=UTF-8\\n\""),
+    ];
+
+    for entry in entries.iter() {
+        lines.push(String::new());
+        lines.push(format!("#. Capacity: {} bytes", entry.capacity));
+        if !entry.pointer_addresses.is_empty() {
+            let pointers = entry
+                .pointer_addresses
+                .iter()
+                .map(|a| format!("0x{:x}", a))
+                .collect::<Vec<_>>()
+                .join(", ");
+            lines.push(format!("#. Pointers: {}", pointers));
+        }
+        lines.push(format!("msgctxt \"0x{:x}\"", entry.address));
+        lines.push(format!("msgid \"{}\"", entry.text));
+        lines.push(String::from("msgstr \"\""));
+    }
+
+    lines.join("\n") + "\n"
+}
+/// Parses translations from a PO file written by [write_po].
+/// Escape sequences are kept as they are for encoding strings with a codec.
+pub fn parse_po(content: &str) -> Result<Vec<Translation>, String> {
+    enum Field {
+        Context,
+        Id,
+        Str,
+    }
+
+    let mut translations = Vec::new();
+
+    let mut context = String::new();
+    let mut id = String::new();
+    let mut str = String::new();
+    let mut current_field: Option<Field> = None;
+
+    let mut finish_entry =
+        |context: &mut String, id: &mut String, str: &mut String| -> Result<(), String> {
+            // Header entry has no context.
+            if !context.is_empty() {
+                let address = context
+                    .strip_prefix("0x")
+                    .and_then(|a| u64::from_str_radix(a, 16).ok())
+                    .ok_or_else(|| format!("Invalid address \"{}\" in msgctxt.", context))?;
+                translations.push(Translation {
+                    address,
+                    source: id.clone(),
+                    translation: str.clone(),
+                });
+            }
+            context.clear();
+            id.clear();
+            str.clear();
+            Ok(())
+        };
+
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        let line_number = i + 1;
+
+        let (field, value) = if let Some(value) = line.strip_prefix("msgctxt ") {
+            // New entry begins from message context.
+            finish_entry(&mut context, &mut id, &mut str)?;
+            (Field::Context, value)
+        } else if let Some(value) = line.strip_prefix("msgid ") {
+            if matches!(current_field, Some(Field::Str)) {
+                finish_entry(&mut context, &mut id, &mut str)?;
+            }
+            (Field::Id, value)
+        } else if let Some(value) = line.strip_prefix("msgstr ") {
+            (Field::Str, value)
+        } else if line.starts_with('"') {
+            // Continuation of the previous field.
+            let Some(field) = current_field.take() else {
+                return Err(format!(
+                    "Line {}: string continuation without a field.",
+                    line_number
+                ));
+            };
+            (field, line)
+        } else if line.is_empty() || line.starts_with('#') {
+            continue;
+        } else {
+            return Err(format!(
+                "Line {}: unknown content \"{}\".",
+                line_number, line
+            ));
+        };
+
+        let value = value
+            .strip_prefix('"')
+            .and_then(|v| v.strip_suffix('"'))
+            .ok_or_else(|| {
+                format!(
+                    "Line {}: String {} is not within double quotes.",
+                    line_number, value
+                )
+            })?;
+        match field {
+            Field::Context => context.push_str(value),
+            Field::Id => id.push_str(value),
+            Field::Str => str.push_str(value),
+        }
+        current_field = Some(field);
+    }
+    finish_entry(&mut context, &mut id, &mut str)?;
+
+    Ok(translations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::{AsciiCodec, SpyroCodec};
+
+    /// Data with two strings and a pointer table pointing to them.
+    fn get_test_data() -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(b"HOME\0\0\0\0"); // 0x80010000
+        data.extend_from_slice(b"STONE HILL\0\0"); // 0x80010008
+        data.extend_from_slice(&0x80010000u32.to_le_bytes()); // 0x80010014
+        data.extend_from_slice(&0x80010008u32.to_le_bytes()); // 0x80010018
+        data
+    }
+
+    #[test]
+    fn find_strings_and_pointers() {
+        let entries = find_text_entries(&get_test_data(), 0x80010000, &AsciiCodec);
+        assert_eq!(
+            entries,
+            vec![
+                TextEntry {
+                    address: 0x80010000,
+                    text: String::from("HOME"),
+                    capacity: 8,
+                    pointer_addresses: vec![0x80010014],
+                },
+                TextEntry {
+                    address: 0x80010008,
+                    text: String::from("STONE HILL"),
+                    capacity: 12,
+                    pointer_addresses: vec![0x80010018],
+                },
+            ]
+        );
+    }
+    #[test]
+    fn plan_writes_for_string_fitting_in_place() {
+        let entries = find_text_entries(&get_test_data(), 0x80010000, &AsciiCodec);
+        let translations = [Translation {
+            address: 0x80010000,
+            source: String::from("HOME"),
+            translation: String::from("KOTI"),
+        }];
+        let writes = plan_text_writes(&entries, &translations, &[], &AsciiCodec).unwrap();
+        assert_eq!(
+            writes,
+            vec![TextWrite {
+                address: 0x80010000,
+                bytes: b"KOTI\0\0\0\0".to_vec(),
+            }]
+        );
+    }
+    #[test]
+    fn plan_writes_for_relocated_string() {
+        let entries = find_text_entries(&get_test_data(), 0x80010000, &AsciiCodec);
+        let translations = [Translation {
+            address: 0x80010008,
+            source: String::from("STONE HILL"),
+            translation: String::from("KIVIKUKKULAT"),
+        }];
+
+        // No free region given for relocating
+        assert!(plan_text_writes(&entries, &translations, &[], &AsciiCodec).is_err());
+
+        // The first free region is too small for the string
+        let free_regions = [0x80020000..0x80020008, 0x80020100..0x80020200];
+        let writes = plan_text_writes(&entries, &translations, &free_regions, &AsciiCodec).unwrap();
+        assert_eq!(
+            writes,
+            vec![
+                TextWrite {
+                    address: 0x80020100,
+                    bytes: b"KIVIKUKKULAT\0\0\0\0".to_vec(),
+                },
+                TextWrite {
+                    address: 0x80010018,
+                    bytes: 0x80020100u32.to_le_bytes().to_vec(),
+                },
+            ]
+        );
+    }
+    #[test]
+    fn write_and_parse_po() {
+        let entries = find_text_entries(&get_test_data(), 0x80010000, &AsciiCodec);
+        let po = write_po(&entries, "SCUS_942.28");
+        let po = po.replace(
+            "msgid \"HOME\"\nmsgstr \"\"",
+            "msgid \"HOME\"\nmsgstr \"\"\n\"KO\\\"TI\"",
+        );
+
+        let translations = parse_po(&po).unwrap();
+        assert_eq!(
+            translations,
+            vec![
+                Translation {
+                    address: 0x80010000,
+                    source: String::from("HOME"),
+                    translation: String::from("KO\\\"TI"),
+                },
+                Translation {
+                    address: 0x80010008,
+                    source: String::from("STONE HILL"),
+                    translation: String::new(),
+                },
+            ]
+        );
+    }
+    #[test]
+    fn write_and_parse_po_with_escape_sequences() {
+        let entries = find_text_entries(b"\x04SAY \"HI\"\nNOW\0\0\0\0", 0x80010000, &SpyroCodec);
+        let po = write_po(&entries, "SCUS_942.28");
+        // Escape sequences of the codec are not escaped again.
+        assert!(
+            po.contains("msgid \"\\c{red}SAY \\\"HI\\\"\\nNOW\""),
+            "{}",
+            po
+        );
+
+        let translations = parse_po(&po).unwrap();
+        assert_eq!(translations[0].source, entries[0].text);
+        assert_eq!(
+            SpyroCodec.encode(&translations[0].source).unwrap(),
+            b"\x04SAY \"HI\"\nNOW"
+        );
+    }
+}
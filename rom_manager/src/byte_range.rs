@@ -49,7 +49,7 @@ impl ByteRange {
         let slice = buf[self.begin..self.end]
             .iter()
             .filter(|b| **b != SPECIAL_EMPTY_CHAR)
-            .map(|b| *b)
+            .copied()
             .collect::<Vec<_>>();
         str::from_utf8(&slice[..]).unwrap().to_string()
     }
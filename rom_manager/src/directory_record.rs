@@ -103,9 +103,9 @@ impl Unserialize<DirectoryRecordError> for DirectoryRecord {
 
         let mut file_identifier = [0u8; 255];
 
-        for i in 0..file_identifier_length as usize {
-            file_identifier[i] = data[33 + i];
-        }
+        let file_identifier_length_usize = file_identifier_length as usize;
+        file_identifier[..file_identifier_length_usize]
+            .copy_from_slice(&data[33..33 + file_identifier_length_usize]);
 
         Ok(Self {
             length,
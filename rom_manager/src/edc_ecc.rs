@@ -0,0 +1,247 @@
+//! Error detection (EDC) and correction (ECC) codes of raw CD sectors, which must be regenerated
+//! after changing data of sectors, as emulators and drives check them.
+
+use std::{
+    fs::File,
+    io::{BufReader, Read, Seek, SeekFrom, Write},
+};
+
+use crate::{CDROMXAVolume, Sector};
+
+/// Sync pattern at the beginning of each raw data sector.
+const SYNC: [u8; 12] = [
+    0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00,
+];
+const HEADER_OFFSET: usize = 0x0C;
+const MODE_OFFSET: usize = 0x0F;
+/// Submode byte of the first copy of the CD-ROM XA subheader, which tells the form of a sector.
+const SUBMODE_OFFSET: usize = 0x12;
+const SUBMODE_FORM_2: u8 = 0x20;
+const P_PARITY_OFFSET: usize = 0x81C;
+const Q_PARITY_OFFSET: usize = 0x8C8;
+
+/// Lookup tables of the codes.
+struct Tables {
+    /// Multiplication by 2 in GF(2^8) used by ECC.
+    ecc_f: [u8; 256],
+    /// Inverse of `x ^ ecc_f[x]`.
+    ecc_b: [u8; 256],
+    /// CRC32 with reversed polynomial 0xD8018001, used by EDC.
+    edc: [u32; 256],
+}
+impl Tables {
+    fn new() -> Self {
+        let mut tables = Self {
+            ecc_f: [0; 256],
+            ecc_b: [0; 256],
+            edc: [0; 256],
+        };
+        for i in 0..256 {
+            let j = ((i << 1) ^ if i & 0x80 != 0 { 0x11D } else { 0 }) as u8;
+            tables.ecc_f[i] = j;
+            tables.ecc_b[i ^ j as usize] = i as u8;
+            let mut edc = i as u32;
+            for _ in 0..8 {
+                edc = (edc >> 1) ^ if edc & 1 != 0 { 0xD8018001 } else { 0 };
+            }
+            tables.edc[i] = edc;
+        }
+        tables
+    }
+    fn compute_edc(&self, data: &[u8]) -> u32 {
+        data.iter().fold(0, |edc, byte| {
+            (edc >> 8) ^ self.edc[((edc ^ *byte as u32) & 0xFF) as usize]
+        })
+    }
+    /// Computes one of the Reed-Solomon product codes (P or Q) over the header and data,
+    /// which are read in vectors of `minor_count` bytes.
+    fn compute_ecc(
+        &self,
+        data: &[u8],
+        major_count: usize,
+        minor_count: usize,
+        major_mult: usize,
+        minor_inc: usize,
+        parity: &mut [u8],
+    ) {
+        let len = major_count * minor_count;
+        for major in 0..major_count {
+            let mut index = (major >> 1) * major_mult + (major & 1);
+            let mut ecc_a = 0u8;
+            let mut ecc_b = 0u8;
+            for _ in 0..minor_count {
+                let byte = data[index];
+                index += minor_inc;
+                if index >= len {
+                    index -= len;
+                }
+                ecc_a ^= byte;
+                ecc_b ^= byte;
+                ecc_a = self.ecc_f[ecc_a as usize];
+            }
+            ecc_a = self.ecc_b[(self.ecc_f[ecc_a as usize] ^ ecc_b) as usize];
+            parity[major] = ecc_a;
+            parity[major + major_count] = ecc_a ^ ecc_b;
+        }
+    }
+    fn write_ecc(&self, sector: &mut [u8]) {
+        let (data, parity) = sector.split_at_mut(P_PARITY_OFFSET);
+        self.compute_ecc(&data[HEADER_OFFSET..], 86, 24, 2, 86, &mut parity[..172]);
+        let (data, parity) = sector.split_at_mut(Q_PARITY_OFFSET);
+        self.compute_ecc(&data[HEADER_OFFSET..], 52, 43, 86, 88, &mut parity[..104]);
+    }
+}
+
+impl Sector {
+    /// Whether given bytes are a raw image of a CD (like a BIN file), whose sectors have
+    /// EDC and ECC, rather than a file extracted from it.
+    pub fn is_raw_image(data: &[u8]) -> bool {
+        !data.is_empty() && data.len() % Self::LOGICAL_SIZE as usize == 0 && data.starts_with(&SYNC)
+    }
+    /// Regenerates EDC and ECC of a raw sector of mode 1 or mode 2 (form 1 or form 2).
+    /// Returns false if the sector is not a data sector (like audio), which is left as is.
+    pub fn regenerate_edc_ecc(sector: &mut [u8]) -> bool {
+        if sector.len() != Self::LOGICAL_SIZE as usize || !sector.starts_with(&SYNC) {
+            return false;
+        }
+        let tables = Tables::new();
+        match sector[MODE_OFFSET] {
+            1 => {
+                let edc = tables.compute_edc(&sector[..0x810]);
+                sector[0x810..0x814].copy_from_slice(&edc.to_le_bytes());
+                sector[0x814..0x81C].fill(0);
+                tables.write_ecc(sector);
+            }
+            2 if sector[SUBMODE_OFFSET] & SUBMODE_FORM_2 != 0 => {
+                let edc = tables.compute_edc(&sector[0x10..0x92C]);
+                sector[0x92C..0x930].copy_from_slice(&edc.to_le_bytes());
+            }
+            2 => {
+                let edc = tables.compute_edc(&sector[0x10..0x818]);
+                sector[0x818..0x81C].copy_from_slice(&edc.to_le_bytes());
+                // Header is not covered by ECC in mode 2, as if it was zeros.
+                let header: [u8; 4] = sector[HEADER_OFFSET..0x10].try_into().unwrap();
+                sector[HEADER_OFFSET..0x10].fill(0);
+                tables.write_ecc(sector);
+                sector[HEADER_OFFSET..0x10].copy_from_slice(&header);
+            }
+            _ => return false,
+        }
+        true
+    }
+    /// Regenerates EDC and ECC of sectors of a raw image which differ from the original image,
+    /// so that unchanged sectors stay exactly as they were. Returns the count of regenerated sectors.
+    pub fn regenerate_changed_edc_ecc(original: &[u8], modified: &mut [u8]) -> usize {
+        let sector_size = Self::LOGICAL_SIZE as usize;
+        let mut count = 0;
+        for (i, sector) in modified.chunks_mut(sector_size).enumerate() {
+            if original.get(i * sector_size..(i + 1) * sector_size) != Some(&sector[..])
+                && Self::regenerate_edc_ecc(sector)
+            {
+                count += 1;
+            }
+        }
+        count
+    }
+}
+
+impl CDROMXAVolume {
+    /// Regenerates EDC and ECC of sectors of the volume which differ from an original image,
+    /// reading both sector by sector, as images are too large to compare in memory.
+    /// Returns the count of regenerated sectors.
+    pub fn regenerate_changed_edc_ecc(&mut self, original_file: &File) -> Result<usize, String> {
+        let sector_size = Sector::LOGICAL_SIZE as usize;
+        let mut original_reader = BufReader::new(original_file);
+        original_reader
+            .seek(SeekFrom::Start(0))
+            .map_err(|err| format!("Failed to seek to the beginning of original ROM: {}", err))?;
+        let mut original_sector = vec![0; sector_size];
+        let mut sector = vec![0; sector_size];
+        let mut count = 0;
+        for sector_index in 0.. {
+            let offset = sector_index * Sector::LOGICAL_SIZE;
+            self.file
+                .seek(SeekFrom::Start(offset))
+                .map_err(|err| format!("Failed to seek to offset {} in ROM: {}", offset, err))?;
+            if self.file.read_exact(&mut sector).is_err() {
+                break;
+            }
+            let is_changed = match original_reader.read_exact(&mut original_sector) {
+                Ok(()) => original_sector != sector,
+                Err(_) => true,
+            };
+            if is_changed && Sector::regenerate_edc_ecc(&mut sector) {
+                self.file
+                    .seek(SeekFrom::Start(offset))
+                    .and_then(|_| self.file.write_all(&sector))
+                    .map_err(|err| {
+                        format!(
+                            "Failed to write sector at offset {} in ROM: {}",
+                            offset, err
+                        )
+                    })?;
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_sector(mode: u8, submode: u8) -> Vec<u8> {
+        let mut sector = vec![0; Sector::LOGICAL_SIZE as usize];
+        sector[..12].copy_from_slice(&SYNC);
+        sector[HEADER_OFFSET..0x10].copy_from_slice(&[0x00, 0x02, 0x16, mode]);
+        sector[0x10..0x18].copy_from_slice(&[0, 0, submode, 0, 0, 0, submode, 0]);
+        for (i, byte) in sector[0x18..0x818].iter_mut().enumerate() {
+            *byte = (i * 31 + i / 256) as u8;
+        }
+        sector
+    }
+
+    #[test]
+    fn regenerate_edc_and_ecc() {
+        let mut sector = get_sector(2, 0x08);
+        assert!(Sector::regenerate_edc_ecc(&mut sector));
+        assert_eq!(sector[0x818..0x81C], [0x67, 0x35, 0x47, 0x83]);
+        assert_eq!(
+            sector[P_PARITY_OFFSET..P_PARITY_OFFSET + 4],
+            [0xCF, 0x69, 0x61, 0x32]
+        );
+        assert_eq!(
+            sector[Q_PARITY_OFFSET..Q_PARITY_OFFSET + 4],
+            [0xDF, 0x3D, 0x45, 0xED]
+        );
+        // Header is kept.
+        assert_eq!(sector[HEADER_OFFSET..0x10], [0x00, 0x02, 0x16, 2]);
+
+        // Only changed sectors are regenerated.
+        let sector_size = Sector::LOGICAL_SIZE as usize;
+        let original = [sector.clone(), sector.clone()].concat();
+        let mut modified = original.clone();
+        modified[sector_size + 0x100] ^= 0xFF;
+        assert!(Sector::is_raw_image(&modified));
+        assert_eq!(
+            Sector::regenerate_changed_edc_ecc(&original, &mut modified),
+            1
+        );
+        assert_eq!(modified[..sector_size], original[..sector_size]);
+        let (original_sector, modified_sector) =
+            (&original[sector_size..], &modified[sector_size..]);
+        assert_ne!(modified_sector[0x818..0x81C], original_sector[0x818..0x81C]);
+        assert_ne!(
+            modified_sector[P_PARITY_OFFSET..Q_PARITY_OFFSET],
+            original_sector[P_PARITY_OFFSET..Q_PARITY_OFFSET]
+        );
+        assert_ne!(
+            modified_sector[Q_PARITY_OFFSET..],
+            original_sector[Q_PARITY_OFFSET..]
+        );
+
+        let mut audio_sector = vec![0x55; Sector::LOGICAL_SIZE as usize];
+        assert!(!Sector::regenerate_edc_ecc(&mut audio_sector));
+    }
+}
@@ -3,7 +3,7 @@ use crate::byte_range::{ByteCount, ByteRange};
 pub(crate) struct BothEndianI16<'a>(&'a ByteRange);
 impl<'a> BothEndianI16<'a> {
     pub fn with_range(range: &'a ByteRange) -> Self {
-        Self(&range)
+        Self(range)
     }
     pub fn write_into(&self, data: &mut [u8], begin_in_data: usize, value_to_write: i16) {
         for (i, b) in value_to_write.to_le_bytes().iter().enumerate() {
@@ -18,7 +18,7 @@ impl<'a> BothEndianI16<'a> {
 pub(crate) struct BothEndianI32<'a>(pub &'a ByteRange);
 impl<'a> BothEndianI32<'a> {
     pub fn with_range(range: &'a ByteRange) -> Self {
-        Self(&range)
+        Self(range)
     }
     pub fn write_into(&self, data: &mut [u8], begin_in_data: usize, value_to_write: i32) {
         for (i, b) in value_to_write.to_le_bytes().iter().enumerate() {
@@ -46,7 +46,7 @@ impl<'a> DateAndTime<'a> {
         )
     }
     pub fn with_range(range: &'a ByteRange) -> Self {
-        Self(&range)
+        Self(range)
     }
     pub fn write_into(&self, data: &mut [u8], begin_in_data: usize, value: &[u8; 7]) {
         let begin = self.0.begin + begin_in_data;
@@ -60,7 +60,7 @@ pub(crate) struct LittleEndian<'a>(pub &'a ByteRange);
 impl<'a> LittleEndian<'a> {
     #[allow(dead_code)]
     pub fn with_range(range: &'a ByteRange) -> Self {
-        Self(&range)
+        Self(range)
     }
     pub fn write_into(&self, data: &mut [u8], begin_in_data: usize, value_to_write: i32) {
         for (i, b) in value_to_write.to_le_bytes().iter().enumerate() {
@@ -72,9 +72,9 @@ impl<'a> LittleEndian<'a> {
 pub(crate) struct StringField<'a>(pub &'a ByteRange);
 impl<'a> StringField<'a> {
     pub fn with_range(range: &'a ByteRange) -> Self {
-        Self(&range)
+        Self(range)
     }
-    pub fn write_into(&self, data: &mut [u8], begin_in_data: usize, value_to_write: &String) {
+    pub fn write_into(&self, data: &mut [u8], begin_in_data: usize, value_to_write: &str) {
         const IDENTIFIER_ZEROFILL_CHARACTER: u8 = 32;
 
         let len = value_to_write.len();
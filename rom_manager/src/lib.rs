@@ -1,6 +1,7 @@
 use std::{
     fs::File,
     io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    ops::Range,
 };
 
 use primary_volume_descriptor::PrimaryVolumeDescriptor;
@@ -9,6 +10,7 @@ use strum::{EnumIter, FromRepr};
 
 mod byte_range;
 mod directory_record;
+mod edc_ecc;
 mod fields;
 mod primary_volume_descriptor;
 
@@ -52,7 +54,7 @@ impl CDROMXAVolume {
 
         let mut reader = BufReader::new(&self.file);
 
-        if let Err(_) = reader.seek(SeekFrom::Start(sector_base_offset)) {
+        if reader.seek(SeekFrom::Start(sector_base_offset)).is_err() {
             return Err(format!("Failed to set seek for directory record data begin in file by offset {}, because it does not exist.", sector_base_offset));
         }
 
@@ -61,8 +63,9 @@ impl CDROMXAVolume {
         let mut file_data_bytes_left = directory_record.data_length as usize;
         let mut sector_data_buf = vec![0_u8; logical_block_size as usize];
 
+        // Each sector holds a logical block of data between its XA header and last bytes.
         let sector_count =
-            f32::ceil(directory_record.data_length as f32 / sector_size as f32) as usize;
+            (directory_record.data_length as usize).div_ceil(logical_block_size as usize);
 
         for i in 0..sector_count {
             // If reading any other sector except the last one
@@ -85,7 +88,7 @@ impl CDROMXAVolume {
             else {
                 reader.seek_relative(Sector::XA_HEADER_BYTE_COUNT).unwrap();
 
-                let mut buf = vec![0_u8; file_data_bytes_left as usize];
+                let mut buf = vec![0_u8; file_data_bytes_left];
                 reader.read_exact(&mut buf).unwrap();
 
                 for b in buf.iter() {
@@ -104,7 +107,7 @@ impl CDROMXAVolume {
         let mut result = Vec::<DirectoryRecord>::new();
 
         if !directory_record.is_dir() {
-            return Err(format!("Directory record in file is not a directory."));
+            return Err("Directory record in file is not a directory.".to_string());
         }
 
         let logical_block_size_usize = logical_block_size as usize;
@@ -114,7 +117,7 @@ impl CDROMXAVolume {
 
         let mut reader = BufReader::new(&self.file);
 
-        if let Err(_) = reader.seek(SeekFrom::Start(sector_base_offset)) {
+        if reader.seek(SeekFrom::Start(sector_base_offset)).is_err() {
             return Err(format!("Failed to set seek for descriptor begin in file by offset {}, because it does not exist.", sector_base_offset));
         }
 
@@ -160,7 +163,7 @@ impl CDROMXAVolume {
 
     pub fn read_primary_volume_descriptor(
         &mut self,
-        desciptor_locations: &Vec<VolumeDescriptorLocation>,
+        desciptor_locations: &[VolumeDescriptorLocation],
     ) -> Result<PrimaryVolumeDescriptor, String> {
         // Find the primary volume descriptor location
         let Some(descriptor_location) = desciptor_locations
@@ -173,13 +176,16 @@ impl CDROMXAVolume {
         // Seek offset in file for the primary volume descriptor
         let mut reader = BufReader::new(&self.file);
 
-        if let Err(_) = reader.seek(SeekFrom::Start(descriptor_location.descriptor_offset)) {
+        if reader
+            .seek(SeekFrom::Start(descriptor_location.descriptor_offset))
+            .is_err()
+        {
             return Err(format!("Failed to set seek for primary volume descriptor CD001 offset in file by offset {}, because it does not exist.", descriptor_location.descriptor_offset));
         }
 
         // Read the entire sector data into memory
         let mut descriptor_buf = [0_u8; Sector::LOGICAL_SIZE as usize];
-        if let Err(_) = reader.read_exact(&mut descriptor_buf) {
+        if reader.read_exact(&mut descriptor_buf).is_err() {
             return Err(format!(
                 "Failed to read a sector from file by offset {}, because it does not exist.",
                 descriptor_location.descriptor_offset
@@ -202,17 +208,20 @@ impl CDROMXAVolume {
             let sector_offset = sector_index * Sector::LOGICAL_SIZE;
             let descriptor_begin_offset = sector_offset + 24;
 
-            if let Err(_) = reader.seek(SeekFrom::Start(descriptor_begin_offset)) {
+            if reader
+                .seek(SeekFrom::Start(descriptor_begin_offset))
+                .is_err()
+            {
                 return Err(format!("Failed to set seek for descriptor begin in file by offset {}, because it does not exist.", descriptor_begin_offset));
             }
 
             let mut buf = [0_u8; 1];
-            if let Err(_) = reader.read_exact(&mut buf) {
+            if reader.read_exact(&mut buf).is_err() {
                 return Err(format!("Failed to read descriptor type from file by offset {}, because it does not exist.", descriptor_begin_offset));
             }
 
             let descriptor_type = match buf[0] {
-                001 => VolumeDescriptorType::Primary,
+                1 => VolumeDescriptorType::Primary,
                 255 => VolumeDescriptorType::SetTerminator,
                 value => {
                     let supported_values = VolumeDescriptorType::iter()
@@ -241,7 +250,7 @@ impl CDROMXAVolume {
         &mut self,
         directory_record: &DirectoryRecord,
         logical_block_size: i16,
-        content: &Vec<u8>,
+        content: &[u8],
     ) -> Result<(), String> {
         let mut writer = BufWriter::new(&self.file);
 
@@ -260,8 +269,9 @@ impl CDROMXAVolume {
                 )
             })?;
 
+        // Each sector holds a logical block of data between its XA header and last bytes.
         let sector_count =
-            f32::ceil(directory_record.data_length as f32 / sector_size as f32) as usize;
+            (directory_record.data_length as usize).div_ceil(logical_block_size as usize);
 
         let data_len = directory_record.data_length as usize;
 
@@ -307,6 +317,80 @@ impl CDROMXAVolume {
 
         Ok(())
     }
+    /// Reads the records of the root directory, which contains the game files,
+    /// and the logical block size of the volume.
+    fn read_root_directory_records(&mut self) -> Result<(Vec<DirectoryRecord>, i16), String> {
+        let vd_locations = self.read_volume_descriptor_locations().map_err(|err| {
+            format!(
+                "ROM has invalid data: failed to read volume descriptor locations: {}",
+                err
+            )
+        })?;
+        let pvd = self
+            .read_primary_volume_descriptor(&vd_locations)
+            .map_err(|err| {
+                format!(
+                    "ROM has invalid data: failed to read primary volume descriptor: {}",
+                    err
+                )
+            })?;
+        let records = self
+            .read_directory_records(
+                &pvd.directory_record_for_root_directory,
+                pvd.logical_block_size,
+            )
+            .map_err(|err| {
+                format!(
+                    "ROM has invalid data: failed to read sub-records by root directory: {}",
+                    err
+                )
+            })?;
+        Ok((records, pvd.logical_block_size))
+    }
+    /// Finds a file in the root directory by its identifier, like "SCUS_942.28" (without the version).
+    fn find_root_file_record(&mut self, file_name: &str) -> Result<(DirectoryRecord, i16), String> {
+        let (records, logical_block_size) = self.read_root_directory_records()?;
+        let record = records
+            .into_iter()
+            .find(|record| !record.is_dir() && record.file_identifier_as_string() == file_name)
+            .ok_or_else(|| format!("ROM does not contain file \"{}\".", file_name))?;
+        Ok((record, logical_block_size))
+    }
+    /// Reads a file in the root directory by its identifier, like "SCUS_942.28" (without the version).
+    pub fn read_root_file(&mut self, file_name: &str) -> Result<Vec<u8>, String> {
+        let (record, logical_block_size) = self.find_root_file_record(file_name)?;
+        self.read_directory_record_data(&record, logical_block_size)
+    }
+    /// Replaces the content of a file in the root directory with content of the same size,
+    /// as files cannot be moved or resized on the volume.
+    pub fn replace_root_file(&mut self, file_name: &str, content: &[u8]) -> Result<(), String> {
+        let (record, logical_block_size) = self.find_root_file_record(file_name)?;
+        if content.len() != record.data_length as usize {
+            return Err(format!(
+                "File \"{}\" on ROM is {} bytes, but its new content is {} bytes. Files cannot be resized on ROM.",
+                file_name,
+                record.data_length,
+                content.len()
+            ));
+        }
+        self.replace_file(&record, logical_block_size, content)
+    }
+    /// Gets the identifiers of files in the root directory with the ranges of sectors containing them.
+    pub fn get_root_file_sector_ranges(&mut self) -> Result<Vec<(String, Range<u64>)>, String> {
+        let (records, logical_block_size) = self.read_root_directory_records()?;
+        Ok(records
+            .iter()
+            .filter(|record| !record.is_dir())
+            .map(|record| {
+                let start = record.location_of_extent as u64;
+                let sector_count = (record.data_length as u64).div_ceil(logical_block_size as u64);
+                (
+                    record.file_identifier_as_string(),
+                    start..start + sector_count,
+                )
+            })
+            .collect())
+    }
 }
 
 pub struct Sector {
@@ -327,14 +411,14 @@ impl Sector {
         descriptor: &PrimaryVolumeDescriptor,
         old_data: &[u8; Self::LOGICAL_SIZE as usize],
     ) -> Result<Sector, String> {
-        let mut data = old_data.clone();
+        let mut data = *old_data;
 
         const HEADER_LEN: usize = Sector::XA_HEADER_BYTE_COUNT as usize;
 
         // TODO: Figure out what header data contains exactly
         let header: [u8; HEADER_LEN] = [
-            00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 00, 00, 02, 0x16, 02,
-            00, 00, 09, 00, 00, 00, 09, 00,
+            0, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0, 0, 2, 0x16, 2, 0, 0,
+            9, 0, 0, 0, 9, 0,
         ];
         write_bytes_into(&mut data, 0, &header);
 
@@ -470,8 +554,8 @@ pub fn parse_numeric_value_as_i16(value: &str) -> Result<(NumberType, i16), Stri
             }
         }
         // If a value given is a positive decimal value
-        else if value.len() > 0 {
-            if let Ok(value) = i16::from_str_radix(&value[..], 10) {
+        else if !value.is_empty() {
+            if let Ok(value) = value.parse::<i16>() {
                 number_type = NumberType::Decimal;
                 number_value = value;
                 break 'init_value;
@@ -514,7 +598,7 @@ pub fn parse_numeric_value_as_i32(value: &str) -> Result<(NumberType, i32), Stri
         }
         // If a value given is a positive decimal value
         else if len > 0 {
-            if let Ok(value) = i32::from_str_radix(&s[..], 10) {
+            if let Ok(value) = s.parse::<i32>() {
                 number_type = NumberType::Decimal;
                 number_value = value;
                 break 'init_value;
@@ -535,25 +619,25 @@ mod parse_numeric_value_tests {
     fn parse_numeric_value_from_decimal_value() {
         {
             let value_raw = &String::from("9");
-            let (number_type, value) = parse_numeric_value_as_i16(&value_raw).unwrap();
+            let (number_type, value) = parse_numeric_value_as_i16(value_raw).unwrap();
             assert_eq!(number_type, NumberType::Decimal);
             assert_eq!(value, 9);
         }
         {
             let value_raw = &String::from("31");
-            let (number_type, value) = parse_numeric_value_as_i16(&value_raw).unwrap();
+            let (number_type, value) = parse_numeric_value_as_i16(value_raw).unwrap();
             assert_eq!(number_type, NumberType::Decimal);
             assert_eq!(value, 31);
         }
         {
             let value_raw = &String::from("250");
-            let (number_type, value) = parse_numeric_value_as_i16(&value_raw).unwrap();
+            let (number_type, value) = parse_numeric_value_as_i16(value_raw).unwrap();
             assert_eq!(number_type, NumberType::Decimal);
             assert_eq!(value, 250);
         }
         {
             let value_raw = &String::from("-250");
-            let (number_type, value) = parse_numeric_value_as_i16(&value_raw).unwrap();
+            let (number_type, value) = parse_numeric_value_as_i16(value_raw).unwrap();
             assert_eq!(number_type, NumberType::Decimal);
             assert_eq!(value, -250);
         }
@@ -562,37 +646,37 @@ mod parse_numeric_value_tests {
     fn parse_numeric_value_from_hexadecimal_value() {
         {
             let value_raw = &String::from("0x2");
-            let (number_type, value) = parse_numeric_value_as_i16(&value_raw).unwrap();
+            let (number_type, value) = parse_numeric_value_as_i16(value_raw).unwrap();
             assert_eq!(number_type, NumberType::Hexadecimal);
             assert_eq!(value, 2);
         }
         {
             let value_raw = &String::from("0xF");
-            let (number_type, value) = parse_numeric_value_as_i16(&value_raw).unwrap();
+            let (number_type, value) = parse_numeric_value_as_i16(value_raw).unwrap();
             assert_eq!(number_type, NumberType::Hexadecimal);
             assert_eq!(value, 15);
         }
         {
             let value_raw = &String::from("0x10");
-            let (number_type, value) = parse_numeric_value_as_i16(&value_raw).unwrap();
+            let (number_type, value) = parse_numeric_value_as_i16(value_raw).unwrap();
             assert_eq!(number_type, NumberType::Hexadecimal);
             assert_eq!(value, 16);
         }
         {
             let value_raw = &String::from("0xFF");
-            let (number_type, value) = parse_numeric_value_as_i16(&value_raw).unwrap();
+            let (number_type, value) = parse_numeric_value_as_i16(value_raw).unwrap();
             assert_eq!(number_type, NumberType::Hexadecimal);
             assert_eq!(value, 255);
         }
         {
             let value_raw = &String::from("0xff");
-            let (number_type, value) = parse_numeric_value_as_i16(&value_raw).unwrap();
+            let (number_type, value) = parse_numeric_value_as_i16(value_raw).unwrap();
             assert_eq!(number_type, NumberType::Hexadecimal);
             assert_eq!(value, 255);
         }
         {
             let value_raw = &String::from("-0x10");
-            let (number_type, value) = parse_numeric_value_as_i16(&value_raw).unwrap();
+            let (number_type, value) = parse_numeric_value_as_i16(value_raw).unwrap();
             assert_eq!(number_type, NumberType::Hexadecimal);
             assert_eq!(value, -16);
         }
@@ -601,11 +685,11 @@ mod parse_numeric_value_tests {
     fn parse_numeric_value_from_hexadecimal_value_with_failure_when_value_is_too_big() {
         {
             let value_raw = &String::from("0xAAAA");
-            assert!(parse_numeric_value_as_i16(&value_raw).is_err());
+            assert!(parse_numeric_value_as_i16(value_raw).is_err());
         }
         {
             let value_raw = &String::from("-0xAAAA");
-            assert!(parse_numeric_value_as_i16(&value_raw).is_err());
+            assert!(parse_numeric_value_as_i16(value_raw).is_err());
         }
     }
 }
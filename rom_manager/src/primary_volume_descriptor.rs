@@ -34,9 +34,7 @@ impl PrimaryVolumeDescriptor {
     pub const PUBLISHER_IDENTIFIER_RANGE: ByteRange = ByteRange::new(318, 446);
     pub const APPLICATION_IDENTIFIER_RANGE: ByteRange = ByteRange::new(574, 702);
 
-    pub fn try_from_buffer(
-        buf: &[u8]
-    ) -> Result<Self, PrimaryVolumeDescriptorError> {
+    pub fn try_from_buffer(buf: &[u8]) -> Result<Self, PrimaryVolumeDescriptorError> {
         const CD001: &[u8] = b"CD001";
         const OFFSET_FROM_DESCRIPTOR_TYPE: usize = 1;
 
@@ -448,7 +448,7 @@ main:
 
     @at 0x8004363c
     jal 66522
-    ori a0, zero, -1031
+    ori a0, zero, 64505
     bne v0, zero, 608
     nop
     lui a0, 32776
@@ -1002,7 +1002,7 @@ spy_render_menu:
     jal 98166
     addu s3, zero, zero
     lui a0, 1
-    ori a0, a0, -16384
+    ori a0, a0, 49152
     lui a1, 65534
     ori a1, a1, 15872
     lui v1, 32776
@@ -1,13 +1,23 @@
 use std::collections::HashMap;
 use std::env;
 use std::fs::{self, File, OpenOptions};
-use std::io::Read;
-
-use bin_manager;
-use mips::{parse_nodes, CustomCommand, NodeKind};
-use ps1exe::{PS1Exe, PS1ExeReader, PS1ExeWriteResult, PS1ExeWriter};
-use rom_manager::CDROMXAVolume;
-use wad::{WADReader, WAD};
+use std::io::{BufReader, Read};
+use std::ops::Range;
+use std::path::Path;
+
+use bin_manager::PatchFormat;
+use mips::{
+    parse_labels, parse_nodes_with_options, parse_section_sizes, CustomCommand, NodeKind,
+    ParseOptions,
+};
+use mod_manager::{find_mod_conflicts, get_changed_ranges, ModChange, ModOperation, ModPackage};
+use ps1exe::{
+    PS1Exe, PS1ExeBuilder, PS1ExeReader, PS1ExeWriteResult, PS1ExeWriter, RegionKind, SymbolMap,
+    SymbolMapFormat, TextCodec, TextEntry, WriteSource, SPLIT_HEADER_FILE_NAME,
+};
+use rom_manager::{CDROMXAVolume, Sector};
+use sha1::{Digest, Sha1};
+use wad::{TextureAnimationTables, Vram, WADReader, WAD};
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("------------------");
@@ -18,7 +28,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let command = args
         .get(1)
-        .ok_or_else(|| "No command provided as a command line argument.")?;
+        .ok_or("No command provided as a command line argument.")?;
 
     let command_args = &args[2..];
 
@@ -51,22 +61,31 @@ macro_rules! get_arg {
     };
 }
 
-const COMMANDS: &[(
-    &str,
-    &str,
-    fn(args: &[String]) -> Result<(), Box<dyn std::error::Error>>,
-)] = &[
+type CommandFunction = fn(args: &[String]) -> Result<(), Box<dyn std::error::Error>>;
+
+const COMMANDS: &[(&str, &str, CommandFunction)] = &[
     ("bin-read-bytes", "Reads bytes from a binary file at a given offset and count.", bin_read_bytes),
     ("generate-doc", "Generates README.md file describing the project at project root.", generate_doc),
     ("mips-assemble", "Converts MIPS assembly instruction into machine code (as hexadecimal) and into LE bytes also.", mips_assemble),
     ("mips-disassemble", "Converts machine code into an MIPS assembly instruction string.", mips_disassemble),
-    ("ps1exe-assemble", "Assembles MIPS assembly code from a given text file into a Playstation executable.", ps1exe_assemble),
-    ("ps1exe-disassemble", "Disassembles a section of MIPS assembly code from a given Playstation executable binary.", ps1exe_disassemble),
+    ("mod-apply", "Applies mod packages (directories with a mod.toml manifest of assembly code, replacement files, WAD file replacements and translations) in order onto a clean ROM, writing a new ROM and a log. The ROM hash each mod is made for is checked. Bytes changed by more than one mod are reported as conflicts, which fail applying unless --permissive is given.", mod_apply),
+    ("mod-verify", "Checks whether a given ROM matches a clean ROM with given mod packages applied in order, listing files which differ.", mod_verify),
+    ("patch-apply", "Applies an IPS, BPS or VCDIFF (xdelta) patch to a given original file, like a BIN image of the ROM or a file extracted from it. EDC and ECC of changed sectors of a BIN image are regenerated.", patch_apply),
+    ("patch-create", "Creates an IPS, BPS or VCDIFF (xdelta) patch from a given original and modified file, by the patch file extension or --format.", patch_create),
+    ("ps1exe-analyze", "Analyzes a whole Playstation executable (or an overlay with --overlay) by following calls from its entry point, listing found functions and how much of it is code, optionally writing the call graph as Graphviz DOT or JSON.", ps1exe_analyze),
+    ("ps1exe-assemble", "Assembles MIPS assembly code from a given text file into a Playstation executable, or all files of a given directory (like one written by ps1exe-split) into a blank executable made out of its header file. New code in sections is placed into free regions given with --free, or appended by growing the executable. Bytes written more than once, and bytes of a directory's executable not written at all, fail assembling unless --permissive is given.", ps1exe_assemble),
+    ("ps1exe-disassemble", "Disassembles a section of MIPS assembly code from a given Playstation executable binary, or from an overlay loaded at a given address with --overlay (read from a file in WAD with --wad).", ps1exe_disassemble),
+    ("ps1exe-disassemble-function", "Disassembles a function from a given Playstation executable (or an overlay with --overlay) by following its branches and jumps, optionally writing its control flow graph as Graphviz DOT.", ps1exe_disassemble_function),
+    ("ps1exe-split", "Splits a whole Playstation executable (or an overlay with --overlay) into a directory of assembly code files (one for each function or run of data) and a header file, which ps1exe-assemble assembles back into the same executable.", ps1exe_split),
     ("rom-check", "Checks the given ROM file structure for correctness.", rom_check),
     ("rom-extract", "Extracts a file from a ROM to a given extract path.", rom_extract),
     ("rom-list", "Lists directories and files in a given ROM.", rom_list),
     ("rom-replace", "Replaces a file in a given ROM with a given input file.", rom_replace),
+    ("symbols-convert", "Converts a symbol map file between formats (open-spyro, splat, ida and nocash).", symbols_convert),
+    ("text-extract", "Extracts strings from a given Playstation executable (or a file in WAD with --wad and its load address with --overlay) into a PO file for translating.", text_extract),
+    ("text-insert", "Inserts translated strings from a given PO file back into a Playstation executable (or a file in WAD with --wad and its load address with --overlay).", text_insert),
     ("wad-read", "Reads information about WAD file. Heavily WIP.", wad_read),
+    ("wad-texture-animations", "Reads texture animation and scrolling tables of a level in WAD file and optionally writes a preview PNG strip of an animation.", wad_texture_animations),
 ];
 
 /// Reads bytes from a binary file at a given offset and count.
@@ -74,30 +93,31 @@ fn bin_read_bytes(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
     let file_path = get_arg!(args, 0, "file path")?;
 
     let offset = get_arg!(args, 1, "offset")?;
-    let offset = offset.parse::<usize>().map_err(|_| {
-        format!(
-            "Failed to parse given offset \"{}\" as a number.",
-            offset
-        )
-    })?;
+    let offset = offset
+        .parse::<usize>()
+        .map_err(|_| format!("Failed to parse given offset \"{}\" as a number.", offset))?;
 
     let count = get_arg!(args, 2, "count")?;
-    let count = count.parse::<usize>().map_err(|_| {
-        format!(
-            "Failed to parse given count \"{}\" as a number.",
-            count
-        )
-    })?;
+    let count = count
+        .parse::<usize>()
+        .map_err(|_| format!("Failed to parse given count \"{}\" as a number.", count))?;
 
-    let bytes = bin_manager::read_bytes_from_file(file_path, offset, count)
-        .map_err(|open_file_err| {
+    let bytes =
+        bin_manager::read_bytes_from_file(file_path, offset, count).map_err(|open_file_err| {
             format!(
                 "Failed to read bytes from file \"{}\" at offset {} and count {}: {}",
                 file_path, offset, count, open_file_err
             )
         })?;
 
-    println!("{}", bytes.iter().map(|b| format!("0x{:x}", b)).collect::<Vec<_>>().join(", "));
+    println!(
+        "{}",
+        bytes
+            .iter()
+            .map(|b| format!("0x{:x}", b))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
     Ok(())
 }
 fn generate_doc(_args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
@@ -123,13 +143,16 @@ fn generate_doc(_args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
         .collect::<Vec<_>>();
     // Ensure all commands are sorted alphabetically.
     commands.sort();
-    let commands = commands.iter().map(|command| command.as_str()).collect::<Vec<_>>();
+    let commands = commands
+        .iter()
+        .map(|command| command.as_str())
+        .collect::<Vec<_>>();
     let commands = &commands[..];
 
     let elements = [
         title(1, "Open Spyro"),
         String::from("![Spyro the Dragon screenshot from Town Square](http://henrijahanna.fi/projects/open_spyro/spyro_town_square.bmp)"),
-        
+
         title(2, "Introduction"),
         String::from("Welcome to the Spyro the Dragon Linux and Windows port project called OpenSpyro! OpenSpyro is an open-source initiative driven by a passionate programmer and fan of the original Spyro the Dragon trilogy on Playstation. The project is focused on reverse-engineering and adapting the game to run natively on Linux and Windows (I don't have a Mac, sorry). I aim to provide an authentic experience that stays true to the original while leveraging the capabilities of modern hardware."),
         String::from("This project will not contain any game data in its original form. You must have an original ROM of the game available in order to play this port."),
@@ -174,7 +197,7 @@ fn generate_doc(_args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
 fn mips_assemble(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
     let value = get_arg!(args, 0, "value")?;
 
-    let instruction = mips::Instruction::parse_from_str(&value)?;
+    let instruction = mips::Instruction::parse_from_str(value)?;
     println!("{:x}", instruction.to_machine_code());
 
     let instruction_bytes = instruction.to_le_bytes();
@@ -216,9 +239,9 @@ fn mips_disassemble(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
                 )
             })?;
 
-            let instruction = mips::Instruction::parse_from_machine_code(value);
+            let instruction = mips::Instruction::parse_from_machine_code(value)?;
 
-            println!("{}", instruction.to_instruction());
+            println!("{}", instruction.to_instruction()?);
             Ok(())
         }
         _ => Err(format!(
@@ -228,39 +251,846 @@ fn mips_disassemble(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
         .into()),
     }
 }
-/// Assembles MIPS assembly code from a given text file into a Playstation executable.
-fn ps1exe_assemble(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
-    let input_assembly_code_file_path = get_arg!(args, 0, "input assembly code file path")?;
-    let input_ps1_exe_file_path = get_arg!(args, 1, "input PS1 EXE file path")?;
-    let output_ps1_exe_file_path = get_arg!(args, 2, "output PS1 EXE file path")?;
+/// Applies mod packages (directories with a "mod.toml" manifest) in order onto a clean ROM,
+/// writing a new ROM and a log of the applied changes.
+fn mod_apply(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let clean_rom_path = get_arg!(args, 0, "clean ROM path")?;
+    let output_rom_path = get_arg!(args, 1, "output ROM path")?;
+    let (mod_directories, options) = split_mod_directories(&args[2..]);
+    let is_permissive = options.iter().any(|option| option == "--permissive");
+    let log_path = match options.iter().position(|option| option == "--log") {
+        Some(option_index) => options
+            .get(option_index + 1)
+            .ok_or("No log file path given after \"--log\" option.")?
+            .clone(),
+        None => format!("{}.log", output_rom_path),
+    };
+    let packages = read_mod_packages(mod_directories)?;
+
+    let mut log = String::new();
+    let result = check_mod_base_rom(clean_rom_path, &packages, &mut log).and_then(|_| {
+        let result = apply_mods(
+            clean_rom_path,
+            output_rom_path,
+            &packages,
+            is_permissive,
+            &mut log,
+        );
+        if result.is_err() {
+            // Partially modded ROM is not left behind to be mistaken for a working one.
+            // A file already at the output path is only removed once the ROM is written over it.
+            let _ = fs::remove_file(output_rom_path);
+        }
+        result
+    });
+    if let Err(err) = &result {
+        log.push_str(&format!("Failed: {}\n", err));
+    }
+    fs::write(&log_path, &log)
+        .map_err(|err| format!("Failed to write log to path \"{}\": {}", log_path, err))?;
+    result?;
 
-    let mut input_file = File::open(input_assembly_code_file_path).map_err(|_| {
+    println!(
+        "Applied {} mods into \"{}\". Log written to \"{}\".",
+        packages.len(),
+        output_rom_path,
+        log_path
+    );
+    Ok(())
+}
+/// Checks whether a given ROM matches a clean ROM with given mod packages applied in order,
+/// listing files which differ.
+fn mod_verify(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let clean_rom_path = get_arg!(args, 0, "clean ROM path")?;
+    let rom_path = get_arg!(args, 1, "ROM path")?;
+    let (mod_directories, options) = split_mod_directories(&args[2..]);
+    let is_permissive = options.iter().any(|option| option == "--permissive");
+    let packages = read_mod_packages(mod_directories)?;
+
+    // The expected ROM is built like with mod-apply and compared sector by sector.
+    let expected_rom_path = env::temp_dir()
+        .join(format!("open-spyro-mod-verify-{}.bin", std::process::id()))
+        .to_string_lossy()
+        .into_owned();
+    let mut log = String::new();
+    let result = check_mod_base_rom(clean_rom_path, &packages, &mut log)
+        .and_then(|_| {
+            apply_mods(
+                clean_rom_path,
+                &expected_rom_path,
+                &packages,
+                is_permissive,
+                &mut log,
+            )
+        })
+        .and_then(|_| {
+            let differing_sectors = get_differing_sectors(rom_path, &expected_rom_path)?;
+            let file_sector_ranges = match differing_sectors.is_empty() {
+                true => Vec::new(),
+                false => CDROMXAVolume::new(File::open(&expected_rom_path)?)
+                    .get_root_file_sector_ranges()?,
+            };
+            Ok((differing_sectors, file_sector_ranges))
+        });
+    let _ = fs::remove_file(&expected_rom_path);
+    let (differing_sectors, file_sector_ranges) = result?;
+
+    if differing_sectors.is_empty() {
+        println!(
+            "ROM \"{}\" matches the clean ROM with {} mods applied.",
+            rom_path,
+            packages.len()
+        );
+        return Ok(());
+    }
+    // Sectors outside of files are the system area, volume descriptors and directories.
+    let mut differing_files = Vec::<(&str, usize)>::new();
+    for sector_index in differing_sectors.iter() {
+        let file_name = file_sector_ranges
+            .iter()
+            .find(|(_, range)| range.contains(sector_index))
+            .map(|(file_name, _)| file_name.as_str())
+            .unwrap_or("(outside of files)");
+        match differing_files
+            .iter_mut()
+            .find(|(name, _)| *name == file_name)
+        {
+            Some((_, count)) => *count += 1,
+            None => differing_files.push((file_name, 1)),
+        }
+    }
+    Err(format!(
+        "ROM \"{}\" does not match the clean ROM with {} mods applied. {} sectors differ:\n{}",
+        rom_path,
+        packages.len(),
+        differing_sectors.len(),
+        differing_files
+            .iter()
+            .map(|(file_name, count)| format!("  {} ({} sectors)", file_name, count))
+            .collect::<Vec<_>>()
+            .join("\n")
+    )
+    .into())
+}
+/// Splits arguments into mod package directories and the options following them.
+fn split_mod_directories(args: &[String]) -> (&[String], &[String]) {
+    let options_index = args
+        .iter()
+        .position(|arg| arg.starts_with("--"))
+        .unwrap_or(args.len());
+    args.split_at(options_index)
+}
+fn read_mod_packages(mod_directories: &[String]) -> Result<Vec<ModPackage>, String> {
+    if mod_directories.is_empty() {
+        return Err(String::from(
+            "No mod directories provided as command line arguments.",
+        ));
+    }
+    mod_directories
+        .iter()
+        .map(|directory| ModPackage::from_directory(Path::new(directory)))
+        .collect()
+}
+/// Checks that mod packages are made for the given clean ROM, writing the ROM and the mods
+/// into a log.
+fn check_mod_base_rom(
+    clean_rom_path: &str,
+    packages: &[ModPackage],
+    log: &mut String,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let base_sha1 = get_file_sha1(clean_rom_path)?;
+    log.push_str(&format!(
+        "Clean ROM: \"{}\" (SHA-1 {})\n",
+        clean_rom_path, base_sha1
+    ));
+    for package in packages.iter() {
+        log.push_str(&format!(
+            "Mod: {} (\"{}\")\n",
+            package,
+            package.directory.display()
+        ));
+        // Mods made for another version of the game would write into wrong places.
+        if let Some(expected_sha1) = &package.base_sha1 {
+            if *expected_sha1 != base_sha1 {
+                return Err(format!(
+                    "Mod \"{}\" is made for a ROM with SHA-1 {}, but the given ROM \"{}\" has SHA-1 {}.",
+                    package.name, expected_sha1, clean_rom_path, base_sha1
+                )
+                .into());
+            }
+        }
+    }
+    Ok(())
+}
+/// Copies a clean ROM into an output ROM and applies mod packages onto it in order
+/// (see [check_mod_base_rom]), writing what was done into a log.
+fn apply_mods(
+    clean_rom_path: &str,
+    output_rom_path: &str,
+    packages: &[ModPackage],
+    is_permissive: bool,
+    log: &mut String,
+) -> Result<(), Box<dyn std::error::Error>> {
+    use colored::*;
+
+    fs::copy(clean_rom_path, output_rom_path).map_err(|err| {
         format!(
-            "Failed to open given input MIPS assembly code file in path \"{}\".",
-            input_assembly_code_file_path
+            "Failed to copy clean ROM to path \"{}\": {}",
+            output_rom_path, err
         )
     })?;
+    let volume_file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(output_rom_path)
+        .map_err(|err| {
+            format!(
+                "Failed to open output ROM in path \"{}\": {}",
+                output_rom_path, err
+            )
+        })?;
+    let mut volume = CDROMXAVolume::new(volume_file);
 
-    let mut input_file_content = String::new();
-    input_file
-        .read_to_string(&mut input_file_content)
-        .map_err(|_| {
+    // Operations run the same code as the commands they correspond to, on files in a temporary directory.
+    let temp_directory =
+        env::temp_dir().join(format!("open-spyro-mod-apply-{}", std::process::id()));
+    fs::create_dir_all(&temp_directory).map_err(|err| {
+        format!(
+            "Failed to create temporary directory \"{}\": {}",
+            temp_directory.display(),
+            err
+        )
+    })?;
+    let mut changes = Vec::new();
+    let mut result = Ok(());
+    'apply: for (mod_index, package) in packages.iter().enumerate() {
+        for operation in package.operations.iter() {
+            println!("{}", format!("{}: {}", package.name, operation).bold());
+            let file_name = operation.get_file_name();
+            let changed_ranges = volume.read_root_file(file_name).and_then(|content| {
+                let new_content =
+                    apply_mod_operation(operation, &content, &temp_directory, is_permissive)
+                        .map_err(|err| err.to_string())?;
+                volume.replace_root_file(file_name, &new_content)?;
+                Ok(get_changed_ranges(&content, &new_content))
+            });
+            let changed_ranges = match changed_ranges {
+                Ok(changed_ranges) => changed_ranges,
+                Err(err) => {
+                    result = Err(format!(
+                        "Failed to apply mod \"{}\": {}: {}",
+                        package.name, operation, err
+                    ));
+                    break 'apply;
+                }
+            };
+            log.push_str(&format!(
+                "{}: {} ({} bytes changed)\n",
+                package.name,
+                operation,
+                changed_ranges
+                    .iter()
+                    .map(|range| range.end - range.start)
+                    .sum::<u64>()
+            ));
+            changes.push(ModChange {
+                mod_index,
+                mod_name: package.name.clone(),
+                file_name: file_name.to_string(),
+                ranges: changed_ranges,
+            });
+        }
+    }
+    let _ = fs::remove_dir_all(&temp_directory);
+    result?;
+
+    // Bytes changed by more than one mod are likely to break the earlier mod.
+    let conflicts = find_mod_conflicts(&changes);
+    if !conflicts.is_empty() {
+        let report = format!(
+            "{} conflicting changes:\n{}",
+            conflicts.len(),
+            conflicts
+                .iter()
+                .map(|conflict| format!("  {}", conflict))
+                .collect::<Vec<_>>()
+                .join("\n")
+        );
+        log.push_str(&format!("{}\n", report));
+        if !is_permissive {
+            return Err(format!(
+                "{}\nUse \"--permissive\" option to apply anyway, with later mods winning.",
+                report
+            )
+            .into());
+        }
+        println!("{}", format!("Warning: {}", report).yellow());
+    }
+
+    let clean_rom_file = File::open(clean_rom_path).map_err(|err| {
+        format!(
+            "Failed to open clean ROM in path \"{}\": {}",
+            clean_rom_path, err
+        )
+    })?;
+    let regenerated_sector_count = volume.regenerate_changed_edc_ecc(&clean_rom_file)?;
+    log.push_str(&format!(
+        "Regenerated EDC and ECC of {} changed sectors.\n",
+        regenerated_sector_count
+    ));
+    log.push_str(&format!(
+        "Output ROM: \"{}\" (SHA-1 {})\n",
+        output_rom_path,
+        get_file_sha1(output_rom_path)?
+    ));
+    Ok(())
+}
+/// Applies an operation of a mod onto the content of a file on the ROM, giving its new content.
+fn apply_mod_operation(
+    operation: &ModOperation,
+    content: &[u8],
+    temp_directory: &Path,
+    is_permissive: bool,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let input_file_path = temp_directory.join("input").to_string_lossy().into_owned();
+    let output_file_path = temp_directory.join("output").to_string_lossy().into_owned();
+    let read_file = |file_path: &Path| {
+        fs::read(file_path).map_err(|err| {
             format!(
-                "Failed to read given input MIPS assembly code file in path \"{}\".",
-                input_assembly_code_file_path
+                "Failed to read file in path \"{}\": {}",
+                file_path.display(),
+                err
+            )
+        })
+    };
+    let path_arg = |file_path: &Path| file_path.to_string_lossy().into_owned();
+    if !matches!(operation, ModOperation::Replace { .. }) {
+        fs::write(&input_file_path, content).map_err(|err| {
+            format!(
+                "Failed to write temporary file to path \"{}\": {}",
+                input_file_path, err
             )
         })?;
+    }
+
+    match operation {
+        ModOperation::Replace { source, .. } => Ok(read_file(source)?),
+        ModOperation::Wad {
+            index,
+            subfile,
+            source,
+            ..
+        } => {
+            let wad = WAD::from_file_path(&input_file_path)?;
+            let wad_reader = WADReader::new(&wad);
+            let file_metadatum = wad_reader.read_file_metadatum_from_header()?;
+            let file_metadata = file_metadatum.get(*index).ok_or_else(|| {
+                format!(
+                    "WAD file contains {} files, no file found by index {}.",
+                    file_metadatum.len(),
+                    index
+                )
+            })?;
+            let begin = file_metadata.offset as usize;
+            let mut range = begin..begin + file_metadata.size as usize;
+            if let Some(subfile) = subfile {
+                let subfile_metadatum = wad_reader.read_subfiles_by_file_metadata(file_metadata)?;
+                let subfile_metadata = subfile_metadatum.get(*subfile).ok_or_else(|| {
+                    format!(
+                        "File #{} in WAD contains {} subfiles, no subfile found by index {}.",
+                        index,
+                        subfile_metadatum.len(),
+                        subfile
+                    )
+                })?;
+                let begin = range.start + subfile_metadata.offset as usize;
+                range = begin..begin + subfile_metadata.size as usize;
+            }
+
+            let replacement = read_file(source)?;
+            if replacement.len() != range.len() {
+                return Err(format!(
+                    "File in WAD is {} bytes, but replacement file \"{}\" is {} bytes. Files in WAD cannot be resized.",
+                    range.len(),
+                    source.display(),
+                    replacement.len()
+                )
+                .into());
+            }
+            let mut new_content = content.to_vec();
+            new_content
+                .get_mut(range)
+                .ok_or("File does not fit in WAD file.")?
+                .copy_from_slice(&replacement);
+            Ok(new_content)
+        }
+        ModOperation::Assembly {
+            source,
+            symbols,
+            free,
+            codec,
+            ..
+        } => {
+            let mut assemble_args = vec![
+                path_arg(source),
+                input_file_path.clone(),
+                output_file_path.clone(),
+            ];
+            if let Some(symbols) = symbols {
+                assemble_args.extend([String::from("--symbols"), path_arg(symbols)]);
+            }
+            for range in free.iter() {
+                assemble_args.extend([
+                    String::from("--free"),
+                    format!("{:x}-{:x}", range.start, range.end),
+                ]);
+            }
+            if let Some(codec) = codec {
+                assemble_args.extend([String::from("--codec"), codec.clone()]);
+            }
+            if is_permissive {
+                assemble_args.push(String::from("--permissive"));
+            }
+            ps1exe_assemble(&assemble_args)?;
+            Ok(read_file(Path::new(&output_file_path))?)
+        }
+        ModOperation::Text {
+            po,
+            wad_file,
+            free,
+            codec,
+            file,
+            ..
+        } => {
+            let mut insert_args = vec![
+                input_file_path.clone(),
+                path_arg(po),
+                output_file_path.clone(),
+            ];
+            if let Some((wad_index, load_address)) = wad_file {
+                insert_args.extend([
+                    String::from("--wad"),
+                    wad_index.to_string(),
+                    String::from("--overlay"),
+                    file.clone(),
+                    format!("{:x}", load_address),
+                ]);
+            }
+            for range in free.iter() {
+                insert_args.extend([
+                    String::from("--free"),
+                    format!("{:x}-{:x}", range.start, range.end),
+                ]);
+            }
+            if let Some(codec) = codec {
+                insert_args.extend([String::from("--codec"), codec.clone()]);
+            }
+            text_insert(&insert_args)?;
+            Ok(read_file(Path::new(&output_file_path))?)
+        }
+    }
+}
+/// Gets the SHA-1 hash of a file, reading it in parts as ROM images are large.
+fn get_file_sha1(file_path: &str) -> Result<String, String> {
+    let mut file = File::open(file_path)
+        .map_err(|err| format!("Failed to open file in path \"{}\": {}", file_path, err))?;
+    let mut hasher = Sha1::new();
+    let mut buffer = vec![0; 0x100000];
+    loop {
+        let count = file
+            .read(&mut buffer)
+            .map_err(|err| format!("Failed to read file in path \"{}\": {}", file_path, err))?;
+        if count == 0 {
+            return Ok(format!("{:x}", hasher.finalize()));
+        }
+        hasher.update(&buffer[..count]);
+    }
+}
+/// Gets the indices of sectors which differ between two ROM images.
+fn get_differing_sectors(rom_path: &str, other_rom_path: &str) -> Result<Vec<u64>, String> {
+    let open = |file_path: &str| {
+        File::open(file_path)
+            .map(BufReader::new)
+            .map_err(|err| format!("Failed to open ROM in path \"{}\": {}", file_path, err))
+    };
+    let mut reader = open(rom_path)?;
+    let mut other_reader = open(other_rom_path)?;
+    let sector_size = Sector::LOGICAL_SIZE as usize;
+    let mut sector = vec![0; sector_size];
+    let mut other_sector = vec![0; sector_size];
+    let mut differing_sectors = Vec::new();
+    for sector_index in 0.. {
+        // Sectors missing from the shorter image differ too.
+        let is_read = reader.read_exact(&mut sector).is_ok();
+        let is_other_read = other_reader.read_exact(&mut other_sector).is_ok();
+        if !is_read && !is_other_read {
+            break;
+        }
+        if !is_read || !is_other_read || sector != other_sector {
+            differing_sectors.push(sector_index);
+        }
+    }
+    Ok(differing_sectors)
+}
+/// Applies an IPS, BPS or VCDIFF patch to a given original file, regenerating EDC and ECC
+/// of changed sectors if the file is a BIN image.
+fn patch_apply(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let patch_file_path = get_arg!(args, 0, "patch file path")?;
+    let original_file_path = get_arg!(args, 1, "original file path")?;
+    let output_file_path = get_arg!(args, 2, "output file path")?;
 
-    let nodes = parse_nodes(&input_file_content).map_err(|e| {
+    let patch = fs::read(patch_file_path).map_err(|err| {
         format!(
-            "Failed to parse given input MIPS assembly code file in path \"{}\": {}",
-            input_assembly_code_file_path,
-            e.to_string()
+            "Failed to read patch file in path \"{}\": {}",
+            patch_file_path, err
         )
     })?;
+    let original = fs::read(original_file_path).map_err(|err| {
+        format!(
+            "Failed to read original file in path \"{}\": {}",
+            original_file_path, err
+        )
+    })?;
+    let format = PatchFormat::detect(&patch).ok_or(format!(
+        "Patch file in path \"{}\" is not an IPS, BPS or VCDIFF patch.",
+        patch_file_path
+    ))?;
+    println!("Patch format: {:?}", format);
 
-    println!("Input PS1 EXE file path: {}", input_ps1_exe_file_path);
-    let mut ps1_exe = PS1Exe::from_file_path(input_ps1_exe_file_path)?;
+    let mut modified = format.apply_patch(&patch, &original).map_err(|err| {
+        format!(
+            "Failed to apply patch in path \"{}\": {}",
+            patch_file_path, err
+        )
+    })?;
+    // Emulators and drives check EDC and ECC of sectors, which patches may not have updated.
+    if Sector::is_raw_image(&original) && Sector::is_raw_image(&modified) {
+        let sector_count = Sector::regenerate_changed_edc_ecc(&original, &mut modified);
+        println!(
+            "Regenerated EDC and ECC of {} changed sectors.",
+            sector_count
+        );
+    }
+
+    fs::write(output_file_path, &modified).map_err(|err| {
+        format!(
+            "Failed to write patched file to path \"{}\": {}",
+            output_file_path, err
+        )
+    })?;
+    println!("Patched file written to \"{}\".", output_file_path);
+    Ok(())
+}
+/// Creates an IPS, BPS or VCDIFF patch from a given original and modified file.
+fn patch_create(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let original_file_path = get_arg!(args, 0, "original file path")?;
+    let modified_file_path = get_arg!(args, 1, "modified file path")?;
+    let output_patch_file_path = get_arg!(args, 2, "output patch file path")?;
+    let options = &args[3..];
+
+    let format = match options.iter().position(|option| option == "--format") {
+        Some(option_index) => PatchFormat::from_name(
+            options
+                .get(option_index + 1)
+                .ok_or("No patch format given after \"--format\" option.")?,
+        )?,
+        None => Path::new(output_patch_file_path)
+            .extension()
+            .and_then(|extension| PatchFormat::from_file_extension(&extension.to_string_lossy()))
+            .ok_or(format!(
+                "Failed to tell patch format by the extension of \"{}\". Use \".ips\", \".bps\" or \".xdelta\", or give \"--format <ips|bps|vcdiff>\" option.",
+                output_patch_file_path
+            ))?,
+    };
+
+    let original = fs::read(original_file_path).map_err(|err| {
+        format!(
+            "Failed to read original file in path \"{}\": {}",
+            original_file_path, err
+        )
+    })?;
+    let modified = fs::read(modified_file_path).map_err(|err| {
+        format!(
+            "Failed to read modified file in path \"{}\": {}",
+            modified_file_path, err
+        )
+    })?;
+    let patch = format.create_patch(&original, &modified)?;
+    fs::write(output_patch_file_path, &patch).map_err(|err| {
+        format!(
+            "Failed to write patch file to path \"{}\": {}",
+            output_patch_file_path, err
+        )
+    })?;
+    println!(
+        "{:?} patch of {} bytes written to \"{}\".",
+        format,
+        patch.len(),
+        output_patch_file_path
+    );
+    Ok(())
+}
+/// Analyzes a whole Playstation executable, listing found functions and how much of it is code.
+fn ps1exe_analyze(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let input_ps1_exe_file_path = get_arg!(args, 0, "input PS1 EXE file path")?;
+    let options = &args[1..];
+    let symbols = get_symbol_map_option(options)?;
+    let get_file_path_option =
+        |name: &str, format: &str| match options.iter().position(|option| option == name) {
+            Some(option_index) => options.get(option_index + 1).map(Some).ok_or(format!(
+                "No {} file path given after \"{}\" option.",
+                format, name
+            )),
+            None => Ok(None),
+        };
+    let dot_file_path = get_file_path_option("--dot", "DOT")?;
+    let json_file_path = get_file_path_option("--json", "JSON")?;
+
+    let ps1_exe = open_ps1_exe(input_ps1_exe_file_path, options)?;
+    let ps1_exe_reader = PS1ExeReader::new(&ps1_exe);
+    let analysis = ps1_exe_reader.analyze(&symbols);
+    let labels = symbols.get_labels();
+
+    println!("Functions: {}", analysis.functions.len());
+    for function in analysis.functions.values() {
+        let range = function.get_range();
+        println!(
+            "0x{:08X} {} size 0x{:X}, {} basic blocks, {} calls",
+            function.entry,
+            labels
+                .get(&function.entry)
+                .cloned()
+                .unwrap_or_else(|| format!("FUN_{:08x}", function.entry)),
+            range.end - range.start,
+            function.blocks.len(),
+            function.calls.len()
+        );
+    }
+    let file_size = ps1_exe.file_size as f32;
+    for (kind, description) in [
+        (RegionKind::Function, "Code in functions"),
+        (RegionKind::Code, "Other code"),
+        (RegionKind::Data, "Data"),
+    ] {
+        let byte_count = analysis.get_byte_count(kind);
+        println!(
+            "{}: {} bytes ({:.2}% of file size)",
+            description,
+            byte_count,
+            byte_count as f32 / file_size * 100.0
+        );
+    }
+
+    for (file_path, content) in [
+        (dot_file_path, analysis.get_call_graph_dot(&labels)),
+        (json_file_path, analysis.get_call_graph_json(&labels)),
+    ] {
+        let Some(file_path) = file_path else {
+            continue;
+        };
+        fs::write(file_path, content)
+            .map_err(|err| format!("Failed to write file to path \"{}\": {}", file_path, err))?;
+        println!("Call graph written to \"{}\".", file_path);
+    }
+    Ok(())
+}
+/// Assembles MIPS assembly code from a given text file into a Playstation executable.
+fn ps1exe_assemble(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let input_assembly_code_file_path = get_arg!(args, 0, "input assembly code file path")?;
+
+    // A directory is assembled as a project of all its assembly code files (".s"),
+    // which share their labels, like files written by ps1exe-split. Its header file
+    // makes a blank executable to assemble them onto, instead of an input executable.
+    let is_project = Path::new(input_assembly_code_file_path).is_dir();
+    let (input_ps1_exe_file_path, output_ps1_exe_file_path, options) = match is_project {
+        true => {
+            let output_ps1_exe_file_path = get_arg!(args, 1, "output PS1 EXE file path")?;
+            if args.get(2).is_some_and(|arg| !arg.starts_with("--")) {
+                return Err(format!(
+                    "Project directory \"{}\" is assembled onto a blank executable made out of its \"{}\" file, so no input PS1 EXE file path is needed.",
+                    input_assembly_code_file_path, SPLIT_HEADER_FILE_NAME
+                )
+                .into());
+            }
+            (None, output_ps1_exe_file_path, &args[2..])
+        }
+        false => (
+            Some(get_arg!(args, 1, "input PS1 EXE file path")?),
+            get_arg!(args, 2, "output PS1 EXE file path")?,
+            &args[3..],
+        ),
+    };
+    let codec = get_text_codec_option(options)?;
+    let symbols = get_symbol_map_option(options)?;
+    let free_ranges = get_free_ranges_option(options)?;
+    let is_permissive = options.iter().any(|option| option == "--permissive");
+    let input_assembly_code_file_paths = if is_project {
+        let entries = fs::read_dir(input_assembly_code_file_path).map_err(|err| {
+            format!(
+                "Failed to read given input directory in path \"{}\": {}",
+                input_assembly_code_file_path, err
+            )
+        })?;
+        let mut file_paths = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|extension| extension == "s"))
+            .map(|path| path.to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        file_paths.sort();
+        file_paths
+    } else {
+        vec![input_assembly_code_file_path.clone()]
+    };
+
+    let mut input_files = Vec::new();
+    for input_assembly_code_file_path in input_assembly_code_file_paths {
+        let mut input_file = File::open(&input_assembly_code_file_path).map_err(|_| {
+            format!(
+                "Failed to open given input MIPS assembly code file in path \"{}\".",
+                input_assembly_code_file_path
+            )
+        })?;
+
+        let mut input_file_content = String::new();
+        input_file
+            .read_to_string(&mut input_file_content)
+            .map_err(|_| {
+                format!(
+                    "Failed to read given input MIPS assembly code file in path \"{}\".",
+                    input_assembly_code_file_path
+                )
+            })?;
+        input_files.push((input_assembly_code_file_path, input_file_content));
+    }
+
+    // Files included with ".incbin" are relative to the assembly code file (or the project directory).
+    let encode_text = |text: &str| codec.encode(text);
+    let mut options = ParseOptions {
+        external_labels: symbols.get_addresses(),
+        include_directory: match is_project {
+            true => Path::new(input_assembly_code_file_path).to_path_buf(),
+            false => Path::new(input_assembly_code_file_path)
+                .parent()
+                .map(Path::to_path_buf)
+                .unwrap_or_default(),
+        },
+        encode_text: &encode_text,
+        section_addresses: HashMap::new(),
+    };
+
+    let mut ps1_exe = match input_ps1_exe_file_path {
+        Some(input_ps1_exe_file_path) => {
+            println!("Input PS1 EXE file path: {}", input_ps1_exe_file_path);
+            PS1Exe::from_file_path(input_ps1_exe_file_path)?
+        }
+        None => {
+            let header_file_path =
+                Path::new(input_assembly_code_file_path).join(SPLIT_HEADER_FILE_NAME);
+            let header = fs::read_to_string(&header_file_path).map_err(|err| {
+                format!(
+                    "Failed to read header file of the project in path \"{}\": {}",
+                    header_file_path.display(),
+                    err
+                )
+            })?;
+            PS1ExeBuilder::from_split_header(&header)
+                .and_then(PS1ExeBuilder::build)
+                .map_err(|err| {
+                    format!(
+                        "Failed to make a blank executable out of header file in path \"{}\": {}",
+                        header_file_path.display(),
+                        err
+                    )
+                })?
+        }
+    };
+
+    // Sections of new code and data are placed into free regions or appended to the executable,
+    // before parsing, so their labels have addresses.
+    let sections = input_files
+        .iter()
+        .flat_map(|(_, input_file_content)| parse_section_sizes(input_file_content, &options))
+        .collect::<Vec<_>>();
+    for placement in ps1_exe.place_sections(&sections, &free_ranges)? {
+        println!(
+            "Section {} placed at 0x{:X}-0x{:X} ({} bytes){}",
+            placement.name,
+            placement.address,
+            placement.address + placement.size,
+            placement.size,
+            match placement.is_appended {
+                true => ", growing the executable",
+                false => "",
+            }
+        );
+        options
+            .section_addresses
+            .insert(placement.name, placement.address);
+    }
+
+    if is_project {
+        let mut label_file_paths: HashMap<String, &str> = HashMap::new();
+        let mut project_labels = HashMap::new();
+        for (input_assembly_code_file_path, input_file_content) in input_files.iter() {
+            for (label, address) in parse_labels(input_file_content, &options) {
+                if let Some(other_file_path) =
+                    label_file_paths.insert(label.clone(), input_assembly_code_file_path)
+                {
+                    return Err(format!(
+                        "Label \"{}\" is defined in both \"{}\" and \"{}\".",
+                        label, other_file_path, input_assembly_code_file_path
+                    )
+                    .into());
+                }
+                project_labels.insert(label, address);
+            }
+        }
+        options.external_labels.extend(project_labels);
+    }
+
+    let mut nodes = Vec::new();
+    for (input_assembly_code_file_path, input_file_content) in input_files.iter() {
+        let file_nodes =
+            parse_nodes_with_options(input_file_content, &options).map_err(|errors| {
+                format!(
+                    "Failed to parse given input MIPS assembly code file in path \"{}\" ({} errors):\n{}",
+                    input_assembly_code_file_path,
+                    errors.len(),
+                    errors
+                        .iter()
+                        .map(|error| error.to_string_with_snippet(input_file_content))
+                        .collect::<Vec<_>>()
+                        .join("\n")
+                )
+            })?;
+        nodes.extend(
+            file_nodes
+                .into_iter()
+                .map(|node| (input_assembly_code_file_path.as_str(), node)),
+        );
+    }
+
+    // Jumps written over existing code by "@hook" must not break delay slots.
+    for (i, (file_path, node)) in nodes.iter().enumerate() {
+        let NodeKind::CustomCommand(CustomCommand::Hook(address)) = node.kind else {
+            continue;
+        };
+        let instruction = nodes[i + 1..]
+            .iter()
+            .map(|(_, node)| node)
+            .find(|node| !matches!(node.kind, NodeKind::Label(_)))
+            .and_then(|node| match &node.kind {
+                NodeKind::Instruction(instruction) => Some(instruction),
+                _ => None,
+            })
+            .ok_or(format!(
+                "Hook at 0x{:X} on {}:{} is not followed by a j or jal instruction.",
+                address, file_path, node.line
+            ))?;
+        ps1_exe
+            .check_hook(address, instruction)
+            .map_err(|err| format!("{}:{}: {}", file_path, node.line, err))?;
+    }
 
     // Print Playstation executable header information
     println!(
@@ -271,144 +1101,119 @@ fn ps1exe_assemble(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
     println!("PS1 EXE initial GP R28: 0x{:X}", ps1_exe.initial_gp_r28);
     println!("PS1 EXE initial PC value: 0x{:X}", ps1_exe.initial_pc);
     println!("PS1 EXE ASCII marker: {}", ps1_exe.ascii_marker);
+    println!("PS1 EXE region: {}", ps1_exe.get_region());
+    println!(
+        "PS1 EXE BSS: 0x{:X} ({} bytes)",
+        ps1_exe.bss_address, ps1_exe.bss_size
+    );
+    println!(
+        "PS1 EXE initial stack pointer: 0x{:X} + 0x{:X}",
+        ps1_exe.stack_base_address, ps1_exe.stack_offset
+    );
 
-    enum UnfinishedOperation{
-        Addr{
+    enum UnfinishedOperation {
+        Addr {
             address: u64,
             name: String,
-        }
+            source: WriteSource,
+        },
     }
 
     let mut constants = HashMap::new();
-    let mut current_address = 0;
     let mut ps1_exe_writer = PS1ExeWriter::new(&mut ps1_exe);
     let mut unfinished_operations = Vec::new();
 
     use colored::*;
 
-    for node in nodes.iter() {
+    // Every byte of a project's blank executable changes, so changes are only told
+    // for an input executable.
+    let print_change = |message: String| {
+        if !is_project {
+            println!("{}", message.red());
+        }
+    };
+
+    for (file_path, node) in nodes.iter() {
+        let source = WriteSource {
+            file_path: file_path.to_string(),
+            line: Some(node.line),
+        };
         match &node.kind {
             NodeKind::Addr(name) => {
-                current_address += 4;
-
                 if let Some(matching_address) = constants.get(name) {
                     let bytes = (*matching_address as u32).to_le_bytes();
                     if let PS1ExeWriteResult::Changed { original_code } =
-                        ps1_exe_writer.write_code(current_address, &bytes)
+                        ps1_exe_writer.write_code(node.address, &bytes, &source)?
                     {
-                        println!(
-                            "{}",
-                            format!(
-                                "Line {}: Addr to {} - changed bytes to {:?} from {:?}",
-                                node.line,
-                                name,
-                                bytes,
-                                original_code
-                            )
-                            .red()
-                        );
+                        print_change(format!(
+                            "{}: Addr to {} - changed bytes to {:?} from {:?}",
+                            source, name, bytes, original_code
+                        ));
                     }
-                }
-                else {
-                    unfinished_operations.push(UnfinishedOperation::Addr{
-                        address: current_address,
+                } else {
+                    unfinished_operations.push(UnfinishedOperation::Addr {
+                        address: node.address,
                         name: name.clone(),
+                        source: source.clone(),
                     });
                 }
             }
-            NodeKind::CustomCommand(command) => match command {
-                CustomCommand::At(address) => {
-                    current_address = *address - 4;
+            NodeKind::Data(bytes) => {
+                if let PS1ExeWriteResult::Changed { original_code } =
+                    ps1_exe_writer.write_code(node.address, bytes, &source)?
+                {
+                    print_change(format!(
+                        "{}: Data - changed bytes to {:?} from {:?}",
+                        source, bytes, original_code
+                    ));
                 }
-            },
+            }
             NodeKind::Instruction(instruction) => {
-                current_address += 4;
-
                 if let PS1ExeWriteResult::Changed { original_code } =
-                    ps1_exe_writer.write_code(current_address, &instruction.to_le_bytes())
+                    ps1_exe_writer.write_code(node.address, &instruction.to_le_bytes(), &source)?
                 {
-                    println!(
-                        "{}",
-                        format!(
-                            "Line {}: {} - changed bytes to {:?} from {:?}",
-                            node.line,
-                            instruction.to_instruction(),
-                            instruction.to_le_bytes(),
-                            original_code
-                        )
-                        .red()
-                    );
+                    print_change(format!(
+                        "{}: {} - changed bytes to {:?} from {:?}",
+                        source,
+                        instruction.to_instruction()?,
+                        instruction.to_le_bytes(),
+                        original_code
+                    ));
                 }
             }
             NodeKind::IntegerAssignment(variable_name, value) => {
-                current_address += 4;
-
                 constants.insert(variable_name, node.address);
 
                 if let PS1ExeWriteResult::Changed { original_code } =
-                    ps1_exe_writer.write_code(current_address, &value.to_le_bytes())
+                    ps1_exe_writer.write_code(node.address, &value.to_le_bytes(), &source)?
                 {
-                    println!(
-                        "{}",
-                        format!(
-                            "Line {}: Assignment {} = {} - changed bytes to {:?} from {:?}",
-                            node.line,
-                            variable_name,
-                            value,
-                            value.to_le_bytes(),
-                            original_code
-                        )
-                        .red()
-                    );
+                    print_change(format!(
+                        "{}: Assignment {} = {} - changed bytes to {:?} from {:?}",
+                        source,
+                        variable_name,
+                        value,
+                        value.to_le_bytes(),
+                        original_code
+                    ));
                 }
             }
             NodeKind::StringAssignment(variable_name, value) => {
-                current_address += 4;
-
                 constants.insert(variable_name, node.address);
 
-                let value_bytes = value.as_bytes();
-                let mut new_value_bytes = Vec::<u8>::with_capacity(value_bytes.len() + 1);
-
-                let mut i = 0;
-                while i < value_bytes.len() {
-                    let b = value_bytes[i];
-                    // If byte is a backslash (\)
-                    if b == 92 && (i + 1) < value_bytes.len() {
-                        i += 1;
-                        // If byte is 0 digit (null termination byte)
-                        if value_bytes[i] == 48 {
-                            i += 1;
-                            new_value_bytes.push(0);
-                        }
-                        else{
-                            return Err(format!(
-                                "Failed to parse given string \"{}\". Invalid escape sequence \"\\{}\" at index {}.",
-                                value, value_bytes[i], i
-                            ).into());
-                        }
-                    }
-                    else{
-                        new_value_bytes.push(b);
-                    }
-                    i += 1;
-                }
+                let new_value_bytes = codec.encode(value).map_err(|err| {
+                    format!(
+                        "Failed to parse given string \"{}\" on line {}: {}",
+                        value, node.line, err
+                    )
+                })?;
 
                 if let PS1ExeWriteResult::Changed { original_code } =
-                    ps1_exe_writer.write_code(current_address, &new_value_bytes[..])
+                    ps1_exe_writer.write_code(node.address, &new_value_bytes[..], &source)?
                 {
-                    println!(
-                        "{}",
-                        format!(
-                            "Line {}: Assignment {} = {} - changed bytes to {:?} from {:?}",
-                            node.line,
-                            variable_name,
-                            value,
-                            value.as_bytes(),
-                            original_code
-                        )
-                        .red()
-                    );
+                    print_change(format!(
+                        "{}: Assignment {} = {} - changed bytes to {:?} from {:?}",
+                        source, variable_name, value, new_value_bytes, original_code
+                    ));
                 }
             }
             _ => {}
@@ -417,23 +1222,27 @@ fn ps1exe_assemble(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
 
     for operation in unfinished_operations.iter() {
         match operation {
-            UnfinishedOperation::Addr { address, name } => {
-                if let Some(matching_address) = constants.get(name) {
-                    let bytes = (*matching_address as u32).to_le_bytes();
+            UnfinishedOperation::Addr {
+                address,
+                name,
+                source,
+            } => {
+                // Constants not found in the code may be symbols of the symbol map.
+                let matching_address = constants
+                    .get(name)
+                    .copied()
+                    .or_else(|| symbols.get_by_name(name).map(|symbol| symbol.address));
+                if let Some(matching_address) = matching_address {
+                    let bytes = (matching_address as u32).to_le_bytes();
                     if let PS1ExeWriteResult::Changed { original_code } =
-                        ps1_exe_writer.write_code(*address, &bytes)
+                        ps1_exe_writer.write_code(*address, &bytes, source)?
                     {
-                        println!(
-                            "{}",
-                            format!(
-                                "Addr to {} - changed bytes to {:?} from {:?}",
-                                name, bytes, original_code
-                            )
-                            .red()
-                        );
+                        print_change(format!(
+                            "{}: Addr to {} - changed bytes to {:?} from {:?}",
+                            source, name, bytes, original_code
+                        ));
                     }
-                }
-                else {
+                } else {
                     return Err(format!(
                         "Failed to find constant \"{}\" at address {}.",
                         name, address
@@ -444,6 +1253,52 @@ fn ps1exe_assemble(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
         }
     }
 
+    // Bytes written more than once are mistakes, unless later writes are meant to win.
+    let conflicts = ps1_exe_writer.get_conflicts();
+    if !conflicts.is_empty() {
+        let report = format!(
+            "{} conflicting writes:\n{}",
+            conflicts.len(),
+            conflicts
+                .iter()
+                .map(|conflict| format!("  {}", conflict))
+                .collect::<Vec<_>>()
+                .join("\n")
+        );
+        if !is_permissive {
+            return Err(format!(
+                "{}\nUse \"--permissive\" option to write anyway, with later writes winning.",
+                report
+            )
+            .into());
+        }
+        println!("{}", format!("Warning: {}", report).yellow());
+    }
+
+    // Code and data not covered by a project are left blank, so they are mistakes too,
+    // like a missing file.
+    let unwritten_ranges = ps1_exe_writer.get_unwritten_ranges();
+    if is_project && !unwritten_ranges.is_empty() {
+        let report = format!(
+            "{} ranges not written by the project, left as zeros:\n{}",
+            unwritten_ranges.len(),
+            unwritten_ranges
+                .iter()
+                .map(|range| format!(
+                    "  0x{:08X}-0x{:08X} ({} bytes)",
+                    range.start,
+                    range.end,
+                    range.end - range.start
+                ))
+                .collect::<Vec<_>>()
+                .join("\n")
+        );
+        if !is_permissive {
+            return Err(format!("{}\nUse \"--permissive\" option to write anyway.", report).into());
+        }
+        println!("{}", format!("Warning: {}", report).yellow());
+    }
+
     ps1_exe_writer.write_into_file(output_ps1_exe_file_path)?;
 
     println!(
@@ -457,9 +1312,12 @@ fn ps1exe_assemble(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
     println!("Done!");
     Ok(())
 }
-/// Disassembles a section of MIPS assembly code from a given Playstation executable binary.
+/// Disassembles a section of MIPS assembly code from a given Playstation executable binary or overlay.
 fn ps1exe_disassemble(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
-    if args.len() > 3 {
+    if args
+        .get(2)
+        .is_some_and(|arg| arg.starts_with("--") && arg != "--string")
+    {
         // Disassemble MIPS assembly code from one given address (as hexadecimal) memory until another given address
         let input_ps1_exe_file_path = get_arg!(args, 0, "input PS1 EXE file path")?;
 
@@ -476,7 +1334,7 @@ fn ps1exe_disassemble(args: &[String]) -> Result<(), Box<dyn std::error::Error>>
 
         if option == "--until" {
             let end_address_in_memory = get_arg!(args, 3, "end address in memory")?;
-                let end_address_in_memory =
+            let end_address_in_memory =
                     u64::from_str_radix(end_address_in_memory, 16).map_err(|_| {
                         format!(
                             "Failed to parse given end address in memory \"{}\" as a hexadecimal number.",
@@ -484,20 +1342,26 @@ fn ps1exe_disassemble(args: &[String]) -> Result<(), Box<dyn std::error::Error>>
                         )
                     })?;
 
-                let ps1_exe = PS1Exe::from_file_path(&input_ps1_exe_file_path)?;
+            let ps1_exe = open_ps1_exe(input_ps1_exe_file_path, &args[4..])?;
 
-                if start_address_in_memory > end_address_in_memory {
-                    return Err(format!(
+            if start_address_in_memory > end_address_in_memory {
+                return Err(format!(
                         "Start address in memory \"{}\" is greater than end address in memory \"{}\". Start address in memory should be less than end address in memory.",
                         start_address_in_memory, end_address_in_memory
                     ).into());
-                }
+            }
 
-                let ps1_exe_reader = PS1ExeReader::new(&ps1_exe);
-                let instruction_count = (end_address_in_memory - start_address_in_memory) as usize / 4; // 4 bytes per instruction
-                ps1_exe_reader.disassemble_at_adress_by_count(start_address_in_memory, instruction_count);
-        }
-        else{
+            let symbols = get_symbol_map_option(&args[4..])?;
+            let fold_pseudo_instructions = args[4..].iter().any(|arg| arg == "--pseudo");
+            let ps1_exe_reader = PS1ExeReader::new(&ps1_exe);
+            let instruction_count = (end_address_in_memory - start_address_in_memory) as usize / 4; // 4 bytes per instruction
+            ps1_exe_reader.disassemble_at_adress_by_count(
+                start_address_in_memory,
+                instruction_count,
+                &symbols,
+                fold_pseudo_instructions,
+            )?;
+        } else {
             return Err(format!(
                 "Invalid option given after the start address \"{}\". Valid until option is \"until\".",
                 option
@@ -508,29 +1372,46 @@ fn ps1exe_disassemble(args: &[String]) -> Result<(), Box<dyn std::error::Error>>
         let input_ps1_exe_file_path = get_arg!(args, 0, "input PS1 EXE file path")?;
 
         let start_address_in_memory = get_arg!(args, 1, "address in memory")?;
-        let start_address_in_memory = u64::from_str_radix(start_address_in_memory, 16).map_err(|_| {
-            format!(
-                "Failed to parse given address in memory \"{}\" as a hexadecimal number.",
-                start_address_in_memory
-            )
-        })?;
+        let start_address_in_memory =
+            u64::from_str_radix(start_address_in_memory, 16).map_err(|_| {
+                format!(
+                    "Failed to parse given address in memory \"{}\" as a hexadecimal number.",
+                    start_address_in_memory
+                )
+            })?;
 
         let instruction_count_or_option = get_arg!(args, 2, "instruction count")?;
 
         if instruction_count_or_option == "--string" {
-            let ps1_exe = PS1Exe::from_file_path(&input_ps1_exe_file_path)?;
+            let codec = get_text_codec_option(&args[3..])?;
+            let symbols = get_symbol_map_option(&args[3..])?;
+            let ps1_exe = open_ps1_exe(input_ps1_exe_file_path, &args[3..])?;
 
             let ps1_exe_reader = PS1ExeReader::new(&ps1_exe);
             let end_byte = 0x00; // Null termination byte
-            let value = ps1_exe_reader.disassemble_str_at_address_until_byte(start_address_in_memory, end_byte).map_err(|err| {
-                format!(
-                    "Failed to disassemble string at address \"{}\" until byte \"{}\": {}",
-                    start_address_in_memory, end_byte, err.to_string()
+            let value = ps1_exe_reader
+                .disassemble_str_at_address_until_byte(
+                    start_address_in_memory,
+                    end_byte,
+                    codec.as_ref(),
                 )
-            })?;
-            println!("@at 0x{:x}\n{} TEMP = \"{}\"", start_address_in_memory, mips::KEYWORD_CONST, value);
-        }
-        else {
+                .map_err(|err| {
+                    format!(
+                        "Failed to disassemble string at address \"{}\" until byte \"{}\": {}",
+                        start_address_in_memory, end_byte, err
+                    )
+                })?;
+            let name = symbols
+                .get_by_address(start_address_in_memory)
+                .map_or("TEMP", |symbol| symbol.name.as_str());
+            println!(
+                "@at 0x{:x}\n{} {} = \"{}\"",
+                start_address_in_memory,
+                mips::KEYWORD_CONST,
+                name,
+                value
+            );
+        } else {
             // Disassemble MIPS assembly code from given address (as hexadecimal) memory onwards
             let instruction_count = instruction_count_or_option.parse::<usize>().map_err(|_| {
                 format!(
@@ -538,16 +1419,118 @@ fn ps1exe_disassemble(args: &[String]) -> Result<(), Box<dyn std::error::Error>>
                     instruction_count_or_option
                 )
             })?;
-    
-            let ps1_exe = PS1Exe::from_file_path(&input_ps1_exe_file_path)?;
-    
+
+            let symbols = get_symbol_map_option(&args[3..])?;
+            let fold_pseudo_instructions = args[3..].iter().any(|arg| arg == "--pseudo");
+            let ps1_exe = open_ps1_exe(input_ps1_exe_file_path, &args[3..])?;
+
             let ps1_exe_reader = PS1ExeReader::new(&ps1_exe);
-            ps1_exe_reader.disassemble_at_adress_by_count(start_address_in_memory, instruction_count);
+            ps1_exe_reader.disassemble_at_adress_by_count(
+                start_address_in_memory,
+                instruction_count,
+                &symbols,
+                fold_pseudo_instructions,
+            )?;
         }
     }
 
     Ok(())
 }
+/// Disassembles a function from a given Playstation executable by following its branches and jumps,
+/// optionally writing its control flow graph as Graphviz DOT.
+fn ps1exe_disassemble_function(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let input_ps1_exe_file_path = get_arg!(args, 0, "input PS1 EXE file path")?;
+    let entry_address_in_memory = get_arg!(args, 1, "entry address in memory")?;
+    let entry_address_in_memory =
+        u64::from_str_radix(entry_address_in_memory, 16).map_err(|_| {
+            format!(
+                "Failed to parse given entry address in memory \"{}\" as a hexadecimal number.",
+                entry_address_in_memory
+            )
+        })?;
+    let options = &args[2..];
+    let symbols = get_symbol_map_option(options)?;
+    let fold_pseudo_instructions = options.iter().any(|arg| arg == "--pseudo");
+    let dot_file_path = match options.iter().position(|option| option == "--dot") {
+        Some(option_index) => Some(
+            options
+                .get(option_index + 1)
+                .ok_or("No DOT file path given after \"--dot\" option.")?,
+        ),
+        None => None,
+    };
+
+    let ps1_exe = open_ps1_exe(input_ps1_exe_file_path, options)?;
+    let ps1_exe_reader = PS1ExeReader::new(&ps1_exe);
+    let function = ps1_exe_reader.analyze_function(entry_address_in_memory);
+    if function.blocks.is_empty() {
+        return Err(format!(
+            "No valid instruction found at entry address 0x{:X}.",
+            entry_address_in_memory
+        )
+        .into());
+    }
+    ps1_exe_reader.disassemble_function(&function, &symbols, fold_pseudo_instructions)?;
+
+    if let Some(dot_file_path) = dot_file_path {
+        let labels = ps1_exe_reader.get_function_labels(&function, &symbols);
+        let dot = function.to_dot(&labels, &|address| ps1_exe_reader.read_instruction(address));
+        fs::write(dot_file_path, dot).map_err(|err| {
+            format!(
+                "Failed to write DOT file to path \"{}\": {}",
+                dot_file_path, err
+            )
+        })?;
+        println!("# Control flow graph written to \"{}\".", dot_file_path);
+    }
+    Ok(())
+}
+/// Splits a whole Playstation executable into a directory of assembly code files.
+fn ps1exe_split(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let input_ps1_exe_file_path = get_arg!(args, 0, "input PS1 EXE file path")?;
+    let output_directory_path = get_arg!(args, 1, "output directory path")?;
+    let options = &args[2..];
+    let symbols = get_symbol_map_option(options)?;
+    let fold_pseudo_instructions = options.iter().any(|arg| arg == "--pseudo");
+
+    let ps1_exe = open_ps1_exe(input_ps1_exe_file_path, options)?;
+    let ps1_exe_reader = PS1ExeReader::new(&ps1_exe);
+    let mut files = ps1_exe_reader.split(&symbols, fold_pseudo_instructions);
+    // Overlays have no header to assemble them back onto.
+    let is_overlay = get_overlay_option(options)?.is_some();
+    if !is_overlay {
+        files.push(ps1_exe_reader.split_header());
+    }
+
+    fs::create_dir_all(output_directory_path).map_err(|err| {
+        format!(
+            "Failed to make output directory in path \"{}\": {}",
+            output_directory_path, err
+        )
+    })?;
+    for file in files.iter() {
+        let file_path = Path::new(output_directory_path).join(&file.name);
+        fs::write(&file_path, &file.content).map_err(|err| {
+            format!(
+                "Failed to write assembly code file to path \"{}\": {}",
+                file_path.display(),
+                err
+            )
+        })?;
+    }
+    println!(
+        "{} files written to \"{}\".",
+        files.len(),
+        output_directory_path
+    );
+    if !is_overlay {
+        println!(
+            "Assemble them back with: ps1exe-assemble {} <output PS1 EXE file path>",
+            output_directory_path
+        );
+    }
+    Ok(())
+}
 /// Checks the given ROM file structure for correctness.
 fn rom_check(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
     let rom_path = get_arg!(args, 0, "ROM path")?;
@@ -561,7 +1544,7 @@ fn rom_check(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
     let vd_locations = volume.read_volume_descriptor_locations().map_err(|e| {
         format!(
             "ROM file given has invalid data: failed to read volume descriptor locations: {}",
-            e.to_string()
+            e
         )
     })?;
 
@@ -570,7 +1553,7 @@ fn rom_check(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
         .map_err(|e| {
             format!(
                 "ROM file given has invalid data: failed to read primary volume descriptor: {}",
-                e.to_string()
+                e
             )
         })?;
 
@@ -594,7 +1577,7 @@ fn rom_check(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
         root_record.recording_date_and_time_formatted(),
         "1998-08-13 16:56:05"
     );
-    assert_eq!(root_record.is_dir(), true);
+    assert!(root_record.is_dir());
     root_record_assert_eq!(
         "file identifier length",
         root_record.file_identifier_length,
@@ -611,12 +1594,12 @@ fn rom_check(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
         .map_err(|err| {
             format!(
                 "ROM file given has invalid data: failed to read sub-records by root directory: {}",
-                err.to_string()
+                err
             )
         })?;
 
     // An array of tuples containing the expected sub-record name and whether it is a directory.
-    const TOP_LEVEL_ENTRIES: &[(&'static str, bool)] = &[
+    const TOP_LEVEL_ENTRIES: &[(&str, bool)] = &[
         ("SOURCE", true),
         ("PETEXA0.STR", false),
         ("PETEXA1.STR", false),
@@ -672,8 +1655,8 @@ fn rom_check(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
 
     // Read the sub-records of the SOURCE directory and ensure the expected directories and files are present.
     let source_dir_records = volume
-        .read_directory_records(&sub_records[02], pvd.logical_block_size)
-        .map_err(|err| format!("ROM file given has invalid data: failed to read sub-records in SOURCE directory: {}", err.to_string()))?;
+        .read_directory_records(&sub_records[2], pvd.logical_block_size)
+        .map_err(|err| format!("ROM file given has invalid data: failed to read sub-records in SOURCE directory: {}", err))?;
 
     let source_dir_entries_num = 3;
     if source_dir_records.len() != source_dir_entries_num {
@@ -684,16 +1667,15 @@ fn rom_check(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
         .into());
     }
 
-    if source_dir_records[02].file_identifier_as_string() != "SOURCE.TRD" {
-        return Err(format!(
+    if source_dir_records[2].file_identifier_as_string() != "SOURCE.TRD" {
+        return Err(
             "ROM file given has invalid data: SOURCE.TRD in SOURCE directory is missing."
-        )
-        .into());
+                .to_string()
+                .into(),
+        );
     }
-    if source_dir_records[02].is_dir() {
-        return Err(format!(
-            "ROM file given has invalid data: SOURCE.TRD in SOURCE directory should be a file, not a directory."
-        )
+    if source_dir_records[2].is_dir() {
+        return Err("ROM file given has invalid data: SOURCE.TRD in SOURCE directory should be a file, not a directory.".to_string()
         .into());
     }
 
@@ -716,7 +1698,7 @@ fn rom_extract(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
     let vd_locations = volume.read_volume_descriptor_locations().map_err(|e| {
         format!(
             "ROM file given has invalid data: failed to read volume descriptor locations: {}",
-            e.to_string()
+            e
         )
     })?;
 
@@ -725,7 +1707,7 @@ fn rom_extract(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
         .map_err(|e| {
             format!(
                 "ROM file given has invalid data: failed to read primary volume descriptor: {}",
-                e.to_string()
+                e
             )
         })?;
 
@@ -738,7 +1720,7 @@ fn rom_extract(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
         .map_err(|err| {
             format!(
                 "ROM file given has invalid data: failed to read sub-records by root directory: {}",
-                err.to_string()
+                err
             )
         })?;
 
@@ -760,8 +1742,7 @@ fn rom_extract(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
     fs::write(entry_extract_path, entry_record_data).map_err(|err| {
         format!(
             "Failed to write extracted file from ROM to path \"{}\": {}",
-            entry_extract_path,
-            err.to_string()
+            entry_extract_path, err
         )
     })?;
 
@@ -785,7 +1766,7 @@ fn rom_list(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
     let vd_locations = volume.read_volume_descriptor_locations().map_err(|e| {
         format!(
             "ROM file given has invalid data: failed to read volume descriptor locations: {}",
-            e.to_string()
+            e
         )
     })?;
 
@@ -794,7 +1775,7 @@ fn rom_list(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
         .map_err(|e| {
             format!(
                 "ROM file given has invalid data: failed to read primary volume descriptor: {}",
-                e.to_string()
+                e
             )
         })?;
 
@@ -807,7 +1788,7 @@ fn rom_list(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
         .map_err(|err| {
             format!(
                 "ROM file given has invalid data: failed to read sub-records by root directory: {}",
-                err.to_string()
+                err
             )
         })?;
 
@@ -838,7 +1819,7 @@ fn rom_replace(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
     let vd_locations = volume.read_volume_descriptor_locations().map_err(|e| {
         format!(
             "ROM file given has invalid data: failed to read volume descriptor locations: {}",
-            e.to_string()
+            e
         )
     })?;
 
@@ -847,7 +1828,7 @@ fn rom_replace(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
         .map_err(|e| {
             format!(
                 "ROM file given has invalid data: failed to read primary volume descriptor: {}",
-                e.to_string()
+                e
             )
         })?;
 
@@ -860,7 +1841,7 @@ fn rom_replace(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
         .map_err(|err| {
             format!(
                 "ROM file given has invalid data: failed to read sub-records by root directory: {}",
-                err.to_string()
+                err
             )
         })?;
 
@@ -906,6 +1887,376 @@ fn rom_replace(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
     );
     Ok(())
 }
+/// Converts a symbol map file between formats (open-spyro, splat, ida and nocash).
+fn symbols_convert(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let input_file_path = get_arg!(args, 0, "input symbol map file path")?;
+    let input_format = get_arg!(args, 1, "input symbol map format")?;
+    let output_file_path = get_arg!(args, 2, "output symbol map file path")?;
+    let output_format = get_arg!(args, 3, "output symbol map format")?;
+
+    let input_format = SymbolMapFormat::from_name(input_format)?;
+    let output_format = SymbolMapFormat::from_name(output_format)?;
+
+    let symbols = SymbolMap::from_file_path(input_file_path, input_format)?;
+    fs::write(output_file_path, symbols.write(output_format)).map_err(|err| {
+        format!(
+            "Failed to write symbol map file to path \"{}\": {}",
+            output_file_path, err
+        )
+    })?;
+
+    println!(
+        "Converted {} symbols into \"{}\".",
+        symbols.symbols().len(),
+        output_file_path
+    );
+    Ok(())
+}
+/// Gets a text codec by an optional "--codec <name>" option. ASCII is used by default.
+fn get_text_codec_option(options: &[String]) -> Result<Box<dyn TextCodec>, String> {
+    match options.iter().position(|option| option == "--codec") {
+        Some(option_index) => {
+            let name = options.get(option_index + 1).ok_or_else(|| {
+                format!(
+                    "No text codec given after \"--codec\" option. Supported text codecs include {}.",
+                    ps1exe::TEXT_CODEC_NAMES.join(", ")
+                )
+            })?;
+            ps1exe::get_text_codec_by_name(name)
+        }
+        None => Ok(Box::new(ps1exe::AsciiCodec)),
+    }
+}
+/// Gets the symbol map given with "--symbols <path>" option (in open-spyro format).
+/// Without the option, the symbol map is empty.
+fn get_symbol_map_option(options: &[String]) -> Result<SymbolMap, String> {
+    match options.iter().position(|option| option == "--symbols") {
+        Some(option_index) => {
+            let file_path = options
+                .get(option_index + 1)
+                .ok_or("No symbol map file path given after \"--symbols\" option.")?;
+            let symbols = SymbolMap::from_file_path(file_path, SymbolMapFormat::OpenSpyro)?;
+            // Symbols of other overlays would label the same addresses wrongly.
+            let overlay = get_overlay_option(options)?;
+            Ok(symbols.for_overlay(overlay.as_ref().map(|(name, _)| name.as_str())))
+        }
+        None => Ok(SymbolMap::new()),
+    }
+}
+/// Gets the name and the load address (hexadecimal) of an overlay given with
+/// "--overlay <name> <load address>" option.
+fn get_overlay_option(options: &[String]) -> Result<Option<(String, u32)>, String> {
+    let Some(option_index) = options.iter().position(|option| option == "--overlay") else {
+        return Ok(None);
+    };
+    let (Some(name), Some(load_address)) =
+        (options.get(option_index + 1), options.get(option_index + 2))
+    else {
+        return Err(String::from(
+            "No overlay name and load address given after \"--overlay\" option.",
+        ));
+    };
+    let load_address = u32::from_str_radix(load_address, 16).map_err(|_| {
+        format!(
+            "Failed to parse given overlay load address \"{}\" as a hexadecimal number.",
+            load_address
+        )
+    })?;
+    Ok(Some((name.clone(), load_address)))
+}
+/// Opens a Playstation executable by a given file path, or an overlay given with
+/// "--overlay <name> <load address>" option. The overlay is either the whole file, or a file
+/// in WAD given with "--wad <file index>" option and an optional "--subfile <index>" option.
+fn open_ps1_exe(file_path: &str, options: &[String]) -> Result<PS1Exe, String> {
+    let Some((_, load_address)) = get_overlay_option(options)? else {
+        if options.iter().any(|option| option == "--wad") {
+            return Err(String::from(
+                "Files in WAD have no header, so \"--wad\" option needs \"--overlay\" option with their load address.",
+            ));
+        }
+        return PS1Exe::from_file_path(file_path);
+    };
+    let get_index_option = |name: &str| -> Result<Option<usize>, String> {
+        let Some(option_index) = options.iter().position(|option| option == name) else {
+            return Ok(None);
+        };
+        let index = options
+            .get(option_index + 1)
+            .ok_or_else(|| format!("No index given after \"{}\" option.", name))?;
+        let index = index
+            .parse::<usize>()
+            .map_err(|_| format!("Failed to parse given index \"{}\" as a number.", index))?;
+        Ok(Some(index))
+    };
+
+    let bytes = match get_index_option("--wad")? {
+        Some(file_index) => {
+            let wad = WAD::from_file_path(file_path)?;
+            let wad_reader = WADReader::new(&wad);
+            let file_metadatum = wad_reader.read_file_metadatum_from_header()?;
+            let file_metadata = file_metadatum.get(file_index).ok_or_else(|| {
+                format!(
+                    "WAD file contains {} files, no file found by index {}.",
+                    file_metadatum.len(),
+                    file_index
+                )
+            })?;
+            match get_index_option("--subfile")? {
+                Some(subfile_index) => {
+                    let subfile_metadatum =
+                        wad_reader.read_subfiles_by_file_metadata(file_metadata)?;
+                    let subfile_metadata =
+                        subfile_metadatum.get(subfile_index).ok_or_else(|| {
+                            format!(
+                                "File #{} in WAD contains {} subfiles, no subfile found by index {}.",
+                                file_index,
+                                subfile_metadatum.len(),
+                                subfile_index
+                            )
+                        })?;
+                    wad_reader.read_subfile_data(file_metadata, subfile_metadata)?
+                }
+                None => wad_reader.read_file_data(file_metadata)?,
+            }
+        }
+        None => fs::read(file_path).map_err(|err| {
+            format!(
+                "Failed to read overlay file in path \"{}\": {}",
+                file_path, err
+            )
+        })?,
+    };
+    PS1Exe::from_overlay_bytes(&bytes, load_address)
+}
+/// Gets free regions of an executable from "--free <start>-<end>" options (hexadecimal addresses),
+/// which may be given many times.
+fn get_free_ranges_option(options: &[String]) -> Result<Vec<Range<u64>>, String> {
+    let mut free_ranges = Vec::new();
+    for (option_index, _) in options
+        .iter()
+        .enumerate()
+        .filter(|(_, option)| *option == "--free")
+    {
+        let range = options
+            .get(option_index + 1)
+            .ok_or("No free region given after \"--free\" option.")?;
+        let parsed_range = range
+            .split_once("-")
+            .and_then(|(start, end)| {
+                let start = u64::from_str_radix(start, 16).ok()?;
+                let end = u64::from_str_radix(end, 16).ok()?;
+                Some(start..end)
+            })
+            .filter(|range| range.start < range.end)
+            .ok_or(format!(
+                "Failed to parse given free region \"{}\" as hexadecimal addresses like \"80070000-80071000\".",
+                range
+            ))?;
+        free_ranges.push(parsed_range);
+    }
+    Ok(free_ranges)
+}
+/// Source of text for extracting and inserting strings.
+enum TextSource {
+    PS1Exe(PS1Exe),
+    /// Whole WAD file content, the index of the file in WAD containing strings and the address
+    /// the file is loaded at.
+    WADFile {
+        data: Vec<u8>,
+        file_index: usize,
+        load_address: u32,
+    },
+}
+impl TextSource {
+    /// Opens a text source by a given file path and an optional "--wad <file index>" option,
+    /// which needs "--overlay <name> <load address>" option too.
+    fn open(file_path: &str, options: &[String]) -> Result<Self, Box<dyn std::error::Error>> {
+        match options.iter().position(|option| option == "--wad") {
+            Some(option_index) => {
+                let Some((_, load_address)) = get_overlay_option(options)? else {
+                    return Err(
+                        "Files in WAD have no header, so \"--wad\" option needs \"--overlay\" option with their load address.".into(),
+                    );
+                };
+                let file_index = options
+                    .get(option_index + 1)
+                    .ok_or("No WAD file index given after \"--wad\" option.")?;
+                let file_index = file_index.parse::<usize>().map_err(|_| {
+                    format!(
+                        "Failed to parse given WAD file index \"{}\" as a number.",
+                        file_index
+                    )
+                })?;
+                let data = fs::read(file_path).map_err(|err| {
+                    format!("Failed to read WAD file in path \"{}\": {}", file_path, err)
+                })?;
+                Ok(TextSource::WADFile {
+                    data,
+                    file_index,
+                    load_address,
+                })
+            }
+            None => Ok(TextSource::PS1Exe(PS1Exe::from_file_path(file_path)?)),
+        }
+    }
+    /// Gets the byte range of the file in WAD containing strings.
+    fn get_wad_file_range(
+        file_path: &str,
+        file_index: usize,
+    ) -> Result<std::ops::Range<usize>, String> {
+        let wad = WAD::from_file_path(file_path)?;
+        let file_metadatum = WADReader::new(&wad).read_file_metadatum_from_header()?;
+        let file_metadata = file_metadatum.get(file_index).ok_or_else(|| {
+            format!(
+                "WAD file contains {} files, no file found by index {}.",
+                file_metadatum.len(),
+                file_index
+            )
+        })?;
+        let begin = file_metadata.offset as usize;
+        Ok(begin..begin + file_metadata.size as usize)
+    }
+    /// Finds all strings. Addresses of strings in WAD are addresses in memory the file is loaded at.
+    fn find_text_entries(
+        &self,
+        file_path: &str,
+        codec: &dyn TextCodec,
+    ) -> Result<Vec<TextEntry>, String> {
+        match self {
+            TextSource::PS1Exe(exe) => Ok(PS1ExeReader::new(exe).find_text_entries(codec)),
+            TextSource::WADFile {
+                data,
+                file_index,
+                load_address,
+            } => {
+                let range = Self::get_wad_file_range(file_path, *file_index)?;
+                let file_data = data
+                    .get(range)
+                    .ok_or_else(|| format!("File #{} does not fit in WAD file.", file_index))?;
+                Ok(ps1exe::find_text_entries(
+                    file_data,
+                    *load_address as u64,
+                    codec,
+                ))
+            }
+        }
+    }
+}
+/// Extracts strings from a given Playstation executable (or a file in WAD with --wad and its load address with --overlay) into a PO file for translating.
+fn text_extract(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let input_file_path = get_arg!(args, 0, "input file path")?;
+    let output_po_file_path = get_arg!(args, 1, "output PO file path")?;
+
+    let codec = get_text_codec_option(&args[2..])?;
+    let source = TextSource::open(input_file_path, &args[2..])?;
+    let entries = source.find_text_entries(input_file_path, codec.as_ref())?;
+
+    fs::write(
+        output_po_file_path,
+        ps1exe::write_po(&entries, input_file_path),
+    )
+    .map_err(|err| {
+        format!(
+            "Failed to write PO file to path \"{}\": {}",
+            output_po_file_path, err
+        )
+    })?;
+
+    println!(
+        "Extracted {} strings ({} with pointers) into \"{}\".",
+        entries.len(),
+        entries
+            .iter()
+            .filter(|e| !e.pointer_addresses.is_empty())
+            .count(),
+        output_po_file_path
+    );
+    Ok(())
+}
+/// Inserts translated strings from a given PO file back into a Playstation executable (or a file in WAD with --wad and its load address with --overlay).
+fn text_insert(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let input_file_path = get_arg!(args, 0, "input file path")?;
+    let po_file_path = get_arg!(args, 1, "PO file path")?;
+    let output_file_path = get_arg!(args, 2, "output file path")?;
+    let options = &args[3..];
+
+    // Longer strings are relocated into free regions given with "--free <start>-<end>".
+    let free_ranges = get_free_ranges_option(options)?;
+
+    let po_content = fs::read_to_string(po_file_path).map_err(|err| {
+        format!(
+            "Failed to read PO file in path \"{}\": {}",
+            po_file_path, err
+        )
+    })?;
+    let translations = ps1exe::parse_po(&po_content).map_err(|err| {
+        format!(
+            "Failed to parse PO file in path \"{}\": {}",
+            po_file_path, err
+        )
+    })?;
+
+    let codec = get_text_codec_option(options)?;
+    let mut source = TextSource::open(input_file_path, options)?;
+    let entries = source.find_text_entries(input_file_path, codec.as_ref())?;
+    let writes = ps1exe::plan_text_writes(&entries, &translations, &free_ranges, codec.as_ref())?;
+
+    match &mut source {
+        TextSource::PS1Exe(exe) => {
+            let mut ps1_exe_writer = PS1ExeWriter::new(exe);
+            let source = WriteSource {
+                file_path: po_file_path.clone(),
+                line: None,
+            };
+            // Writes outside of the executable fail (see PS1Exe::get_file_offset).
+            for write in writes.iter() {
+                ps1_exe_writer.write_code(write.address, &write.bytes, &source)?;
+            }
+            ps1_exe_writer.write_into_file(output_file_path)?;
+        }
+        TextSource::WADFile {
+            data,
+            file_index,
+            load_address,
+        } => {
+            let range = TextSource::get_wad_file_range(input_file_path, *file_index)?;
+            let load_address = *load_address as u64;
+            for write in writes.iter() {
+                // Writes must stay inside the file, or they would overwrite the next file in WAD.
+                let end_address = write.address + write.bytes.len() as u64;
+                if write.address < load_address || end_address > load_address + range.len() as u64 {
+                    return Err(format!(
+                        "Failed to write {} bytes at address 0x{:x}, because it is outside of file #{} in WAD loaded at 0x{:x}-0x{:x}.",
+                        write.bytes.len(),
+                        write.address,
+                        file_index,
+                        load_address,
+                        load_address + range.len() as u64
+                    )
+                    .into());
+                }
+                let begin = range.start + (write.address - load_address) as usize;
+                data[begin..begin + write.bytes.len()].copy_from_slice(&write.bytes);
+            }
+            fs::write(output_file_path, data).map_err(|err| {
+                format!(
+                    "Failed to write WAD file to path \"{}\": {}",
+                    output_file_path, err
+                )
+            })?;
+        }
+    }
+
+    println!(
+        "Inserted {} translated strings into \"{}\".",
+        translations
+            .iter()
+            .filter(|t| !t.translation.is_empty())
+            .count(),
+        output_file_path
+    );
+    Ok(())
+}
 /// Reads information about WAD file. Heavily WIP.
 fn wad_read(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
     let wad_path = get_arg!(args, 0, "WAD path")?;
@@ -916,7 +2267,8 @@ fn wad_read(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
 
     let file_metadatum = wad_reader.read_file_metadatum_from_header()?;
 
-    for (i, file_metadata) in file_metadatum.iter().enumerate() {
+    // Only the first file is read for now.
+    for (i, file_metadata) in file_metadatum.iter().enumerate().take(1) {
         println!("File #{} metadata: {:?}", i + 1, file_metadata);
 
         for (j, file) in wad_reader
@@ -926,8 +2278,123 @@ fn wad_read(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
         {
             println!("File #{} content: {:?}", j + 1, file);
         }
+    }
+
+    Ok(())
+}
+/// Reads texture animation and scrolling tables of a level in WAD file and optionally writes a preview PNG strip of an animation.
+fn wad_texture_animations(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let wad_path = get_arg!(args, 0, "WAD path")?;
+
+    /// Parses a given argument as an index (a number starting from 0).
+    fn parse_index(value: &str, name: &str) -> Result<usize, String> {
+        value
+            .parse::<usize>()
+            .map_err(|_| format!("Failed to parse given {} \"{}\" as a number.", name, value))
+    }
+
+    let file_index = get_arg!(args, 1, "file index")?;
+    let file_index = parse_index(file_index, "file index")?;
+
+    let subfile_index = get_arg!(args, 2, "subfile index")?;
+    let subfile_index = parse_index(subfile_index, "subfile index")?;
+
+    let table_offset = get_arg!(args, 3, "table offset")?;
+    let table_offset = usize::from_str_radix(table_offset, 16).map_err(|_| {
+        format!(
+            "Failed to parse given table offset \"{}\" as a hexadecimal number.",
+            table_offset
+        )
+    })?;
+
+    let wad = WAD::from_file_path(wad_path)?;
+    let wad_reader = WADReader::new(&wad);
+
+    let file_metadatum = wad_reader.read_file_metadatum_from_header()?;
+    let file_metadata = file_metadatum.get(file_index).ok_or_else(|| {
+        format!(
+            "WAD file contains {} files, no file found by index {}.",
+            file_metadatum.len(),
+            file_index
+        )
+    })?;
+    let subfile_metadatum = wad_reader.read_subfiles_by_file_metadata(file_metadata)?;
+
+    let get_subfile_data = |index: usize| -> Result<Vec<u8>, String> {
+        let subfile_metadata = subfile_metadatum.get(index).ok_or_else(|| {
+            format!(
+                "File #{} contains {} subfiles, no subfile found by index {}.",
+                file_index,
+                subfile_metadatum.len(),
+                index
+            )
+        })?;
+        wad_reader.read_subfile_data(file_metadata, subfile_metadata)
+    };
+
+    let subfile_data = get_subfile_data(subfile_index)?;
+    let table_bytes = subfile_data.get(table_offset..).ok_or_else(|| {
+        format!(
+            "Table offset 0x{:x} is outside of subfile #{} ({} bytes).",
+            table_offset,
+            subfile_index,
+            subfile_data.len()
+        )
+    })?;
+    let tables = TextureAnimationTables::from_bytes(table_bytes)?;
+
+    for (i, animation) in tables.animations.iter().enumerate() {
+        println!(
+            "Animation #{}: texture {}, {} frames, {} game frames per frame ({} game frames in total)",
+            i,
+            animation.texture_index,
+            animation.frames.len(),
+            animation.frame_delay,
+            animation.get_duration_in_game_frames()
+        );
+        for (j, frame) in animation.frames.iter().enumerate() {
+            println!(
+                "  Frame #{}: tpage 0x{:04x}, CLUT 0x{:04x}, UVs {:?}",
+                j, frame.tpage, frame.clut, frame.uvs
+            );
+        }
+    }
+    for (i, scroll) in tables.scrolls.iter().enumerate() {
+        println!(
+            "Scroll #{}: polygon {}, U speed {}, V speed {}",
+            i, scroll.polygon_index, scroll.u_speed, scroll.v_speed
+        );
+    }
+
+    if let Some(option) = args.get(4) {
+        if option != "--preview" {
+            return Err(format!(
+                "Invalid option given after the table offset \"{}\". Valid option is \"--preview\".",
+                option
+            )
+            .into());
+        }
+
+        let animation_index = get_arg!(args, 5, "animation index")?;
+        let animation_index = parse_index(animation_index, "animation index")?;
+        let vram_subfile_index = get_arg!(args, 6, "VRAM subfile index")?;
+        let vram_subfile_index = parse_index(vram_subfile_index, "VRAM subfile index")?;
+        let output_file_path = get_arg!(args, 7, "output PNG file path")?;
+
+        let animation = tables.animations.get(animation_index).ok_or_else(|| {
+            format!(
+                "Level has {} texture animations, no animation found by index {}.",
+                tables.animations.len(),
+                animation_index
+            )
+        })?;
+        let vram = Vram::from_le_bytes(&get_subfile_data(vram_subfile_index)?)?;
+        animation.write_png_strip(&vram, output_file_path)?;
 
-        break;
+        println!(
+            "Preview of animation #{} written to \"{}\".",
+            animation_index, output_file_path
+        );
     }
 
     Ok(())
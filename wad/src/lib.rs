@@ -3,6 +3,14 @@ use std::{
     io::{BufReader, Read, Seek, SeekFrom},
 };
 
+mod texture_animation;
+mod vram;
+
+pub use texture_animation::{
+    TextureAnimation, TextureAnimationTables, TextureFrame, TextureScroll,
+};
+pub use vram::{TextureColorMode, Vram};
+
 /// WAD file format is a custom file format used for Spyro the Dragon.
 /// It is a container format that contains multiple files.
 ///
@@ -25,13 +33,8 @@ pub struct WAD<'a> {
 }
 impl<'a> WAD<'a> {
     pub fn from_file_path(file_path: &'a str) -> Result<Self, String> {
-        let file = File::open(file_path).map_err(|err| {
-            format!(
-                "Failed to open WAD file \"{}\": {}",
-                file_path,
-                err.to_string()
-            )
-        })?;
+        let file = File::open(file_path)
+            .map_err(|err| format!("Failed to open WAD file \"{}\": {}", file_path, err))?;
         let file_size = get_file_size(&file, file_path)?;
         Ok(Self {
             file,
@@ -56,6 +59,52 @@ impl<'a> WADReader<'a> {
     pub fn new(wad: &'a WAD) -> Self {
         Self { wad }
     }
+    /// Reads the data of a file by its metadata from WAD file.
+    pub fn read_file_data(&self, file_metadata: &WADFileMetadata) -> Result<Vec<u8>, String> {
+        self.read_bytes(file_metadata.offset as u64, file_metadata.size as usize)
+    }
+    /// Reads the data of a subfile by its metadata from WAD file.
+    /// Subfile offsets are relative to the beginning of the file containing them.
+    pub fn read_subfile_data(
+        &self,
+        file_metadata: &WADFileMetadata,
+        subfile_metadata: &WADFileMetadata,
+    ) -> Result<Vec<u8>, String> {
+        if subfile_metadata.offset as u64 + subfile_metadata.size as u64 > file_metadata.size as u64
+        {
+            return Err(format!(
+                "Subfile (offset {}, size {}) does not fit in its file (offset {}, size {}) in WAD file in path \"{}\".",
+                subfile_metadata.offset,
+                subfile_metadata.size,
+                file_metadata.offset,
+                file_metadata.size,
+                self.wad.file_path
+            ));
+        }
+        self.read_bytes(
+            file_metadata.offset as u64 + subfile_metadata.offset as u64,
+            subfile_metadata.size as usize,
+        )
+    }
+    fn read_bytes(&self, offset: u64, count: usize) -> Result<Vec<u8>, String> {
+        let mut reader = BufReader::new(&self.wad.file);
+
+        reader.seek(SeekFrom::Start(offset)).map_err(|err| {
+            format!(
+                "Failed to seek to offset {} in WAD file in path \"{}\": {}",
+                offset, self.wad.file_path, err
+            )
+        })?;
+
+        let mut buffer = vec![0u8; count];
+        reader.read_exact(&mut buffer).map_err(|err| {
+            format!(
+                "Failed to read {} bytes at offset {} from WAD file in path \"{}\": {}",
+                count, offset, self.wad.file_path, err
+            )
+        })?;
+        Ok(buffer)
+    }
     pub fn read_subfiles_by_file_metadata(
         &self,
         file_metadata: &WADFileMetadata,
@@ -68,8 +117,7 @@ impl<'a> WADReader<'a> {
             .map_err(|err| {
                 format!(
                     "Failed to seek to the beginning of the file in WAD file in path \"{}\": {}",
-                    self.wad.file_path,
-                    err.to_string()
+                    self.wad.file_path, err
                 )
             })?;
 
@@ -83,8 +131,7 @@ impl<'a> WADReader<'a> {
                 .map_err(|err| {
                     format!(
                         "Failed to read bytes from WAD file header in file in path \"{}\": {}",
-                        self.wad.file_path,
-                        err.to_string()
+                        self.wad.file_path, err
                     )
                 })?;
 
@@ -97,11 +144,10 @@ impl<'a> WADReader<'a> {
             file_metadatum.push(file);
 
             // Get the current position in the WAD file. If it is at the end of the file, stop reading.
-            let position = reader.seek(SeekFrom::Current(0)).map_err(|err| {
+            let position = reader.stream_position().map_err(|err| {
                 format!(
                     "Failed to get current position in WAD file in path \"{}\": {}",
-                    self.wad.file_path,
-                    err.to_string()
+                    self.wad.file_path, err
                 )
             })?;
 
@@ -129,8 +175,7 @@ impl<'a> WADReader<'a> {
                 .map_err(|err| {
                     format!(
                         "Failed to read bytes from WAD file header in file in path \"{}\": {}",
-                        self.wad.file_path,
-                        err.to_string()
+                        self.wad.file_path, err
                     )
                 })?;
 
@@ -143,11 +188,10 @@ impl<'a> WADReader<'a> {
             file_metadatum.push(file);
 
             // Get the current position in the WAD file. If it is at the end of the file, stop reading.
-            let position = reader.seek(SeekFrom::Current(0)).map_err(|err| {
+            let position = reader.stream_position().map_err(|err| {
                 format!(
                     "Failed to get current position in WAD file in path \"{}\": {}",
-                    self.wad.file_path,
-                    err.to_string()
+                    self.wad.file_path, err
                 )
             })?;
 
@@ -181,7 +225,7 @@ fn get_file_size(file: &File, file_path: &str) -> Result<u64, String> {
     let metadata = file.metadata().map_err(|err| {
         format!(
             "Failed to get metadata (in order to determine file size in bytes) of given WAD file in path \"{}\": {}",
-            file_path, err.to_string()
+            file_path, err
         )
     })?;
     Ok(metadata.len())
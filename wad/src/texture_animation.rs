@@ -0,0 +1,331 @@
+use std::{fs::File, io::BufWriter};
+
+use crate::vram::Vram;
+
+/// Texture animation and texture scrolling tables of a level.
+///
+/// Water, lava and portal effects are made by either swapping a texture
+/// through a sequence of frames (texture animation) or by moving texture
+/// coordinates of polygons over time (texture scrolling).
+///
+/// # Table layout
+/// All values are in LE byte order.
+///
+/// ## Texture animations
+/// * `u32` Animation count
+/// * For each animation:
+///     * `u16` Index of the level texture that is animated
+///     * `u8` Frame count
+///     * `u8` Frame delay in game frames, which defines animation speed
+///     * Frames, each being texture info of 12 bytes (see [TextureFrame])
+///
+/// ## Texture scrolls
+/// * `u32` Scroll count
+/// * For each scroll (4 bytes):
+///     * `u16` Index of the level polygon whose texture is scrolled
+///     * `i8` U coordinate change per game frame
+///     * `i8` V coordinate change per game frame
+///
+/// # Notes
+/// Location of these tables in level data is not fully known yet,
+/// which is why the offset to the tables must be given when reading them.
+#[derive(Debug, PartialEq)]
+pub struct TextureAnimationTables {
+    pub animations: Vec<TextureAnimation>,
+    pub scrolls: Vec<TextureScroll>,
+}
+impl TextureAnimationTables {
+    pub fn from_bytes(value: &[u8]) -> Result<Self, String> {
+        let mut offset = 0;
+
+        let animation_count = read_u32(value, &mut offset)?;
+        check_count(value, offset, animation_count, 4, "texture animations")?;
+        let mut animations = Vec::with_capacity(animation_count as usize);
+        for i in 0..animation_count {
+            let animation = TextureAnimation::from_bytes(value, &mut offset)
+                .map_err(|err| format!("Failed to read texture animation #{}: {}", i + 1, err))?;
+            animations.push(animation);
+        }
+
+        let scroll_count = read_u32(value, &mut offset)?;
+        check_count(value, offset, scroll_count, 4, "texture scrolls")?;
+        let mut scrolls = Vec::with_capacity(scroll_count as usize);
+        for i in 0..scroll_count {
+            let scroll = TextureScroll::from_bytes(value, &mut offset)
+                .map_err(|err| format!("Failed to read texture scroll #{}: {}", i + 1, err))?;
+            scrolls.push(scroll);
+        }
+
+        Ok(Self {
+            animations,
+            scrolls,
+        })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct TextureAnimation {
+    pub texture_index: u16,
+    /// How many game frames each animation frame is shown for.
+    pub frame_delay: u8,
+    pub frames: Vec<TextureFrame>,
+}
+impl TextureAnimation {
+    fn from_bytes(value: &[u8], offset: &mut usize) -> Result<Self, String> {
+        let texture_index = read_u16(value, offset)?;
+        let frame_count = read_bytes(value, offset, 1)?[0];
+        let frame_delay = read_bytes(value, offset, 1)?[0];
+
+        let mut frames = Vec::with_capacity(frame_count as usize);
+        for _ in 0..frame_count {
+            let frame_bytes = read_bytes(value, offset, TextureFrame::LEN)?;
+            frames.push(TextureFrame::from_bytes(frame_bytes.try_into().unwrap()));
+        }
+
+        Ok(Self {
+            texture_index,
+            frame_delay,
+            frames,
+        })
+    }
+    /// Gets the length of one full animation loop in game frames.
+    pub fn get_duration_in_game_frames(&self) -> usize {
+        self.frames.len() * self.frame_delay as usize
+    }
+    /// Writes every frame of the animation side by side into a PNG image.
+    /// Frames are read from the given VRAM.
+    pub fn write_png_strip(&self, vram: &Vram, file_path: &str) -> Result<(), String> {
+        if self.frames.is_empty() {
+            return Err(String::from(
+                "Failed to write PNG strip: texture animation has no frames.",
+            ));
+        }
+
+        let frame_width = self.frames.iter().map(|f| f.get_width()).max().unwrap();
+        let frame_height = self.frames.iter().map(|f| f.get_height()).max().unwrap();
+        let strip_width = frame_width * self.frames.len();
+
+        let mut pixels = vec![0u8; strip_width * frame_height * 4];
+        for (i, frame) in self.frames.iter().enumerate() {
+            let (u_min, v_min) = frame.get_uv_min();
+            for y in 0..frame.get_height() {
+                for x in 0..frame.get_width() {
+                    let rgba = vram.read_texel_as_rgba(
+                        frame.tpage,
+                        frame.clut,
+                        u_min.wrapping_add(x as u8),
+                        v_min.wrapping_add(y as u8),
+                    );
+                    let pixel_index = (y * strip_width + i * frame_width + x) * 4;
+                    pixels[pixel_index..pixel_index + 4].copy_from_slice(&rgba);
+                }
+            }
+        }
+
+        let file = File::create(file_path).map_err(|err| {
+            format!(
+                "Failed to create PNG file to path \"{}\": {}",
+                file_path, err
+            )
+        })?;
+        let mut encoder = png::Encoder::new(
+            BufWriter::new(file),
+            strip_width as u32,
+            frame_height as u32,
+        );
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let mut writer = encoder.write_header().map_err(|err| {
+            format!(
+                "Failed to write PNG header to path \"{}\": {}",
+                file_path, err
+            )
+        })?;
+        writer.write_image_data(&pixels).map_err(|err| {
+            format!(
+                "Failed to write PNG image data to path \"{}\": {}",
+                file_path, err
+            )
+        })?;
+        Ok(())
+    }
+}
+
+/// Texture info for one frame of a texture animation.
+///
+/// Layout follows how textured quads are given to the Playstation GPU:
+/// * `u8` U0, `u8` V0, `u16` CLUT
+/// * `u8` U1, `u8` V1, `u16` texture page
+/// * `u8` U2, `u8` V2
+/// * `u8` U3, `u8` V3
+#[derive(Debug, PartialEq)]
+pub struct TextureFrame {
+    pub uvs: [(u8, u8); 4],
+    pub clut: u16,
+    pub tpage: u16,
+}
+impl TextureFrame {
+    const LEN: usize = 12;
+
+    fn from_bytes(value: &[u8; Self::LEN]) -> Self {
+        Self {
+            uvs: [
+                (value[0], value[1]),
+                (value[4], value[5]),
+                (value[8], value[9]),
+                (value[10], value[11]),
+            ],
+            clut: u16::from_le_bytes([value[2], value[3]]),
+            tpage: u16::from_le_bytes([value[6], value[7]]),
+        }
+    }
+    fn get_uv_min(&self) -> (u8, u8) {
+        let u = self.uvs.iter().map(|(u, _)| *u).min().unwrap();
+        let v = self.uvs.iter().map(|(_, v)| *v).min().unwrap();
+        (u, v)
+    }
+    pub fn get_height(&self) -> usize {
+        let v_max = self.uvs.iter().map(|(_, v)| *v).max().unwrap();
+        (v_max - self.get_uv_min().1) as usize + 1
+    }
+    pub fn get_width(&self) -> usize {
+        let u_max = self.uvs.iter().map(|(u, _)| *u).max().unwrap();
+        (u_max - self.get_uv_min().0) as usize + 1
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct TextureScroll {
+    pub polygon_index: u16,
+    /// U coordinate change per game frame.
+    pub u_speed: i8,
+    /// V coordinate change per game frame.
+    pub v_speed: i8,
+}
+impl TextureScroll {
+    fn from_bytes(value: &[u8], offset: &mut usize) -> Result<Self, String> {
+        let polygon_index = read_u16(value, offset)?;
+        let speeds = read_bytes(value, offset, 2)?;
+        Ok(Self {
+            polygon_index,
+            u_speed: speeds[0] as i8,
+            v_speed: speeds[1] as i8,
+        })
+    }
+}
+
+/// Reads a given count of bytes from the given offset onwards and advances the offset.
+fn read_bytes<'a>(value: &'a [u8], offset: &mut usize, count: usize) -> Result<&'a [u8], String> {
+    let bytes = value.get(*offset..*offset + count).ok_or_else(|| {
+        format!(
+            "Not enough bytes to read {} bytes at offset {} (data is {} bytes long).",
+            count,
+            offset,
+            value.len()
+        )
+    })?;
+    *offset += count;
+    Ok(bytes)
+}
+/// Checks that a count read from the data fits into the bytes left, given the smallest size
+/// of an entry, so that a wrong offset does not allocate memory for billions of entries.
+fn check_count(
+    value: &[u8],
+    offset: usize,
+    count: u32,
+    min_entry_len: usize,
+    name: &str,
+) -> Result<(), String> {
+    let bytes_left = value.len().saturating_sub(offset);
+    if (count as usize).saturating_mul(min_entry_len) > bytes_left {
+        return Err(format!(
+            "Count of {} {} at offset {} needs at least {} bytes each, but only {} bytes are left.",
+            count,
+            name,
+            offset - 4,
+            min_entry_len,
+            bytes_left
+        ));
+    }
+    Ok(())
+}
+fn read_u16(value: &[u8], offset: &mut usize) -> Result<u16, String> {
+    let bytes = read_bytes(value, offset, 2)?;
+    Ok(u16::from_le_bytes(bytes.try_into().unwrap()))
+}
+fn read_u32(value: &[u8], offset: &mut usize) -> Result<u32, String> {
+    let bytes = read_bytes(value, offset, 4)?;
+    Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_texture_animation_tables() {
+        #[rustfmt::skip]
+        let bytes = [
+            // One animation
+            0x01, 0x00, 0x00, 0x00,
+            0x05, 0x00, 0x02, 0x04, // Texture 5, 2 frames, delay of 4 game frames
+            0x00, 0x00, 0x00, 0x7F, 0x1F, 0x00, 0x08, 0x00, 0x00, 0x1F, 0x1F, 0x1F,
+            0x20, 0x00, 0x00, 0x7F, 0x3F, 0x00, 0x08, 0x00, 0x20, 0x1F, 0x3F, 0x1F,
+            // Two scrolls
+            0x02, 0x00, 0x00, 0x00,
+            0x10, 0x00, 0x01, 0xFF, // Polygon 16, U +1, V -1
+            0x11, 0x00, 0x00, 0x02, // Polygon 17, U 0, V +2
+        ];
+        let tables = TextureAnimationTables::from_bytes(&bytes).unwrap();
+
+        assert_eq!(tables.animations.len(), 1);
+        let animation = &tables.animations[0];
+        assert_eq!(animation.texture_index, 5);
+        assert_eq!(animation.frame_delay, 4);
+        assert_eq!(animation.get_duration_in_game_frames(), 8);
+        assert_eq!(animation.frames.len(), 2);
+        assert_eq!(
+            animation.frames[1],
+            TextureFrame {
+                uvs: [(0x20, 0x00), (0x3F, 0x00), (0x20, 0x1F), (0x3F, 0x1F)],
+                clut: 0x7F00,
+                tpage: 0x0008,
+            }
+        );
+        assert_eq!(animation.frames[1].get_width(), 32);
+        assert_eq!(animation.frames[1].get_height(), 32);
+
+        assert_eq!(
+            tables.scrolls,
+            vec![
+                TextureScroll {
+                    polygon_index: 16,
+                    u_speed: 1,
+                    v_speed: -1
+                },
+                TextureScroll {
+                    polygon_index: 17,
+                    u_speed: 0,
+                    v_speed: 2
+                }
+            ]
+        );
+    }
+    #[test]
+    fn fail_read_texture_animation_tables_with_truncated_frames() {
+        let bytes = [0x01, 0x00, 0x00, 0x00, 0x05, 0x00, 0x02, 0x04, 0x00, 0x00];
+        assert!(TextureAnimationTables::from_bytes(&bytes).is_err());
+    }
+    #[test]
+    fn fail_read_texture_animation_tables_with_huge_counts() {
+        let bytes = [0xFF, 0xFF, 0xFF, 0xFF, 0x05, 0x00, 0x02, 0x04];
+        assert!(TextureAnimationTables::from_bytes(&bytes)
+            .unwrap_err()
+            .contains("4294967295 texture animations"));
+        let bytes = [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x40, 0x10, 0x00];
+        assert!(TextureAnimationTables::from_bytes(&bytes)
+            .unwrap_err()
+            .contains("texture scrolls"));
+    }
+}
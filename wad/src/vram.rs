@@ -0,0 +1,190 @@
+/// Playstation VRAM is 1024x512 halfwords (16-bit values) in size.
+/// Textures are stored in it as 4-bit or 8-bit indexed colors (using a CLUT,
+/// that is, a color lookup table) or as 15-bit direct colors.
+pub struct Vram {
+    data: Vec<u16>,
+}
+impl Vram {
+    pub const WIDTH: usize = 1024;
+    pub const HEIGHT: usize = 512;
+
+    /// Creates VRAM from LE bytes. Bytes are placed into VRAM row by row
+    /// starting from the top left corner. VRAM rows not covered by the given
+    /// bytes are zerofilled.
+    ///
+    /// Level data in WAD file contains only a part of VRAM, which is why
+    /// the given bytes do not need to cover the entire VRAM.
+    pub fn from_le_bytes(value: &[u8]) -> Result<Self, String> {
+        const ROW_LEN_IN_BYTES: usize = Vram::WIDTH * 2;
+
+        if value.len() % ROW_LEN_IN_BYTES != 0 {
+            return Err(format!(
+                "VRAM data size {} is not in a multiple of {} bytes (one VRAM row).",
+                value.len(),
+                ROW_LEN_IN_BYTES
+            ));
+        }
+        if value.len() > Self::WIDTH * Self::HEIGHT * 2 {
+            return Err(format!(
+                "VRAM data size {} is larger than VRAM itself ({} bytes).",
+                value.len(),
+                Self::WIDTH * Self::HEIGHT * 2
+            ));
+        }
+
+        let mut data = vec![0u16; Self::WIDTH * Self::HEIGHT];
+        for (i, halfword) in value.chunks_exact(2).enumerate() {
+            data[i] = u16::from_le_bytes([halfword[0], halfword[1]]);
+        }
+        Ok(Self { data })
+    }
+    #[inline]
+    fn get_halfword(&self, x: usize, y: usize) -> u16 {
+        self.data[(y % Self::HEIGHT) * Self::WIDTH + (x % Self::WIDTH)]
+    }
+    /// Reads a texel from a texture page as an RGBA color.
+    ///
+    /// `u` and `v` are texture coordinates within the texture page.
+    pub fn read_texel_as_rgba(&self, tpage: u16, clut: u16, u: u8, v: u8) -> [u8; 4] {
+        let page_x = (tpage & 0b1111) as usize * 64;
+        let page_y = ((tpage >> 4) & 0b1) as usize * 256;
+        let clut_x = (clut & 0b111111) as usize * 16;
+        let clut_y = ((clut >> 6) & 0b111111111) as usize;
+
+        let u = u as usize;
+        let y = page_y + v as usize;
+
+        let color = match TextureColorMode::from_tpage(tpage) {
+            TextureColorMode::Indexed4Bit => {
+                let halfword = self.get_halfword(page_x + u / 4, y);
+                let index = (halfword >> ((u % 4) * 4)) & 0b1111;
+                self.get_halfword(clut_x + index as usize, clut_y)
+            }
+            TextureColorMode::Indexed8Bit => {
+                let halfword = self.get_halfword(page_x + u / 2, y);
+                let index = (halfword >> ((u % 2) * 8)) & 0xFF;
+                self.get_halfword(clut_x + index as usize, clut_y)
+            }
+            TextureColorMode::Direct15Bit => self.get_halfword(page_x + u, y),
+        };
+
+        bgr555_to_rgba(color)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum TextureColorMode {
+    Indexed4Bit,
+    Indexed8Bit,
+    Direct15Bit,
+}
+impl TextureColorMode {
+    /// Reads the color mode from bits 7-8 of a texture page value.
+    /// Value 3 is reserved, but acts the same as 15-bit colors on hardware.
+    pub fn from_tpage(tpage: u16) -> Self {
+        match (tpage >> 7) & 0b11 {
+            0 => TextureColorMode::Indexed4Bit,
+            1 => TextureColorMode::Indexed8Bit,
+            _ => TextureColorMode::Direct15Bit,
+        }
+    }
+}
+
+/// Converts a 15-bit BGR color used by Playstation into an RGBA color.
+/// Color value 0 is fully transparent, like it is on Playstation.
+pub fn bgr555_to_rgba(value: u16) -> [u8; 4] {
+    #[inline]
+    fn expand_5_bits(value: u16) -> u8 {
+        let value = (value & 0b11111) as u8;
+        (value << 3) | (value >> 2)
+    }
+    let alpha = if value == 0 { 0 } else { 0xFF };
+    [
+        expand_5_bits(value),
+        expand_5_bits(value >> 5),
+        expand_5_bits(value >> 10),
+        alpha,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// VRAM of two rows: texels at the top left corner and a CLUT on the second row.
+    fn get_vram() -> Vram {
+        let mut halfwords = vec![0u16; Vram::WIDTH * 2];
+        // 4-bit indices 0, 1, 2, 3 (or 8-bit indices 0x10, 0x32).
+        halfwords[0] = 0x3210;
+        // 15-bit color in texture page 1.
+        halfwords[64 + 2] = 0x4210;
+        halfwords[Vram::WIDTH..Vram::WIDTH + 4].copy_from_slice(&[0x0000, 0x001F, 0x03E0, 0x7C00]);
+        halfwords[Vram::WIDTH + 0x10] = 0x8000;
+        halfwords[Vram::WIDTH + 0x32] = 0x7FFF;
+        let bytes = halfwords
+            .iter()
+            .flat_map(|halfword| halfword.to_le_bytes())
+            .collect::<Vec<_>>();
+        Vram::from_le_bytes(&bytes).unwrap()
+    }
+
+    #[test]
+    fn convert_bgr555_to_rgba() {
+        assert_eq!(bgr555_to_rgba(0x001F), [0xFF, 0x00, 0x00, 0xFF]);
+        assert_eq!(bgr555_to_rgba(0x03E0), [0x00, 0xFF, 0x00, 0xFF]);
+        assert_eq!(bgr555_to_rgba(0x7C00), [0x00, 0x00, 0xFF, 0xFF]);
+        assert_eq!(bgr555_to_rgba(0x4210), [0x84, 0x84, 0x84, 0xFF]);
+        // Black is transparent, unless its semi-transparency bit is set.
+        assert_eq!(bgr555_to_rgba(0x0000), [0x00, 0x00, 0x00, 0x00]);
+        assert_eq!(bgr555_to_rgba(0x8000), [0x00, 0x00, 0x00, 0xFF]);
+    }
+    #[test]
+    fn read_texels_through_clut() {
+        let vram = get_vram();
+        // CLUT at x 0, y 1.
+        let clut = 1 << 6;
+        let tpage_4_bit = 0x0000;
+        assert_eq!(
+            vram.read_texel_as_rgba(tpage_4_bit, clut, 0, 0),
+            [0x00, 0x00, 0x00, 0x00]
+        );
+        assert_eq!(
+            vram.read_texel_as_rgba(tpage_4_bit, clut, 1, 0),
+            [0xFF, 0x00, 0x00, 0xFF]
+        );
+        assert_eq!(
+            vram.read_texel_as_rgba(tpage_4_bit, clut, 2, 0),
+            [0x00, 0xFF, 0x00, 0xFF]
+        );
+        assert_eq!(
+            vram.read_texel_as_rgba(tpage_4_bit, clut, 3, 0),
+            [0x00, 0x00, 0xFF, 0xFF]
+        );
+
+        let tpage_8_bit = 1 << 7;
+        assert_eq!(
+            vram.read_texel_as_rgba(tpage_8_bit, clut, 0, 0),
+            [0x00, 0x00, 0x00, 0xFF]
+        );
+        assert_eq!(
+            vram.read_texel_as_rgba(tpage_8_bit, clut, 1, 0),
+            [0xFF, 0xFF, 0xFF, 0xFF]
+        );
+
+        // Texture page 1 starts at x 64.
+        let tpage_15_bit = (2 << 7) | 1;
+        assert_eq!(
+            vram.read_texel_as_rgba(tpage_15_bit, 0, 2, 0),
+            [0x84, 0x84, 0x84, 0xFF]
+        );
+        assert_eq!(
+            TextureColorMode::from_tpage(3 << 7),
+            TextureColorMode::Direct15Bit
+        );
+    }
+    #[test]
+    fn fail_create_vram_from_partial_rows() {
+        assert!(Vram::from_le_bytes(&[0; 2]).is_err());
+        assert!(Vram::from_le_bytes(&vec![0; Vram::WIDTH * 2 * (Vram::HEIGHT + 1)]).is_err());
+    }
+}